@@ -0,0 +1,122 @@
+//! Arguments for guest programs, so one program can run with different parameters without being assembled again. The
+//! host writes an argument block into RAM before the program starts, see [arguments](../docs/arguments.md):
+//! the argument count, one word per argument and then the strings the words point to. A [`HostEnv`] passes host values
+//! like environment variables the same way, in a block that is read only for the guest.
+use alloc::{format, string::String, vec::Vec};
+
+use crate::{
+    BError,
+    vm::{RAM_LEN, VmDescription},
+};
+
+/// Where `bric run` puts the argument block unless `--args-at` is given
+pub const ARGS_BLOCK: u16 = 0x7800;
+
+/// Where `bric run` puts the environment block unless `--env-at` is given
+pub const ENV_BLOCK: u16 = 0x7c00;
+
+/// An argument of a guest program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GuestArg {
+    /// Passed as is
+    Value(u16),
+    /// Passed as the address of the string. One byte per word and terminated by 0
+    Str(String),
+}
+
+/// The argument block for `args` at `address`
+/// ## Examples
+/// ```rust
+/// use bric_vm::args::{GuestArg, encode};
+///
+/// let block = encode(0x100, &[GuestArg::Str("hi".to_string()), GuestArg::Value(7)]);
+/// assert_eq!(block, [2, 0x103, 7, b'h' as u16, b'i' as u16, 0]);
+/// ```
+pub fn encode(address: u16, args: &[GuestArg]) -> Vec<u16> {
+    let mut block = Vec::with_capacity(1 + args.len());
+    block.push(args.len() as u16);
+    let mut strings = Vec::new();
+    for arg in args {
+        match arg {
+            GuestArg::Value(value) => block.push(*value),
+            GuestArg::Str(text) => {
+                let pointer = address as usize + 1 + args.len() + strings.len();
+                block.push(pointer as u16);
+                strings.extend(text.bytes().map(u16::from));
+                strings.push(0);
+            }
+        }
+    }
+    block.extend(strings);
+    block
+}
+
+/// Write the argument block for `args` at `address` into the initial RAM of `vm_desc`
+/// ## Errors
+/// [`BError::OutOfBoundsError`] if the block does not fit into RAM
+/// ## Examples
+/// ```rust
+/// use bric_vm::{args::{GuestArg, write_args}, assembler, vm::Vm};
+///
+/// // add the first argument to the first character of the second one
+/// let code = r"[text]
+/// A = 0x7001
+/// D = add, 0, *A
+/// A = 0x7002
+/// A = add, 0, *A
+/// A = add, 0, *A
+/// D = add, D, A
+/// [consts 0x100]";
+/// let mut vm_desc = assembler::run(code).unwrap();
+/// write_args(&mut vm_desc, 0x7000, &[GuestArg::Value(1), GuestArg::Str("A".to_string())]).unwrap();
+/// let mut vm = Vm::new(vm_desc).unwrap();
+/// for _ in 0..6 {
+///     vm.cycle().unwrap();
+/// }
+/// assert_eq!(vm.cpu_state().d, b'B' as u16);
+/// ```
+pub fn write_args(
+    vm_desc: &mut VmDescription,
+    address: u16,
+    args: &[GuestArg],
+) -> Result<(), BError> {
+    let block = encode(address, args);
+    let start = address as usize;
+    if start + block.len() > RAM_LEN {
+        return Err(BError::OutOfBoundsError(address, block.len(), RAM_LEN));
+    }
+    vm_desc.mem[start..start + block.len()].copy_from_slice(&block);
+    Ok(())
+}
+
+/// Host values handed to the guest as `NAME=VALUE` strings, e.g. environment variables or the ID of a test run. The
+/// block is laid out like the argument block and written by [`crate::vm::Vm::new()`], which makes it read only. See
+/// [`crate::vm::VmDescription`]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct HostEnv {
+    pub address: u16,
+    /// Ordered like in the block
+    pub vars: Vec<(String, String)>,
+}
+
+impl HostEnv {
+    /// The environment block
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::{args::HostEnv, vm::{Vm, VmDescription}};
+    ///
+    /// let env = HostEnv { address: 0x7c00, vars: vec![("RUN".to_string(), "7".to_string())] };
+    /// assert_eq!(env.encode()[..3], [1, 0x7c02, b'R' as u16]);
+    ///
+    /// let mut vm = Vm::new(VmDescription { host_env: Some(env), ..Default::default() }).unwrap();
+    /// assert_eq!(*vm.get_ram_region(0x7c06, 2), [b'7' as u16, 0]);
+    /// ```
+    pub fn encode(&self) -> Vec<u16> {
+        let strings: Vec<GuestArg> = self
+            .vars
+            .iter()
+            .map(|(name, value)| GuestArg::Str(format!("{}={}", name, value)))
+            .collect();
+        encode(self.address, &strings)
+    }
+}
@@ -1,18 +1,154 @@
-use std::cell::LazyCell;
+use std::{cell::LazyCell, collections::BTreeMap, sync::LazyLock};
 
 use regex::Regex;
 
-use crate::{BError, util::number_literal_to_u16, vm::VmDescription};
+use crate::{
+    BError,
+    assembler::debug_info::{DebugInfo, SourceLine},
+    util::number_literal_to_u16,
+    vm::VmDescription,
+};
+
+/// Arithmetic on number literals and names, e.g. the values of defines like `BASE + 0x10`
+pub mod expression {
+    use crate::util::number_literal_to_u16;
+
+    /// Evaluate `expr` with 16 bit wrapping arithmetic. It consists of number literals, names, `+`, `-`, `*`, `/`,
+    /// parentheses, `lo(...)` for the low byte and `hi(...)` for the high byte. `lookup` gives the value of a name
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::assembler::expression::evaluate;
+    ///
+    /// let mut lookup = |name: &str| match name {
+    ///     "BASE" => Ok(0x7000),
+    ///     _ => Err(format!("unknown name {}", name)),
+    /// };
+    /// assert_eq!(evaluate("BASE + 2 * (0x10 - 8)", &mut lookup), Ok(0x7010));
+    /// assert_eq!(evaluate("0 - 1", &mut lookup), Ok(0xffff));
+    /// assert_eq!(evaluate("hi(BASE + 0x1ab) * 2 + lo(0x1ab)", &mut lookup), Ok(0xe2 + 0xab));
+    /// assert!(evaluate("BASE +", &mut lookup).is_err());
+    /// ```
+    pub fn evaluate(
+        expr: &str,
+        lookup: &mut dyn FnMut(&str) -> Result<u16, String>,
+    ) -> Result<u16, String> {
+        let tokens = tokenize(expr)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+            lookup,
+        };
+        let value = parser.sum()?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(value),
+            Some(token) => Err(format!("unexpected `{}`", token)),
+        }
+    }
+
+    /// Split into numbers, names and single character operators
+    fn tokenize(expr: &str) -> Result<Vec<&str>, String> {
+        let mut tokens = Vec::new();
+        let mut rest = expr.trim_start();
+        while let Some(first) = rest.chars().next() {
+            let len = if first.is_ascii_alphanumeric() || first == '.' || first == '_' {
+                rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '_'))
+                    .unwrap_or(rest.len())
+            } else if "+-*/()".contains(first) {
+                1
+            } else {
+                return Err(format!("unexpected `{}`", first));
+            };
+            tokens.push(&rest[..len]);
+            rest = rest[len..].trim_start();
+        }
+        Ok(tokens)
+    }
+
+    struct Parser<'a, 'b> {
+        tokens: &'a [&'a str],
+        position: usize,
+        lookup: &'b mut dyn FnMut(&str) -> Result<u16, String>,
+    }
+
+    impl<'a> Parser<'a, '_> {
+        fn next(&mut self) -> Option<&'a str> {
+            let token = self.tokens.get(self.position).copied();
+            self.position += 1;
+            token
+        }
+
+        fn peek(&self) -> Option<&'a str> {
+            self.tokens.get(self.position).copied()
+        }
+
+        fn sum(&mut self) -> Result<u16, String> {
+            let mut value = self.product()?;
+            while let Some(op @ ("+" | "-")) = self.peek() {
+                self.position += 1;
+                let right = self.product()?;
+                value = match op {
+                    "+" => value.wrapping_add(right),
+                    _ => value.wrapping_sub(right),
+                };
+            }
+            Ok(value)
+        }
+
+        fn product(&mut self) -> Result<u16, String> {
+            let mut value = self.atom()?;
+            while let Some(op @ ("*" | "/")) = self.peek() {
+                self.position += 1;
+                let right = self.atom()?;
+                value = match op {
+                    "*" => value.wrapping_mul(right),
+                    _ => value.checked_div(right).ok_or("division by zero")?,
+                };
+            }
+            Ok(value)
+        }
+
+        fn atom(&mut self) -> Result<u16, String> {
+            match self.next() {
+                Some("(") => {
+                    let value = self.sum()?;
+                    match self.next() {
+                        Some(")") => Ok(value),
+                        _ => Err("missing `)`".into()),
+                    }
+                }
+                Some(number) if number.starts_with(|c: char| c.is_ascii_digit()) => {
+                    number_literal_to_u16(number).map_err(|_| format!("invalid number {}", number))
+                }
+                Some(part @ ("lo" | "hi")) if self.peek() == Some("(") => {
+                    let value = self.atom()?;
+                    Ok(match part {
+                        "lo" => value & 0xff,
+                        _ => value >> 8,
+                    })
+                }
+                Some(name)
+                    if name
+                        .starts_with(|c: char| c.is_ascii_alphabetic() || c == '.' || c == '_') =>
+                {
+                    (self.lookup)(name)
+                }
+                Some(token) => Err(format!("unexpected `{}`", token)),
+                None => Err("unexpected end of expression".into()),
+            }
+        }
+    }
+}
 
 /// Processes the `\[macro\]` section of a .basm file
 /// Reads macros and definitions and copies them to the appropriate locations in the \[text\] section.
 pub mod preprocessor {
     use regex::{Captures, Regex};
     use std::cell::LazyCell;
-    use std::collections::{HashMap, HashSet};
+    use std::collections::{BTreeMap, HashMap, HashSet};
 
+    use super::expression::evaluate;
     use crate::BError;
-    use crate::util::number_literal_to_u16;
+    use crate::util::AsmError;
 
     // TODO: test macros with multiple arguments
 
@@ -83,13 +219,97 @@ pub mod preprocessor {
         LazyCell::new(|| Regex::new(r"^(0x[0-9a-fA-F]+|0b[01]+|[0-9]+)?$").unwrap());
 
     /// Do the pre-processing step. This replaces macros and defines in the \[text\] section
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::assembler::preprocessor::preprocess;
+    ///
+    /// let macros = "define BUF_END BUF_START + LEN\ndefine BUF_START 0x7000\ndefine LEN 0x20";
+    /// let (out, _) = preprocess(macros, "A = BUF_END\n").unwrap();
+    /// assert_eq!(out, "A = 28704\n");
+    ///
+    /// assert!(preprocess("define A_ B_\ndefine B_ A_ + 1", "").is_err());
+    /// ```
     pub fn preprocess(macros_text: &str, code: &str) -> Result<(String, usize), BError> {
+        preprocess_with_lines(macros_text, code, false).map(|(out, mline, _, _)| (out, mline))
+    }
+
+    /// A suffix for the labels of the `n`th macro use, like `_a` or `_ba`. Names can't contain digits
+    fn local_suffix(mut n: usize) -> String {
+        let mut suffix = Vec::new();
+        loop {
+            suffix.push(b'a' + (n % 26) as u8);
+            n /= 26;
+            if n == 0 {
+                break;
+            }
+        }
+        suffix.push(b'_');
+        suffix.iter().rev().map(|c| *c as char).collect()
+    }
+
+    /// Evaluate the define `name` and the defines it uses, which can come before or after it. `chain` holds the defines
+    /// being evaluated, to find cycles
+    fn resolve_define(
+        name: &str,
+        exprs: &HashMap<String, (String, usize, &str)>,
+        values: &mut HashMap<String, u16>,
+        chain: &mut Vec<String>,
+    ) -> Result<u16, BError> {
+        if let Some(value) = values.get(name) {
+            return Ok(*value);
+        }
+        let (expr, line_no, line) = &exprs[name];
+        if chain.iter().any(|other| other == name) {
+            chain.push(name.to_string());
+            return Err(BError::AsmParseError(AsmError::at(
+                *line_no,
+                line,
+                format!("The defines depend on each other: {}", chain.join(" -> ")),
+            )));
+        }
+        chain.push(name.to_string());
+        // errors of the defines this one uses are reported at their own line
+        let mut inner = None;
+        let value = evaluate(expr, &mut |other| {
+            if !exprs.contains_key(other) {
+                return Err(format!("unknown define {}", other));
+            }
+            resolve_define(other, exprs, values, chain).map_err(|e| {
+                inner = Some(e);
+                String::new()
+            })
+        });
+        chain.pop();
+        let value = match (value, inner) {
+            (_, Some(e)) => return Err(e),
+            (Ok(value), None) => value,
+            (Err(message), None) => {
+                return Err(BError::AsmParseError(AsmError::at(*line_no, line, message)));
+            }
+        };
+        values.insert(name.to_string(), value);
+        Ok(value)
+    }
+
+    /// The output, the line offset, the line of every output line and the defines, see [`preprocess_with_lines()`]
+    pub type Preprocessed = (String, usize, Vec<usize>, BTreeMap<String, u16>);
+
+    /// Like [`preprocess()`], but also returns the line of `code` each line of the output comes from and the values of
+    /// the defines. The lines a macro expands to come from the line it was used in. `std` includes the
+    /// [`super::STD_PRELUDE`] before `macros_text`
+    pub fn preprocess_with_lines(
+        macros_text: &str,
+        code: &str,
+        std: bool,
+    ) -> Result<Preprocessed, BError> {
         // This is incredibly inefficient because we go through the entire file for each step instead of going through only once or twice.
         // A more efficient lexer might be logos.
         // This only runs on the `\[macros\]` section so it shouldn't be too bad...
 
         let mut macros: HashMap<String, Macro> = HashMap::new();
-        let mut defines = HashMap::<String, u16>::new();
+        // the expression, line number and line of every define, evaluated once all are known
+        let mut define_exprs = HashMap::<String, (String, usize, &str)>::new();
+        let mut define_order = Vec::new();
 
         let mut in_macro = false; // are we currently in a macro definition? if so the below variables are read
         let mut current_macro_name = None;
@@ -97,7 +317,27 @@ pub mod preprocessor {
         let mut current_macro_text = None;
         // allows us to efficiently count the number of lines, because this should get optimized away... (hopefully)
         let mut mline = 0;
+        // `include <std>` is replaced by the prelude, whose lines count as the line of the include
+        let mut lines = Vec::new();
+        if std {
+            lines.extend(super::STD_PRELUDE.lines().map(|line| (0, line)));
+        }
         for (line_no, line) in macros_text.lines().enumerate() {
+            match line.split_whitespace().collect::<Vec<&str>>()[..] {
+                ["include", "<std>"] => {
+                    lines.extend(super::STD_PRELUDE.lines().map(|line| (line_no, line)))
+                }
+                ["include", ..] => {
+                    return Err(BError::AsmParseError(AsmError::at(
+                        line_no,
+                        line,
+                        "unknown include, only `include <std>` exists",
+                    )));
+                }
+                _ => lines.push((line_no, line)),
+            }
+        }
+        for (line_no, line) in lines {
             mline = line_no;
             if in_macro {
                 // If in_macro is true current_ variables should be Some
@@ -125,24 +365,26 @@ pub mod preprocessor {
             let mut tokens = line.split_whitespace();
             if let Some(mode) = tokens.next() {
                 match mode {
-                    // define is built like: `define name value`, where value is a number literal
+                    // define is built like: `define name value`, where value is an expression over number literals
+                    // and other defines
                     "define" => {
-                        let define_name = tokens.next().ok_or(BError::AsmParseError(format!(
-                            "error on line {line_no}: {line}\nNo name for define"
-                        )))?;
-                        let define_value = tokens.next().ok_or(BError::AsmParseError(format!(
-                            "error on line {line_no}: {line}\nNo value for define"
-                        )))?;
-
-                        if !RE_NAME.is_match(define_name) {
-                            return Err(BError::AsmParseError(format!(
-                                "error on line {line_no}: {line}\nInvalid define name"
+                        let define_name = tokens.next().ok_or(BError::AsmParseError(
+                            AsmError::at(line_no, line, "No name for define"),
+                        ))?;
+                        let define_value = tokens.collect::<Vec<&str>>().join(" ");
+                        if define_value.is_empty() {
+                            return Err(BError::AsmParseError(AsmError::at(
+                                line_no,
+                                line,
+                                "No value for define",
                             )));
                         }
 
-                        if !RE_NUMBER_LIT.is_match(define_value) {
-                            return Err(BError::AsmParseError(format!(
-                                "error on line {line_no}: {line}\nInvalid number literal"
+                        if !RE_NAME.is_match(define_name) {
+                            return Err(BError::AsmParseError(AsmError::at(
+                                line_no,
+                                line,
+                                "Invalid define name",
                             )));
                         }
 
@@ -150,53 +392,51 @@ pub mod preprocessor {
                         if REGISTERS.contains(define_name)
                             | KEYWORDS.contains(define_name)
                             | INSTRUCTIONS.contains(define_name.to_lowercase().as_str())
-                            | defines.contains_key(define_name)
+                            | define_exprs.contains_key(define_name)
                         {
-                            return Err(BError::AsmParseError(format!(
-                                "error on line {line_no}: {line}\nThe name {define_name} is already in use"
+                            return Err(BError::AsmParseError(AsmError::at(
+                                line_no,
+                                line,
+                                format!("The name {define_name} is already in use"),
                             )));
                         }
 
-                        let number = number_literal_to_u16(define_value).map_err( |_| BError::AsmParseError(
-                                format!("error on line {line_no}: {line}\nCan't parse {define_value} to a number."),
-                            ))?;
-
-                        defines.insert(define_name.to_string(), number);
-
-                        // error if more tokens in line
-                        if let Some(_) = tokens.next() {
-                            return Err(BError::AsmParseError(format!(
-                                "error on line {line_no}: {line}\nThe line contains unnecessary text"
-                            )));
-                        }
+                        define_order.push(define_name.to_string());
+                        define_exprs.insert(define_name.to_string(), (define_value, line_no, line));
                     }
                     // begin syntax for beginning a macro `begin name(arg1, ...)`, if only one arg the comma can be left out
                     "begin" => {
-                        let macro_name = tokens.next().ok_or(BError::AsmParseError(format!(
-                            "error on line {line_no}: {line}\nNo name for macro"
-                        )))?;
+                        let macro_name = tokens.next().ok_or(BError::AsmParseError(
+                            AsmError::at(line_no, line, "No name for macro"),
+                        ))?;
 
                         // check whether the name is already taken
                         if REGISTERS.contains(macro_name)
                             | KEYWORDS.contains(macro_name)
                             | INSTRUCTIONS.contains(macro_name.to_lowercase().as_str())
-                            | defines.contains_key(macro_name)
+                            | define_exprs.contains_key(macro_name)
                             | macros.contains_key(macro_name)
                         {
-                            return Err(BError::AsmParseError(format!(
-                                "error on line {line_no}: {line}\nThe name {macro_name} is already in use"
+                            return Err(BError::AsmParseError(AsmError::at(
+                                line_no,
+                                line,
+                                format!("The name {macro_name} is already in use"),
                             )));
                         }
 
                         let args = tokens.collect::<Vec<&str>>().join("");
                         if !(args.starts_with("(") & args.ends_with(")")) {
-                            return Err(BError::AsmParseError(format!(
-                                "error on line {line_no}: {line}\nInvalid arguments or unnecessary text"
+                            return Err(BError::AsmParseError(AsmError::at(
+                                line_no,
+                                line,
+                                "Invalid arguments or unnecessary text",
                             )));
                         }
                         let mut arg_names = Vec::new();
                         for arg in args[1..args.len() - 1].split(",") {
-                            arg_names.push(arg.to_string())
+                            if !arg.is_empty() {
+                                arg_names.push(arg.to_string())
+                            }
                         }
                         current_macro_name = Some(macro_name.to_string());
                         current_macro_args = Some(arg_names);
@@ -208,20 +448,29 @@ pub mod preprocessor {
                         continue;
                     }
                     _ => {
-                        return Err(BError::AsmParseError(format!(
-                            "error on line {line_no}: {line}\nInvalid Text in `[macros]`"
+                        return Err(BError::AsmParseError(AsmError::at(
+                            line_no,
+                            line,
+                            "Invalid Text in `[macros]`",
                         )));
                     }
                 }
             }
         }
 
+        let mut defines = HashMap::new();
+        for name in &define_order {
+            resolve_define(name, &define_exprs, &mut defines, &mut Vec::new())?;
+        }
+
         let mut out = code.to_string();
+        let mut origins: Vec<usize> = (0..out.split('\n').count()).collect();
 
         // replace macros
+        let mut expansions = 0;
         for (name, mac) in macros {
             let clean = name.replace(".", "\\.").replace("_", "\\_");
-            let pattern = format!("(?m)^\\s*{clean}\\s*\\((.+)\\)\\s*$");
+            let pattern = format!("(?m)^\\s*{clean}\\s*\\((.*)\\)\\s*$");
             // should be fine. sanitization above
             let rgx = Regex::new(&pattern).unwrap();
             loop {
@@ -246,14 +495,28 @@ pub mod preprocessor {
                 } else {
                     break;
                 }
-                let repl_text = mac.replace_args(args.unwrap());
-                out.replace_range(range.unwrap(), &repl_text);
+                // `%` makes the labels of the macro unique to this use
+                let repl_text = mac
+                    .replace_args(args.unwrap())
+                    .replace('%', &local_suffix(expansions));
+                expansions += 1;
+                let range = range.unwrap();
+                // the match may start with the line breaks of empty lines before the use
+                let matched = &out[range.clone()];
+                let first = out[..range.start].matches('\n').count();
+                let leading = &matched[..matched.len() - matched.trim_start().len()];
+                let used_in = first + leading.matches('\n').count();
+                let removed = matched.matches('\n').count();
+                let inserted = repl_text.matches('\n').count();
+                let origin = origins[used_in];
+                origins.splice(first..=first + removed, vec![origin; inserted + 1]);
+                out.replace_range(range, &repl_text);
             }
         }
 
         // Replaces the defines
         // Super dirty
-        for (name, value) in defines {
+        for (name, value) in &defines {
             let clean = name.replace(".", "\\.").replace("_", "\\_");
             let pattern = format!("=\\s({clean})(?<right>\\s*[\\s|;])");
             // should be fine. sanitization
@@ -265,7 +528,7 @@ pub mod preprocessor {
                 .to_string()
         }
 
-        Ok((out, mline))
+        Ok((out, mline, origins, defines.into_iter().collect()))
     }
 }
 
@@ -273,11 +536,19 @@ pub mod preprocessor {
 pub mod text_processor {
     use crate::{
         BError,
-        assembler::preprocessor::{RE_NAME, RE_NUMBER_LIT},
-        util::number_literal_to_u16,
+        assembler::{
+            Directives, Layout,
+            expression::evaluate,
+            preprocessor::{RE_NAME, RE_NUMBER_LIT},
+            run_directive,
+        },
+        util::{AsmError, number_literal_to_u16},
         vm::{AluInstruction, Instruction, Register},
     };
-    use std::collections::HashMap;
+    use std::{
+        collections::{BTreeMap, HashMap},
+        str::FromStr,
+    };
 
     /// Jump conditions
     #[derive(Debug)]
@@ -323,8 +594,8 @@ pub mod text_processor {
                 }
                 Self::Jge => {
                     alu_inst.set_eq(true);
-                    alu_inst.set_gt(false);
-                    alu_inst.set_lt(true);
+                    alu_inst.set_gt(true);
+                    alu_inst.set_lt(false);
                 }
                 Self::Jmp => {
                     alu_inst.set_eq(true);
@@ -412,8 +683,8 @@ pub mod text_processor {
                 "lsl" => Some(Self::Lsl),
                 "lsr" => Some(Self::Lsr),
                 "asr" => Some(Self::Asr),
-                "rol" => Some(Self::Ror),
-                "ror" => Some(Self::Rol),
+                "rol" => Some(Self::Rol),
+                "ror" => Some(Self::Ror),
                 _ => None,
             }
         }
@@ -443,7 +714,114 @@ pub mod text_processor {
         pub rom: Vec<u16>,
         pub label_definitions: HashMap<String, usize>,
         pub label_uses: HashMap<String, Vec<usize>>,
+        /// Data instructions with an expression, calculated once the labels are known
+        pub expressions: Vec<Deferred>,
         pub rom_lines: usize,
+        /// The line of the code each word of `rom` was assembled from. The padding word at the end has no line
+        pub lines: Vec<usize>,
+    }
+
+    /// A value given as an expression, e.g. `lo(table)`, that is calculated once all labels are known. See
+    /// [`super::expression::evaluate()`]
+    pub struct Deferred {
+        /// Index of the word in ROM
+        pub position: usize,
+        pub expr: String,
+        /// Line number and text of the line, for errors
+        pub line: usize,
+        pub text: String,
+    }
+
+    impl Deferred {
+        /// Calculate the value. Names are defines or labels, whose value `label` gives
+        pub fn evaluate(
+            &self,
+            defines: &BTreeMap<String, u16>,
+            label: impl Fn(&str) -> Option<usize>,
+        ) -> Result<u16, BError> {
+            evaluate(&self.expr, &mut |name| {
+                defines
+                    .get(name)
+                    .copied()
+                    .or_else(|| label(name).map(|value| value as u16))
+                    .ok_or_else(|| format!("unknown name {}", name))
+            })
+            .map_err(|message| BError::AsmParseError(AsmError::at(self.line, &self.text, message)))
+        }
+
+        /// Calculate the value of a data instruction, which has to fit into 15 bits
+        pub fn evaluate_data(
+            &self,
+            defines: &BTreeMap<String, u16>,
+            label: impl Fn(&str) -> Option<usize>,
+        ) -> Result<u16, BError> {
+            let value = self.evaluate(defines, label)?;
+            if value > 0x7fff {
+                return Err(BError::AsmParseError(AsmError::at(
+                    self.line,
+                    &self.text,
+                    format!("{} is to large", value),
+                )));
+            }
+            Ok(value)
+        }
+    }
+
+    /// Assemble lines of the \[text\] section that are placed at ROM address `address`, e.g. to patch a running
+    /// program. Labels have to be defined in `code`, macros and defines are not available
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::assembler::text_processor::assemble_at;
+    ///
+    /// let words = assemble_at("label here:\nA = here\nD = add, 0, A", 0x10).unwrap();
+    /// assert_eq!(words, vec![0x8010, 0x18d8]);
+    /// ```
+    pub fn assemble_at(code: &str, address: u16) -> Result<Vec<u16>, BError> {
+        let mut out = assemble(code.to_string(), 0)?;
+        // the padding word
+        out.rom.pop();
+        for (name, positions) in out.label_uses {
+            let value = out
+                .label_definitions
+                .get(&name)
+                .map(|position| address as usize + position)
+                .ok_or_else(|| BError::AsmParseError(format!("unknown label {}", name).into()))?;
+            if value > 0x7fff {
+                return Err(BError::AsmParseError(
+                    format!("label {} is to large: {}", name, value).into(),
+                ));
+            }
+            for position in positions {
+                out.rom[position] |= value as u16;
+            }
+        }
+        for deferred in &out.expressions {
+            out.rom[deferred.position] |= deferred.evaluate_data(&BTreeMap::new(), |name| {
+                out.label_definitions
+                    .get(name)
+                    .map(|position| address as usize + position)
+            })?;
+        }
+        Ok(out.rom)
+    }
+
+    impl FromStr for Instruction {
+        type Err = BError;
+
+        /// Assemble a single line of the \[text\] section. Labels can't be used
+        fn from_str(line: &str) -> Result<Self, BError> {
+            if line.lines().count() > 1 {
+                return Err(BError::AsmParseError(
+                    format!("expected a single line: {}", line).into(),
+                ));
+            }
+            match assemble_at(line, 0)?[..] {
+                [word] => Ok(Instruction::from_u16(word)),
+                _ => Err(BError::AsmParseError(
+                    format!("expected an instruction: {}", line).into(),
+                )),
+            }
+        }
     }
 
     /// Parse both operands of a two operand mnemonic into source, switch and zero fields
@@ -484,9 +862,20 @@ pub mod text_processor {
     /// not very efficient but okay
     /// TODO: we don't check the label names in this function against the list of keywords and registers
     pub fn assemble(code: String, code_offset: usize) -> Result<AssemblerOutput, BError> {
+        assemble_with_directives(code, code_offset, &Directives::new())
+    }
+
+    /// Like [`assemble()`], with custom directives. See [`super::AsmOptions::register_directive()`]
+    pub fn assemble_with_directives(
+        code: String,
+        code_offset: usize,
+        directives: &Directives,
+    ) -> Result<AssemblerOutput, BError> {
         let mut label_definitions = HashMap::new(); // where the labels are defined
         let mut label_uses: HashMap<String, Vec<usize>> = HashMap::new(); // where the labels are used (if we know yet)
+        let mut expressions = Vec::new();
         let mut mem = Vec::new(); // output memory
+        let mut lines = Vec::new(); // line of each word in mem
         // allows us to efficiently count the lines, as this should get optimized away... (hopefully)
         let mut cline = 0;
         for (code_idx, line) in code.lines().enumerate() {
@@ -500,12 +889,25 @@ pub mod text_processor {
             if matches!(trline, "") {
                 continue;
             }
+            // custom directive
+            if trline.starts_with('.') {
+                let layout = Layout {
+                    rom_address: mem.len() as u16,
+                    ram_address: None,
+                };
+                let words = run_directive(directives, trline, layout).map_err(|message| {
+                    BError::AsmParseError(AsmError::at(code_idx + code_offset, line, message))
+                })?;
+                lines.extend(words.iter().map(|_| code_idx));
+                mem.extend(words);
+                continue;
+            }
             // label for jumps
             if trline.starts_with("label") {
-                let terr = Err(BError::AsmParseError(format!(
-                    "error on line {}: {}\nincorrect label",
+                let terr = Err(BError::AsmParseError(AsmError::at(
                     code_idx + code_offset,
-                    line
+                    line,
+                    "incorrect label",
                 )));
 
                 if !trline.ends_with(":") {
@@ -518,10 +920,10 @@ pub mod text_processor {
                     return terr;
                 }
                 if label_definitions.contains_key(label) {
-                    return Err(BError::AsmParseError(format!(
-                        "error on line {}: {}\nlabel already in use",
+                    return Err(BError::AsmParseError(AsmError::at(
                         code_idx + code_offset,
-                        line
+                        line,
+                        "label already in use",
                     )));
                 }
                 // this is okay as we always add an extra instruction to the end
@@ -531,6 +933,7 @@ pub mod text_processor {
             if trline == "JMP" {
                 // Always Jump
                 mem.push(Instruction::Alu(AluInstruction(0b0000000000000111)).to_u16());
+                lines.push(code_idx);
                 continue;
             }
 
@@ -543,10 +946,10 @@ pub mod text_processor {
                 match c {
                     '=' => {
                         if seen_eq | seen_sc {
-                            return Err(BError::AsmParseError(format!(
-                                "error on line {}: {}\nsections wrong",
+                            return Err(BError::AsmParseError(AsmError::at(
                                 code_idx + code_offset,
-                                line
+                                line,
+                                "sections wrong",
                             )));
                         }
                         parts.push(&trline[current_start..idx]);
@@ -555,10 +958,10 @@ pub mod text_processor {
                     }
                     ';' => {
                         if seen_sc {
-                            return Err(BError::AsmParseError(format!(
-                                "error on line {}: {}\nsections wrong",
+                            return Err(BError::AsmParseError(AsmError::at(
                                 code_idx + code_offset,
-                                line
+                                line,
+                                "sections wrong",
                             )));
                         }
                         parts.push(&trline[current_start..idx]);
@@ -574,33 +977,32 @@ pub mod text_processor {
             let mut parts_slice = &parts[..];
 
             // parse target
-            let target = if seen_eq {
-                let tgt_str = parts_slice[0];
-                parts_slice = &parts_slice[1..];
-                Register::from_str(tgt_str.trim()).ok_or(BError::AsmParseError(format!(
-                    "error on line {}: {}\nimproper target",
-                    code_idx + code_offset,
-                    line
-                )))?
-            } else {
-                Register::None
-            };
+            let target =
+                if seen_eq {
+                    let tgt_str = parts_slice[0];
+                    parts_slice = &parts_slice[1..];
+                    Register::from_str(tgt_str.trim()).ok_or(BError::AsmParseError(
+                        AsmError::at(code_idx + code_offset, line, "improper target"),
+                    ))?
+                } else {
+                    Register::None
+                };
 
             // parse jump condition
             let jump = if seen_sc {
                 let jmp_str = parts_slice
                     .get(1)
-                    .ok_or(BError::AsmParseError(format!(
-                        "error on line {}: {}\nconditional jump without computation",
+                    .ok_or(BError::AsmParseError(AsmError::at(
                         code_idx + code_offset,
-                        line
+                        line,
+                        "conditional jump without computation",
                     )))?
                     .trim();
                 parts_slice = &parts_slice[..parts_slice.len() - 1];
-                Jumps::parse_str(&jmp_str).ok_or(BError::AsmParseError(format!(
-                    "error on line {}: {}\nimproper jump",
+                Jumps::parse_str(jmp_str).ok_or(BError::AsmParseError(AsmError::at(
                     code_idx + code_offset,
-                    line
+                    line,
+                    "improper jump",
                 )))?
             } else {
                 Jumps::None
@@ -609,30 +1011,30 @@ pub mod text_processor {
             // parse operation
             let operation = {
                 if parts_slice.len() != 1 {
-                    return Err(BError::AsmParseError(format!(
-                        "error on line {}: {}\nno operation",
+                    return Err(BError::AsmParseError(AsmError::at(
                         code_idx + code_offset,
-                        line
+                        line,
+                        "no operation",
                     )));
                 }
                 let mut operands = parts_slice[0].split(",");
                 let cmd_or_lit = operands
                     .next()
-                    .ok_or(BError::AsmParseError(format!(
-                        "error on line {}: {}\nno operation or number",
+                    .ok_or(BError::AsmParseError(AsmError::at(
                         code_idx + code_offset,
-                        line
+                        line,
+                        "no operation or number",
                     )))?
                     .trim();
 
                 if let Some(cmd) = Cmds::from_str(cmd_or_lit) {
                     let inputs: Vec<&str> = operands.map(|v| v.trim()).collect();
                     // check whether we have enough operands
-                    if !inputs.len() == cmd.arg_num() {
-                        return Err(BError::AsmParseError(format!(
-                            "error on line {}: {}\nnot enough arguments for operation",
+                    if inputs.len() < cmd.arg_num() {
+                        return Err(BError::AsmParseError(AsmError::at(
                             code_idx + code_offset,
-                            line
+                            line,
+                            "not enough arguments for operation",
                         )));
                     };
                     // create our ALU instruction
@@ -640,18 +1042,18 @@ pub mod text_processor {
                     match cmd {
                         h @ Cmds::Add | h @ Cmds::Sub => {
                             let (x, sw, zx) = parse_two(inputs[0], inputs[1]).map_err(|_| {
-                                BError::AsmParseError(format!(
-                                    "error on line {}: {}\none or both operands invalid",
+                                BError::AsmParseError(AsmError::at(
                                     code_idx + code_offset,
-                                    line
+                                    line,
+                                    "one or both operands invalid",
                                 ))
                             })?;
 
                             if matches!(x, XOps::Zero) {
-                                Err(BError::AsmParseError(format!(
-                                    "error on line {}: {}\nright operand may not be zero here",
+                                Err(BError::AsmParseError(AsmError::at(
                                     code_idx + code_offset,
-                                    line
+                                    line,
+                                    "right operand may not be zero here",
                                 )))?;
                             }
                             inst.set_sw(sw);
@@ -670,13 +1072,13 @@ pub mod text_processor {
                             Instruction::Alu(inst)
                         }
                         h @ Cmds::Asr | h @ Cmds::Inc | h @ Cmds::Dec => {
-                            let x =
-                                XOps::from_str(inputs[0]).ok_or(BError::AsmParseError(format!(
-                                    "error on line {}: {}\ninvalid operand {}",
+                            let x = XOps::from_str(inputs[0]).ok_or(BError::AsmParseError(
+                                AsmError::at(
                                     code_idx + code_offset,
                                     line,
-                                    inputs[0]
-                                )))?;
+                                    format!("invalid operand {}", inputs[0]),
+                                ),
+                            ))?;
                             set_source(x, &mut inst);
                             jump.set_alu_inst(&mut inst);
                             inst.set_op(match h {
@@ -692,18 +1094,18 @@ pub mod text_processor {
                         }
                         h @ Cmds::And | h @ Cmds::Or | h @ Cmds::Xor => {
                             let (x, sw, zx) = parse_two(inputs[0], inputs[1]).map_err(|_| {
-                                BError::AsmParseError(format!(
-                                    "error on line {}: {}\none or both operands invalid",
+                                BError::AsmParseError(AsmError::at(
                                     code_idx + code_offset,
-                                    line
+                                    line,
+                                    "one or both operands invalid",
                                 ))
                             })?;
 
                             if matches!(x, XOps::Zero) {
-                                Err(BError::AsmParseError(format!(
-                                    "error on line {}: {}\nright operand may not be zero here",
+                                Err(BError::AsmParseError(AsmError::at(
                                     code_idx + code_offset,
-                                    line
+                                    line,
+                                    "right operand may not be zero here",
                                 )))?;
                             }
                             inst.set_sw(sw);
@@ -725,13 +1127,13 @@ pub mod text_processor {
                         | h @ Cmds::Lsr
                         | h @ Cmds::Rol
                         | h @ Cmds::Ror => {
-                            let x =
-                                XOps::from_str(inputs[0]).ok_or(BError::AsmParseError(format!(
-                                    "error on line {}: {}\ninvalid operand {}",
+                            let x = XOps::from_str(inputs[0]).ok_or(BError::AsmParseError(
+                                AsmError::at(
                                     code_idx + code_offset,
                                     line,
-                                    inputs[0]
-                                )))?;
+                                    format!("invalid operand {}", inputs[0]),
+                                ),
+                            ))?;
                             set_source(x, &mut inst);
                             jump.set_alu_inst(&mut inst);
                             inst.set_op(match h {
@@ -750,33 +1152,39 @@ pub mod text_processor {
                 } else if RE_NUMBER_LIT.is_match(cmd_or_lit) {
                     // we have a number here -> literal to put in A
                     let value = number_literal_to_u16(cmd_or_lit).map_err(|_| {
-                        BError::AsmParseError(format!(
-                            "error on line {}: {}\nunable to parse {} as a number",
+                        BError::AsmParseError(AsmError::at(
                             code_idx + code_offset,
                             line,
-                            cmd_or_lit
+                            format!("unable to parse {} as a number", cmd_or_lit),
                         ))
                     })?;
                     if value > 0x7fff {
-                        Err(BError::AsmParseError(format!(
-                            "error on line {}: {}\n{} is to large",
+                        Err(BError::AsmParseError(AsmError::at(
                             code_idx + code_offset,
                             line,
-                            value
+                            format!("{} is to large", value),
                         )))?;
                     }
                     Instruction::Data(value)
+                } else if cmd_or_lit.contains(['+', '-', '*', '/', '(']) {
+                    // an expression, calculated in the second pass
+                    expressions.push(Deferred {
+                        position: mem.len(),
+                        expr: cmd_or_lit.to_string(),
+                        line: code_idx + code_offset,
+                        text: line.to_string(),
+                    });
+                    Instruction::Data(0)
                 } else {
                     // is the element a label?
                     if let Some(uselist) = label_uses.get_mut(cmd_or_lit) {
                         uselist.push(mem.len());
                     } else {
                         if !RE_NAME.is_match(cmd_or_lit) {
-                            Err(BError::AsmParseError(format!(
-                                "error on line {}: {}\n cant parse {}.",
+                            Err(BError::AsmParseError(AsmError::at(
                                 code_idx + code_offset,
                                 line,
-                                cmd_or_lit
+                                format!(" cant parse {}.", cmd_or_lit),
                             )))?;
                         }
                         label_uses.insert(cmd_or_lit.to_string(), vec![mem.len()]);
@@ -786,23 +1194,25 @@ pub mod text_processor {
             };
             let v = operation.to_u16();
             mem.push(v);
+            lines.push(code_idx);
         }
         // make sure there is always a last instruction incase there is a label at the very end
         mem.push(Instruction::Data(0).to_u16());
 
         // there will be problems after a length of 0x7fff
         if mem.len() > 0xffff {
-            return Err(BError::AsmParseError(format!(
-                "your program is to large: {} words",
-                mem.len()
-            )));
+            return Err(BError::AsmParseError(
+                format!("your program is to large: {} words", mem.len()).into(),
+            ));
         }
 
         Ok(AssemblerOutput {
             rom: mem,
             label_definitions,
             label_uses,
+            expressions,
             rom_lines: cline,
+            lines,
         })
     }
 }
@@ -812,12 +1222,58 @@ pub mod const_processor {
     use crate::{
         BError,
         assembler::{
+            Directives, Layout,
             preprocessor::{RE_NAME, RE_NUMBER_LIT},
-            text_processor::AssemblerOutput,
+            run_directive,
+            text_processor::{AssemblerOutput, Deferred},
         },
-        util::number_literal_to_u16,
-        vm::VmDescription,
+        util::{AsmError, number_literal_to_u16},
+        vm::{RAM_LEN, VmDescription},
     };
+    use std::collections::BTreeMap;
+
+    /// How `.checksum` sums up ROM
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ChecksumKind {
+        /// Wrapping sum of the words, simple to check in a program
+        Sum,
+        /// CRC-16/CCITT-FALSE over the words, high byte first
+        Crc16,
+    }
+
+    /// The checksum of `words`
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::assembler::const_processor::{ChecksumKind, checksum};
+    ///
+    /// assert_eq!(checksum(&[0xffff, 2], ChecksumKind::Sum), 1);
+    /// assert_eq!(checksum(&[0x3132, 0x3334, 0x3536, 0x3738], ChecksumKind::Crc16), 0xa12b);
+    /// ```
+    pub fn checksum(words: &[u16], kind: ChecksumKind) -> u16 {
+        match kind {
+            ChecksumKind::Sum => words.iter().fold(0, |sum, word| sum.wrapping_add(*word)),
+            ChecksumKind::Crc16 => {
+                let mut crc = 0xffffu16;
+                for byte in words.iter().flat_map(|word| word.to_be_bytes()) {
+                    crc ^= (byte as u16) << 8;
+                    for _ in 0..8 {
+                        crc = (crc << 1) ^ (0x1021 & (crc >> 15).wrapping_neg());
+                    }
+                }
+                crc
+            }
+        }
+    }
+
+    /// A `.checksum` word, filled in once the rest of ROM is known
+    struct Checksum {
+        position: usize,
+        /// Start and end of the range, the \[text\] section if not given
+        range: Option<(Deferred, Deferred)>,
+        kind: ChecksumKind,
+        line: usize,
+        text: String,
+    }
 
     /// build the const section in ROM,
     /// replace labels to consts and then build a VmDescription which maps consts to the `mount_position`
@@ -829,6 +1285,28 @@ pub mod const_processor {
         const_offset: usize,
         mount_position: u16,
     ) -> Result<VmDescription, BError> {
+        place(
+            asm,
+            constants,
+            const_offset,
+            mount_position,
+            &BTreeMap::new(),
+            &Directives::new(),
+        )
+        .map(|(vm_desc, _)| vm_desc)
+    }
+
+    /// Like [`find_and_place()`], but also returns the labels of the \[text\] section with their ROM address and the
+    /// labels of the \[const\] section with their RAM address. Expressions can use `defines`
+    pub(crate) fn place(
+        asm: AssemblerOutput,
+        constants: &str,
+        const_offset: usize,
+        mount_position: u16,
+        defines: &BTreeMap<String, u16>,
+        directives: &Directives,
+    ) -> Result<(VmDescription, Labels), BError> {
+        let text_labels = asm.label_definitions.clone();
         let mut label_definitions = asm.label_definitions;
         let mut mem = asm.rom;
         // Compute the amount of bytes we need to align the memory to the next 16 byte boundary
@@ -837,14 +1315,20 @@ pub mod const_processor {
 
         let consts_start = mem.len();
         let mut consts_amount = 0;
+        // ROM and RAM address of the consts since the last `.res`, which ends a ROM mapping
+        let mut mappings = Vec::new();
+        let mut mapped_from = (consts_start, mount_position as usize);
+        let mut expressions = Vec::new();
+        let mut checksums = Vec::new();
+        let code_len = asm.lines.len();
         for (line_idx, line) in constants.lines().enumerate() {
             match line.trim() {
                 // label
                 s if s.starts_with("label") => {
-                    let terr = Err(BError::AsmParseError(format!(
-                        "error on line {}: {}\nincorrect label",
+                    let terr = Err(BError::AsmParseError(AsmError::at(
                         line_idx + const_offset,
-                        line
+                        line,
+                        "incorrect label",
                     )));
 
                     if !s.ends_with(":") {
@@ -856,16 +1340,80 @@ pub mod const_processor {
                         return terr;
                     }
                     if label_definitions.contains_key(label) {
-                        return Err(BError::AsmParseError(format!(
-                            "error on line {}: {}\nlabel already in use",
+                        return Err(BError::AsmParseError(AsmError::at(
                             line_idx + const_offset,
-                            line
+                            line,
+                            "label already in use",
                         )));
                     }
                     // this is okay as we always add an extra instruction to the end
                     label_definitions
                         .insert(label.to_string(), mount_position as usize + consts_amount);
                 }
+                // `.checksum [START END] [sum|crc16]`, a word holding the checksum of ROM from START to END
+                s if s.starts_with(".checksum") => {
+                    let mut args: Vec<&str> = s.split_whitespace().skip(1).collect();
+                    let kind = match args.last() {
+                        Some(&"crc16") => Some(ChecksumKind::Crc16),
+                        Some(&"sum") => Some(ChecksumKind::Sum),
+                        _ => None,
+                    };
+                    if kind.is_some() {
+                        args.pop();
+                    }
+                    let deferred = |expr: &str| Deferred {
+                        position: mem.len(),
+                        expr: expr.to_string(),
+                        line: line_idx + const_offset,
+                        text: line.to_string(),
+                    };
+                    let range = match args[..] {
+                        [] => None,
+                        [start, end] => Some((deferred(start), deferred(end))),
+                        _ => {
+                            return Err(BError::AsmParseError(AsmError::at(
+                                line_idx + const_offset,
+                                line,
+                                "expected `.checksum [START END] [sum|crc16]`",
+                            )));
+                        }
+                    };
+                    checksums.push(Checksum {
+                        position: mem.len(),
+                        range,
+                        kind: kind.unwrap_or(ChecksumKind::Sum),
+                        line: line_idx + const_offset,
+                        text: line.to_string(),
+                    });
+                    mem.push(0);
+                    consts_amount += 1;
+                }
+                // `.res N`, N words of RAM that start out as zero. They are left out of ROM and of the mappings,
+                // which also keeps them writable
+                s if s.starts_with(".res") => {
+                    let amount = Deferred {
+                        position: mem.len(),
+                        expr: s[4..].trim().to_string(),
+                        line: line_idx + const_offset,
+                        text: line.to_string(),
+                    }
+                    .evaluate(defines, |_| None)?;
+                    mappings.push((mapped_from.0, mem.len() - mapped_from.0, mapped_from.1));
+                    consts_amount += amount as usize;
+                    mapped_from = (mem.len(), mount_position as usize + consts_amount);
+                }
+                // custom directive
+                s if s.starts_with('.') => {
+                    let layout = Layout {
+                        rom_address: mem.len() as u16,
+                        ram_address: Some((mount_position as usize + consts_amount) as u16),
+                    };
+                    let words = run_directive(directives, s, layout).map_err(|message| {
+                        BError::AsmParseError(AsmError::at(line_idx + const_offset, line, message))
+                    })?;
+                    consts_amount += words.len();
+                    mem.extend(words);
+                }
                 // const memory
                 s if s.starts_with("M") => {
                     let mut parts = s.split("=");
@@ -874,19 +1422,22 @@ pub mod const_processor {
                     if let Some(number) = parts.next() {
                         let tnum = number.trim();
                         if !RE_NUMBER_LIT.is_match(tnum) {
-                            return Err(BError::AsmParseError(format!(
-                                "error on line {}: {}\ninvalid number {}",
-                                line_idx + const_offset,
-                                line,
-                                tnum
-                            )));
+                            // an expression, calculated once all labels are known
+                            expressions.push(Deferred {
+                                position: mem.len(),
+                                expr: tnum.to_string(),
+                                line: line_idx + const_offset,
+                                text: line.to_string(),
+                            });
+                            mem.push(0);
+                            consts_amount += 1;
+                            continue;
                         }
                         let value = number_literal_to_u16(tnum).map_err(|_| {
-                            BError::AsmParseError(format!(
-                                "error on line {}: {}\ninvalid number {}",
+                            BError::AsmParseError(AsmError::at(
                                 line_idx + const_offset,
                                 line,
-                                tnum
+                                format!("invalid number {}", tnum),
                             ))
                         })?;
                         mem.push(value);
@@ -897,10 +1448,10 @@ pub mod const_processor {
                 s if s.starts_with("#") => {}
                 "" => {}
                 _ => {
-                    return Err(BError::AsmParseError(format!(
-                        "error on line {}: {}\nonly comments, labels and memory allowed",
+                    return Err(BError::AsmParseError(AsmError::at(
                         line_idx + const_offset,
-                        line
+                        line,
+                        "only comments, labels, memory and directives allowed",
                     )));
                 }
             }
@@ -909,38 +1460,316 @@ pub mod const_processor {
         let memlen = mem.len();
         // as said before we already get problems if memlen > 0x7fff
         if memlen > 0xffff {
-            return Err(BError::AsmParseError(format!(
-                "your program is to large: {} words",
-                memlen
-            )));
+            return Err(BError::AsmParseError(
+                format!("your program is to large: {} words", memlen).into(),
+            ));
+        }
+        if mount_position as usize + consts_amount > RAM_LEN {
+            return Err(BError::AsmParseError(
+                format!(
+                    "the consts don't fit into RAM: {} words at {:#06x}",
+                    consts_amount, mount_position
+                )
+                .into(),
+            ));
+        }
+        mappings.push((mapped_from.0, memlen - mapped_from.0, mapped_from.1));
+        // the first mapping stays even if it is empty, it marks the start of the consts
+        let first = mappings[0];
+        mappings.retain(|(_, length, _)| *length > 0);
+        if mappings.is_empty() {
+            mappings.push(first);
         }
 
         // second pass
         for (name, positions) in asm.label_uses {
-            // we make sure that label_definitions contains our name during construction
-            let value = label_definitions[&name];
+            let value = *label_definitions
+                .get(&name)
+                .ok_or_else(|| BError::AsmParseError(format!("unknown label {}", name).into()))?;
             for pos in positions.iter() {
                 if value > 0x7fff {
                     Err(BError::AsmParseError(format!(
                         "error when inserting labels: {} is to large, you may have to long of a program",
                         value
-                    )))?;
+                    ).into()))?;
                 }
                 // we make sure the memory has appropriate length before
                 let mpos = mem.get_mut(*pos).unwrap();
                 *mpos |= value as u16;
             }
         }
+        let label = |name: &str| label_definitions.get(name).copied();
+        for deferred in &asm.expressions {
+            mem[deferred.position] |= deferred.evaluate_data(defines, label)?;
+        }
+        for deferred in &expressions {
+            mem[deferred.position] = deferred.evaluate(defines, label)?;
+        }
+        // in order, so a checksum can cover the ones before it
+        for sum in &checksums {
+            let (start, end) = match &sum.range {
+                Some((start, end)) => (
+                    start.evaluate(defines, label)? as usize,
+                    end.evaluate(defines, label)? as usize,
+                ),
+                None => (0, code_len),
+            };
+            if start > end || end > memlen || (start..end).contains(&sum.position) {
+                return Err(BError::AsmParseError(AsmError::at(
+                    sum.line,
+                    &sum.text,
+                    format!(
+                        "invalid checksum range {:#06x}-{:#06x}, ROM ends at {:#06x} and the checksum is at {:#06x}",
+                        start, end, memlen, sum.position
+                    ),
+                )));
+            }
+            mem[sum.position] = checksum(&mem[start..end], sum.kind);
+        }
 
-        Ok(VmDescription {
+        let labels = Labels {
+            consts: label_definitions
+                .into_iter()
+                .filter(|(name, _)| !text_labels.contains_key(name))
+                .map(|(name, address)| (name, address as u16))
+                .collect(),
+            text: text_labels
+                .into_iter()
+                .map(|(name, address)| (name, address as u16))
+                .collect(),
+        };
+        let vm_desc = VmDescription {
             rom: mem,
-            rom_mappings: vec![(
-                consts_start as u16,
-                (memlen - consts_start) as u16,
-                mount_position,
-            )],
+            rom_mappings: mappings
+                .into_iter()
+                .map(|(rom, length, ram)| (rom as u16, length as u16, ram as u16))
+                .collect(),
             ..Default::default()
-        })
+        };
+        Ok((vm_desc, labels))
+    }
+
+    /// Labels defined in a program
+    pub(crate) struct Labels {
+        pub text: BTreeMap<String, u16>,
+        pub consts: BTreeMap<String, u16>,
+    }
+}
+
+/// Debug information about an assembled program: where the labels are and which line each instruction came from
+pub mod debug_info {
+    use std::{collections::BTreeMap, fmt::Write};
+
+    use crate::{
+        BError,
+        disassembler::disassemble_inst,
+        util::{check_slice, number_literal_to_u16},
+    };
+
+    /// Magic of the .bdbg format
+    const MAGIC: &[u8] = b"BDG\x01";
+
+    /// Where an instruction came from
+    /// - `address`: ROM address of the instruction
+    /// - `line`: line in the .basm file, starting at 1. Instructions of a macro have the line the macro is used in
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct SourceLine {
+        pub address: u16,
+        pub line: u32,
+    }
+
+    /// Debug information produced by [`super::run_with_debug_info()`]
+    /// - `labels`: labels of the \[text\] section and their ROM address
+    /// - `consts`: labels of the \[consts\] section and their RAM address
+    /// - `defines`: defines of the \[macros\] section and their value
+    /// - `lines`: the source line of every instruction, ordered by address
+    #[derive(Debug, Clone, PartialEq, Eq, Default)]
+    pub struct DebugInfo {
+        pub labels: BTreeMap<String, u16>,
+        pub consts: BTreeMap<String, u16>,
+        pub defines: BTreeMap<String, u16>,
+        pub lines: Vec<SourceLine>,
+    }
+
+    impl DebugInfo {
+        /// Get the source line of the instruction at ROM address `address`
+        pub fn line_of(&self, address: u16) -> Option<u32> {
+            self.lines
+                .binary_search_by_key(&address, |l| l.address)
+                .ok()
+                .map(|idx| self.lines[idx].line)
+        }
+
+        /// Get the label at ROM address `address`, if there is one
+        pub fn label_at(&self, address: u16) -> Option<&str> {
+            self.labels
+                .iter()
+                .find(|(_, a)| **a == address)
+                .map(|(name, _)| name.as_str())
+        }
+
+        /// The value of `symbol`: a label, const label or define, optionally followed by `+OFFSET` or `-OFFSET`. Const
+        /// labels come first, the RAM address is what memory is inspected at
+        /// ## Examples
+        /// ```rust
+        /// use bric_vm::assembler;
+        ///
+        /// let code = r"[macros]
+        /// define LEN 4
+        /// [text]
+        /// label start:
+        /// A = table
+        /// [consts 0x100]
+        /// label table:
+        /// M = 1
+        /// M = 2";
+        /// let (_, info) = assembler::run_with_debug_info(code).unwrap();
+        /// assert_eq!(info.resolve("table+1"), Some(0x101));
+        /// assert_eq!(info.resolve("LEN - 1"), Some(3));
+        /// assert_eq!(info.resolve("start"), Some(0));
+        /// assert_eq!(info.resolve("nothing"), None);
+        /// ```
+        pub fn resolve(&self, symbol: &str) -> Option<u16> {
+            let (name, offset) = match symbol.find(['+', '-']) {
+                Some(idx) => {
+                    let offset = number_literal_to_u16(symbol[idx + 1..].trim()).ok()?;
+                    match &symbol[idx..idx + 1] {
+                        "+" => (&symbol[..idx], offset),
+                        _ => (&symbol[..idx], offset.wrapping_neg()),
+                    }
+                }
+                None => (symbol, 0),
+            };
+            let name = name.trim();
+            let value = self
+                .consts
+                .get(name)
+                .or_else(|| self.defines.get(name))
+                .or_else(|| self.labels.get(name))?;
+            Some(value.wrapping_add(offset))
+        }
+
+        /// Create a listing of the program: every instruction with its address, encoding, line and source text.
+        /// `rom` and `source` are the assembled ROM and the .basm source
+        pub fn listing(&self, rom: &[u16], source: &str) -> String {
+            let source: Vec<&str> = source.lines().collect();
+            let mut out = String::new();
+            for line in &self.lines {
+                if let Some(label) = self.label_at(line.address) {
+                    let _ = writeln!(out, "{:>23}label {}:", "", label);
+                }
+                let word = rom.get(line.address as usize).copied().unwrap_or(0);
+                let mut text = String::new();
+                if disassemble_inst(&word, &mut text).is_err() {
+                    text.clear();
+                }
+                let src = source.get(line.line as usize - 1).map_or("", |l| l.trim());
+                let _ = writeln!(
+                    out,
+                    "{:04x}  {:04x}  {:>6}  {:<24} # {}",
+                    line.address, word, line.line, src, text
+                );
+            }
+            out
+        }
+
+        /// Create a symbol map: one label per line as `rom|ram ADDRESS NAME`, ordered by address
+        pub fn symbol_map(&self) -> String {
+            let mut symbols: Vec<(&str, u16, &String)> = self
+                .labels
+                .iter()
+                .map(|(name, address)| ("rom", *address, name))
+                .chain(
+                    self.consts
+                        .iter()
+                        .map(|(name, address)| ("ram", *address, name)),
+                )
+                .collect();
+            symbols.sort_by_key(|(kind, address, _)| (*kind != "rom", *address));
+            let mut out = String::new();
+            for (kind, address, name) in symbols {
+                let _ = writeln!(out, "{} {:#06x} {}", kind, address, name);
+            }
+            out
+        }
+
+        /// Serialize into the .bdbg format. See docs/vmformat.md
+        pub fn serialize(&self) -> Result<Vec<u8>, BError> {
+            let symbols = self.labels.len() + self.consts.len() + self.defines.len();
+            if symbols > 0xffff || self.lines.len() > 0xffff {
+                return Err(BError::SerializationError(
+                    "The debug info is to large".into(),
+                ));
+            }
+            let mut output = MAGIC.to_vec();
+            output.extend_from_slice(&(symbols as u16).to_be_bytes());
+            for (kind, map) in [
+                (0u8, &self.labels),
+                (1u8, &self.consts),
+                (2u8, &self.defines),
+            ] {
+                for (name, address) in map {
+                    if name.len() > 0xff {
+                        return Err(BError::SerializationError(
+                            format!("The label {} is to long", name).into(),
+                        ));
+                    }
+                    output.push(kind);
+                    output.push(name.len() as u8);
+                    output.extend_from_slice(name.as_bytes());
+                    output.extend_from_slice(&address.to_be_bytes());
+                }
+            }
+            output.extend_from_slice(&(self.lines.len() as u16).to_be_bytes());
+            for line in &self.lines {
+                output.extend_from_slice(&line.address.to_be_bytes());
+                output.extend_from_slice(&line.line.to_be_bytes());
+            }
+            Ok(output)
+        }
+
+        /// Deserialize the output of [`DebugInfo::serialize()`]
+        pub fn deserialize(input: &[u8]) -> Result<Self, BError> {
+            let invalid = || BError::DeserializationError("Invalid debug info".into());
+            if check_slice(input, 4)? != MAGIC {
+                return Err(invalid());
+            }
+            let mut current = &input[4..];
+            let count = check_slice(current, 2)?;
+            let count = u16::from_be_bytes([count[0], count[1]]);
+            current = &current[2..];
+
+            let mut info = Self::default();
+            for _ in 0..count {
+                let header = check_slice(current, 2)?;
+                let (kind, len) = (header[0], header[1] as usize);
+                let entry = check_slice(&current[2..], len + 2)?;
+                let name = String::from_utf8(entry[..len].to_vec()).map_err(|_| invalid())?;
+                let address = u16::from_be_bytes([entry[len], entry[len + 1]]);
+                match kind {
+                    0 => info.labels.insert(name, address),
+                    1 => info.consts.insert(name, address),
+                    2 => info.defines.insert(name, address),
+                    _ => return Err(invalid()),
+                };
+                current = &current[4 + len..];
+            }
+
+            let count = check_slice(current, 2)?;
+            let count = u16::from_be_bytes([count[0], count[1]]) as usize;
+            let lines = check_slice(&current[2..], count * 6)?;
+            info.lines = lines
+                .chunks(6)
+                .map(|l| SourceLine {
+                    address: u16::from_be_bytes([l[0], l[1]]),
+                    line: u32::from_be_bytes([l[2], l[3], l[4], l[5]]),
+                })
+                .collect();
+            if current.len() != 2 + count * 6 {
+                return Err(invalid());
+            }
+            Ok(info)
+        }
     }
 }
 
@@ -950,9 +1779,146 @@ const RE_TEXT: LazyCell<Regex> = LazyCell::new(|| Regex::new(r"(?m)^\s*\[text\]\
 const RE_CONSTS: LazyCell<Regex> = LazyCell::new(|| {
     Regex::new(r"(?m)^\s*\[consts\s+(?<number>0x[0-9a-fA-F]+|0b[01]+|[0-9]+)?\]\s*$").unwrap()
 });
+static RE_TESTS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?m)^\s*\[tests\]\s*$").unwrap());
+
+/// Split off the `[tests]` section, which has to be the last section. Returns the program, and the tests with the line
+/// they start on, or `None` if there are no tests. The tests are run by [`crate::testing`]
+pub fn split_tests(assembly: &str) -> (&str, Option<(&str, usize)>) {
+    match RE_TESTS.find(assembly) {
+        Some(tests_match) => {
+            let line = assembly[..tests_match.end()].matches('\n').count() + 1;
+            (
+                &assembly[..tests_match.start()],
+                Some((&assembly[tests_match.end()..], line)),
+            )
+        }
+        None => (assembly, None),
+    }
+}
 
-/// Runs the entire assembler chain, resulting in a VmDescription
+/// Runs the entire assembler chain, resulting in a VmDescription. A `[tests]` section is ignored
 pub fn run(assembly: &str) -> Result<VmDescription, BError> {
+    run_with_debug_info(assembly).map(|(vm_desc, _)| vm_desc)
+}
+
+/// Like [`run()`], but also returns the labels and the source line of every instruction
+/// ## Examples
+/// ```rust
+/// use bric_vm::assembler::{self, debug_info::DebugInfo};
+///
+/// let code = r"[text]
+/// label start:
+/// A = 5
+/// D = add, 0, A
+/// [consts 0x100]
+/// label five:
+/// M = 5";
+/// let (vm_desc, info) = assembler::run_with_debug_info(code).unwrap();
+/// assert_eq!(info.labels["start"], 0);
+/// assert_eq!(info.consts["five"], 0x100);
+/// assert_eq!(info.line_of(1), Some(4));
+/// assert!(info.listing(&vm_desc.rom, code).contains("D = add, 0, A"));
+/// assert_eq!(DebugInfo::deserialize(&info.serialize().unwrap()).unwrap(), info);
+/// ```
+pub fn run_with_debug_info(assembly: &str) -> Result<(VmDescription, DebugInfo), BError> {
+    run_with_options(assembly, &AsmOptions::default())
+}
+
+/// Options of [`run_with_options()`]
+/// - `std`: include the [`STD_PRELUDE`] as if the `[macros]` section started with `include <std>`
+/// - `directives`: handlers of custom directives by name without the `.`, see [`AsmOptions::register_directive()`]
+#[derive(Default)]
+pub struct AsmOptions {
+    pub std: bool,
+    pub directives: Directives,
+}
+
+impl AsmOptions {
+    /// Handle `.name ARGS` lines in the \[text\] and \[consts\] sections with `handler`. Replaces an existing handler
+    /// of the same name. Built-in directives like `.checksum` and `.res` can't be replaced
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::assembler::{self, AsmOptions};
+    ///
+    /// let mut options = AsmOptions::default();
+    /// // `.ascii TEXT`: one word per byte
+    /// options.register_directive(
+    ///     "ascii",
+    ///     Box::new(|args, _| Ok(args.join(" ").bytes().map(u16::from).collect())),
+    /// );
+    /// // `.here`: the RAM address of the word itself
+    /// options.register_directive(
+    ///     "here",
+    ///     Box::new(|_, layout| layout.ram_address.map(|a| vec![a]).ok_or("only in [consts]".into())),
+    /// );
+    ///
+    /// let code = r"[text]
+    /// A = 1
+    /// [consts 0x100]
+    /// .ascii hi you
+    /// .here";
+    /// let (vm_desc, _) = assembler::run_with_options(code, &options).unwrap();
+    /// let consts = vm_desc.rom_mappings[0].0 as usize;
+    /// assert_eq!(&vm_desc.rom[consts..consts + 6], b"hi you".map(u16::from));
+    /// assert_eq!(vm_desc.rom[consts + 6], 0x106);
+    ///
+    /// let code = "[text]\n.here\n[consts 0x100]";
+    /// assert!(assembler::run_with_options(code, &options).is_err());
+    /// ```
+    pub fn register_directive(&mut self, name: &str, handler: DirectiveHandler) {
+        self.directives.insert(name.to_string(), handler);
+    }
+}
+
+/// Where the words of a directive go, passed to a [`DirectiveHandler`]
+/// - `rom_address`: ROM address of the first word
+/// - `ram_address`: RAM address of the first word in the \[consts\] section, `None` in the \[text\] section
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub rom_address: u16,
+    pub ram_address: Option<u16>,
+}
+
+/// Handles a custom directive. Gets the whitespace separated arguments and where the directive is placed, returns
+/// the words that replace it or an error message
+pub type DirectiveHandler = Box<dyn Fn(&[&str], &Layout) -> Result<Vec<u16>, String> + Send + Sync>;
+
+/// Handlers of custom directives by name
+pub type Directives = BTreeMap<String, DirectiveHandler>;
+
+/// Run the handler of the directive in `line`, e.g. `.name arg`
+fn run_directive(directives: &Directives, line: &str, layout: Layout) -> Result<Vec<u16>, String> {
+    let mut tokens = line.split_whitespace();
+    let name = tokens.next().unwrap_or_default().trim_start_matches('.');
+    let handler = directives
+        .get(name)
+        .ok_or_else(|| format!("unknown directive .{}", name))?;
+    handler(&tokens.collect::<Vec<&str>>(), &layout)
+}
+
+/// The standard library of macros and defines, included by `include <std>` in the `[macros]` section. See
+/// [std](../docs/std.md)
+pub const STD_PRELUDE: &str = include_str!("assembler/std.basm");
+
+/// Like [`run_with_debug_info()`], with `options`
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler::{self, AsmOptions}, vm::Vm};
+///
+/// let code = r"[text]
+/// std.memset(0x200, 7, 3)
+/// [consts 0x100]";
+/// let options = AsmOptions { std: true, ..Default::default() };
+/// let (vm_desc, _) = assembler::run_with_options(code, &options).unwrap();
+/// let mut vm = Vm::new(vm_desc).unwrap();
+/// while vm.cycle().is_ok() {}
+/// assert_eq!(*vm.get_ram_region(0x200, 4), [7, 7, 7, 0]);
+/// ```
+pub fn run_with_options(
+    assembly: &str,
+    options: &AsmOptions,
+) -> Result<(VmDescription, DebugInfo), BError> {
+    let (assembly, _) = split_tests(assembly);
     // find the ranges of each section
     let macros_start = match RE_MACROS.find(assembly) {
         Some(macros_match) => macros_match.end(),
@@ -974,9 +1940,9 @@ pub fn run(assembly: &str) -> Result<VmDescription, BError> {
                 .unwrap()["number"];
             // doesn't fail because we already found it
             let number = number_literal_to_u16(n_str).map_err(|_| {
-                BError::AsmParseError(format!(
-                    "error parsing consts section. The number {n_str} isn't good.",
-                ))
+                BError::AsmParseError(
+                    format!("error parsing consts section. The number {n_str} isn't good.",).into(),
+                )
             })?;
             (
                 text_start + consts_match.start(),
@@ -988,20 +1954,42 @@ pub fn run(assembly: &str) -> Result<VmDescription, BError> {
     };
     if !((macros_start <= text_start) & (text_start < consts_start)) {
         return Err(BError::AsmParseError(
-            "bad section ordering or `[text]` section is missing".to_string(),
+            "bad section ordering or `[text]` section is missing".into(),
         ));
     }
     // run the assembler in sequence
-    let (preprocessed, t_offset) = preprocessor::preprocess(
+    let (preprocessed, t_offset, origins, defines) = preprocessor::preprocess_with_lines(
         &assembly[macros_start..macros_end],
         &assembly[text_start..text_end],
+        options.std,
     )?;
-    let assembled = text_processor::assemble(preprocessed, t_offset)?;
+    let assembled =
+        text_processor::assemble_with_directives(preprocessed, t_offset, &options.directives)?;
     let const_offset = t_offset + assembled.rom_lines;
-    const_processor::find_and_place(
+    // the code starts on the line of the `[text]` label
+    let first_line = assembly[..text_start].matches('\n').count() + 1;
+    let lines = assembled
+        .lines
+        .iter()
+        .enumerate()
+        .map(|(address, line)| SourceLine {
+            address: address as u16,
+            line: (first_line + origins[*line]) as u32,
+        })
+        .collect();
+    let (vm_desc, labels) = const_processor::place(
         assembled,
         &assembly[consts_start..consts_end],
         const_offset,
         consts_mount,
-    )
+        &defines,
+        &options.directives,
+    )?;
+    let info = DebugInfo {
+        labels: labels.text,
+        consts: labels.consts,
+        defines,
+        lines,
+    };
+    Ok((vm_desc, info))
 }
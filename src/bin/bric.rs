@@ -0,0 +1,31 @@
+use bric_vm::cli::{asm, compare, dbg, disasm, run, test};
+use clap::{Parser, Subcommand};
+
+/// Assemble, run, debug, disassemble and test BRIC programs
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    Asm(asm::Args),
+    Run(Box<run::Args>),
+    Dbg(dbg::Args),
+    Disasm(disasm::Args),
+    Test(test::Args),
+    Compare(compare::Args),
+}
+
+fn main() {
+    match Cli::parse().command {
+        Command::Asm(args) => asm::main(args),
+        Command::Run(args) => run::main(*args),
+        Command::Dbg(args) => dbg::main(args),
+        Command::Disasm(args) => disasm::main(args),
+        Command::Test(args) => test::main(args),
+        Command::Compare(args) => compare::main(args),
+    }
+}
@@ -0,0 +1,14 @@
+use bric_vm::cli::test;
+use clap::Parser;
+
+/// Run the tests of .basm files with a `[tests]` section. Same as `bric test`
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    #[command(flatten)]
+    args: test::Args,
+}
+
+fn main() {
+    test::main(Cli::parse().args);
+}
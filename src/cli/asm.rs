@@ -0,0 +1,137 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    assembler::{self, AsmOptions},
+    cli::fail,
+    vm::Metadata,
+};
+
+/// Assemble a .basm file into a .bvm
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// path to the .basm file
+    #[arg(short, long)]
+    pub in_path: PathBuf,
+
+    /// path to output to
+    #[arg(short, long)]
+    pub out_path: PathBuf,
+
+    /// program name stored in the metadata, defaults to the name of the input file
+    #[arg(short, long)]
+    pub name: Option<String>,
+
+    /// symbol of the entry point stored in the metadata
+    #[arg(short, long)]
+    pub entry: Option<String>,
+
+    /// free-form metadata entry, can be given multiple times
+    #[arg(short, long, value_name = "KEY=VALUE")]
+    pub meta: Vec<String>,
+
+    /// embed the source into the .bvm so it can be shown by bdb and bdisasm
+    #[arg(short, long, default_value_t = false)]
+    pub source: bool,
+
+    /// compress ROM and RAM in the .bvm
+    #[arg(short, long, default_value_t = false)]
+    pub compress: bool,
+
+    /// write a listing with the address, encoding and source line of every instruction to FILE
+    #[arg(long, value_name = "FILE")]
+    pub listing: Option<PathBuf>,
+
+    /// write the addresses of all labels to FILE
+    #[arg(long, value_name = "FILE")]
+    pub map: Option<PathBuf>,
+
+    /// write the labels and source lines as .bdbg debug info to FILE
+    #[arg(long, value_name = "FILE")]
+    pub debug_info: Option<PathBuf>,
+
+    /// include the standard library of macros, like `include <std>` in the `[macros]` section
+    #[arg(long, default_value_t = false)]
+    pub std: bool,
+}
+
+/// Assemble the program
+pub fn main(args: Args) {
+    let input_string = std::fs::read_to_string(&args.in_path)
+        .unwrap_or_else(|e| fail(format!("unable to read {}: {}", args.in_path.display(), e)));
+
+    let options = AsmOptions {
+        std: args.std,
+        ..Default::default()
+    };
+    let (mut vmdesc, info) = assembler::run_with_options(&input_string, &options)
+        .unwrap_or_else(|e| fail(format!("assembly error: {}", e)));
+
+    let mut entries = Vec::new();
+    for entry in args.meta {
+        match entry.split_once('=') {
+            Some((key, value)) => entries.push((key.to_string(), value.to_string())),
+            None => fail(format!(
+                "metadata entry {} is not of the form KEY=VALUE",
+                entry
+            )),
+        }
+    }
+    if args.source {
+        vmdesc.source = Some(input_string.clone());
+    }
+    vmdesc.metadata = Some(Metadata {
+        name: args.name.or_else(|| {
+            args.in_path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+        }),
+        entry_point: args.entry,
+        assembler_version: Some(format!("basm {}", env!("CARGO_PKG_VERSION"))),
+        build_timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_secs()),
+        entries,
+    });
+
+    if let Some(path) = &args.listing {
+        write(path, info.listing(&vmdesc.rom, &input_string).as_bytes());
+    }
+    if let Some(path) = &args.map {
+        write(path, info.symbol_map().as_bytes());
+    }
+    if let Some(path) = &args.debug_info {
+        let serialized = info
+            .serialize()
+            .unwrap_or_else(|e| fail(format!("serialization error: {}", e)));
+        write(path, &serialized);
+    }
+
+    {
+        #[cfg(feature = "compression")]
+        let serialized = if args.compress {
+            vmdesc.serialize_compressed()
+        } else {
+            vmdesc.serialize()
+        };
+        #[cfg(not(feature = "compression"))]
+        let serialized = if args.compress {
+            fail("basm was built without the `compression` feature");
+        } else {
+            vmdesc.serialize()
+        };
+        let out_bytes = serialized.unwrap_or_else(|e| fail(format!("serialization error: {}", e)));
+        write(&args.out_path, &out_bytes);
+    }
+}
+
+/// Write `contents` to `path`
+fn write(path: &Path, contents: &[u8]) {
+    if let Err(e) = fs::write(path, contents) {
+        fail(format!("unable to write {}: {}", path.display(), e));
+    }
+}
@@ -0,0 +1,123 @@
+//! Connects a UART to the outside, so terminal emulators like picocom or netcat can be attached to it
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener},
+    sync::mpsc::Sender,
+    thread,
+};
+
+use crate::{BError, mmio::uart::UartIo};
+
+/// Forward everything read from `reader` to the UART until it closes or fails
+fn forward(mut reader: impl Read, input: Sender<u8>) {
+    let mut buf = [0; 0x100];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return,
+            Ok(len) => {
+                for byte in &buf[..len] {
+                    let _ = input.send(*byte);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(_) => return,
+        }
+    }
+}
+
+/// Listen on `address`, e.g. `127.0.0.1:4000`, and connect the UART to the accepted connections one after another.
+/// The guest's output goes to the connection instead of [`UartIo::output`], output written while no connection is
+/// established is dropped. Returns the address listened on.
+/// ## Errors
+/// [`BError::IoError`] if the address can't be listened on
+pub fn bridge_tcp(uart: &UartIo, address: &str) -> Result<SocketAddr, BError> {
+    let listener = TcpListener::bind(address).map_err(|e| BError::IoError(e.to_string()))?;
+    let local_addr = listener
+        .local_addr()
+        .map_err(|e| BError::IoError(e.to_string()))?;
+    let shared = uart.uart.clone();
+    let input = uart.input.clone();
+    shared.lock().unwrap().set_output_handler(Box::new(|_| {}));
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let _ = stream.set_nodelay(true);
+            let Ok(mut writer) = stream.try_clone() else {
+                continue;
+            };
+            shared
+                .lock()
+                .unwrap()
+                .set_output_handler(Box::new(move |byte| {
+                    let _ = writer.write_all(&[byte]);
+                }));
+            forward(stream, input.clone());
+            shared.lock().unwrap().set_output_handler(Box::new(|_| {}));
+        }
+    });
+    Ok(local_addr)
+}
+
+/// Create a pseudo-terminal and connect the UART to it. The guest's output goes to the pseudo-terminal instead of
+/// [`UartIo::output`]. Returns the path of the terminal to attach to, e.g. `/dev/pts/3`
+/// ## Errors
+/// [`BError::IoError`] if the pseudo-terminal can't be created
+#[cfg(unix)]
+pub fn bridge_pty(uart: &UartIo) -> Result<std::path::PathBuf, BError> {
+    use std::{ffi::CStr, fs::File, os::fd::FromRawFd, time::Duration};
+
+    let error = || BError::IoError(std::io::Error::last_os_error().to_string());
+    // SAFETY: the calls only use the descriptor returned by posix_openpt, which is owned by `master` afterwards
+    let (master, path) = unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        if fd < 0 {
+            return Err(error());
+        }
+        let master = File::from_raw_fd(fd);
+        if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+            return Err(error());
+        }
+        let name = libc::ptsname(fd);
+        if name.is_null() {
+            return Err(error());
+        }
+        let path = CStr::from_ptr(name).to_string_lossy().into_owned();
+        // pass every byte through unchanged
+        let mut termios = std::mem::zeroed::<libc::termios>();
+        if libc::tcgetattr(fd, &mut termios) == 0 {
+            libc::cfmakeraw(&mut termios);
+            libc::tcsetattr(fd, libc::TCSANOW, &termios);
+        }
+        // don't block the VM if nobody reads the output
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+        (master, path)
+    };
+
+    let mut writer = master
+        .try_clone()
+        .map_err(|e| BError::IoError(e.to_string()))?;
+    uart.uart
+        .lock()
+        .unwrap()
+        .set_output_handler(Box::new(move |byte| {
+            let _ = writer.write_all(&[byte]);
+        }));
+    let input = uart.input.clone();
+    thread::spawn(move || {
+        let mut master = master;
+        let mut buf = [0; 0x100];
+        loop {
+            match master.read(&mut buf) {
+                Ok(len) if len > 0 => {
+                    for byte in &buf[..len] {
+                        let _ = input.send(*byte);
+                    }
+                }
+                // nothing to read or no terminal attached yet
+                _ => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+    });
+    Ok(path.into())
+}
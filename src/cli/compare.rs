@@ -0,0 +1,49 @@
+use std::{ops::RangeInclusive, path::PathBuf};
+
+use crate::{
+    cli::{fail, parse_range, read_description},
+    regression::{self, DEFAULT_MAX_CYCLES, Options},
+};
+
+/// Run the old and the new build of a program with the same input and compare their UART output and final RAM
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// .bvm file of the old build
+    pub old: PathBuf,
+
+    /// .bvm file of the new build
+    pub new: PathBuf,
+
+    /// send the contents of FILE to the UART of both builds before they start
+    #[arg(long, value_name = "FILE")]
+    pub input: Option<PathBuf>,
+
+    /// stop a build after N cycles
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_CYCLES)]
+    pub max_cycles: u64,
+
+    /// RAM not to compare, e.g. `0x7000-0x7fff,0x100`
+    #[arg(long, value_name = "RANGE", value_parser = parse_range, value_delimiter = ',')]
+    pub ignore: Vec<RangeInclusive<u16>>,
+}
+
+/// Compare the builds
+pub fn main(args: Args) {
+    let input = args.input.map_or_else(Vec::new, |path| {
+        std::fs::read(&path)
+            .unwrap_or_else(|e| fail(format!("unable to read {}: {}", path.display(), e)))
+    });
+    let options = Options {
+        input,
+        max_cycles: args.max_cycles,
+        ignore: args.ignore,
+    };
+    let old = read_description(&args.old, None);
+    let new = read_description(&args.new, None);
+    let regression = regression::compare(old, new, &options)
+        .unwrap_or_else(|e| fail(format!("error during vm instantiation: {}", e)));
+    println!("{}", regression);
+    if !regression.passed() {
+        std::process::exit(-1);
+    }
+}
@@ -0,0 +1,572 @@
+use crate::{
+    assembler::text_processor::assemble_at,
+    cli::{RawImage, bridge, fail, read_debug_info, read_description},
+    debugger::Debugger,
+    disassembler::{self, disassemble_inst},
+    util::number_literal_to_u16,
+    vm::{
+        CostTable, Register,
+        inject::{Effect, Injection, Target},
+    },
+};
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
+
+// TODOs
+// - Memory editing
+// - Memory Breakpoints
+
+/// Runs a BRIC from a .bvm or .bdb in the debugger
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// from coredump (.bdb file)
+    #[arg(short, long, default_value_t = false)]
+    pub coredump: bool,
+
+    /// use uart. Coredumps have to be opened with the UART if they were written with it
+    #[arg(short, long, default_value_t = false)]
+    pub uart: bool,
+
+    /// path to the .bvm or .bdb file
+    #[arg(short, long)]
+    pub path: PathBuf,
+
+    /// connect the UART to TCP connections accepted on ADDR, e.g. for netcat
+    #[arg(long, value_name = "ADDR")]
+    pub uart_tcp: Option<String>,
+
+    /// connect the UART to a new pseudo-terminal, e.g. for picocom. Its path is printed on start
+    #[cfg(unix)]
+    #[arg(long, default_value_t = false, conflicts_with = "uart_tcp")]
+    pub uart_pty: bool,
+
+    /// send the contents of FILE to the UART
+    #[arg(long, value_name = "FILE")]
+    pub uart_file: Option<PathBuf>,
+
+    /// send the UART file BYTES bytes at a time, one chunk every CYCLES cycles, e.g. `1/1000`
+    #[arg(long, value_name = "BYTES/CYCLES", value_parser = parse_rate, requires = "uart_file")]
+    pub uart_rate: Option<(usize, u64)>,
+
+    /// max amount of iterations to continue the CPU for when continuing
+    #[arg(short, long, default_value_t = 0xffff)]
+    pub max_iter: usize,
+
+    /// wait for GDB to connect on ADDR, e.g. `127.0.0.1:9000`, before starting the prompt
+    #[cfg(feature = "gdb")]
+    #[arg(long, value_name = "ADDR")]
+    pub gdb: Option<String>,
+
+    /// serve the debugger on ADDR, e.g. `127.0.0.1:9100`, with the JSON protocol instead of starting the prompt
+    #[cfg(feature = "remote")]
+    #[arg(long, value_name = "ADDR")]
+    pub remote: Option<String>,
+
+    /// labels and defines for addresses, from debug info written by `basm --debug-info`. The source embedded by
+    /// `basm --source` is assembled for them if not given
+    #[arg(long, value_name = "FILE")]
+    pub symbols: Option<PathBuf>,
+
+    /// treat the file as a flat binary ROM image with the given byte order instead of a .bvm file
+    #[arg(short, long, conflicts_with = "coredump")]
+    pub raw: Option<RawImage>,
+}
+
+/// The const labels that `length` words at `address` lie in, like ` # table+2 next`, or nothing if there are none
+fn const_labels(debugger: &Debugger, address: u16, length: usize) -> String {
+    let mut names = String::new();
+    let mut last = None;
+    for offset in 0..length as u16 {
+        if let Some((name, into)) = debugger.const_label_at(address + offset)
+            && last != Some(name)
+        {
+            names.push(' ');
+            names.push_str(name);
+            if into > 0 {
+                names.push_str(&format!("+{}", into));
+            }
+            last = Some(name);
+        }
+    }
+    if names.is_empty() {
+        names
+    } else {
+        format!(" #{}", names)
+    }
+}
+
+/// Start the debugger
+pub fn main(args: Args) {
+    #[cfg(unix)]
+    let uart_pty = args.uart_pty;
+    #[cfg(not(unix))]
+    let uart_pty = false;
+    let use_uart = args.uart || args.uart_tcp.is_some() || uart_pty || args.uart_file.is_some();
+
+    let debugger = if args.coredump {
+        let input = std::fs::read(&args.path)
+            .unwrap_or_else(|e| fail(format!("unable to read {}: {}", args.path.display(), e)));
+        Debugger::deserialize(&input, use_uart)
+    } else {
+        Debugger::new(read_description(&args.path, args.raw), vec![], use_uart)
+    };
+    let mut debugger = debugger.unwrap_or_else(|e| fail(format!("error deserializing: {}", e)));
+    debugger.enable_coverage();
+    debugger.enable_cost(CostTable::default());
+    let source = debugger.get_source().map(str::to_string);
+    if let Some(symbols) = read_debug_info(args.symbols.as_deref(), source.as_deref()) {
+        debugger.load_symbols(symbols);
+    }
+
+    if let (Some(address), Some(uart)) = (&args.uart_tcp, debugger.uart()) {
+        match bridge::bridge_tcp(uart, address) {
+            Ok(address) => println!("UART listening on {}", address),
+            Err(e) => fail(format!("unable to listen on {}: {}", address, e)),
+        }
+    }
+    #[cfg(unix)]
+    if let (true, Some(uart)) = (uart_pty, debugger.uart()) {
+        match bridge::bridge_pty(uart) {
+            Ok(path) => println!("UART connected to {}", path.display()),
+            Err(e) => fail(format!("unable to create a pseudo-terminal: {}", e)),
+        }
+    }
+
+    if let Some(path) = &args.uart_file {
+        let data = std::fs::read(path)
+            .unwrap_or_else(|e| fail(format!("unable to read {}: {}", path.display(), e)));
+        debugger.feed_uart(&data, args.uart_rate);
+    }
+
+    #[cfg(feature = "gdb")]
+    if let Some(address) = &args.gdb {
+        use gdbstub::stub::DisconnectReason;
+
+        println!("waiting for GDB on {}", address);
+        match crate::gdb::listen(&mut debugger, address) {
+            Ok(DisconnectReason::Disconnect) => println!("GDB detached"),
+            Ok(reason) => {
+                println!("GDB session ended: {:?}", reason);
+                std::process::exit(0);
+            }
+            Err(e) => fail(format!("GDB session failed: {}", e)),
+        }
+    }
+
+    #[cfg(feature = "remote")]
+    if let Some(address) = &args.remote {
+        println!("serving the debugger on {}", address);
+        if let Err(e) = crate::debugger::remote::listen(&mut debugger, address) {
+            fail(format!("unable to listen on {}: {}", address, e));
+        }
+    }
+
+    // an empty line repeats it, if it only steps or inspects
+    let mut last_command = String::new();
+    loop {
+        let uout = debugger.get_uart_out();
+        if !uout.is_empty() {
+            println!("uart>> {:?}", uout);
+        }
+
+        let mut user_input = String::new();
+        print!("bdb> ");
+        let _ = io::stdout().flush();
+        let read = io::stdin()
+            .read_line(&mut user_input)
+            .expect("unable to read stdin");
+        if read == 0 {
+            // end of input, repeating the last command would never end
+            std::process::exit(0);
+        }
+        let command = match user_input.trim() {
+            "" => last_command.clone(),
+            input => input.to_string(),
+        };
+        last_command = if repeatable(&command) {
+            command.clone()
+        } else {
+            String::new()
+        };
+
+        match command.as_str() {
+            "q" => {
+                std::process::exit(0);
+            }
+            "c" => {
+                debugger.run(args.max_iter);
+                if let Some((address, target)) = debugger.taken_jump() {
+                    println!("jump at {:#06x} taken to {:#06x}", address, target);
+                }
+                print_halt(&debugger);
+            }
+            "rbj" => {
+                debugger.break_on_jumps(None);
+                println!("no longer stopping on taken jumps");
+            }
+            "s" => {
+                debugger.step();
+                print_halt(&debugger);
+            }
+            "dis" => {
+                match disassembler::disassemble_annotated(debugger.get_rom(), true) {
+                    Ok(s) => println!("{}", s),
+                    Err(e) => eprintln!("unable to disassemble {}", e),
+                };
+            }
+            "u" => {
+                if !use_uart {
+                    eprintln!("UART not activated");
+                    continue;
+                }
+                println!("capturing uart input... enter `quit_uart` to leave");
+                loop {
+                    print!("uart> ");
+                    let mut uart_input = String::new();
+                    let _ = io::stdout().flush();
+                    io::stdin()
+                        .read_line(&mut uart_input)
+                        .expect("unable to read stdin");
+                    if matches!(uart_input.as_str(), "quit_uart\n") {
+                        break;
+                    }
+                    for c in uart_input.chars() {
+                        debugger.write_uart_byte(c as u8);
+                    }
+                }
+            }
+            "" => {}
+            o => {
+                if o.starts_with("i") {
+                    let parts: Vec<&str> = o.split_whitespace().collect();
+                    if parts.len() < 2 || parts[0] != "i" {
+                        eprintln!("unrecognized input");
+                        continue;
+                    }
+
+                    match parts[1] {
+                        "reg" => {
+                            if let Some(reg_text) = parts.get(2) {
+                                if let Some(reg) = Register::from_str(reg_text) {
+                                    println!("{} = {:#04x}", reg_text, debugger.inspect_reg(reg));
+                                } else {
+                                    eprintln!("invalid register name");
+                                    continue;
+                                }
+                            } else {
+                                eprintln!("not enough arguments for `i reg`");
+                            }
+                        }
+                        v @ "mem" | v @ "rom" => {
+                            if parts.len() != 4 {
+                                eprintln!("not enough arguments for `i {}`", v);
+                                continue;
+                            }
+                            let Some(start_addr) = debugger.resolve(parts[2]) else {
+                                eprintln!("invalid starting address");
+                                continue;
+                            };
+                            let Some(length) = debugger.resolve(parts[3]) else {
+                                eprintln!("invalid length");
+                                continue;
+                            };
+                            let mem_dump = match v {
+                                "mem" => debugger.inspect_memory(start_addr, length),
+                                "rom" => debugger.inspect_rom(start_addr, length).into(),
+                                _ => panic!("unreachable"),
+                            };
+                            for (row, words) in mem_dump.chunks(16).enumerate() {
+                                // should not panic because we limit the size of inspect memory to the size of RAM.
+                                let row_addr = start_addr + (row * 16) as u16;
+                                print!("\n{:#06x}\t", row_addr);
+                                for v in words {
+                                    print!("{:#06x} ", v);
+                                }
+                                if v == "mem" {
+                                    print!("{}", const_labels(&debugger, row_addr, words.len()));
+                                }
+                            }
+                            println!()
+                        }
+                        "ci" => {
+                            let pc = debugger.get_pc();
+                            if let Some(inst) = debugger.inspect_rom(pc, 1).first() {
+                                let mut out = String::new();
+                                match disassemble_inst(inst, &mut out) {
+                                    Ok(_) => {
+                                        println!("{}", out);
+                                    }
+                                    Err(_) => {
+                                        eprintln!("unable to decode instruction");
+                                    }
+                                }
+                            } else {
+                                eprintln!("PC points outside of valid ROM range");
+                            }
+                        }
+                        "pc" => {
+                            println!("PC = {}", debugger.get_pc());
+                        }
+                        "state" => {
+                            println!("{}", debugger.dump_state());
+                        }
+                        "addr" => {
+                            match parts.get(2).map(|address| number_literal_to_u16(address)) {
+                                Some(Ok(address)) => {
+                                    println!("{}", debugger.describe_address(address))
+                                }
+                                Some(Err(_)) => eprintln!("invalid address"),
+                                None => eprintln!("not enough arguments for `i addr`"),
+                            }
+                        }
+                        "inj" => {
+                            for injection in debugger.pending_injections() {
+                                println!("{}", injection);
+                            }
+                        }
+                        "cov" => match debugger.coverage() {
+                            Some(coverage) => println!("{}", coverage),
+                            None => eprintln!("coverage is not enabled"),
+                        },
+                        "cost" => match debugger.cost() {
+                            Some(cost) => println!("{}", cost),
+                            None => eprintln!("the cost is not recorded"),
+                        },
+                        "fault" => match debugger.get_fault() {
+                            Some(fault) => println!(
+                                "{:?} on {:#06x} (value {:#06x}) by the instruction at {:#06x}",
+                                fault.kind, fault.address, fault.value, fault.pc
+                            ),
+                            None => println!("no fault recorded"),
+                        },
+                        "src" => match debugger.get_source() {
+                            Some(source) => {
+                                for (idx, line) in source.lines().enumerate() {
+                                    println!("{:>5} {}", idx + 1, line);
+                                }
+                            }
+                            None => println!("no source embedded"),
+                        },
+                        _ => {
+                            eprintln!("unrecognized input");
+                        }
+                    }
+                } else if let Some(rest) = o.strip_prefix("asm ") {
+                    let (ram, address) = match rest.split_whitespace().collect::<Vec<_>>()[..] {
+                        ["ram", address] => (true, address),
+                        [address] => (false, address),
+                        _ => {
+                            eprintln!("unrecognized input");
+                            continue;
+                        }
+                    };
+                    let Ok(address) = number_literal_to_u16(address) else {
+                        eprintln!("invalid address");
+                        continue;
+                    };
+                    println!("enter the code, an empty line assembles it");
+                    let mut code = String::new();
+                    loop {
+                        print!("asm> ");
+                        let _ = io::stdout().flush();
+                        let mut line = String::new();
+                        io::stdin()
+                            .read_line(&mut line)
+                            .expect("unable to read stdin");
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                        code.push_str(&line);
+                    }
+                    let words = match assemble_at(&code, address) {
+                        Ok(words) => words,
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            continue;
+                        }
+                    };
+                    let written = if ram {
+                        debugger.set_memory(address, words.clone())
+                    } else {
+                        debugger.set_rom(address, words.clone())
+                    };
+                    match written {
+                        Ok(_) => println!(
+                            "wrote {} words to {} at {:#06x}",
+                            words.len(),
+                            if ram { "RAM" } else { "ROM" },
+                            address
+                        ),
+                        Err(e) => eprintln!("{}", e),
+                    }
+                } else if let Some(rest) = o.strip_prefix("uf ") {
+                    if !use_uart {
+                        eprintln!("UART not activated");
+                        continue;
+                    }
+                    let parts: Vec<&str> = rest.split_whitespace().collect();
+                    let rate = match parts.get(1).map(|rate| parse_rate(rate)) {
+                        Some(Err(e)) => {
+                            eprintln!("{}", e);
+                            continue;
+                        }
+                        rate => rate.and_then(Result::ok),
+                    };
+                    match parts.first().map(std::fs::read) {
+                        Some(Ok(data)) => {
+                            debugger.feed_uart(&data, rate);
+                            println!("sending {} bytes to the uart", data.len());
+                        }
+                        Some(Err(e)) => eprintln!("unable to read the file: {}", e),
+                        None => eprintln!("not enough arguments for `uf`"),
+                    }
+                } else if o.starts_with("flip ") || o.starts_with("force ") {
+                    let parts: Vec<&str> = o.split_whitespace().collect();
+                    let (Some(target), Some(value)) = (parts.get(1), parts.get(2)) else {
+                        eprintln!("not enough arguments for `{}`", parts[0]);
+                        continue;
+                    };
+                    let Some(target) = Target::parse(target) else {
+                        eprintln!("invalid target, expected pc, a register, *ADDR or rom:ADDR");
+                        continue;
+                    };
+                    let Ok(value) = number_literal_to_u16(value) else {
+                        eprintln!("invalid value");
+                        continue;
+                    };
+                    let effect = match parts[0] {
+                        "flip" => Effect::Flip(value),
+                        _ => Effect::Force(value),
+                    };
+                    match parts.get(3).map(|cycle| cycle.parse::<u64>()) {
+                        Some(Ok(cycle)) => {
+                            let injection = Injection {
+                                cycle,
+                                target,
+                                effect,
+                            };
+                            debugger.schedule_injection(injection);
+                            println!("scheduled: {}", injection);
+                        }
+                        Some(Err(_)) => eprintln!("invalid cycle"),
+                        None => debugger.inject(target, effect),
+                    }
+                } else if o == "explain" || o.starts_with("explain ") {
+                    let word = match o.split_whitespace().nth(1) {
+                        None => debugger.dump_state().instruction,
+                        Some(location) => {
+                            let (rom, address) = match location.strip_prefix("rom:") {
+                                Some(address) => (Some(true), address),
+                                None => match location.strip_prefix('*') {
+                                    Some(address) => (Some(false), address),
+                                    None => (None, location),
+                                },
+                            };
+                            let Ok(value) = number_literal_to_u16(address) else {
+                                eprintln!("expected a word, rom:ADDR or *ADDR");
+                                continue;
+                            };
+                            match rom {
+                                Some(true) => debugger.inspect_rom(value, 1).first().copied(),
+                                Some(false) => debugger.inspect_memory(value, 1).first().copied(),
+                                None => Some(value),
+                            }
+                        }
+                    };
+                    match word {
+                        Some(word) => print!("{}", disassembler::explain(word)),
+                        None => eprintln!("no instruction there"),
+                    }
+                } else if let Some(count) = o.strip_prefix("s ") {
+                    let Ok(count) = count.trim().parse::<usize>() else {
+                        eprintln!("invalid count");
+                        continue;
+                    };
+                    let stepped = debugger.step_n(count);
+                    if stepped < count {
+                        let reason = if debugger.is_halted() {
+                            "the execution halted"
+                        } else {
+                            "a breakpoint was hit"
+                        };
+                        println!("stepped {} of {} instructions, {}", stepped, count, reason);
+                    }
+                    print_halt(&debugger);
+                } else if o == "bj" || o.starts_with("bj ") {
+                    let bounds: Result<Vec<u16>, _> = o
+                        .split_whitespace()
+                        .skip(1)
+                        .map(number_literal_to_u16)
+                        .collect();
+                    let range = match bounds.as_deref() {
+                        Ok([]) => 0..=u16::MAX,
+                        Ok([start]) => *start..=u16::MAX,
+                        Ok([start, end]) => *start..=*end,
+                        _ => {
+                            eprintln!("expected `bj [start] [end]`");
+                            continue;
+                        }
+                    };
+                    println!(
+                        "stopping on taken conditional jumps in {:#06x}-{:#06x}",
+                        range.start(),
+                        range.end()
+                    );
+                    debugger.break_on_jumps(Some(range));
+                } else if o.starts_with("b") || o.starts_with("rb") {
+                    let parts: Vec<&str> = o.split_whitespace().collect();
+                    if parts.len() < 2 || parts[0] != "b" {
+                        eprintln!("unrecognized input");
+                        continue;
+                    }
+
+                    match number_literal_to_u16(parts[1]) {
+                        Ok(v) => {
+                            if parts[0] == "b" {
+                                debugger.register_breakpoint(v);
+                                println!("registered new breakpoint at {:#04x}", v);
+                            } else {
+                                if debugger.remove_breakpoint(v) {
+                                    println!("removed breakpoint at {:#04x}", v);
+                                } else {
+                                    eprintln!("that breakpoint does not exist");
+                                }
+                            }
+                        }
+                        Err(_) => {
+                            eprintln!("unable to parse breakpoint address");
+                        }
+                    }
+                } else {
+                    eprintln!("unknown input");
+                }
+            }
+        }
+    }
+}
+
+/// Say why the execution halted, if it did
+fn print_halt(debugger: &Debugger) {
+    if debugger.is_halted()
+        && let Some(cause) = debugger.halt_cause()
+    {
+        println!("execution halted: {}", cause);
+    }
+}
+
+/// Parse a rate like `1/1000` into bytes and cycles
+fn parse_rate(input: &str) -> Result<(usize, u64), String> {
+    let invalid = || format!("invalid rate {}, expected BYTES/CYCLES", input);
+    let (bytes, cycles) = input.split_once('/').ok_or_else(invalid)?;
+    match (bytes.trim().parse(), cycles.trim().parse()) {
+        (Ok(bytes), Ok(cycles)) if bytes > 0 && cycles > 0 => Ok((bytes, cycles)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Whether an empty line repeats `command`. Commands that change the VM, like writing memory, aren't repeated
+fn repeatable(command: &str) -> bool {
+    matches!(command.split_whitespace().next(), Some("s" | "c" | "i"))
+}
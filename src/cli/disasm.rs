@@ -0,0 +1,71 @@
+use std::{fs::File, io::Write, path::PathBuf};
+
+use crate::{cli::fail, disassembler, vm::VmDescription};
+
+/// Disassemble a .bvm file into a .basm file
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// path to the .bvm file
+    #[arg(short, long)]
+    pub in_path: PathBuf,
+
+    /// path to output .basm to
+    #[arg(short, long, required_unless_present = "info")]
+    pub out_path: Option<PathBuf>,
+
+    /// print the metadata of the .bvm file instead of disassembling it
+    #[arg(long)]
+    pub info: bool,
+
+    /// write the embedded source instead of disassembling, if there is one
+    #[arg(short, long, default_value_t = false)]
+    pub source: bool,
+
+    /// name the standard device registers whose address is loaded in comments
+    #[arg(short, long, default_value_t = false)]
+    pub annotate: bool,
+}
+
+/// Disassemble the program
+pub fn main(args: Args) {
+    let bvm_file = std::fs::read(&args.in_path)
+        .unwrap_or_else(|e| fail(format!("unable to read {}: {}", args.in_path.display(), e)));
+    let vm_desc = VmDescription::deserialize(&bvm_file)
+        .unwrap_or_else(|e| fail(format!("bad input file: {}", e)));
+
+    if args.info {
+        match vm_desc.metadata() {
+            Some(metadata) => {
+                for (key, value) in metadata.to_entries() {
+                    println!("{}: {}", key, value);
+                }
+            }
+            None => println!("no metadata"),
+        }
+        return;
+    }
+
+    let disassemble = if args.annotate {
+        disassembler::disassemble_annotated
+    } else {
+        disassembler::disassemble
+    };
+    let disassembled = match (args.source, vm_desc.source) {
+        (true, Some(source)) => Ok(source),
+        (true, None) => {
+            eprintln!("no source embedded, disassembling instead");
+            disassemble(&vm_desc.rom, false)
+        }
+        (false, _) => disassemble(&vm_desc.rom, false),
+    };
+
+    match disassembled {
+        Ok(s) => {
+            // clap makes sure the path is there without --info
+            let mut file = File::create(args.out_path.unwrap()).expect("cant create outptu file");
+            file.write_all(s.as_bytes())
+                .expect("unable to write to output path");
+        }
+        Err(e) => fail(format!("error disassembling: {}", e)),
+    }
+}
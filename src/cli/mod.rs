@@ -0,0 +1,71 @@
+//! The command line tools. Each subcommand of `bric` is a module with its arguments and entry point, the `bric_vm`,
+//! `basm`, `bdb`, `bdisasm` and `btest` executables are thin wrappers around them.
+use std::{fmt, ops::RangeInclusive, path::Path};
+
+use clap::ValueEnum;
+
+use crate::{
+    assembler::{self, debug_info::DebugInfo},
+    util::number_literal_to_u16,
+    vm::{Endianness, VmDescription},
+};
+
+pub mod asm;
+pub mod bridge;
+pub mod compare;
+pub mod dbg;
+pub mod disasm;
+pub mod run;
+pub mod test;
+
+/// Byte order of a flat binary image
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum RawImage {
+    Be,
+    Le,
+}
+
+/// Print `message` to stderr and exit with an error
+pub fn fail(message: impl fmt::Display) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(-1);
+}
+
+/// Read a .bvm file or, if `raw` is set, a flat binary ROM image. Exits with an error if that fails
+pub fn read_description(path: &Path, raw: Option<RawImage>) -> VmDescription {
+    let input = std::fs::read(path)
+        .unwrap_or_else(|e| fail(format!("unable to read {}: {}", path.display(), e)));
+    let vm_desc = match raw {
+        Some(RawImage::Be) => VmDescription::from_rom_image(&input, Endianness::Big),
+        Some(RawImage::Le) => VmDescription::from_rom_image(&input, Endianness::Little),
+        None => VmDescription::deserialize(&input),
+    };
+    vm_desc.unwrap_or_else(|e| fail(format!("bad input file: {}", e)))
+}
+
+/// Read the debug info at `path`, or assemble the embedded `source` for it. Exits with an error if `path` can't be read
+pub fn read_debug_info(path: Option<&Path>, source: Option<&str>) -> Option<DebugInfo> {
+    if let Some(path) = path {
+        let input = std::fs::read(path)
+            .unwrap_or_else(|e| fail(format!("unable to read {}: {}", path.display(), e)));
+        let info = DebugInfo::deserialize(&input)
+            .unwrap_or_else(|e| fail(format!("bad debug info {}: {}", path.display(), e)));
+        return Some(info);
+    }
+    assembler::run_with_debug_info(source?)
+        .ok()
+        .map(|(_, info)| info)
+}
+
+/// Parse an address like `0x7000`, `0b01` or `10` for clap
+pub fn parse_address(input: &str) -> Result<u16, String> {
+    number_literal_to_u16(input).map_err(|_| format!("invalid address {}", input))
+}
+
+/// Parse an address or an inclusive range of addresses like `0x7000-0x7fff` for clap
+pub fn parse_range(input: &str) -> Result<RangeInclusive<u16>, String> {
+    match input.split_once('-') {
+        Some((start, end)) => Ok(parse_address(start)?..=parse_address(end)?),
+        None => parse_address(input).map(|address| address..=address),
+    }
+}
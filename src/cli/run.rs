@@ -0,0 +1,716 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    ops::RangeInclusive,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, TryRecvError},
+    },
+    thread,
+};
+
+use crate::{
+    BError,
+    args::{ARGS_BLOCK, ENV_BLOCK, GuestArg, HostEnv, write_args},
+    cli::{RawImage, fail, parse_address, parse_range, read_debug_info, read_description},
+    cosim::{self, HdlTrace, record_cycle},
+    debugger::Debugger,
+    disassembler::{disassemble_inst, disassemble_profile},
+    mmio::{
+        map,
+        net::{NetMode, mount_net},
+        panel::{Panel, mount_panel},
+        registry::DeviceRegistry,
+        screen::{SCREEN_HEIGHT, TextScreen, mount_screen},
+        semihost::mount_semihosting,
+        uart::mount_uart_io,
+    },
+    util::number_literal_to_u16,
+    vcd::VcdWriter,
+    vm::{CostTable, Latency, Pacer, Vm},
+};
+use crossterm::{
+    cursor::MoveTo,
+    execute,
+    terminal::{self, Clear, ClearType},
+};
+
+/// Amount of cycles between redraws of the screen and the panel
+const SCREEN_REFRESH_CYCLES: u64 = 0x1000;
+
+/// Instructions listed by --profile without labels
+const HOTTEST: usize = 20;
+
+/// Line that leaves the UART mode
+const QUIT_UART: &str = "quit_uart";
+
+/// Byte of Ctrl-], which leaves the console mode
+const CONSOLE_ESCAPE: u8 = 0x1d;
+
+/// Why the program stopped running
+enum Stop {
+    /// The user quit
+    Quit,
+    /// The program ran past the end of the ROM
+    Halted,
+    /// `--max-cycles` was reached
+    CycleLimit,
+    /// The program matched the whole `--cosim` trace
+    TraceMatched,
+    /// An error occurred, holds its message
+    Failed(String),
+}
+
+/// Runs a BRIC from a .bvm file
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// path to the .bvm file
+    #[arg(short, long)]
+    pub path: PathBuf,
+
+    /// treat the file as a flat binary ROM image with the given byte order instead of a .bvm file
+    #[arg(short, long)]
+    pub raw: Option<RawImage>,
+
+    /// mount a text screen and draw it into the terminal
+    #[arg(short, long, default_value_t = false)]
+    pub screen: bool,
+
+    /// mount a lamp and switch panel and draw it into the terminal. Enter `s<N>` to flip switch N
+    #[arg(short = 'l', long, default_value_t = false)]
+    pub panel: bool,
+
+    /// mount a UART. Enter `u` to type into it, its output is printed as it arrives
+    #[arg(short, long, default_value_t = false)]
+    pub uart: bool,
+
+    /// mount a UART and connect it to the terminal in raw mode, so every key is sent to it. Press Ctrl-] to quit
+    #[arg(short, long, default_value_t = false, conflicts_with_all = ["screen", "panel"])]
+    pub console: bool,
+
+    /// mount a random number generator seeded with SEED
+    #[arg(long, value_name = "SEED")]
+    pub rng: Option<u64>,
+
+    /// give the program access to the files in DIR through semihosting
+    #[arg(long, value_name = "DIR")]
+    pub semihost: Option<PathBuf>,
+
+    /// bridge the network device to a TCP connection accepted on ADDR
+    #[arg(long, value_name = "ADDR", conflicts_with = "net_connect")]
+    pub net_listen: Option<String>,
+
+    /// bridge the network device to a TCP connection to ADDR
+    #[arg(long, value_name = "ADDR")]
+    pub net_connect: Option<String>,
+
+    /// expose the performance counters to the program at ADDR, e.g. 0x7000
+    #[arg(long, value_name = "ADDR", value_parser = parse_address)]
+    pub perf_counters: Option<u16>,
+
+    /// let the program reset the VM by writing to ADDR
+    #[arg(long, value_name = "ADDR", value_parser = parse_address)]
+    pub reset_register: Option<u16>,
+
+    /// pass VALUE to the program in the argument block, see --args-at. Can be given multiple times
+    #[arg(long = "arg", value_name = "VALUE", value_parser = parse_value, allow_negative_numbers = true)]
+    pub args: Vec<u16>,
+
+    /// pass the address of TEXT to the program in the argument block, after the --arg values. Can be given multiple
+    /// times
+    #[arg(long = "arg-str", value_name = "TEXT")]
+    pub arg_strs: Vec<String>,
+
+    /// write the argument block of --arg and --arg-str to ADDR
+    #[arg(long, value_name = "ADDR", value_parser = parse_address, default_value_t = ARGS_BLOCK)]
+    pub args_at: u16,
+
+    /// expose the host environment variable NAME, or NAME with VALUE, to the program in the read only environment
+    /// block, see --env-at. Variables the host doesn't have are left out. Can be given multiple times
+    #[arg(long = "env", value_name = "NAME[=VALUE]")]
+    pub env: Vec<String>,
+
+    /// write the environment block of --env to ADDR
+    #[arg(long, value_name = "ADDR", value_parser = parse_address, default_value_t = ENV_BLOCK)]
+    pub env_at: u16,
+
+    /// stop with an error after N cycles
+    #[arg(long, value_name = "N")]
+    pub max_cycles: Option<u64>,
+
+    /// run at HZ cycles per second instead of as fast as possible, e.g. 1000000 for a 1 MHz CPU
+    #[arg(long, value_name = "HZ", conflicts_with = "bench")]
+    pub clock: Option<u64>,
+
+    /// extra cycles of memory accesses, e.g. `ram=2,rom=1,mmio=10`. Left out kinds cost nothing
+    #[arg(long, value_name = "KIND=CYCLES,...", value_parser = parse_latency)]
+    pub latency: Option<Latency>,
+
+    /// RAM of devices whose accesses cost the `mmio` latency, e.g. `0x6000-0x60ff,0x7000`
+    #[arg(long, value_name = "RANGES", value_parser = parse_range, value_delimiter = ',', requires = "latency")]
+    pub mmio: Vec<RangeInclusive<u16>>,
+
+    /// write every executed instruction and the registers before it to FILE
+    #[arg(long, value_name = "FILE")]
+    pub trace: Option<PathBuf>,
+
+    /// write the PC, the registers and the RAM words given by --vcd-watch to FILE as a VCD waveform, sampled every cycle
+    #[arg(long, value_name = "FILE")]
+    pub vcd: Option<PathBuf>,
+
+    /// RAM addresses to add to the VCD waveform, e.g. `0x100,0x6000`
+    #[arg(long, value_name = "ADDR", value_parser = parse_address, value_delimiter = ',', requires = "vcd")]
+    pub vcd_watch: Vec<u16>,
+
+    /// run the program flat-out for SECONDS, 5 if not given, and print the throughput of the interpreter. The program is
+    /// restarted whenever it halts
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        num_args = 0..=1,
+        default_missing_value = "5",
+        conflicts_with_all = ["console", "cosim", "trace"]
+    )]
+    pub bench: Option<f64>,
+
+    /// compare the execution against the cycle trace FILE of an HDL simulation and stop at the first divergence
+    #[arg(long, value_name = "FILE")]
+    pub cosim: Option<PathBuf>,
+
+    /// exit with the word at ADDR as the exit code when the program halts, 0xfffe if no ADDR is given. ADDR can't be
+    /// the register of a standard device
+    #[arg(
+        long,
+        value_name = "ADDR",
+        value_parser = parse_address,
+        num_args = 0..=1,
+        default_missing_value = "0xfffe"
+    )]
+    pub exit_code: Option<u16>,
+
+    /// run reproducibly: devices that depend on the host are rejected and no commands are read. With the UART, all of
+    /// stdin is sent to it before the first cycle
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["console", "semihost", "net_listen", "net_connect"]
+    )]
+    pub deterministic: bool,
+
+    /// print how many instructions were executed and the ones that never were when the program stops
+    #[arg(long, default_value_t = false)]
+    pub coverage: bool,
+
+    /// print how often the instructions were executed when the program stops, by label if the labels are known
+    #[arg(long, default_value_t = false)]
+    pub profile: bool,
+
+    /// write the disassembly with the execution count of every instruction to FILE when the program stops
+    #[arg(long, value_name = "FILE")]
+    pub annotate: Option<PathBuf>,
+
+    /// write how often every RAM address was read and written to FILE when the program stops. CSV if FILE ends in
+    /// .csv, a 256x256 image with one pixel per address otherwise: PNG if it ends in .png, PPM else
+    #[arg(long, value_name = "FILE")]
+    pub heat_map: Option<PathBuf>,
+
+    /// labels for --profile and --annotate, from debug info written by `basm --debug-info`. The source embedded by
+    /// `basm --source` is assembled for them if not given
+    #[arg(long, value_name = "FILE")]
+    pub symbols: Option<PathBuf>,
+
+    /// print the cost of the executed instructions when the program stops. Costs not given in TABLE, e.g.
+    /// `data=1,arithmetic=2,logic=1,shift=1,jump=3,ram_read=4,ram_write=4`, keep their default
+    #[arg(long, value_name = "TABLE", value_parser = parse_cost_table, num_args = 0..=1, default_missing_value = "")]
+    pub cost: Option<CostTable>,
+
+    /// save a .bdb coredump to FILE when the program stops, for any reason
+    #[arg(long, value_name = "FILE")]
+    pub dump: Option<PathBuf>,
+}
+
+/// Run the program
+pub fn main(args: Args) {
+    // a device would overwrite the exit code or ignore it
+    if let Some(entry) = args.exit_code.and_then(map::lookup) {
+        fail(format!(
+            "--exit-code can't be at {:#06x}, the register {} of the {} device",
+            args.exit_code.unwrap(),
+            entry.name,
+            entry.device
+        ));
+    }
+    let mut vm_desc = read_description(&args.path, args.raw);
+    let source = vm_desc.source.clone();
+
+    let screen = args.screen.then(|| mount_screen(&mut vm_desc));
+    let panel = args.panel.then(|| mount_panel(&mut vm_desc));
+    let uart = (args.uart || args.console).then(|| mount_uart_io(&mut vm_desc));
+    vm_desc.rng_seed = args.rng;
+    vm_desc.deterministic = args.deterministic;
+    vm_desc.perf_counters = args.perf_counters;
+    vm_desc.reset_register = args.reset_register;
+    if !args.args.is_empty() || !args.arg_strs.is_empty() {
+        let guest_args: Vec<GuestArg> = args
+            .args
+            .iter()
+            .map(|value| GuestArg::Value(*value))
+            .chain(args.arg_strs.iter().cloned().map(GuestArg::Str))
+            .collect();
+        if let Err(e) = write_args(&mut vm_desc, args.args_at, &guest_args) {
+            fail(format!("unable to write the arguments: {}", e));
+        }
+    }
+    if !args.env.is_empty() {
+        let vars = args
+            .env
+            .iter()
+            .filter_map(|spec| match spec.split_once('=') {
+                Some((name, value)) => Some((name.to_string(), value.to_string())),
+                None => std::env::var(spec).ok().map(|value| (spec.clone(), value)),
+            })
+            .collect();
+        vm_desc.host_env = Some(HostEnv {
+            address: args.env_at,
+            vars,
+        });
+    }
+    if let Some(latency) = &args.latency {
+        vm_desc.latency = Latency {
+            mmio_regions: args.mmio.clone(),
+            ..latency.clone()
+        };
+    }
+    if let Some(root) = args.semihost {
+        mount_semihosting(&mut vm_desc, root);
+    }
+    let net_mode = match (args.net_listen, args.net_connect) {
+        (Some(address), _) => Some(NetMode::Listen(address)),
+        (_, Some(address)) => Some(NetMode::Connect(address)),
+        _ => None,
+    };
+    if let Some(mode) = net_mode
+        && let Err(e) = mount_net(&mut vm_desc, mode)
+    {
+        fail(format!("unable to set up the network bridge: {}", e));
+    }
+    // devices listed in the file
+    let _devices = DeviceRegistry::default()
+        .mount(&mut vm_desc)
+        .unwrap_or_else(|e| fail(format!("unable to mount the devices of the file: {}", e)));
+    let mut vm =
+        Vm::new(vm_desc).unwrap_or_else(|e| fail(format!("error during vm instantiation: {}", e)));
+    if let Some(seconds) = args.bench {
+        match vm.benchmark(seconds) {
+            Ok(report) => println!("{}", report),
+            Err(e) => fail(format!("error during execution: {}", e)),
+        }
+        return;
+    }
+    if let Some(table) = args.cost {
+        vm.enable_cost(table);
+    }
+    if args.coverage {
+        vm.enable_coverage();
+    }
+    if args.heat_map.is_some() {
+        vm.enable_heat_map();
+    }
+    let labels = if args.profile || args.annotate.is_some() {
+        let labels = read_debug_info(args.symbols.as_deref(), source.as_deref()).map(|i| i.labels);
+        let functions: Vec<u16> = labels.iter().flat_map(|l| l.values().copied()).collect();
+        vm.enable_profile(&functions);
+        labels
+    } else {
+        None
+    };
+    let mut trace = args.trace.map(|path| {
+        File::create(&path)
+            .map(BufWriter::new)
+            .unwrap_or_else(|e| fail(format!("unable to create {}: {}", path.display(), e)))
+    });
+    let mut vcd = args.vcd.map(|path| {
+        File::create(&path)
+            .map(BufWriter::new)
+            .and_then(|file| VcdWriter::new(file, &args.vcd_watch))
+            .unwrap_or_else(|e| fail(format!("unable to create {}: {}", path.display(), e)))
+    });
+    if let Some(vcd) = &mut vcd
+        && let Err(e) = vcd.sample(&vm)
+    {
+        fail(format!("unable to write the waveform: {}", e));
+    }
+    let hdl_trace = args.cosim.map(|path| {
+        let input = fs::read_to_string(&path)
+            .unwrap_or_else(|e| fail(format!("unable to read {}: {}", path.display(), e)));
+        HdlTrace::parse(&input).unwrap_or_else(|e| fail(e))
+    });
+    let mut cosim_cycles = 0;
+    if screen.is_some() {
+        execute!(io::stdout(), Clear(ClearType::All)).expect("unable to clear the terminal");
+    }
+
+    let (tx, rx) = mpsc::channel::<String>();
+    if let (true, Some(uart)) = (args.console, &uart) {
+        terminal::enable_raw_mode()
+            .unwrap_or_else(|e| fail(format!("unable to enter the raw mode: {}", e)));
+        print!("console mode, press Ctrl-] to quit\r\n");
+        let input = uart.input.clone();
+        thread::spawn(move || {
+            for byte in io::stdin().lock().bytes() {
+                match byte {
+                    Ok(CONSOLE_ESCAPE) | Err(_) => break,
+                    Ok(byte) => {
+                        let _ = input.send(byte);
+                    }
+                }
+            }
+            let _ = tx.send("q".to_string());
+        });
+    } else if args.deterministic {
+        // the whole input is on the line before the first cycle, so every run receives it at the same cycles
+        if let Some(uart) = &uart {
+            let mut input = Vec::new();
+            io::stdin()
+                .read_to_end(&mut input)
+                .unwrap_or_else(|e| fail(format!("unable to read stdin: {}", e)));
+            for byte in input {
+                let _ = uart.input.send(byte);
+            }
+        }
+    } else {
+        thread::spawn(move || {
+            loop {
+                let mut buffer = String::new();
+                io::stdin().read_line(&mut buffer).unwrap();
+                tx.send(buffer).unwrap();
+            }
+        });
+    }
+
+    let mut uart_mode = false;
+    let mut pacer = args.clock.map(Pacer::new);
+    // cycle count at the last call to Pacer::pace()
+    let mut paced = 0;
+    let stop = loop {
+        match rx.try_recv() {
+            Ok(line) if uart_mode => {
+                if line.trim() == QUIT_UART {
+                    uart_mode = false;
+                } else if let Some(uart) = &uart {
+                    for byte in line.bytes() {
+                        let _ = uart.input.send(byte);
+                    }
+                }
+            }
+            Ok(line) => match line.trim() {
+                "q" => break Stop::Quit,
+                "u" => {
+                    if uart.is_some() {
+                        println!(
+                            "sending input to the uart... enter `{}` to leave",
+                            QUIT_UART
+                        );
+                        uart_mode = true;
+                    } else {
+                        eprintln!("UART not activated");
+                    }
+                }
+                l if l.starts_with('s') => {
+                    if let (Some(panel), Ok(switch)) = (&panel, l[1..].trim().parse::<u8>()) {
+                        panel.lock().unwrap().toggle_switch(switch);
+                    }
+                }
+                _ => {}
+            },
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => {
+                std::process::exit(-1);
+            }
+        }
+
+        if vm.get_cycles().is_multiple_of(SCREEN_REFRESH_CYCLES) {
+            draw(&screen, &panel, false);
+        }
+
+        if args.max_cycles.is_some_and(|max| vm.get_cycles() >= max) {
+            break Stop::CycleLimit;
+        }
+        if let Some(trace) = &mut trace
+            && let Err(e) = write_trace(&vm, trace)
+        {
+            break Stop::Failed(format!("unable to write the trace: {}", e));
+        }
+
+        let result = match &hdl_trace {
+            Some(hdl) if cosim_cycles == hdl.records.len() => break Stop::TraceMatched,
+            Some(hdl) => {
+                let result = record_cycle(&mut vm);
+                let actual = result.as_ref().ok().cloned();
+                if matches!(result, Ok(_) | Err(BError::ExecutionHaltedError { .. }))
+                    && let Some(divergence) = cosim::check(
+                        cosim_cycles,
+                        hdl.lines[cosim_cycles],
+                        &hdl.records[cosim_cycles],
+                        actual,
+                    )
+                {
+                    break Stop::Failed(divergence.to_string());
+                }
+                cosim_cycles += 1;
+                result.map(|_| ())
+            }
+            None => vm.cycle(),
+        };
+        match result {
+            Err(BError::ExecutionHaltedError { value: _ }) => break Stop::Halted,
+            Err(e) => break Stop::Failed(format!("error during execution: {}", e)),
+            Ok(_) => {}
+        }
+        if let Some(pacer) = &mut pacer {
+            let unpaced = vm.get_cycles().saturating_sub(paced);
+            if unpaced >= pacer.batch() {
+                pacer.pace(unpaced);
+                paced = vm.get_cycles();
+            }
+        }
+        if let Some(vcd) = &mut vcd
+            && let Err(e) = vcd.sample(&vm)
+        {
+            break Stop::Failed(format!("unable to write the waveform: {}", e));
+        }
+
+        if let Some(uart) = &uart {
+            let mut output: Vec<u8> = uart.output.try_iter().collect();
+            if args.console {
+                output = output
+                    .into_iter()
+                    .flat_map(|byte| match byte {
+                        b'\n' => vec![b'\r', b'\n'],
+                        byte => vec![byte],
+                    })
+                    .collect();
+            }
+            if !output.is_empty() {
+                let mut stdout = io::stdout();
+                stdout
+                    .write_all(&output)
+                    .and_then(|_| stdout.flush())
+                    .expect("unable to write the UART output");
+            }
+        }
+    };
+
+    if args.console {
+        let _ = terminal::disable_raw_mode();
+        println!();
+    }
+    let code = match stop {
+        Stop::Quit => 0,
+        Stop::Halted => {
+            draw(&screen, &panel, true);
+            match vm.halt_cause() {
+                Some(cause) => println!("Execution halted: {}", cause),
+                None => println!("Execution halted"),
+            }
+            args.exit_code
+                .map_or(0, |address| vm.get_ram_region(address, 1)[0] as i32)
+        }
+        Stop::CycleLimit => {
+            draw(&screen, &panel, true);
+            eprintln!("Cycle limit reached");
+            -1
+        }
+        Stop::TraceMatched => {
+            println!(
+                "The execution matches all {} cycles of the trace",
+                cosim_cycles
+            );
+            0
+        }
+        Stop::Failed(message) => {
+            eprintln!("{}", message);
+            eprintln!("{}", vm.dump_state());
+            -1
+        }
+    };
+    if let Some(cost) = vm.cost() {
+        println!("{}", cost);
+    }
+    if let Some(path) = &args.annotate
+        && let Some(profile) = vm.profile()
+    {
+        let listing = disassemble_profile(&profile, labels.as_ref())
+            .unwrap_or_else(|e| fail(format!("error disassembling: {}", e)));
+        if let Err(e) = fs::write(path, listing) {
+            fail(format!("unable to write {}: {}", path.display(), e));
+        }
+    }
+    if let Some(path) = &args.heat_map
+        && let Some(heat) = vm.heat_map()
+    {
+        let image = match path.extension().and_then(|e| e.to_str()) {
+            Some("csv") => heat.to_csv().into_bytes(),
+            #[cfg(feature = "compression")]
+            Some("png") => heat.to_png(),
+            #[cfg(not(feature = "compression"))]
+            Some("png") => fail("PNG heat maps need the compression feature, use .ppm"),
+            _ => heat.to_ppm(),
+        };
+        if let Err(e) = fs::write(path, image) {
+            fail(format!("unable to write {}: {}", path.display(), e));
+        }
+    }
+    if args.profile
+        && let Some(profile) = vm.profile()
+    {
+        match &labels {
+            Some(labels) => println!("{}", profile.by_symbol(labels)),
+            None => {
+                println!("most executed instructions:");
+                for (address, count) in profile.hottest(HOTTEST) {
+                    let mut instruction = String::new();
+                    if let Some(inst) = vm.get_rom_region(address, 1).first() {
+                        let _ = disassemble_inst(inst, &mut instruction);
+                    }
+                    println!("{:>12}  {:#06x}: {}", count, address, instruction);
+                }
+            }
+        }
+    }
+    if let Some(coverage) = vm.coverage() {
+        println!("{}", coverage);
+    }
+    if let Some(mut trace) = trace
+        && let Err(e) = trace.flush()
+    {
+        fail(format!("unable to write the trace: {}", e));
+    }
+    if let Some(mut vcd) = vcd
+        && let Err(e) = vcd.flush()
+    {
+        fail(format!("unable to write the waveform: {}", e));
+    }
+    if let Some(path) = &args.dump {
+        let coredump = Debugger::from_vm(vm, source)
+            .serialize()
+            .unwrap_or_else(|e| fail(format!("serialization error: {}", e)));
+        if let Err(e) = fs::write(path, coredump) {
+            fail(format!("unable to write {}: {}", path.display(), e));
+        }
+    }
+    std::process::exit(code);
+}
+
+/// Parse a list like `ram=2,rom=1`, calling `set` with each name and number
+fn parse_assignments(
+    input: &str,
+    mut set: impl FnMut(&str, u64) -> Result<(), String>,
+) -> Result<(), String> {
+    for entry in input.split(',').filter(|entry| !entry.trim().is_empty()) {
+        let (name, value) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=NUMBER, found {}", entry))?;
+        let value = value
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid number {}", value))?;
+        set(name.trim(), value)?;
+    }
+    Ok(())
+}
+
+/// Parse a word like `0x7000`, `0b01`, `10` or `-1` for clap. Negative numbers are stored in two's complement
+fn parse_value(input: &str) -> Result<u16, String> {
+    input
+        .parse::<i16>()
+        .map(|value| value as u16)
+        .or_else(|_| number_literal_to_u16(input))
+        .map_err(|_| format!("invalid value {}", input))
+}
+
+/// Parse latencies like `ram=2,rom=1,mmio=10` for clap
+fn parse_latency(input: &str) -> Result<Latency, String> {
+    let mut latency = Latency::default();
+    parse_assignments(input, |kind, cycles| {
+        match kind {
+            "ram" => latency.ram = cycles,
+            "rom" => latency.rom = cycles,
+            "mmio" => latency.mmio = cycles,
+            kind => return Err(format!("unknown kind {}, expected ram, rom or mmio", kind)),
+        }
+        Ok(())
+    })?;
+    Ok(latency)
+}
+
+/// Parse changes to the default cost table like `jump=3,ram_read=4` for clap
+fn parse_cost_table(input: &str) -> Result<CostTable, String> {
+    let mut table = CostTable::default();
+    parse_assignments(input, |class, cost| {
+        let entry = match class {
+            "data" => &mut table.data,
+            "arithmetic" => &mut table.arithmetic,
+            "logic" => &mut table.logic,
+            "shift" => &mut table.shift,
+            "jump" => &mut table.jump,
+            "ram_read" => &mut table.ram_read,
+            "ram_write" => &mut table.ram_write,
+            class => return Err(format!("unknown cost {}", class)),
+        };
+        *entry = cost;
+        Ok(())
+    })?;
+    Ok(table)
+}
+
+/// Write the next instruction and the registers as a line of the trace
+fn write_trace(vm: &Vm, trace: &mut impl Write) -> io::Result<()> {
+    let cpu = vm.cpu_state();
+    let mut instruction = String::new();
+    if let Some(inst) = vm.get_rom_region(cpu.pc, 1).first() {
+        let _ = disassemble_inst(inst, &mut instruction);
+    }
+    writeln!(
+        trace,
+        "{} {:04x} {:<24} A={:04x} D={:04x} E={:04x} F={:04x} G={:04x} H={:04x}",
+        vm.get_cycles(),
+        cpu.pc,
+        instruction,
+        cpu.a,
+        cpu.d,
+        cpu.e,
+        cpu.f,
+        cpu.g,
+        cpu.h
+    )
+}
+
+/// Draw the screen and the panel if they changed. The panel is drawn below the screen.
+/// `last` moves the cursor to the next line afterwards
+fn draw(screen: &Option<Arc<Mutex<TextScreen>>>, panel: &Option<Arc<Mutex<Panel>>>, last: bool) {
+    let mut stdout = io::stdout();
+    if let Some(screen) = screen {
+        let mut screen = screen.lock().unwrap();
+        if screen.is_dirty() {
+            screen
+                .render(&mut stdout)
+                .expect("unable to draw the screen");
+            execute!(stdout, MoveTo(0, SCREEN_HEIGHT)).expect("unable to move the cursor");
+        }
+    }
+    if let Some(panel) = panel {
+        let mut panel = panel.lock().unwrap();
+        if panel.is_dirty() || last {
+            panel.render(&mut stdout).expect("unable to draw the panel");
+        }
+        if last {
+            println!();
+        }
+    }
+    if last && screen.is_some() && panel.is_none() {
+        execute!(stdout, MoveTo(0, SCREEN_HEIGHT)).expect("unable to move the cursor");
+    }
+    stdout.flush().expect("unable to flush stdout");
+}
@@ -0,0 +1,209 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use crate::{
+    BError, assembler,
+    cli::fail,
+    testing::{self, TestCase, TestResult},
+    vm::Coverage,
+};
+
+/// Run the `[tests]` sections of .basm files
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// .basm file or directory to search for .basm files with a `[tests]` section
+    #[arg(default_value = ".")]
+    pub path: PathBuf,
+
+    /// only run the tests whose name, prefixed with the path of their file and `::`, contains FILTER
+    pub filter: Option<String>,
+
+    /// amount of tests to run at the same time, the amount of CPUs if not given
+    #[arg(short, long, value_name = "N")]
+    pub jobs: Option<usize>,
+
+    /// print the instructions of every file executed by none of its tests
+    #[arg(long, default_value_t = false)]
+    pub coverage: bool,
+}
+
+/// A .basm file with tests
+struct Suite {
+    path: PathBuf,
+    source: String,
+    cases: Vec<TestCase>,
+}
+
+/// Collect the .basm files at `path` in a stable order, searching directories recursively. Hidden directories are
+/// skipped
+fn find_sources(path: &Path, found: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if !path.is_dir() {
+        found.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for entry in entries {
+        let hidden = entry
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if entry.is_dir() && !hidden {
+            find_sources(&entry, found)?;
+        } else if entry.extension().is_some_and(|ext| ext == "basm") {
+            found.push(entry);
+        }
+    }
+    Ok(())
+}
+
+/// Run the tests
+pub fn main(args: Args) {
+    let mut sources = Vec::new();
+    if let Err(e) = find_sources(&args.path, &mut sources) {
+        fail(format!("unable to search {}: {}", args.path.display(), e));
+    }
+
+    let mut suites = Vec::new();
+    let mut broken = 0;
+    for path in sources {
+        let source = fs::read_to_string(&path)
+            .unwrap_or_else(|e| fail(format!("unable to read {}: {}", path.display(), e)));
+        if assembler::split_tests(&source).1.is_none() {
+            continue;
+        }
+        match testing::load_tests(&source) {
+            Ok(mut cases) => {
+                cases.retain(|case| {
+                    let name = format!("{}::{}", path.display(), case.name);
+                    args.filter
+                        .as_ref()
+                        .is_none_or(|filter| name.contains(filter.as_str()))
+                });
+                suites.push(Suite {
+                    path,
+                    source,
+                    cases,
+                });
+            }
+            Err(e) => {
+                eprintln!("error in {}: {}", path.display(), e);
+                broken += 1;
+            }
+        }
+    }
+
+    let jobs: Vec<(usize, &TestCase)> = suites
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, suite)| suite.cases.iter().map(move |case| (idx, case)))
+        .collect();
+    let files = suites
+        .iter()
+        .filter(|suite| !suite.cases.is_empty())
+        .count();
+    println!("running {} tests from {} files", jobs.len(), files);
+    let results = run_parallel(&suites, &jobs, args.jobs);
+
+    let mut passed = 0;
+    let mut failures = Vec::new();
+    for ((idx, case), result) in jobs.iter().zip(&results) {
+        let name = format!("{}::{}", suites[*idx].path.display(), case.name);
+        match result {
+            Ok(result) if result.passed() => {
+                println!("test {} ... ok ({} cycles)", name, result.cycles);
+                passed += 1;
+            }
+            Ok(result) => {
+                println!("test {} ... FAILED ({} cycles)", name, result.cycles);
+                failures.push((name, result));
+            }
+            Err(e) => {
+                println!("test {} ... ERROR: {}", name, e);
+                broken += 1;
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (name, result) in &failures {
+            println!("\n---- {} ----", name);
+            for failure in &result.failures {
+                println!("{}", failure);
+            }
+            println!("{}", result.state);
+        }
+    }
+
+    if args.coverage {
+        for (idx, suite) in suites.iter().enumerate() {
+            let coverage = jobs
+                .iter()
+                .zip(&results)
+                .filter(|((job, _), _)| *job == idx)
+                .filter_map(|(_, result)| result.as_ref().ok())
+                .map(|result| result.coverage.clone())
+                .reduce(|mut all: Coverage, coverage| {
+                    all.merge(&coverage);
+                    all
+                });
+            if let Some(coverage) = coverage {
+                println!("\n{}: {}", suite.path.display(), coverage);
+            }
+        }
+    }
+
+    let failed = failures.len() + broken;
+    let outcome = if failed == 0 { "ok" } else { "FAILED" };
+    println!(
+        "\ntest result: {}. {} passed; {} failed",
+        outcome, passed, failed
+    );
+    if failed > 0 {
+        std::process::exit(-1);
+    }
+}
+
+/// Run `jobs` on `workers` threads and return the results in the order of `jobs`
+fn run_parallel(
+    suites: &[Suite],
+    jobs: &[(usize, &TestCase)],
+    workers: Option<usize>,
+) -> Vec<Result<TestResult, BError>> {
+    let workers = workers
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+        .clamp(1, jobs.len().max(1));
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let tx = tx.clone();
+            let next = &next;
+            scope.spawn(move || {
+                loop {
+                    let job = next.fetch_add(1, Ordering::Relaxed);
+                    let Some((idx, case)) = jobs.get(job) else {
+                        break;
+                    };
+                    let result = testing::run_test(&suites[*idx].source, case);
+                    if tx.send((job, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    drop(tx);
+    let mut results: Vec<_> = rx.into_iter().collect();
+    results.sort_by_key(|(job, _)| *job);
+    results.into_iter().map(|(_, result)| result).collect()
+}
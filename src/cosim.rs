@@ -0,0 +1,191 @@
+//! Co-simulation against a hardware implementation of the BRIC. An HDL simulation of the CPU, e.g. in Verilog, writes a
+//! cycle trace, which is compared cycle by cycle against the VM running the same program to find the first divergence.
+//!
+//! The trace has one line per executed instruction: the PC of the instruction in hex, followed by the register it
+//! wrote as `REG=VALUE`, e.g. `0003 D=0005`. REG is one of `A`, `D`, `E`, `F`, `G`, `H` and `*A`, the latter being
+//! the RAM word addressed by A. Values are hex, optionally prefixed with `0x`. Instructions that write nothing, like
+//! jumps, have just the PC. Blank lines and everything after `#` are ignored. A Verilog testbench can emit it with
+//! `$fdisplay(f, "%h D=%h", pc, d)`.
+use std::fmt;
+
+use crate::{
+    BError,
+    vm::{Instruction, Register, Vm},
+};
+
+/// One executed instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CycleRecord {
+    /// Address of the instruction
+    pub pc: u16,
+    /// The registers written by the instruction and their new values
+    pub writes: Vec<(Register, u16)>,
+}
+
+impl fmt::Display for CycleRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}", self.pc)?;
+        for (register, value) in &self.writes {
+            write!(f, " {}={:04x}", register, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where the VM and the hardware went different ways
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Index of the diverging instruction, starting at 0
+    pub cycle: usize,
+    /// Line of the trace holding the instruction
+    pub line: usize,
+    /// What the hardware did
+    pub expected: CycleRecord,
+    /// What the VM did, `None` if it halted
+    pub actual: Option<CycleRecord>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "divergence at cycle {} (line {} of the trace): expected `{}`, ",
+            self.cycle, self.line, self.expected
+        )?;
+        match &self.actual {
+            Some(actual) => write!(f, "the VM executed `{}`", actual),
+            None => write!(f, "the VM halted"),
+        }
+    }
+}
+
+/// A parsed HDL trace
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HdlTrace {
+    pub records: Vec<CycleRecord>,
+    /// Line of each record, starting at 1
+    pub lines: Vec<usize>,
+}
+
+impl HdlTrace {
+    /// Parse a trace in the format described in the module documentation
+    /// ## Errors
+    /// [`BError::DeserializationError`] naming the first malformed line
+    pub fn parse(input: &str) -> Result<Self, BError> {
+        let mut trace = Self::default();
+        for (idx, line) in input.lines().enumerate() {
+            let line = line.split('#').next().unwrap();
+            let mut fields = line.split_whitespace();
+            let Some(pc) = fields.next() else { continue };
+            let invalid = |field: &str| {
+                BError::DeserializationError(
+                    format!("invalid field `{}` in line {} of the trace", field, idx + 1).into(),
+                )
+            };
+            let pc = parse_hex(pc).ok_or_else(|| invalid(pc))?;
+            let writes = fields
+                .map(|field| {
+                    let (register, value) = field.split_once('=').ok_or_else(|| invalid(field))?;
+                    let register = Register::from_str(&register.to_uppercase())
+                        .ok_or_else(|| invalid(field))?;
+                    let value = parse_hex(value).ok_or_else(|| invalid(field))?;
+                    Ok((register, value))
+                })
+                .collect::<Result<_, BError>>()?;
+            trace.records.push(CycleRecord { pc, writes });
+            trace.lines.push(idx + 1);
+        }
+        Ok(trace)
+    }
+}
+
+fn parse_hex(input: &str) -> Option<u16> {
+    let digits = input.strip_prefix("0x").unwrap_or(input);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+/// Execute one cycle and record the PC and the register written
+/// ## Errors
+/// Any error of [`Vm::cycle()`], including [`BError::ExecutionHaltedError`]
+pub fn record_cycle(vm: &mut Vm) -> Result<CycleRecord, BError> {
+    let before = vm.cpu_state();
+    let target = match vm.get_rom_region(before.pc, 1).first() {
+        Some(inst) => match Instruction::from_u16(*inst) {
+            Instruction::Data(_) => Register::A,
+            Instruction::Alu(alu) => Register::try_from(alu.get_target())?,
+        },
+        None => Register::None,
+    };
+    vm.cycle()?;
+    let value = match target {
+        Register::None => None,
+        Register::MA => Some(vm.get_ram_region(before.a, 1)[0]),
+        register => Some(vm.get_reg(register)),
+    };
+    Ok(CycleRecord {
+        pc: before.pc,
+        writes: value.map(|value| (target, value)).into_iter().collect(),
+    })
+}
+
+/// Run the VM along the trace and find the first instruction where they differ. Writes are compared regardless of
+/// their order. Returns `None` if the whole trace matches
+/// ## Errors
+/// Errors of [`Vm::cycle()`] other than the halt, which is reported as a divergence
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler, cosim::{HdlTrace, compare}, vm::Vm};
+///
+/// let code = r"[text]
+/// A = 5
+/// D = add, 0, A
+/// A = 0x100
+/// *A = add, 0, D
+/// [consts 0x200]";
+/// let mut vm = Vm::new(assembler::run(code).unwrap()).unwrap();
+/// // the hardware stored D + 1
+/// let trace = HdlTrace::parse("0000 A=0005\n0001 D=0005\n0002 A=0100\n0003 *A=0006\n").unwrap();
+///
+/// let divergence = compare(&mut vm, &trace).unwrap().unwrap();
+/// assert_eq!(divergence.cycle, 3);
+/// assert_eq!(
+///     divergence.to_string(),
+///     "divergence at cycle 3 (line 4 of the trace): expected `0003 *A=0006`, the VM executed `0003 *A=0005`"
+/// );
+/// ```
+pub fn compare(vm: &mut Vm, trace: &HdlTrace) -> Result<Option<Divergence>, BError> {
+    for (cycle, (expected, line)) in trace.records.iter().zip(&trace.lines).enumerate() {
+        let actual = match record_cycle(vm) {
+            Ok(actual) => Some(actual),
+            Err(BError::ExecutionHaltedError { .. }) => None,
+            Err(e) => return Err(e),
+        };
+        if let Some(divergence) = check(cycle, *line, expected, actual) {
+            return Ok(Some(divergence));
+        }
+    }
+    Ok(None)
+}
+
+/// Compare a cycle of the VM against the trace. `actual` is `None` if the VM halted
+pub fn check(
+    cycle: usize,
+    line: usize,
+    expected: &CycleRecord,
+    actual: Option<CycleRecord>,
+) -> Option<Divergence> {
+    let matches = actual.as_ref().is_some_and(|actual| {
+        actual.pc == expected.pc
+            && actual.writes.len() == expected.writes.len()
+            && actual
+                .writes
+                .iter()
+                .all(|write| expected.writes.contains(write))
+    });
+    (!matches).then(|| Divergence {
+        cycle,
+        line,
+        expected: expected.clone(),
+        actual,
+    })
+}
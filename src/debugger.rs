@@ -1,21 +1,57 @@
 use std::{
-    collections::HashSet,
-    sync::{Arc, Mutex},
+    borrow::Cow,
+    collections::{HashSet, VecDeque},
+    ops::RangeInclusive,
 };
 
 use crate::{
     BError,
-    mmio::uart::{Uart, connect_uart},
-    vm::{self, Register, Vm, VmDescription},
+    assembler::debug_info::DebugInfo,
+    mmio::uart::{UartIo, connect_uart},
+    util::number_literal_to_u16,
+    vm::{
+        self, CostReport, CostTable, Coverage, CpuState, Fault, FaultKind, HaltCause, Instruction,
+        Register, StateDump, Vm, VmDescription,
+        inject::{Effect, Injection, Target},
+    },
 };
 
+#[cfg(feature = "remote")]
+pub mod remote;
+
 /// Represents a debugger with breakpoints and uart
 pub struct Debugger {
     vm: Vm,
     breakpoints: HashSet<u16>,
     halted: bool,
-    uart: Option<Arc<Mutex<Uart>>>,
+    uart: Option<UartIo>,
     current_uart_out: String,
+    source: Option<String>,
+    uart_feed: Option<UartFeed>,
+    /// Addresses of conditional jumps that stop [`Debugger::run()`] when taken
+    jump_break: Option<RangeInclusive<u16>>,
+    /// Address and target of the jump that stopped the last run
+    taken_jump: Option<(u16, u16)>,
+    symbols: Option<DebugInfo>,
+}
+
+/// Whether `inst` jumps on some results of the ALU but not on all of them
+fn is_conditional_jump(inst: u16) -> bool {
+    match Instruction::from_u16(inst) {
+        Instruction::Alu(alu) => {
+            let conditions = [alu.get_lt(), alu.get_eq(), alu.get_gt()];
+            conditions.contains(&true) && conditions.contains(&false)
+        }
+        Instruction::Data(_) => false,
+    }
+}
+
+/// Input waiting to be sent to the UART by [`Debugger::feed_uart()`]
+struct UartFeed {
+    data: VecDeque<u8>,
+    bytes: usize,
+    cycles: u64,
+    next: u64,
 }
 
 impl Debugger {
@@ -25,12 +61,8 @@ impl Debugger {
         breakpoints: Vec<u16>,
         use_uart: bool,
     ) -> Result<Self, BError> {
-        let (vm, uart) = if use_uart {
-            let (v, u) = connect_uart(description)?;
-            (v, Some(u))
-        } else {
-            (Vm::new(description)?, None)
-        };
+        let source = description.source.clone();
+        let (vm, uart) = Self::build_vm(description, use_uart)?;
 
         Ok(Self {
             vm,
@@ -38,32 +70,99 @@ impl Debugger {
             halted: false,
             uart,
             current_uart_out: String::new(),
+            source,
+            uart_feed: None,
+            jump_break: None,
+            taken_jump: None,
+            symbols: None,
         })
     }
 
+    /// Create a debugger for a VM that is already running, e.g. to save a coredump of it. `source` is the .basm
+    /// source of the program
+    pub fn from_vm(vm: Vm, source: Option<String>) -> Self {
+        Self {
+            vm,
+            breakpoints: HashSet::new(),
+            halted: false,
+            uart: None,
+            current_uart_out: String::new(),
+            source,
+            uart_feed: None,
+            jump_break: None,
+            taken_jump: None,
+            symbols: None,
+        }
+    }
+
+    fn build_vm(
+        description: VmDescription,
+        use_uart: bool,
+    ) -> Result<(Vm, Option<UartIo>), BError> {
+        if use_uart {
+            let (vm, uart) = connect_uart(description)?;
+            Ok((vm, Some(uart)))
+        } else {
+            Ok((Vm::new(description)?, None))
+        }
+    }
+
     fn cycle(&mut self) -> Result<(), BError> {
-        self.vm.cycle()?;
-        if let Some(uart) = &mut self.uart {
-            let mut curt = uart.lock().unwrap();
-            // read uart vm -> dbg
-            if let Some(v) = curt.get_output() {
-                self.current_uart_out.push(v as char);
+        if let (Some(feed), Some(uart)) = (&mut self.uart_feed, &self.uart) {
+            let cycles = self.vm.get_cycles();
+            if cycles >= feed.next {
+                let len = feed.bytes.min(feed.data.len());
+                for byte in feed.data.drain(..len) {
+                    let _ = uart.input.send(byte);
+                }
+                feed.next = cycles + feed.cycles;
             }
-            // synchronize the uart object with the vm
-            self.vm.set_ram(crate::mmio::uart::U_IN, curt.get_input());
-            self.vm
-                .set_ram(crate::mmio::uart::U_IFL, curt.get_in_flags());
+            if feed.data.is_empty() {
+                self.uart_feed = None;
+            }
+        }
+        self.vm.cycle()?;
+        if let Some(uart) = &self.uart {
+            self.current_uart_out
+                .extend(uart.output.try_iter().map(|v| v as char));
         }
         Ok(())
     }
 
     /// Write a byte to the UART
     pub fn write_uart_byte(&mut self, byte: u8) {
-        if let Some(uart) = &mut self.uart {
-            uart.lock().unwrap().put_input(byte);
+        if let Some(uart) = &self.uart {
+            let _ = uart.input.send(byte);
+        }
+    }
+
+    /// Send `data` to the UART. With a `rate` of `(bytes, cycles)` the data is sent `bytes` bytes at a time, one chunk
+    /// every `cycles` cycles starting with the next cycle, so the input FIFO doesn't overflow. Without a rate everything
+    /// is sent at once. Replaces data that is still waiting from an earlier call
+    pub fn feed_uart(&mut self, data: &[u8], rate: Option<(usize, u64)>) {
+        self.uart_feed = None;
+        match rate {
+            Some((bytes, cycles)) => {
+                self.uart_feed = Some(UartFeed {
+                    data: data.iter().copied().collect(),
+                    bytes: bytes.max(1),
+                    cycles: cycles.max(1),
+                    next: self.vm.get_cycles(),
+                })
+            }
+            None => {
+                for byte in data {
+                    self.write_uart_byte(*byte);
+                }
+            }
         }
     }
 
+    /// Amount of bytes passed to [`Debugger::feed_uart()`] that are still waiting to be sent
+    pub fn uart_feed_pending(&self) -> usize {
+        self.uart_feed.as_ref().map_or(0, |feed| feed.data.len())
+    }
+
     /// Get the UART output as a string
     pub fn get_uart_out(&mut self) -> String {
         let out = self.current_uart_out.clone();
@@ -71,9 +170,19 @@ impl Debugger {
         out
     }
 
+    /// Get the streams of the UART, if it is initialized
+    pub fn uart(&self) -> Option<&UartIo> {
+        self.uart.as_ref()
+    }
+
+    /// Get the .basm source embedded in the program, if there is any
+    pub fn get_source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
     /// Get ROM
     pub fn get_rom(&self) -> &[u16] {
-        self.vm.get_rom_region(0x00, 0xffff)
+        self.vm.rom()
     }
 
     /// Step the CPU
@@ -88,11 +197,83 @@ impl Debugger {
         }
     }
 
+    /// Step up to `n` instructions, stopping early after reaching a breakpoint or when the execution halts. Returns how
+    /// many instructions were executed
+    pub fn step_n(&mut self, n: usize) -> usize {
+        for stepped in 0..n {
+            self.step();
+            if self.halted {
+                return stepped;
+            }
+            if self.breakpoints.contains(&self.get_pc()) {
+                return stepped + 1;
+            }
+        }
+        n
+    }
+
+    /// Whether the execution halted
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Wraps [`Vm::halt_cause()`]
+    pub fn halt_cause(&self) -> Option<HaltCause> {
+        self.vm.halt_cause()
+    }
+
     /// Inspect memory in range `from`:`from + length`
-    pub fn inspect_memory(&self, from: u16, length: u16) -> &[u16] {
+    pub fn inspect_memory(&self, from: u16, length: u16) -> Cow<'_, [u16]> {
         self.vm.get_ram_region(from, length as usize)
     }
 
+    /// Like [`Debugger::inspect_memory()`], but `from` can also be a symbol. See [`Debugger::resolve()`]
+    pub fn inspect_symbol(&self, from: &str, length: u16) -> Option<(u16, Cow<'_, [u16]>)> {
+        let address = self.resolve(from)?;
+        Some((address, self.inspect_memory(address, length)))
+    }
+
+    /// Use the labels and defines of `symbols` for addresses, e.g. from [`crate::assembler::run_with_debug_info()`]
+    pub fn load_symbols(&mut self, symbols: DebugInfo) {
+        self.symbols = Some(symbols);
+    }
+
+    /// Get the symbols loaded by [`Debugger::load_symbols()`]
+    pub fn symbols(&self) -> Option<&DebugInfo> {
+        self.symbols.as_ref()
+    }
+
+    /// The value of a number literal or, once symbols are loaded, of `name`, `name+OFFSET` or `name-OFFSET`. See
+    /// [`DebugInfo::resolve()`]
+    pub fn resolve(&self, address: &str) -> Option<u16> {
+        number_literal_to_u16(address)
+            .ok()
+            .or_else(|| self.symbols.as_ref()?.resolve(address))
+    }
+
+    /// The const label that `address` lies in and the offset into it. A label reaches up to the next one or the end of
+    /// the ROM mapping it is in
+    pub fn const_label_at(&self, address: u16) -> Option<(&str, u16)> {
+        let consts = &self.symbols.as_ref()?.consts;
+        let (name, start) = consts
+            .iter()
+            .filter(|(_, start)| **start <= address)
+            .max_by_key(|(_, start)| **start)?;
+        let next = consts.values().filter(|other| *other > start).min();
+        let end = match next {
+            Some(next) => *next as usize,
+            None => {
+                self.vm
+                    .rom_mappings()
+                    .iter()
+                    .map(|(_, length, ram)| *ram as usize..*ram as usize + *length as usize)
+                    .find(|mapping| mapping.contains(&(*start as usize)))?
+                    .end
+            }
+        };
+        ((address as usize) < end).then(|| (name.as_str(), address - start))
+    }
+
     /// Set memory in range `from`:`from + values.len()` to `values`
     pub fn set_memory(&mut self, from: u16, values: Vec<u16>) -> Result<(), BError> {
         if (from as usize) + values.len() > vm::RAM_LEN {
@@ -104,6 +285,22 @@ impl Debugger {
         Ok(())
     }
 
+    /// Patch ROM starting at `from` with `values`. See [`Vm::set_rom()`]
+    pub fn set_rom(&mut self, from: u16, values: Vec<u16>) -> Result<(), BError> {
+        if (from as usize) + values.len() > vm::RAM_LEN {
+            return Err(BError::OutOfBoundsError(from, values.len(), vm::RAM_LEN));
+        }
+        for (idx, val) in values.iter().enumerate() {
+            self.vm.set_rom(from + idx as u16, *val);
+        }
+        Ok(())
+    }
+
+    /// Get the last memory access that violated the memory protections. See [`Vm::get_fault()`]
+    pub fn get_fault(&self) -> Option<Fault> {
+        self.vm.get_fault()
+    }
+
     /// Inspect a register
     pub fn inspect_reg(&self, register: Register) -> u16 {
         self.vm.get_reg(register)
@@ -119,11 +316,71 @@ impl Debugger {
         self.vm.set_reg(register, value);
     }
 
+    /// Get the registers and the program counter at once
+    pub fn cpu_state(&self) -> CpuState {
+        self.vm.cpu_state()
+    }
+
+    /// Summarize the state of the VM. See [`Vm::dump_state()`]
+    pub fn dump_state(&self) -> StateDump {
+        self.vm.dump_state()
+    }
+
+    /// Describe what lives at a RAM address. See [`Vm::describe_address()`]
+    pub fn describe_address(&self, address: u16) -> String {
+        self.vm.describe_address(address)
+    }
+
+    /// Start recording which instructions are executed. See [`Vm::enable_coverage()`]
+    pub fn enable_coverage(&mut self) {
+        self.vm.enable_coverage();
+    }
+
+    /// Get the instructions executed since coverage was enabled. See [`Vm::coverage()`]
+    pub fn coverage(&self) -> Option<Coverage> {
+        self.vm.coverage()
+    }
+
+    /// Start adding up the cost of the executed instructions. See [`Vm::enable_cost()`]
+    pub fn enable_cost(&mut self, table: CostTable) {
+        self.vm.enable_cost(table);
+    }
+
+    /// Get the cost of the instructions executed since it was enabled. See [`Vm::cost()`]
+    pub fn cost(&self) -> Option<CostReport> {
+        self.vm.cost()
+    }
+
+    /// Apply a fault right away. See [`Vm::inject()`]
+    pub fn inject(&mut self, target: Target, effect: Effect) {
+        self.vm.inject(target, effect);
+    }
+
+    /// Apply a fault at a cycle. See [`Vm::schedule_injection()`]
+    pub fn schedule_injection(&mut self, injection: Injection) {
+        self.vm.schedule_injection(injection);
+    }
+
+    /// Get the injections that haven't happened yet
+    pub fn pending_injections(&self) -> &[Injection] {
+        self.vm.pending_injections()
+    }
+
+    /// Set the registers and the program counter at once
+    pub fn set_cpu_state(&mut self, state: CpuState) {
+        self.vm.set_cpu_state(state);
+    }
+
     /// Set the program counter
     pub fn set_pc(&mut self, new_value: u16) {
         self.vm.set_pc(new_value);
     }
 
+    /// Get the amount of cycles executed
+    pub fn get_cycles(&self) -> u64 {
+        self.vm.get_cycles()
+    }
+
     /// Get the program counter
     pub fn get_pc(&self) -> u16 {
         self.vm.get_pc()
@@ -134,7 +391,18 @@ impl Debugger {
         if self.halted {
             return;
         }
+        self.taken_jump = None;
         for _ in 0..max_iter {
+            let pc = self.get_pc();
+            let jump = self
+                .jump_break
+                .as_ref()
+                .is_some_and(|range| range.contains(&pc))
+                && self
+                    .vm
+                    .current_instruction()
+                    .is_some_and(is_conditional_jump);
+            let branches = self.vm.perf_counters().branches;
             match self.cycle() {
                 Ok(_) => {}
                 Err(BError::ExecutionHaltedError { value: _ }) => {
@@ -143,25 +411,57 @@ impl Debugger {
                 }
                 Err(e) => panic!("{}", e),
             }
+            if jump && self.vm.perf_counters().branches != branches {
+                self.taken_jump = Some((pc, self.get_pc()));
+                return;
+            }
             if self.breakpoints.contains(&self.get_pc()) {
                 return;
             }
         }
     }
 
+    /// Also stop [`Debugger::run()`] after a conditional jump whose address is in `range` is taken, e.g. `0..=0xffff`
+    /// for all of them. `None` stops doing so. Unconditional jumps never stop it
+    pub fn break_on_jumps(&mut self, range: Option<RangeInclusive<u16>>) {
+        self.jump_break = range;
+    }
+
+    /// Address and target of the conditional jump that stopped the last [`Debugger::run()`], see
+    /// [`Debugger::break_on_jumps()`]
+    pub fn taken_jump(&self) -> Option<(u16, u16)> {
+        self.taken_jump
+    }
+
     /// Register a breakpoint at ROM address `breakpoint`
     pub fn register_breakpoint(&mut self, breakpoint: u16) {
         self.breakpoints.insert(breakpoint);
     }
 
+    /// Whether a breakpoint is registered at ROM address `address`
+    pub fn has_breakpoint(&self, address: u16) -> bool {
+        self.breakpoints.contains(&address)
+    }
+
     /// Remove a breakpoint at ROM address `breakpoint`
     pub fn remove_breakpoint(&mut self, breakpoint: u16) -> bool {
         self.breakpoints.remove(&breakpoint)
     }
 
     /// Serialize the current state of the debugger. Does not save Memory access levels, ROM mappings, or callbacks.
-    /// Also does not serialize the halted state
+    /// Also does not serialize the halted state. The last fault and the state of the devices, e.g. the UART FIFOs, are
+    /// saved.
     pub fn serialize(&self) -> Result<Vec<u8>, BError> {
+        self.serialize_with(false)
+    }
+
+    /// Like [`Debugger::serialize()`], but compresses ROM and RAM. See [`VmDescription::serialize_compressed()`]
+    #[cfg(feature = "compression")]
+    pub fn serialize_compressed(&self) -> Result<Vec<u8>, BError> {
+        self.serialize_with(true)
+    }
+
+    fn serialize_with(&self, compress: bool) -> Result<Vec<u8>, BError> {
         let mut output = Vec::new();
 
         // Magic
@@ -171,7 +471,7 @@ impl Debugger {
         let no_bps = self.breakpoints.len();
         if no_bps > 0xffff {
             return Err(BError::SerializationError(
-                "Number of breakpoints to large".to_string(),
+                "Number of breakpoints to large".into(),
             ));
         }
         output.append(&mut (no_bps as u16).to_be_bytes().to_vec());
@@ -183,27 +483,73 @@ impl Debugger {
         }
         output.push(0x00);
 
-        let mut vm_ser = self.vm.to_vm_desc().serialize()?;
+        // Fault
+        output.append(&mut b"FLT\x00".to_vec());
+        match self.vm.get_fault() {
+            Some(fault) => {
+                output.push(0x01);
+                output.append(&mut fault.pc.to_be_bytes().to_vec());
+                output.append(&mut fault.address.to_be_bytes().to_vec());
+                output.append(&mut fault.value.to_be_bytes().to_vec());
+                output.push(fault.kind as u8);
+            }
+            None => output.push(0x00),
+        }
+        output.push(0x00);
+
+        // Devices
+        let devices = self.vm.save_devices();
+        if devices.len() > 0xffff {
+            return Err(BError::SerializationError(
+                "Number of devices to large".into(),
+            ));
+        }
+        output.append(&mut b"DVS\x00".to_vec());
+        output.append(&mut (devices.len() as u16).to_be_bytes().to_vec());
+        output.push(0x00);
+        for mut device in devices {
+            output.append(&mut (device.len() as u32).to_be_bytes().to_vec());
+            output.append(&mut device);
+        }
+        output.push(0x00);
+
+        let mut vm_desc = self.vm.to_vm_desc();
+        vm_desc.source = self.source.clone();
+        #[cfg(feature = "compression")]
+        let mut vm_ser = if compress {
+            vm_desc.serialize_compressed()?
+        } else {
+            vm_desc.serialize()?
+        };
+        #[cfg(not(feature = "compression"))]
+        let mut vm_ser = {
+            let _ = compress;
+            vm_desc.serialize()?
+        };
         output.append(&mut vm_ser);
 
         Ok(output)
     }
 
-    /// Create a debugger from a .bdb file
-    /// The UART is not yet implemented in the .bdb spec, so we do not deserialize it here
-    pub fn deserialize(input: &[u8]) -> Result<Self, BError> {
+    /// Create a debugger from a .bdb file. `use_uart` specifies whether UART is initialized, it has to match the
+    /// debugger that wrote the file if the file holds the state of the devices
+    /// ## Errors
+    /// [`BError::DeserializationError`] if the file is malformed or its devices differ from the devices of the VM
+    pub fn deserialize(input: &[u8], use_uart: bool) -> Result<Self, BError> {
         use crate::util::{check_slice, extract_number};
         let current = input;
 
         // Check Magic
         if check_slice(current, 4)? != b"BDB\x00" {
-            return Err(BError::DeserializationError(
-                "Invalid file format".to_string(),
-            ));
+            return Err(BError::DeserializationError("Invalid file format".into()));
         }
         let current = &current[4..];
 
         // Breakpoints
+        if check_slice(current, 4)? != b"BPS\x00" {
+            return Err(BError::DeserializationError("No breakpoints".into()));
+        }
+        let current = &current[4..];
         let bp_nums = check_slice(current, 3)?;
         let bp_amount = extract_number(bp_nums)? as usize;
         let current = &current[3..];
@@ -212,7 +558,7 @@ impl Debugger {
         let bp_region = check_slice(current, bp_len)?;
         if bp_region[bp_len - 1] != 0x00 {
             return Err(BError::DeserializationError(
-                "Invalid region separators".to_string(),
+                "Invalid region separators".into(),
             ));
         }
         let mut breakpoints = HashSet::new();
@@ -222,14 +568,242 @@ impl Debugger {
         }
         let current = &current[bp_len..];
 
-        let vm = Vm::new(VmDescription::deserialize(current)?)?;
+        // Fault (optional, older files go straight to the VM)
+        let (fault, current) = if check_slice(current, 4)? == b"FLT\x00" {
+            let current = &current[4..];
+            let fault_len = if check_slice(current, 1)?[0] == 0x01 {
+                8
+            } else {
+                1
+            };
+            let fault_region = check_slice(current, fault_len + 1)?;
+            if fault_region[fault_len] != 0x00 {
+                return Err(BError::DeserializationError(
+                    "Invalid region separators".into(),
+                ));
+            }
+            let fault = match fault_region[0] {
+                0x00 => None,
+                0x01 => Some(Fault {
+                    pc: u16::from_be_bytes([fault_region[1], fault_region[2]]),
+                    address: u16::from_be_bytes([fault_region[3], fault_region[4]]),
+                    value: u16::from_be_bytes([fault_region[5], fault_region[6]]),
+                    kind: FaultKind::from_u8(fault_region[7])
+                        .ok_or(BError::DeserializationError("Invalid fault kind".into()))?,
+                }),
+                _ => {
+                    return Err(BError::DeserializationError("Invalid fault marker".into()));
+                }
+            };
+            (fault, &current[fault_len + 1..])
+        } else {
+            (None, current)
+        };
+
+        // Devices (optional, older files go straight to the VM)
+        let (devices, current) = if check_slice(current, 4)? == b"DVS\x00" {
+            let current = &current[4..];
+            let dev_nums = check_slice(current, 3)?;
+            let dev_amount = extract_number(dev_nums)? as usize;
+            let mut current = &current[3..];
+            let mut devices = Vec::with_capacity(dev_amount);
+            for _ in 0..dev_amount {
+                let len = check_slice(current, 4)?;
+                let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+                devices.push(check_slice(&current[4..], len)?.to_vec());
+                current = &current[4 + len..];
+            }
+            if check_slice(current, 1)? != [0x00] {
+                return Err(BError::DeserializationError(
+                    "Invalid region separators".into(),
+                ));
+            }
+            (Some(devices), &current[1..])
+        } else {
+            (None, current)
+        };
+
+        let description = VmDescription::deserialize(current)?;
+        let source = description.source.clone();
+        let (mut vm, uart) = Self::build_vm(description, use_uart)?;
+        if let Some(devices) = devices {
+            vm.load_devices(&devices)?;
+        }
+        vm.set_fault(fault);
 
         Ok(Self {
             vm,
             breakpoints,
             halted: false,
-            uart: None,
+            uart,
             current_uart_out: String::new(),
+            source,
+            uart_feed: None,
+            jump_break: None,
+            taken_jump: None,
+            symbols: None,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::ForbiddenWritePolicy;
+
+    #[test]
+    fn test_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Debugger>();
+    }
+
+    #[test]
+    fn test_fault_coredump() {
+        let code = r"[text]
+A = 0x10
+*A = add, 0, A
+[consts 0x100]";
+        let mut vm_desc = crate::assembler::run(code).unwrap();
+        vm_desc.rom_blocks.push((0x10, 1));
+        vm_desc.forbidden_write_policy = ForbiddenWritePolicy::Ignore;
+
+        let mut debugger = Debugger::new(vm_desc, vec![1], false).unwrap();
+        debugger.step();
+        debugger.step();
+        let fault = debugger.get_fault().unwrap();
+        assert_eq!((fault.pc, fault.address), (1, 0x10));
+
+        let restored = Debugger::deserialize(&debugger.serialize().unwrap(), false).unwrap();
+        assert_eq!(restored.get_fault(), Some(fault));
+        assert!(restored.breakpoints.contains(&1));
+    }
+
+    #[test]
+    fn test_device_coredump() {
+        use crate::mmio::uart::U_IN;
+        // read a byte from the UART
+        let code = r"[text]
+A = 2
+D = add, 0, A
+A = 0x6004
+*A = add, 0, D
+[consts 0x100]";
+        let mut debugger =
+            Debugger::new(crate::assembler::run(code).unwrap(), vec![], true).unwrap();
+        debugger.write_uart_byte(b'x');
+        debugger.step();
+
+        // the byte is still in the input FIFO of the restored UART
+        let coredump = debugger.serialize().unwrap();
+        let mut restored = Debugger::deserialize(&coredump, true).unwrap();
+        for _ in 0..3 {
+            restored.step();
+        }
+        assert_eq!(*restored.inspect_memory(U_IN, 1), [b'x' as u16]);
+
+        // the devices have to match
+        assert!(matches!(
+            Debugger::deserialize(&coredump, false),
+            Err(BError::DeserializationError(_))
+        ));
+    }
+
+    #[test]
+    fn test_uart_feed() {
+        let code = r"[text]
+label loop:
+A = loop
+add, 0, A; JMP
+[consts 0x100]";
+        let mut debugger =
+            Debugger::new(crate::assembler::run(code).unwrap(), vec![], true).unwrap();
+        // one byte every 10 cycles
+        debugger.feed_uart(b"abc", Some((1, 10)));
+        debugger.step();
+        assert_eq!(debugger.uart_feed_pending(), 2);
+        debugger.run(9);
+        assert_eq!(debugger.uart_feed_pending(), 2);
+        debugger.step();
+        assert_eq!(debugger.uart_feed_pending(), 1);
+        debugger.run(20);
+        assert_eq!(debugger.uart_feed_pending(), 0);
+    }
+
+    #[test]
+    fn test_step_n() {
+        let code = r"[text]
+A = 1
+A = 2
+A = 3
+A = 4
+[consts 5]";
+        let mut debugger =
+            Debugger::new(crate::assembler::run(code).unwrap(), vec![3], false).unwrap();
+        assert_eq!(debugger.step_n(10), 3);
+        assert_eq!(debugger.step_n(0), 0);
+        assert_eq!(debugger.step_n(2), 2);
+        let rest = debugger.step_n(1000);
+        assert!(rest < 1000 && debugger.is_halted());
+        assert_eq!(debugger.step_n(1), 0);
+    }
+
+    #[test]
+    fn test_symbols() {
+        let code = r"[text]
+A = 1
+[consts 0x200]
+label first:
+M = 5
+M = 6
+label second:
+M = 7";
+        let (vm_desc, info) = crate::assembler::run_with_debug_info(code).unwrap();
+        let mut debugger = Debugger::new(vm_desc, vec![], false).unwrap();
+        assert_eq!(debugger.inspect_symbol("first", 1), None);
+        debugger.load_symbols(info);
+        assert_eq!(
+            debugger.inspect_symbol("first+1", 2),
+            Some((0x201, Cow::Borrowed(&[6, 7][..])))
+        );
+        assert_eq!(debugger.resolve("0x10"), Some(0x10));
+        assert_eq!(debugger.const_label_at(0x1ff), None);
+        assert_eq!(debugger.const_label_at(0x201), Some(("first", 1)));
+        assert_eq!(debugger.const_label_at(0x202), Some(("second", 0)));
+        assert_eq!(debugger.const_label_at(0x203), None);
+    }
+
+    #[test]
+    fn test_break_on_jumps() {
+        let code = r"[text]
+A = 3
+D = add, 0, A
+label loop:
+A = loop
+D = dec, D; JGT
+A = 0x7ff0
+JMP
+[consts 0x7ff0]";
+        let mut debugger =
+            Debugger::new(crate::assembler::run(code).unwrap(), vec![], false).unwrap();
+        debugger.break_on_jumps(Some(0..=0xffff));
+        debugger.run(100);
+        assert_eq!(
+            (debugger.taken_jump(), debugger.get_cycles()),
+            (Some((3, 2)), 4)
+        );
+        debugger.run(100);
+        assert_eq!(debugger.taken_jump(), Some((3, 2)));
+        // the last iteration falls through and the unconditional jump doesn't stop the run
+        debugger.run(100);
+        assert_eq!((debugger.taken_jump(), debugger.get_pc()), (None, 0x7ff0));
+
+        assert!(debugger.is_halted());
+
+        let mut debugger =
+            Debugger::new(crate::assembler::run(code).unwrap(), vec![], false).unwrap();
+        debugger.break_on_jumps(Some(4..=6));
+        debugger.run(100);
+        assert_eq!(debugger.taken_jump(), None);
+        assert!(debugger.is_halted());
+    }
+}
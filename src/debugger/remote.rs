@@ -0,0 +1,247 @@
+//! Serves a [`Debugger`] over TCP, so frontends in any language can control a headless BRIC. Every line sent by the
+//! client is a JSON [`Request`] and is answered with one line holding a JSON [`Response`]. See
+//! `docs/remote.md` for the protocol.
+//! ```text
+//! > {"cmd": "set_breakpoint", "address": 3}
+//! < {"result":"ok"}
+//! > {"cmd": "run", "max_cycles": 1000}
+//! < {"result":"state","pc":3,"a":256,"d":5,"e":0,"f":0,"g":0,"h":0,"cycles":3,"halted":false}
+//! > {"cmd": "read_memory", "from": 256, "length": 2}
+//! < {"result":"words","words":[0,0]}
+//! ```
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::Debugger;
+use crate::{BError, vm::Register};
+
+/// A command sent by the client, tagged by `cmd`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Request {
+    /// Execute one instruction. Answered with the state
+    Step,
+    /// Run until a breakpoint, the program halts or `max_cycles` cycles are executed. Answered with the state
+    Run { max_cycles: usize },
+    /// Get the registers, program counter, cycle count and whether the program halted
+    State,
+    /// Set a register, `A` or `D` to `H`
+    SetRegister { register: String, value: u16 },
+    /// Set the program counter
+    SetPc { value: u16 },
+    /// Get `length` words of RAM starting at `from`
+    ReadMemory { from: u16, length: u16 },
+    /// Write `values` to RAM starting at `from`
+    WriteMemory { from: u16, values: Vec<u16> },
+    /// Get `length` words of ROM starting at `from`
+    ReadRom { from: u16, length: u16 },
+    /// Patch ROM starting at `from` with `values`
+    WriteRom { from: u16, values: Vec<u16> },
+    /// Set a breakpoint at ROM address `address`
+    SetBreakpoint { address: u16 },
+    /// Remove the breakpoint at ROM address `address`. Answered with whether it existed
+    RemoveBreakpoint { address: u16 },
+    /// Send text to the UART
+    UartWrite { data: String },
+    /// Get the output of the UART since the last read
+    UartRead,
+}
+
+/// The answer to a [`Request`], tagged by `result`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Response {
+    Ok,
+    State {
+        pc: u16,
+        a: u16,
+        d: u16,
+        e: u16,
+        f: u16,
+        g: u16,
+        h: u16,
+        cycles: u64,
+        halted: bool,
+    },
+    Words {
+        words: Vec<u16>,
+    },
+    Removed {
+        removed: bool,
+    },
+    Uart {
+        data: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl Response {
+    fn state(debugger: &Debugger) -> Self {
+        let cpu = debugger.cpu_state();
+        Self::State {
+            pc: cpu.pc,
+            a: cpu.a,
+            d: cpu.d,
+            e: cpu.e,
+            f: cpu.f,
+            g: cpu.g,
+            h: cpu.h,
+            cycles: debugger.get_cycles(),
+            halted: debugger.is_halted(),
+        }
+    }
+
+    fn error(message: impl ToString) -> Self {
+        Self::Error {
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Execute a request on the debugger
+pub fn handle(debugger: &mut Debugger, request: Request) -> Response {
+    let done = |result: Result<(), BError>| match result {
+        Ok(()) => Response::Ok,
+        Err(e) => Response::error(e),
+    };
+    match request {
+        Request::Step => {
+            debugger.step();
+            Response::state(debugger)
+        }
+        Request::Run { max_cycles } => {
+            debugger.run(max_cycles);
+            Response::state(debugger)
+        }
+        Request::State => Response::state(debugger),
+        Request::SetRegister { register, value } => match Register::from_str(&register) {
+            Some(register) if register != Register::MA => {
+                debugger.set_reg(register, value);
+                Response::Ok
+            }
+            _ => Response::error(format!("unknown register {}", register)),
+        },
+        Request::SetPc { value } => {
+            debugger.set_pc(value);
+            Response::Ok
+        }
+        Request::ReadMemory { from, length } => Response::Words {
+            words: debugger.inspect_memory(from, length).to_vec(),
+        },
+        Request::WriteMemory { from, values } => done(debugger.set_memory(from, values)),
+        Request::ReadRom { from, length } => Response::Words {
+            words: debugger.inspect_rom(from, length).to_vec(),
+        },
+        Request::WriteRom { from, values } => done(debugger.set_rom(from, values)),
+        Request::SetBreakpoint { address } => {
+            debugger.register_breakpoint(address);
+            Response::Ok
+        }
+        Request::RemoveBreakpoint { address } => Response::Removed {
+            removed: debugger.remove_breakpoint(address),
+        },
+        Request::UartWrite { data } => {
+            if debugger.uart().is_none() {
+                return Response::error("UART not activated");
+            }
+            debugger.feed_uart(data.as_bytes(), None);
+            Response::Ok
+        }
+        Request::UartRead => Response::Uart {
+            data: debugger.get_uart_out(),
+        },
+    }
+}
+
+/// Parse a line of JSON, execute it and serialize the response. Malformed requests are answered with an error
+pub fn handle_line(debugger: &mut Debugger, line: &str) -> String {
+    let response = match serde_json::from_str(line) {
+        Ok(request) => handle(debugger, request),
+        Err(e) => Response::error(format!("invalid request: {}", e)),
+    };
+    serde_json::to_string(&response).unwrap()
+}
+
+/// Answer the requests sent over `stream` until the client disconnects
+/// ## Errors
+/// [`BError::IoError`] if the connection fails
+pub fn serve(debugger: &mut Debugger, stream: TcpStream) -> Result<(), BError> {
+    let io_error = |e: std::io::Error| BError::IoError(e.to_string());
+    let mut writer = stream.try_clone().map_err(io_error)?;
+    for line in BufReader::new(stream).lines() {
+        let line = line.map_err(io_error)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut response = handle_line(debugger, &line);
+        response.push('\n');
+        writer.write_all(response.as_bytes()).map_err(io_error)?;
+    }
+    Ok(())
+}
+
+/// Listen on `address`, e.g. `127.0.0.1:9100`, and serve the accepted connections one after another. Only returns if
+/// the address can't be listened on
+/// ## Errors
+/// [`BError::IoError`] if the address can't be listened on
+pub fn listen(debugger: &mut Debugger, address: &str) -> Result<(), BError> {
+    let listener = TcpListener::bind(address).map_err(|e| BError::IoError(e.to_string()))?;
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else { continue };
+        // a failed connection only ends that session
+        let _ = serve(debugger, stream);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_protocol() {
+        let code = r"[text]
+A = 5
+D = add, 0, A
+A = 0x100
+*A = add, 0, D
+[consts 0x200]";
+        let vm_desc = crate::assembler::run(code).unwrap();
+        let mut debugger = Debugger::new(vm_desc, vec![], true).unwrap();
+        let mut send = |line: &str| handle_line(&mut debugger, line);
+
+        assert_eq!(
+            send(r#"{"cmd": "set_breakpoint", "address": 3}"#),
+            r#"{"result":"ok"}"#
+        );
+        assert_eq!(
+            send(r#"{"cmd": "run", "max_cycles": 100}"#),
+            r#"{"result":"state","pc":3,"a":256,"d":5,"e":0,"f":0,"g":0,"h":0,"cycles":3,"halted":false}"#
+        );
+        assert_eq!(
+            send(r#"{"cmd": "set_register", "register": "E", "value": 7}"#),
+            r#"{"result":"ok"}"#
+        );
+        send(r#"{"cmd": "step"}"#);
+        assert_eq!(
+            send(r#"{"cmd": "read_memory", "from": 256, "length": 2}"#),
+            r#"{"result":"words","words":[5,0]}"#
+        );
+        assert_eq!(
+            send(r#"{"cmd": "remove_breakpoint", "address": 3}"#),
+            r#"{"result":"removed","removed":true}"#
+        );
+        assert!(send(r#"{"cmd": "state"}"#).contains(r#""e":7"#));
+        assert!(
+            send(r#"{"cmd": "jump"}"#)
+                .starts_with(r#"{"result":"error","message":"invalid request"#)
+        );
+        assert!(send(r#"{"cmd": "set_register", "register": "*A", "value": 1}"#).contains("error"));
+    }
+}
@@ -1,8 +1,11 @@
 use crate::{
     BError,
-    vm::{AluInstruction, BIT_15, Register},
+    vm::{AluInstruction, BIT_15, HaltCause, Instruction, Register, profile::Profile},
+};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
 };
-use std::fmt::Write;
 
 /// Disassembles an instruction into a string
 pub fn disassemble_inst(instruction: &u16, output: &mut String) -> Result<(), BError> {
@@ -41,9 +44,9 @@ pub fn disassemble_inst(instruction: &u16, output: &mut String) -> Result<(), BE
         // write instruction mnemonic
         let op = op | if u { 0b1000 } else { 0 };
         match op {
-            0 => write!(output, "and, {y}, {x}"),
-            1 => write!(output, "or, {y}, {x}"),
-            2 => write!(output, "xor, {y}, {x}"),
+            0 => write!(output, "and, {x}, {y}"),
+            1 => write!(output, "or, {x}, {y}"),
+            2 => write!(output, "xor, {x}, {y}"),
             3 => write!(output, "not, {x}"),
             4 => write!(output, "lsl, {x}"),
             5 => write!(output, "lsr, {x}"),
@@ -76,11 +79,173 @@ pub fn disassemble_inst(instruction: &u16, output: &mut String) -> Result<(), BE
     Ok(())
 }
 
+impl fmt::Display for Instruction {
+    /// Disassembles the instruction, see [`disassemble_inst()`]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let word = match self {
+            Instruction::Alu(inst) => inst.0,
+            Instruction::Data(value) => value | BIT_15,
+        };
+        let mut out = String::new();
+        disassemble_inst(&word, &mut out).map_err(|_| fmt::Error)?;
+        f.write_str(&out)
+    }
+}
+
+impl fmt::Display for HaltCause {
+    /// Names the jump that left the ROM with its disassembly
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HaltCause::FellOffEnd { pc } => {
+                write!(
+                    f,
+                    "fell off the end of ROM after the instruction at {:#06x}",
+                    pc
+                )
+            }
+            HaltCause::WildJump {
+                pc,
+                instruction,
+                target,
+            } => write!(
+                f,
+                "wild jump to {:#06x} by the instruction at {:#06x}: {}",
+                target,
+                pc,
+                Instruction::from_u16(*instruction)
+            ),
+            HaltCause::Placed { pc } => {
+                write!(f, "the PC was placed outside of ROM at {:#06x}", pc)
+            }
+        }
+    }
+}
+
+/// Describe every bitfield of an instruction word, one per line behind its disassembly. See [ISA](../docs/ISA.md)
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler::text_processor::assemble_at, disassembler::explain};
+///
+/// let text = explain(assemble_at("D = sub, D, A; JEQ", 0).unwrap()[0]);
+/// assert!(text.starts_with("0x391a = 0b0011100100011010: D = sub, D, A; JEQ\n"));
+/// assert!(text.contains("  11     u       1    arithmetic\n"));
+/// assert!(text.contains("  2-0    jump    010  JEQ: jump to A if the result = 0\n"));
+/// assert!(explain(0x8005).contains("14-0   value   000000000000101  A = 5 (0x0005)"));
+/// ```
+pub fn explain(instruction: u16) -> String {
+    let mut text = String::new();
+    let _ = disassemble_inst(&instruction, &mut text);
+    let mut text = format!("{:#06x} = {:#018b}: {}\n", instruction, instruction, text);
+    let mut field = |bits: &str, name: &str, value: String, meaning: &str| {
+        let _ = writeln!(text, "  {:<6} {:<7} {:<3}  {}", bits, name, value, meaning);
+    };
+    let alu = match Instruction::from_u16(instruction) {
+        Instruction::Data(value) => {
+            field("15", "ci", "1".to_string(), "data instruction");
+            field(
+                "14-0",
+                "value",
+                format!("{:015b}", value),
+                &format!("A = {} ({:#06x})", value, value),
+            );
+            return text;
+        }
+        Instruction::Alu(alu) => alu,
+    };
+    field("15", "ci", "0".to_string(), "ALU instruction");
+    let source = alu.get_source();
+    let name = match Register::try_from(source) {
+        Ok(Register::None) | Err(_) => "0".to_string(),
+        Ok(register) => register.to_string(),
+    };
+    field("14-12", "source", format!("{:03b}", source), &name);
+    let u = alu.get_u();
+    let kind = if u { "arithmetic" } else { "logic and shifts" };
+    field("11", "u", format!("{}", u as u8), kind);
+    let operation = match alu.get_op() | if u { 0b1000 } else { 0 } {
+        0 => "and: x & y",
+        1 => "or: x | y",
+        2 => "xor: x ^ y",
+        3 => "not: !x",
+        4 => "lsl: x shifted left by 1",
+        5 => "lsr: x shifted right by 1, filling in 0",
+        6 => "rol: x rotated left by 1",
+        7 => "ror: x rotated right by 1",
+        8 => "add: x + y",
+        9 => "sub: x - y",
+        10 => "inc: x + 1",
+        11 => "dec: x - 1",
+        12 => "asr: x shifted right by 1, keeping the sign",
+        _ => "invalid, the instruction fails",
+    };
+    field("10-8", "op", format!("{:03b}", alu.get_op()), operation);
+    let sw = alu.get_sw();
+    let inputs = if sw {
+        "x is A, y is the source"
+    } else {
+        "x is the source, y is A"
+    };
+    field("7", "sw", format!("{}", sw as u8), inputs);
+    let zx = alu.get_zx();
+    let zero = if zx { "x is 0" } else { "x is kept" };
+    field("6", "zx", format!("{}", zx as u8), zero);
+    let target = alu.get_target();
+    let destination = match Register::try_from(target) {
+        Ok(Register::None) | Err(_) => "the result is dropped".to_string(),
+        Ok(register) => format!("the result goes to {}", register),
+    };
+    field("5-3", "target", format!("{:03b}", target), &destination);
+    let bits = (alu.get_lt() as u8) << 2 | (alu.get_eq() as u8) << 1 | alu.get_gt() as u8;
+    let jump = match bits {
+        0b000 => "no jump",
+        0b001 => "JGT: jump to A if the result > 0",
+        0b010 => "JEQ: jump to A if the result = 0",
+        0b011 => "JGE: jump to A if the result >= 0",
+        0b100 => "JLT: jump to A if the result < 0",
+        0b101 => "JNE: jump to A if the result != 0",
+        0b110 => "JLE: jump to A if the result <= 0",
+        _ => "JMP: always jump to A",
+    };
+    field("2-0", "jump", format!("{:03b}", bits), jump);
+    text
+}
+
 /// Disassembles every word of the ROM (including consts)
 /// if `lines` is `true` the disassembly includes the address of the instructions
 pub fn disassemble(input: &[u16], lines: bool) -> Result<String, BError> {
+    disassemble_with(input, lines, false)
+}
+
+/// Like [`disassemble()`], but loads of the address of a standard device register are preceded by a comment naming
+/// the register, see [`crate::mmio::map`]. The output still assembles
+/// ## Examples
+/// ```rust
+/// use bric_vm::disassembler::disassemble_annotated;
+///
+/// let out = disassemble_annotated(&[0x6001 | 0x8000, 0x0001 | 0x8000], false).unwrap();
+/// assert_eq!(out, "# U_OUT (uart): write one byte into the output FIFO\nA = 24577\nA = 1\n");
+/// ```
+pub fn disassemble_annotated(input: &[u16], lines: bool) -> Result<String, BError> {
+    disassemble_with(input, lines, true)
+}
+
+fn disassemble_with(input: &[u16], lines: bool, annotate: bool) -> Result<String, BError> {
     let mut out = String::new();
     for (idx, instruction) in input.iter().enumerate() {
+        if annotate
+            && let Instruction::Data(value) = Instruction::from_u16(*instruction)
+            && let Some(entry) = crate::mmio::map::lookup(value)
+        {
+            if lines {
+                out.push('\t');
+            }
+            writeln!(
+                &mut out,
+                "# {} ({}): {}",
+                entry.name, entry.device, entry.description
+            )
+            .map_err(|e| BError::IoError(e.to_string()))?;
+        }
         if lines {
             write!(&mut out, "{:#06x}:\t", idx).map_err(|e| BError::IoError(e.to_string()))?;
         }
@@ -89,3 +254,61 @@ pub fn disassemble(input: &[u16], lines: bool) -> Result<String, BError> {
     }
     Ok(out)
 }
+
+/// Disassemble a profiled program with the share of the executed instructions and the execution count in front of
+/// every instruction, like `perf annotate`. Instructions that were never executed are marked with `#####`. The
+/// `labels` of the program, if known, are put in front of the instructions they point to
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler, disassembler::disassemble_profile, vm::Vm};
+///
+/// let code = r"[text]
+/// A = 2
+/// D = add, 0, A
+/// label loop:
+/// A = loop
+/// D = dec, D; JGT
+/// A = 1
+/// [consts 0x200]";
+/// let (vm_desc, info) = assembler::run_with_debug_info(code).unwrap();
+/// let mut vm = Vm::new(vm_desc).unwrap();
+/// vm.enable_profile(&[]);
+/// for _ in 0..6 {
+///     vm.cycle().unwrap();
+/// }
+/// let listing = disassemble_profile(&vm.profile().unwrap(), Some(&info.labels)).unwrap();
+/// let lines: Vec<&str> = listing.lines().collect();
+/// assert_eq!(lines[3], "                  label loop:");
+/// assert_eq!(lines[4], " 33.3%         2  0x0002:  A = 2");
+/// assert_eq!(lines[6], "     -     #####  0x0004:  A = 1");
+/// ```
+pub fn disassemble_profile(
+    profile: &Profile,
+    labels: Option<&BTreeMap<String, u16>>,
+) -> Result<String, BError> {
+    let total = profile.total().max(1) as f64;
+    let mut out = String::new();
+    writeln!(&mut out, "{:>6}  {:>8}  instruction", "share", "count")
+        .map_err(|e| BError::IoError(e.to_string()))?;
+    for (idx, (instruction, count)) in profile.rom.iter().zip(&profile.counts).enumerate() {
+        for (name, _) in labels
+            .into_iter()
+            .flatten()
+            .filter(|(_, address)| **address as usize == idx)
+        {
+            writeln!(&mut out, "{:18}label {}:", "", name)
+                .map_err(|e| BError::IoError(e.to_string()))?;
+        }
+        if *count == 0 {
+            write!(&mut out, "{:>6}  {:>8}", "-", "#####")
+        } else {
+            let share = *count as f64 * 100.0 / total;
+            write!(&mut out, "{:>5.1}%  {:>8}", share, count)
+        }
+        .map_err(|e| BError::IoError(e.to_string()))?;
+        write!(&mut out, "  {:#06x}:  ", idx).map_err(|e| BError::IoError(e.to_string()))?;
+        disassemble_inst(instruction, &mut out)?;
+        out.push('\n');
+    }
+    Ok(out)
+}
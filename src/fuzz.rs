@@ -0,0 +1,206 @@
+//! Structured inputs for fuzzing with `cargo fuzz` and other `arbitrary` based fuzzers, enabled by the `arbitrary`
+//! feature. [`VmDescription`] and [`Instruction`] implement [`Arbitrary`], [`AsmSource`] generates assembler source
+//! that is mostly well formed, so the fuzzer gets past the parser. [`execute()`] runs a description for a bounded
+//! amount of cycles.
+//! ```rust,ignore
+//! // fuzz/fuzz_targets/execute.rs
+//! libfuzzer_sys::fuzz_target!(|vm_desc: bric_vm::vm::VmDescription| {
+//!     let _ = bric_vm::fuzz::execute(vm_desc, 1000);
+//! });
+//! ```
+//! Raw ROM images need no feature, [`crate::vm::execute_sandboxed()`] takes any bytes:
+//! ```rust,ignore
+//! // fuzz/fuzz_targets/rom_image.rs
+//! libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+//!     let _ = bric_vm::vm::execute_sandboxed(data, 1000);
+//! });
+//! ```
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::{
+    BError,
+    vm::{
+        CpuState, ExecutionMode, ForbiddenWritePolicy, Instruction, Vm, VmDescription, empty_ram,
+    },
+};
+
+/// Most lists are kept short, so inputs are spent on the program instead of thousands of regions
+const MAX_ENTRIES: usize = 8;
+
+impl<'a> Arbitrary<'a> for Instruction {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self::from_u16(u.arbitrary()?))
+    }
+}
+
+/// A list of at most [`MAX_ENTRIES`] items
+fn short_list<'a, T: Arbitrary<'a>>(u: &mut Unstructured<'a>) -> Result<Vec<T>> {
+    let len = u.int_in_range(0..=MAX_ENTRIES)?;
+    (0..len).map(|_| u.arbitrary()).collect()
+}
+
+impl<'a> Arbitrary<'a> for VmDescription {
+    /// Generates the program, registers, initial RAM and the memory layout. Callbacks, devices and metadata are left
+    /// out, forbidden writes are either ignored or errors so nothing is printed.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut mem = empty_ram();
+        for _ in 0..u.int_in_range(0..=0x100)? {
+            let (address, value): (u16, u16) = u.arbitrary()?;
+            mem[address as usize] = value;
+        }
+        Ok(Self {
+            pc: u.arbitrary()?,
+            rom: u.arbitrary()?,
+            mem,
+            regs: u.arbitrary()?,
+            rom_mappings: short_list(u)?,
+            rom_blocks: short_list(u)?,
+            mirrors: short_list(u)?,
+            forbidden_write_policy: if u.arbitrary()? {
+                ForbiddenWritePolicy::Error
+            } else {
+                ForbiddenWritePolicy::Ignore
+            },
+            execution_mode: if u.arbitrary()? {
+                ExecutionMode::VonNeumann
+            } else {
+                ExecutionMode::Harvard
+            },
+            rom_window: u.arbitrary()?,
+            rom_writable: u.arbitrary()?,
+            perf_counters: u.arbitrary()?,
+            reset_register: u.arbitrary()?,
+            rng_seed: u.arbitrary()?,
+            ..Default::default()
+        })
+    }
+}
+
+/// Assembler source generated from the grammar of the assembler, with the occasional line of garbage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmSource(pub String);
+
+const NAMES: [&str; 6] = ["start", "loop", "end", "a.b", "x_1", "value"];
+const TARGETS: [&str; 8] = ["", "A", "*A", "D", "E", "F", "G", "H"];
+const OPERANDS: [&str; 8] = ["0", "A", "*A", "D", "E", "F", "G", "H"];
+const OPS: [&str; 13] = [
+    "and", "or", "xor", "add", "sub", "inc", "dec", "not", "lsl", "lsr", "asr", "rol", "ror",
+];
+const JUMPS: [&str; 8] = ["", "JLT", "JEQ", "JGT", "JLE", "JGE", "JMP", "JNE"];
+
+fn number<'a>(u: &mut Unstructured<'a>, max: u16) -> Result<String> {
+    let value = u.int_in_range(0..=max)?;
+    Ok(match u.int_in_range(0..=2)? {
+        0 => format!("{:#x}", value),
+        1 => format!("{:#b}", value),
+        _ => value.to_string(),
+    })
+}
+
+fn text_line<'a>(u: &mut Unstructured<'a>) -> Result<String> {
+    Ok(match u.int_in_range(0..=15)? {
+        0 => format!("label {}:", u.choose(&NAMES)?),
+        1..=3 => {
+            let value = if u.arbitrary()? {
+                u.choose(&NAMES)?.to_string()
+            } else {
+                number(u, 0x7fff)?
+            };
+            format!("A = {}", value)
+        }
+        4 => format!("# {}", u.choose(&NAMES)?),
+        5 => u.arbitrary::<String>()?,
+        _ => {
+            let target = u.choose(&TARGETS)?;
+            let mut line = if target.is_empty() {
+                String::new()
+            } else {
+                format!("{} = ", target)
+            };
+            line.push_str(u.choose(&OPS)?);
+            for _ in 0..u.int_in_range(1..=2)? {
+                line.push_str(", ");
+                line.push_str(u.choose(&OPERANDS)?);
+            }
+            let jump = u.choose(&JUMPS)?;
+            if !jump.is_empty() {
+                line.push_str("; ");
+                line.push_str(jump);
+            }
+            line
+        }
+    })
+}
+
+impl<'a> Arbitrary<'a> for AsmSource {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut lines = Vec::new();
+        if u.arbitrary()? {
+            lines.push("[macros]".to_string());
+            for _ in 0..u.int_in_range(0..=2)? {
+                lines.push(format!(
+                    "define {} {}",
+                    u.choose(&NAMES)?,
+                    number(u, 0xffff)?
+                ));
+            }
+        }
+        lines.push("[text]".to_string());
+        for _ in 0..u.int_in_range(0..=64)? {
+            lines.push(text_line(u)?);
+        }
+        if u.arbitrary()? {
+            lines.push(format!("[consts {}]", number(u, 0xffff)?));
+            for _ in 0..u.int_in_range(0..=4)? {
+                lines.push(format!("label {}:", u.choose(&NAMES)?));
+                lines.push(format!("M = {}", number(u, 0xffff)?));
+            }
+        }
+        Ok(Self(lines.join("\n")))
+    }
+}
+
+/// Build a VM and run it for at most `max_cycles` cycles. Running past the end of ROM ends the run successfully. Any
+/// panic on the way is a bug, which is what fuzzers look for
+/// ## Errors
+/// Errors of [`Vm::new()`] and [`Vm::cycle()`] other than [`BError::ExecutionHaltedError`]
+/// ## Examples
+/// ```rust
+/// use arbitrary::{Arbitrary, Unstructured};
+/// use bric_vm::{assembler, fuzz::{AsmSource, execute}, vm::VmDescription};
+///
+/// let bytes: Vec<u8> = (0..=255).cycle().take(0x1000).collect();
+/// let vm_desc = VmDescription::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+/// let _ = execute(vm_desc, 1000);
+///
+/// let source = AsmSource::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+/// assert!(source.0.contains("[text]"));
+/// if let Ok(vm_desc) = assembler::run(&source.0) {
+///     let _ = execute(vm_desc, 1000);
+/// }
+/// ```
+pub fn execute(vm_desc: VmDescription, max_cycles: u64) -> std::result::Result<CpuState, BError> {
+    let mut vm = Vm::new(vm_desc)?;
+    for _ in 0..max_cycles {
+        match vm.cycle() {
+            Ok(()) => {}
+            Err(BError::ExecutionHaltedError { .. }) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(vm.cpu_state())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler;
+
+    #[test]
+    fn test_found_by_fuzzing() {
+        // missing second operand
+        assert!(assembler::run("[text]\nD = add, A").is_err());
+        assert!(assembler::run("[text]\nD = and, D").is_err());
+        // undefined label
+        assert!(assembler::run("[text]\nA = nowhere").is_err());
+    }
+}
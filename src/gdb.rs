@@ -0,0 +1,439 @@
+//! Remote debugging of the [`Debugger`] with GDB through the [`gdbstub`] crate.
+//!
+//! GDB addresses bytes, so the word addressed ROM and RAM are mapped into one byte addressed space: word `n` of ROM is
+//! at `2 * n` and word `n` of RAM at [`RAM_OFFSET`]` + 2 * n`, low byte first. The program counter is reported as a
+//! byte address too, so breakpoints are set on the ROM addresses shown by `x/i $pc`.
+//! ```text
+//! $ bric dbg -p program.bvm --gdb 127.0.0.1:9000
+//! $ gdb -ex 'target remote 127.0.0.1:9000'
+//! (gdb) break *0x10
+//! (gdb) continue
+//! (gdb) x/4xh 0x20100
+//! ```
+use std::{
+    marker::PhantomData,
+    net::{TcpListener, TcpStream},
+};
+
+use gdbstub::{
+    arch::{Arch, Registers},
+    common::Signal,
+    conn::ConnectionExt,
+    stub::{DisconnectReason, GdbStub, SingleThreadStopReason, run_blocking},
+    target::{
+        Target, TargetError, TargetResult,
+        ext::{
+            base::{
+                BaseOps,
+                singlethread::{
+                    SingleThreadBase, SingleThreadResume, SingleThreadResumeOps,
+                    SingleThreadSingleStep, SingleThreadSingleStepOps,
+                },
+            },
+            breakpoints::{Breakpoints, BreakpointsOps, SwBreakpoint, SwBreakpointOps},
+        },
+    },
+};
+
+use crate::{
+    BError,
+    debugger::Debugger,
+    vm::{self, CpuState},
+};
+
+/// Byte address of the first RAM word
+pub const RAM_OFFSET: u32 = 0x2_0000;
+
+/// Cycles run between checks for a GDB interrupt while continuing
+const POLL_CYCLES: usize = 0x400;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.bric.core">
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+    <reg name="a" bitsize="16" type="uint16"/>
+    <reg name="d" bitsize="16" type="uint16"/>
+    <reg name="e" bitsize="16" type="uint16"/>
+    <reg name="f" bitsize="16" type="uint16"/>
+    <reg name="g" bitsize="16" type="uint16"/>
+    <reg name="h" bitsize="16" type="uint16"/>
+  </feature>
+</target>"#;
+
+/// The BRIC as seen by GDB. See the module documentation for the address mapping
+pub enum BricArch {}
+
+impl Arch for BricArch {
+    type Usize = u32;
+    type Registers = BricRegs;
+    type BreakpointKind = usize;
+    type RegId = ();
+
+    fn target_description_xml() -> Option<&'static str> {
+        Some(TARGET_XML)
+    }
+}
+
+/// The registers in the order of the target description. `pc` is a byte address
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BricRegs {
+    pub pc: u32,
+    pub regs: [u16; 6],
+}
+
+impl From<CpuState> for BricRegs {
+    fn from(state: CpuState) -> Self {
+        Self {
+            pc: state.pc as u32 * 2,
+            regs: [state.a, state.d, state.e, state.f, state.g, state.h],
+        }
+    }
+}
+
+impl From<&BricRegs> for CpuState {
+    fn from(regs: &BricRegs) -> Self {
+        let [a, d, e, f, g, h] = regs.regs;
+        Self {
+            pc: (regs.pc / 2) as u16,
+            a,
+            d,
+            e,
+            f,
+            g,
+            h,
+        }
+    }
+}
+
+impl Registers for BricRegs {
+    type ProgramCounter = u32;
+
+    fn pc(&self) -> u32 {
+        self.pc
+    }
+
+    fn gdb_serialize(&self, mut write_byte: impl FnMut(Option<u8>)) {
+        for byte in self.pc.to_le_bytes() {
+            write_byte(Some(byte));
+        }
+        for byte in self.regs.iter().flat_map(|reg| reg.to_le_bytes()) {
+            write_byte(Some(byte));
+        }
+    }
+
+    fn gdb_deserialize(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        if bytes.len() != 4 + 2 * self.regs.len() {
+            return Err(());
+        }
+        self.pc = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        for (reg, word) in self.regs.iter_mut().zip(bytes[4..].chunks(2)) {
+            *reg = u16::from_le_bytes([word[0], word[1]]);
+        }
+        Ok(())
+    }
+}
+
+/// What to do on the next resume
+#[derive(Clone, Copy, PartialEq)]
+enum ExecMode {
+    Step,
+    Continue,
+}
+
+/// Exposes a [`Debugger`] to GDB
+pub struct GdbTarget<'a> {
+    debugger: &'a mut Debugger,
+    mode: ExecMode,
+}
+
+impl<'a> GdbTarget<'a> {
+    pub fn new(debugger: &'a mut Debugger) -> Self {
+        Self {
+            debugger,
+            mode: ExecMode::Continue,
+        }
+    }
+
+    /// Read the word containing byte address `addr`, `None` past the end of ROM or RAM
+    fn read_word(&self, addr: u32) -> Option<u16> {
+        let word = (addr % RAM_OFFSET / 2) as u16;
+        match addr / RAM_OFFSET {
+            0 => self.debugger.inspect_rom(word, 1).first().copied(),
+            1 => self.debugger.inspect_memory(word, 1).first().copied(),
+            _ => None,
+        }
+    }
+
+    /// Write the word containing byte address `addr`. ROM doesn't grow, writes past its end fail
+    fn write_word(&mut self, addr: u32, value: u16) -> Result<(), BError> {
+        let word = (addr % RAM_OFFSET / 2) as u16;
+        match addr / RAM_OFFSET {
+            0 if self.debugger.inspect_rom(word, 1).is_empty() => Err(BError::OutOfBoundsError(
+                word,
+                1,
+                self.debugger.get_rom().len(),
+            )),
+            0 => self.debugger.set_rom(word, vec![value]),
+            1 => self.debugger.set_memory(word, vec![value]),
+            _ => Err(BError::OutOfBoundsError(word, 1, vm::RAM_LEN)),
+        }
+    }
+
+    /// Execute according to the last resume until the program stops or `interrupted` returns true
+    fn execute(
+        &mut self,
+        mut interrupted: impl FnMut() -> bool,
+    ) -> Option<SingleThreadStopReason<u32>> {
+        if self.mode == ExecMode::Step {
+            self.debugger.step();
+            return Some(self.stop_reason(SingleThreadStopReason::DoneStep));
+        }
+        loop {
+            self.debugger.run(POLL_CYCLES);
+            if self.debugger.is_halted() || self.debugger.has_breakpoint(self.debugger.get_pc()) {
+                return Some(self.stop_reason(SingleThreadStopReason::SwBreak(())));
+            }
+            if interrupted() {
+                return None;
+            }
+        }
+    }
+
+    fn stop_reason(&self, reason: SingleThreadStopReason<u32>) -> SingleThreadStopReason<u32> {
+        if self.debugger.is_halted() {
+            SingleThreadStopReason::Exited(0)
+        } else {
+            reason
+        }
+    }
+}
+
+impl Target for GdbTarget<'_> {
+    type Arch = BricArch;
+    type Error = BError;
+
+    fn base_ops(&mut self) -> BaseOps<'_, BricArch, BError> {
+        BaseOps::SingleThread(self)
+    }
+
+    fn support_breakpoints(&mut self) -> Option<BreakpointsOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadBase for GdbTarget<'_> {
+    fn read_registers(&mut self, regs: &mut BricRegs) -> TargetResult<(), Self> {
+        *regs = self.debugger.cpu_state().into();
+        Ok(())
+    }
+
+    fn write_registers(&mut self, regs: &BricRegs) -> TargetResult<(), Self> {
+        self.debugger.set_cpu_state(regs.into());
+        Ok(())
+    }
+
+    fn read_addrs(&mut self, start_addr: u32, data: &mut [u8]) -> TargetResult<usize, Self> {
+        for (idx, (addr, byte)) in (start_addr..).zip(data.iter_mut()).enumerate() {
+            let Some(word) = self.read_word(addr) else {
+                // a short read, or an error if nothing could be read
+                return if idx == 0 {
+                    Err(TargetError::NonFatal)
+                } else {
+                    Ok(idx)
+                };
+            };
+            *byte = word.to_le_bytes()[addr as usize % 2];
+        }
+        Ok(data.len())
+    }
+
+    fn write_addrs(&mut self, start_addr: u32, data: &[u8]) -> TargetResult<(), Self> {
+        for (addr, byte) in (start_addr..).zip(data) {
+            let mut word = self
+                .read_word(addr)
+                .ok_or(TargetError::NonFatal)?
+                .to_le_bytes();
+            word[addr as usize % 2] = *byte;
+            self.write_word(addr, u16::from_le_bytes(word))
+                .map_err(|_| TargetError::NonFatal)?;
+        }
+        Ok(())
+    }
+
+    fn support_resume(&mut self) -> Option<SingleThreadResumeOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadResume for GdbTarget<'_> {
+    fn resume(&mut self, signal: Option<Signal>) -> Result<(), BError> {
+        if signal.is_some() {
+            return Err(BError::IoError("signals are not supported".to_string()));
+        }
+        self.mode = ExecMode::Continue;
+        Ok(())
+    }
+
+    fn support_single_step(&mut self) -> Option<SingleThreadSingleStepOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadSingleStep for GdbTarget<'_> {
+    fn step(&mut self, signal: Option<Signal>) -> Result<(), BError> {
+        if signal.is_some() {
+            return Err(BError::IoError("signals are not supported".to_string()));
+        }
+        self.mode = ExecMode::Step;
+        Ok(())
+    }
+}
+
+impl Breakpoints for GdbTarget<'_> {
+    fn support_sw_breakpoint(&mut self) -> Option<SwBreakpointOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SwBreakpoint for GdbTarget<'_> {
+    fn add_sw_breakpoint(&mut self, addr: u32, _kind: usize) -> TargetResult<bool, Self> {
+        if addr >= RAM_OFFSET {
+            return Ok(false);
+        }
+        self.debugger.register_breakpoint((addr / 2) as u16);
+        Ok(true)
+    }
+
+    fn remove_sw_breakpoint(&mut self, addr: u32, _kind: usize) -> TargetResult<bool, Self> {
+        if addr >= RAM_OFFSET {
+            return Ok(false);
+        }
+        Ok(self.debugger.remove_breakpoint((addr / 2) as u16))
+    }
+}
+
+struct EventLoop<'a>(PhantomData<&'a mut Debugger>);
+
+impl<'a> run_blocking::BlockingEventLoop for EventLoop<'a> {
+    type Target = GdbTarget<'a>;
+    type Connection = TcpStream;
+    type StopReason = SingleThreadStopReason<u32>;
+
+    #[allow(clippy::type_complexity)]
+    fn wait_for_stop_reason(
+        target: &mut GdbTarget<'a>,
+        conn: &mut TcpStream,
+    ) -> Result<
+        run_blocking::Event<SingleThreadStopReason<u32>>,
+        run_blocking::WaitForStopReasonError<BError, std::io::Error>,
+    > {
+        let interrupted = || conn.peek().map(|byte| byte.is_some()).unwrap_or(true);
+        match target.execute(interrupted) {
+            Some(reason) => Ok(run_blocking::Event::TargetStopped(reason)),
+            None => {
+                let byte = conn
+                    .read()
+                    .map_err(run_blocking::WaitForStopReasonError::Connection)?;
+                Ok(run_blocking::Event::IncomingData(byte))
+            }
+        }
+    }
+
+    fn on_interrupt(
+        _target: &mut GdbTarget<'a>,
+    ) -> Result<Option<SingleThreadStopReason<u32>>, BError> {
+        Ok(Some(SingleThreadStopReason::Signal(Signal::SIGINT)))
+    }
+}
+
+/// Serve a GDB connection until GDB detaches, kills the program or the program halts
+/// ## Errors
+/// [`BError::IoError`] if the connection fails
+pub fn serve(debugger: &mut Debugger, stream: TcpStream) -> Result<DisconnectReason, BError> {
+    let mut target = GdbTarget::new(debugger);
+    GdbStub::new(stream)
+        .run_blocking::<EventLoop>(&mut target)
+        .map_err(|e| BError::IoError(e.to_string()))
+}
+
+/// Listen on `address`, e.g. `127.0.0.1:9000`, and serve the first GDB connection. See [`serve()`]
+/// ## Errors
+/// [`BError::IoError`] if the address can't be listened on or the connection fails
+pub fn listen(debugger: &mut Debugger, address: &str) -> Result<DisconnectReason, BError> {
+    let listener = TcpListener::bind(address).map_err(|e| BError::IoError(e.to_string()))?;
+    let (stream, _) = listener
+        .accept()
+        .map_err(|e| BError::IoError(e.to_string()))?;
+    serve(debugger, stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    /// Send a packet and return the payload of the reply with run-length encoding expanded
+    fn packet(stream: &mut TcpStream, payload: &str) -> String {
+        let checksum = payload
+            .bytes()
+            .fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(stream, "${}#{:02x}", payload, checksum).unwrap();
+        let mut reply = Vec::new();
+        let mut byte = [0];
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            match byte[0] {
+                b'+' if reply.is_empty() => {}
+                b'#' => break,
+                b'$' => reply.clear(),
+                b'*' => {
+                    stream.read_exact(&mut byte).unwrap();
+                    let last = *reply.last().unwrap();
+                    reply.extend(std::iter::repeat_n(last, byte[0] as usize - 29));
+                }
+                other => reply.push(other),
+            }
+        }
+        stream.read_exact(&mut [0; 2]).unwrap();
+        stream.write_all(b"+").unwrap();
+        String::from_utf8(reply).unwrap()
+    }
+
+    #[test]
+    fn test_gdb_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let code = r"[text]
+A = 5
+D = add, 0, A
+A = 0x100
+*A = add, 0, D
+[consts 0x200]";
+            let vm_desc = crate::assembler::run(code).unwrap();
+            let mut debugger = Debugger::new(vm_desc, vec![], false).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            serve(&mut debugger, stream).unwrap()
+        });
+
+        let mut gdb = TcpStream::connect(address).unwrap();
+        // break before the store, at word 3
+        assert_eq!(packet(&mut gdb, "Z0,6,2"), "OK");
+        assert!(packet(&mut gdb, "c").starts_with("T05"));
+        assert_eq!(packet(&mut gdb, "g"), "06000000000105000000000000000000");
+        assert_eq!(packet(&mut gdb, "s"), "S05");
+        assert_eq!(packet(&mut gdb, "m20200,2"), "0500");
+        assert_eq!(packet(&mut gdb, "M20201,1:12"), "OK");
+        assert_eq!(packet(&mut gdb, "m20200,2"), "0512");
+        // past the end of ROM reads are cut short and writes fail
+        assert_eq!(packet(&mut gdb, "m1c,8"), "0000");
+        assert!(packet(&mut gdb, "m1000,2").starts_with('E'));
+        assert!(packet(&mut gdb, "M1000,1:12").starts_with('E'));
+        assert_eq!(packet(&mut gdb, "c"), "W00");
+        assert!(matches!(
+            server.join().unwrap(),
+            DisconnectReason::TargetExited(0)
+        ));
+    }
+}
@@ -3,17 +3,53 @@
 //! A VM for a [`NAND-Game`] inspired architecture.
 //!
 //! [`NAND-Game`]: https://nandgame.com
+//!
+//! Without the default `std` feature only the interpreter core (`vm`, `util`, `program`, `args` and the
+//! [`mmio::Device`] trait) is built and the crate is `no_std` (but requires `alloc`). The command line tools need the `cli` feature, which is also a
+//! default feature. With `std` but without `cli` the crate builds for `wasm32-unknown-unknown`, the `wasm` feature adds
+//! JavaScript bindings in `wasm`. The `gdb` feature
+//! lets GDB debug programs remotely through `gdb`.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
-pub use util::BError;
+pub use util::{AsmError, BError, FormatError};
 
+pub mod args;
+#[cfg(feature = "std")]
 pub mod assembler;
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "std")]
+pub mod cosim;
+#[cfg(feature = "std")]
 pub mod disassembler;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
+#[cfg(feature = "std")]
+pub mod oracle;
+#[cfg(feature = "std")]
+pub mod regression;
 pub mod util;
+#[cfg(feature = "std")]
+pub mod vcd;
 
 /// Debugging BRICs
+#[cfg(feature = "std")]
 pub mod debugger;
 
 pub mod mmio;
 
+pub mod program;
+
+#[cfg(feature = "std")]
+pub mod testing;
+
 /// Routines for simulating a BRIC
 pub mod vm;
+
+#[cfg(feature = "gdb")]
+pub mod gdb;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
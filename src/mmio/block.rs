@@ -0,0 +1,255 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    BError,
+    mmio::Device,
+    vm::{
+        Ram, VmDescription, WriteEvent,
+        boot::{BOOT_RAM_LEN, BOOT_ROM_BASE, BOOT_WINDOW},
+    },
+};
+
+/// Sector register. Selects the sector of the word in BLK_DATA
+pub const BLK_SECTOR: u16 = 0x6a00;
+/// Offset register. Selects the word in the sector, offsets past the sector reach into the following ones
+pub const BLK_OFFSET: u16 = 0x6a01;
+/// Data register. Holds the selected word, writing it stores the word on the disk
+pub const BLK_DATA: u16 = 0x6a02;
+/// Amount of sectors of the disk, read only
+pub const BLK_SECTORS: u16 = 0x6a03;
+
+/// Words per sector
+pub const SECTOR_LEN: usize = 256;
+
+/// A disk of sectors of [`SECTOR_LEN`] words. The guest accesses one word at a time, selected by a sector and an
+/// offset. See [boot](../docs/boot.md)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BlockStorage {
+    words: Vec<u16>,
+    sector: u16,
+    offset: u16,
+}
+
+impl BlockStorage {
+    /// Create a disk holding `words`, padded with zeroes to whole sectors
+    pub fn new(mut words: Vec<u16>) -> Self {
+        words.resize(words.len().div_ceil(SECTOR_LEN) * SECTOR_LEN, 0);
+        Self {
+            words,
+            sector: 0,
+            offset: 0,
+        }
+    }
+
+    /// The contents of the disk
+    pub fn words(&self) -> &[u16] {
+        &self.words
+    }
+
+    fn position(&self) -> usize {
+        self.sector as usize * SECTOR_LEN + self.offset as usize
+    }
+
+    /// called when the BLK_SECTOR register is written to
+    pub fn sector_changed(&mut self, reg_content: u16) {
+        self.sector = reg_content;
+    }
+
+    /// called when the BLK_OFFSET register is written to
+    pub fn offset_changed(&mut self, reg_content: u16) {
+        self.offset = reg_content;
+    }
+
+    /// called when the BLK_DATA register is written to. Words past the end of the disk are dropped
+    pub fn data_written(&mut self, reg_content: u16) {
+        let position = self.position();
+        if let Some(word) = self.words.get_mut(position) {
+            *word = reg_content;
+        }
+    }
+
+    /// Get the BLK_DATA register, 0 past the end of the disk
+    pub fn get_data(&self) -> u16 {
+        self.words.get(self.position()).copied().unwrap_or(0)
+    }
+
+    /// Get the BLK_SECTORS register
+    pub fn sectors(&self) -> u16 {
+        (self.words.len() / SECTOR_LEN).min(0xffff) as u16
+    }
+
+    /// Save the state of the disk. Laid out as: sector, offset (2 bytes each), then the words (2 bytes each). Big
+    /// endian
+    pub fn save(&self) -> Vec<u8> {
+        let mut out = self.sector.to_be_bytes().to_vec();
+        out.extend_from_slice(&self.offset.to_be_bytes());
+        for word in &self.words {
+            out.extend_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+
+    /// Restore the state of the disk from the output of [`BlockStorage::save()`]
+    /// ## Errors
+    /// [`BError::DeserializationError`] if the state is malformed
+    pub fn load(&mut self, state: &[u8]) -> Result<(), BError> {
+        if state.len() < 4 || !(state.len() - 4).is_multiple_of(2 * SECTOR_LEN) {
+            return Err(BError::DeserializationError(
+                "Invalid block storage state length".into(),
+            ));
+        }
+        self.sector = u16::from_be_bytes([state[0], state[1]]);
+        self.offset = u16::from_be_bytes([state[2], state[3]]);
+        self.words = state[4..]
+            .chunks(2)
+            .map(|w| u16::from_be_bytes([w[0], w[1]]))
+            .collect();
+        Ok(())
+    }
+}
+
+/// Synchronizes the registers of a shared [`BlockStorage`] with RAM on every tick
+struct BlockDevice(Arc<Mutex<BlockStorage>>);
+
+impl Device for BlockDevice {
+    fn tick(&mut self, _cycles: u64, ram: &mut Ram) {
+        let storage = self.0.lock().unwrap();
+        ram.set_ram(BLK_DATA, storage.get_data());
+        ram.set_ram(BLK_SECTORS, storage.sectors());
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.0.lock().unwrap().save()
+    }
+
+    fn load(&mut self, state: &[u8]) -> Result<(), BError> {
+        self.0.lock().unwrap().load(state)
+    }
+}
+
+/// Modifies a VmDescription to mount a block storage device. BLK_DATA and BLK_SECTORS are updated by the VM after
+/// every cycle.
+pub fn mount_block(vm_desc: &mut VmDescription, storage: BlockStorage) -> Arc<Mutex<BlockStorage>> {
+    let storage = Arc::new(Mutex::new(storage));
+    let sector_storage = storage.clone();
+    let offset_storage = storage.clone();
+    let data_storage = storage.clone();
+
+    vm_desc.callbacks.push((
+        BLK_SECTOR,
+        Box::new(move |write: WriteEvent| {
+            sector_storage.lock().unwrap().sector_changed(write.value)
+        }),
+    ));
+    vm_desc.callbacks.push((
+        BLK_OFFSET,
+        Box::new(move |write: WriteEvent| {
+            offset_storage.lock().unwrap().offset_changed(write.value)
+        }),
+    ));
+    vm_desc.callbacks.push((
+        BLK_DATA,
+        Box::new(move |write: WriteEvent| data_storage.lock().unwrap().data_written(write.value)),
+    ));
+    vm_desc.rom_blocks.push((BLK_SECTORS, 1));
+    vm_desc.devices.push(Box::new(BlockDevice(storage.clone())));
+    storage
+}
+
+/// The disk image the boot ROM loads `program` from: the amount of ROM words, the amount of ROM mappings, the ROM and
+/// then every mapping as ROM address, length and RAM address. See [`crate::vm::VmDescription::boot_rom`]
+/// ## Errors
+/// [`BError::OutOfBoundsError`] if the ROM doesn't end in front of the boot ROM and [`BError::MapError`] if a mapping
+/// copies consts into the RAM the boot ROM uses while copying, see [`crate::vm::boot::BOOT_RAM_LEN`]
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler, mmio::block::{BlockStorage, boot_image, mount_block}, vm::{Vm, VmDescription}};
+///
+/// let code = r"[text]
+/// A = value
+/// D = add, 0, *A
+/// A = 0x7fff
+/// JMP
+/// [consts 0x100]
+/// label value:
+/// M = 42";
+/// let image = boot_image(&assembler::run(code).unwrap()).unwrap();
+///
+/// let mut vm_desc = VmDescription { boot_rom: true, ..Default::default() };
+/// mount_block(&mut vm_desc, BlockStorage::new(image));
+/// let mut vm = Vm::new(vm_desc).unwrap();
+/// while vm.cycle().is_ok() {}
+/// assert_eq!(vm.cpu_state().d, 42);
+/// ```
+pub fn boot_image(program: &VmDescription) -> Result<Vec<u16>, BError> {
+    if program.rom.len() > BOOT_ROM_BASE as usize {
+        return Err(BError::OutOfBoundsError(
+            0,
+            program.rom.len(),
+            BOOT_ROM_BASE as usize,
+        ));
+    }
+    let boot_ram = BOOT_WINDOW as usize..BOOT_WINDOW as usize + BOOT_RAM_LEN;
+    for (_, length, ram_address) in &program.rom_mappings {
+        let start = *ram_address as usize;
+        if start < boot_ram.end && start + *length as usize > boot_ram.start {
+            return Err(BError::MapError(format!(
+                "the mapping to {:#06x} overlaps the RAM of the boot ROM at {:#06x}",
+                ram_address, BOOT_WINDOW
+            )));
+        }
+    }
+    let mut image = vec![program.rom.len() as u16, program.rom_mappings.len() as u16];
+    image.extend_from_slice(&program.rom);
+    for (rom_address, length, ram_address) in &program.rom_mappings {
+        image.extend([*rom_address, *length, *ram_address]);
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler, vm::Vm};
+
+    #[test]
+    fn test_boot_image_mappings() {
+        let mut program = assembler::run(
+            r"[text]
+A = 0x100
+D = add, 0, *A
+A = 0x200
+E = add, 0, *A
+A = 0x201
+F = add, 0, *A
+A = 0x7fff
+JMP
+[consts 0x100]
+M = 11",
+        )
+        .unwrap();
+        let data = program.rom.len() as u16;
+        program.rom.extend([22, 33]);
+        program.rom_mappings.push((data, 2, 0x200));
+        let image = boot_image(&program).unwrap();
+        assert_eq!(&image[..2], &[program.rom.len() as u16, 2]);
+
+        let mut vm_desc = VmDescription {
+            boot_rom: true,
+            ..Default::default()
+        };
+        mount_block(&mut vm_desc, BlockStorage::new(image));
+        let mut vm = Vm::new(vm_desc).unwrap();
+        while vm.cycle().is_ok() {}
+        let state = vm.cpu_state();
+        assert_eq!((state.d, state.e, state.f), (11, 22, 33));
+        // the boot window is closed and its RAM cleared
+        assert_eq!(
+            *vm.get_ram_region(BOOT_WINDOW, BOOT_RAM_LEN),
+            [0; BOOT_RAM_LEN]
+        );
+
+        program.rom_mappings.push((data, 2, BOOT_WINDOW - 1));
+        assert!(matches!(boot_image(&program), Err(BError::MapError(_))));
+    }
+}
@@ -0,0 +1,99 @@
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::{
+    BError,
+    mmio::Device,
+    vm::{Ram, VmDescription},
+};
+
+/// Low word of the cycle counter, read only
+pub const CYCLE_LOW: u16 = 0x6700;
+/// High word of the cycle counter, read only
+pub const CYCLE_HIGH: u16 = 0x6701;
+
+/// A free running 32 bit counter of the cycles since the VM was created. Wraps around on overflow.
+/// Clones share their state.
+#[derive(Clone, Debug, Default)]
+pub struct CycleCounter {
+    count: Arc<AtomicU32>,
+}
+
+impl CycleCounter {
+    /// Get the value of the counter
+    pub fn get(&self) -> u32 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Advance the counter by `cycles`
+    fn advance(&self, cycles: u64) {
+        self.count.fetch_add(cycles as u32, Ordering::Relaxed);
+    }
+
+    /// Save the state of the counter. 4 bytes, big endian.
+    pub fn save(&self) -> Vec<u8> {
+        self.get().to_be_bytes().to_vec()
+    }
+
+    /// Restore the state of the counter from the output of [`CycleCounter::save()`]
+    /// ## Errors
+    /// [`BError::DeserializationError`] if the state is malformed
+    pub fn load(&self, state: &[u8]) -> Result<(), BError> {
+        let state: [u8; 4] = state.try_into().map_err(|_| {
+            BError::DeserializationError("Invalid cycle counter state length".into())
+        })?;
+        self.count
+            .store(u32::from_be_bytes(state), Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Advances a shared [`CycleCounter`] and writes it to RAM on every tick
+struct CycleCounterDevice(CycleCounter);
+
+impl Device for CycleCounterDevice {
+    fn tick(&mut self, cycles: u64, ram: &mut Ram) {
+        self.0.advance(cycles);
+        let count = self.0.get();
+        ram.set_ram(CYCLE_LOW, count as u16);
+        ram.set_ram(CYCLE_HIGH, (count >> 16) as u16);
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.0.save()
+    }
+
+    fn load(&mut self, state: &[u8]) -> Result<(), BError> {
+        self.0.load(state)
+    }
+}
+
+/// Modifies a VmDescription to mount a cycle counter at [`CYCLE_LOW`] and creates it.
+/// The counter is incremented and written to RAM after every cycle.
+/// ## Examples
+/// ```rust
+/// use bric_vm::{
+///     mmio::counter::{CYCLE_LOW, mount_cycle_counter},
+///     vm::{Vm, VmDescription},
+/// };
+///
+/// let mut vm_desc = VmDescription {
+///     rom: vec![0x8000; 100],
+///     ..Default::default()
+/// };
+/// let counter = mount_cycle_counter(&mut vm_desc);
+/// let mut vm = Vm::new(vm_desc).unwrap();
+/// for _ in 0..3 {
+///     vm.cycle().unwrap();
+/// }
+/// assert_eq!(counter.get(), 3);
+/// assert_eq!(*vm.get_ram_region(CYCLE_LOW, 2), [3, 0]);
+/// ```
+pub fn mount_cycle_counter(vm_desc: &mut VmDescription) -> CycleCounter {
+    let counter = CycleCounter::default();
+    vm_desc.rom_blocks.push((CYCLE_LOW, 2));
+    vm_desc
+        .devices
+        .push(Box::new(CycleCounterDevice(counter.clone())));
+    counter
+}
@@ -0,0 +1,189 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    BError,
+    mmio::Device,
+    vm::{Ram, Vm, VmDescription, WriteEvent},
+};
+
+/// Direction register. A set bit makes the pin an output
+pub const GPIO_DIR: u16 = 0x6100;
+/// Output register. Levels of the output pins
+pub const GPIO_OUT: u16 = 0x6101;
+/// Input register. Levels of all pins, read only
+pub const GPIO_IN: u16 = 0x6102;
+
+/// Called with the levels of the output pins whenever they change
+pub type OutputHandler = Box<dyn FnMut(u16) + Send>;
+
+/// Represents a GPIO port with 16 pins. Pins are inputs after reset.
+/// The embedder drives the input pins with [`Gpio::set_pin()`] and observes the output pins with
+/// [`Gpio::read_pin()`] or an [`OutputHandler`].
+#[derive(Default)]
+pub struct Gpio {
+    direction: u16,
+    output: u16,
+    external: u16,
+    handler: Option<OutputHandler>,
+}
+
+impl Gpio {
+    /// called when the GPIO_DIR register is written to
+    pub fn direction_changed(&mut self, reg_content: u16) {
+        let before = self.output_levels();
+        self.direction = reg_content;
+        self.notify(before);
+    }
+
+    /// called when the GPIO_OUT register is written to
+    pub fn output_changed(&mut self, reg_content: u16) {
+        let before = self.output_levels();
+        self.output = reg_content;
+        self.notify(before);
+    }
+
+    fn notify(&mut self, before: u16) {
+        let after = self.output_levels();
+        if after != before
+            && let Some(handler) = &mut self.handler
+        {
+            handler(after);
+        }
+    }
+
+    /// Set a handler that is called with the levels of the output pins whenever they change
+    pub fn set_output_handler(&mut self, handler: OutputHandler) {
+        self.handler = Some(handler);
+    }
+
+    /// Drive `pin` from the outside. Has no effect on the level of an output pin
+    pub fn set_pin(&mut self, pin: u8, level: bool) {
+        let mask = 1 << (pin & 0xf);
+        if level {
+            self.external |= mask;
+        } else {
+            self.external &= !mask;
+        }
+    }
+
+    /// Drive all pins from the outside at once
+    pub fn set_pins(&mut self, levels: u16) {
+        self.external = levels;
+    }
+
+    /// Get the level of `pin`
+    pub fn read_pin(&self, pin: u8) -> bool {
+        self.get_levels() & (1 << (pin & 0xf)) != 0
+    }
+
+    /// Get the levels of the output pins. Input pins read as low
+    pub fn output_levels(&self) -> u16 {
+        self.output & self.direction
+    }
+
+    /// Get the levels of all pins, the GPIO_IN register
+    pub fn get_levels(&self) -> u16 {
+        self.output_levels() | (self.external & !self.direction)
+    }
+
+    /// Get the direction of all pins, the GPIO_DIR register
+    pub fn get_direction(&self) -> u16 {
+        self.direction
+    }
+
+    /// Save the state of the GPIO port. Laid out as: direction, output, external levels. 2 bytes each, big endian.
+    /// The output handler is not saved
+    pub fn save(&self) -> Vec<u8> {
+        [self.direction, self.output, self.external]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect()
+    }
+
+    /// Restore the state of the GPIO port from the output of [`Gpio::save()`]
+    /// ## Errors
+    /// [`BError::DeserializationError`] if the state is malformed
+    pub fn load(&mut self, state: &[u8]) -> Result<(), BError> {
+        if state.len() != 6 {
+            return Err(BError::DeserializationError(
+                "Invalid GPIO state length".into(),
+            ));
+        }
+        self.direction = u16::from_be_bytes([state[0], state[1]]);
+        self.output = u16::from_be_bytes([state[2], state[3]]);
+        self.external = u16::from_be_bytes([state[4], state[5]]);
+        Ok(())
+    }
+}
+
+/// Synchronizes the input register of a shared [`Gpio`] with RAM on every tick
+struct GpioDevice(Arc<Mutex<Gpio>>);
+
+impl Device for GpioDevice {
+    fn tick(&mut self, _cycles: u64, ram: &mut Ram) {
+        ram.set_ram(GPIO_IN, self.0.lock().unwrap().get_levels());
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.0.lock().unwrap().save()
+    }
+
+    fn load(&mut self, state: &[u8]) -> Result<(), BError> {
+        self.0.lock().unwrap().load(state)
+    }
+}
+
+/// Modifies a VmDescription to mount a GPIO port and creates the port.
+/// The input register is updated by the VM after every cycle.
+pub fn mount_gpio(vm_desc: &mut VmDescription) -> Arc<Mutex<Gpio>> {
+    let gpio = Arc::new(Mutex::new(Gpio::default()));
+    let dir_gpio = gpio.clone();
+    let out_gpio = gpio.clone();
+
+    vm_desc.callbacks.push((
+        GPIO_DIR,
+        Box::new(move |write: WriteEvent| dir_gpio.lock().unwrap().direction_changed(write.value)),
+    ));
+    vm_desc.callbacks.push((
+        GPIO_OUT,
+        Box::new(move |write: WriteEvent| out_gpio.lock().unwrap().output_changed(write.value)),
+    ));
+    vm_desc.rom_blocks.push((GPIO_IN, 1));
+    vm_desc.devices.push(Box::new(GpioDevice(gpio.clone())));
+    gpio
+}
+
+/// Modifies a VmDescription to mount a GPIO port, creates the port and builds the VM. See [`mount_gpio()`]
+/// The GPIO object itself can also be used on another thread.
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler, mmio::gpio::{GPIO_IN, connect_gpio}};
+/// use std::sync::mpsc;
+///
+/// // make pins 0-7 outputs and set pins 0 and 2
+/// let code = r"[text]
+/// A = 0xff
+/// D = add, 0, A
+/// A = 0x6100
+/// *A = add, 0, D
+/// A = 5
+/// D = add, 0, A
+/// A = 0x6101
+/// *A = add, 0, D
+/// [consts 0x100]";
+/// let (mut vm, gpio) = connect_gpio(assembler::run(code).unwrap()).unwrap();
+/// let (tx, rx) = mpsc::channel();
+/// gpio.lock().unwrap().set_output_handler(Box::new(move |levels| tx.send(levels).unwrap()));
+/// gpio.lock().unwrap().set_pin(8, true);
+///
+/// for _ in 0..8 {
+///     vm.cycle().unwrap();
+/// }
+/// assert_eq!(rx.try_recv(), Ok(0b101));
+/// assert_eq!(*vm.get_ram_region(GPIO_IN, 1), [0x105]);
+/// ```
+pub fn connect_gpio(mut vm_desc: VmDescription) -> Result<(Vm, Arc<Mutex<Gpio>>), BError> {
+    let gpio = mount_gpio(&mut vm_desc);
+    let vm = Vm::new(vm_desc)?;
+    Ok((vm, gpio))
+}
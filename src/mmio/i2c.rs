@@ -0,0 +1,264 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    BError,
+    mmio::Device,
+    vm::{Ram, Vm, VmDescription, WriteEvent},
+};
+
+/// Data register. The low byte is sent by [`I2C_WRITE`], a byte received by [`I2C_READ`] is placed here
+pub const I2C_DATA: u16 = 0x6500;
+/// Control register. Writing a combination of the control flags runs them in the order start, write, read, stop
+pub const I2C_CTRL: u16 = 0x6501;
+/// Status register, read only. See the status flags
+pub const I2C_STATUS: u16 = 0x6502;
+
+// control flags
+/// Send a start condition. The next byte written is the address byte: 7 bit address, then the read bit
+pub const I2C_START: u16 = 1;
+/// Send the byte in I2C_DATA
+pub const I2C_WRITE: u16 = 1 << 1;
+/// Receive a byte into I2C_DATA and acknowledge it
+pub const I2C_READ: u16 = 1 << 2;
+/// Don't acknowledge the byte received by I2C_READ, ending the read
+pub const I2C_NACK: u16 = 1 << 3;
+/// Send a stop condition
+pub const I2C_STOP: u16 = 1 << 4;
+
+// status flags
+/// The last byte written was acknowledged
+pub const I2C_ACK: u16 = 1;
+/// The bus is held between a start and a stop condition
+pub const I2C_BUSY: u16 = 1 << 1;
+
+/// A simulated device on the I2C bus
+pub trait I2cSlave: Send {
+    /// Called when the master addresses the device after a start condition. Returns whether it acknowledges
+    fn start(&mut self, _read: bool) -> bool {
+        true
+    }
+
+    /// Receive a byte from the master. Returns whether the device acknowledges it
+    fn write(&mut self, byte: u8) -> bool;
+
+    /// Send a byte to the master. `ack` is whether the master acknowledges it and wants to read more
+    fn read(&mut self, ack: bool) -> u8;
+
+    /// Called on a stop condition while the device is addressed
+    fn stop(&mut self) {}
+}
+
+/// Represents an I2C master. Transfers complete instantly.
+/// Reads without an addressed device receive 0xff and writes are not acknowledged.
+#[derive(Default)]
+pub struct I2c {
+    slaves: BTreeMap<u8, Box<dyn I2cSlave>>,
+    addressed: Option<u8>,
+    address_next: bool,
+    tx: u8,
+    rx: Option<u8>,
+    status: u16,
+}
+
+impl I2c {
+    /// Attach a device to the bus at a 7 bit `address`
+    /// ## Errors
+    /// [`BError::MapError`] if the address doesn't fit into 7 bits or is already taken
+    pub fn attach(&mut self, address: u8, slave: Box<dyn I2cSlave>) -> Result<(), BError> {
+        if address > 0x7f || self.slaves.contains_key(&address) {
+            return Err(BError::MapError(format!(
+                "I2C address {:#04x} is invalid or taken",
+                address
+            )));
+        }
+        self.slaves.insert(address, slave);
+        Ok(())
+    }
+
+    /// called when the I2C_DATA register is written to
+    pub fn data_changed(&mut self, reg_content: u16) {
+        self.tx = reg_content as u8;
+    }
+
+    /// called when the I2C_CTRL register is written to
+    pub fn control_changed(&mut self, reg_content: u16) {
+        if reg_content & I2C_START != 0 {
+            self.addressed = None;
+            self.address_next = true;
+            self.status |= I2C_BUSY;
+        }
+
+        if reg_content & I2C_WRITE != 0 {
+            let ack = if self.status & I2C_BUSY == 0 {
+                false
+            } else if self.address_next {
+                self.address_next = false;
+                let address = self.tx >> 1;
+                let read = self.tx & 1 != 0;
+                let ack = self
+                    .slaves
+                    .get_mut(&address)
+                    .is_some_and(|slave| slave.start(read));
+                self.addressed = ack.then_some(address);
+                ack
+            } else {
+                let tx = self.tx;
+                self.addressed_slave().is_some_and(|slave| slave.write(tx))
+            };
+            if ack {
+                self.status |= I2C_ACK;
+            } else {
+                self.status &= !I2C_ACK;
+            }
+        }
+
+        if reg_content & I2C_READ != 0 {
+            let ack = reg_content & I2C_NACK == 0;
+            let rx = self.addressed_slave().map_or(0xff, |slave| slave.read(ack));
+            self.rx = Some(rx);
+        }
+
+        if reg_content & I2C_STOP != 0 {
+            if let Some(slave) = self.addressed_slave() {
+                slave.stop();
+            }
+            self.addressed = None;
+            self.address_next = false;
+            self.status &= !I2C_BUSY;
+        }
+    }
+
+    fn addressed_slave(&mut self) -> Option<&mut Box<dyn I2cSlave>> {
+        self.slaves.get_mut(&self.addressed?)
+    }
+
+    /// Get the I2C_STATUS register
+    pub fn get_status(&self) -> u16 {
+        self.status
+    }
+
+    /// Save the state of the master. Laid out as: transmit byte, addressed device (0xff if none), whether the next
+    /// byte is an address byte, status (2 bytes, big endian). The state of the devices is not saved
+    pub fn save(&self) -> Vec<u8> {
+        let mut out = vec![
+            self.tx,
+            self.addressed.unwrap_or(0xff),
+            self.address_next as u8,
+        ];
+        out.extend_from_slice(&self.status.to_be_bytes());
+        out
+    }
+
+    /// Restore the state of the master from the output of [`I2c::save()`]
+    /// ## Errors
+    /// [`BError::DeserializationError`] if the state is malformed
+    pub fn load(&mut self, state: &[u8]) -> Result<(), BError> {
+        if state.len() != 5 {
+            return Err(BError::DeserializationError(
+                "Invalid I2C state length".into(),
+            ));
+        }
+        self.tx = state[0];
+        self.addressed = (state[1] != 0xff).then_some(state[1]);
+        self.address_next = state[2] != 0;
+        self.status = u16::from_be_bytes([state[3], state[4]]);
+        self.rx = None;
+        Ok(())
+    }
+}
+
+/// Synchronizes the registers of a shared [`I2c`] with RAM on every tick
+struct I2cDevice(Arc<Mutex<I2c>>);
+
+impl Device for I2cDevice {
+    fn tick(&mut self, _cycles: u64, ram: &mut Ram) {
+        let mut i2c = self.0.lock().unwrap();
+        if let Some(rx) = i2c.rx.take() {
+            ram.set_ram(I2C_DATA, rx as u16);
+        }
+        ram.set_ram(I2C_STATUS, i2c.get_status());
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.0.lock().unwrap().save()
+    }
+
+    fn load(&mut self, state: &[u8]) -> Result<(), BError> {
+        self.0.lock().unwrap().load(state)
+    }
+}
+
+/// Modifies a VmDescription to mount an I2C master and creates it. Devices are attached with [`I2c::attach()`].
+/// The received byte and the status register are updated by the VM after every cycle.
+pub fn mount_i2c(vm_desc: &mut VmDescription) -> Arc<Mutex<I2c>> {
+    let i2c = Arc::new(Mutex::new(I2c::default()));
+    let data_i2c = i2c.clone();
+    let ctrl_i2c = i2c.clone();
+
+    vm_desc.callbacks.push((
+        I2C_DATA,
+        Box::new(move |write: WriteEvent| data_i2c.lock().unwrap().data_changed(write.value)),
+    ));
+    vm_desc.callbacks.push((
+        I2C_CTRL,
+        Box::new(move |write: WriteEvent| ctrl_i2c.lock().unwrap().control_changed(write.value)),
+    ));
+    vm_desc.rom_blocks.push((I2C_STATUS, 1));
+    vm_desc.devices.push(Box::new(I2cDevice(i2c.clone())));
+    i2c
+}
+
+/// Modifies a VmDescription to mount an I2C master, creates it and builds the VM. See [`mount_i2c()`]
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler, mmio::i2c::{I2C_DATA, I2cSlave, connect_i2c}};
+///
+/// /// A sensor that always measures 21
+/// struct Thermometer;
+///
+/// impl I2cSlave for Thermometer {
+///     fn write(&mut self, _byte: u8) -> bool {
+///         false
+///     }
+///
+///     fn read(&mut self, _ack: bool) -> u8 {
+///         21
+///     }
+/// }
+///
+/// // address the device at 0x48 for reading, read one byte and stop
+/// let code = r"[text]
+/// A = 0x91
+/// D = add, 0, A
+/// A = 0x6500
+/// *A = add, 0, D
+/// A = 3
+/// D = add, 0, A
+/// A = 0x6501
+/// *A = add, 0, D
+/// A = 0x1c
+/// D = add, 0, A
+/// A = 0x6501
+/// *A = add, 0, D
+/// [consts 0x100]";
+/// let (mut vm, i2c) = connect_i2c(assembler::run(code).unwrap()).unwrap();
+/// i2c.lock().unwrap().attach(0x48, Box::new(Thermometer)).unwrap();
+///
+/// for _ in 0..8 {
+///     vm.cycle().unwrap();
+/// }
+/// // the address byte was acknowledged
+/// assert_eq!(*vm.get_ram_region(I2C_DATA, 3), [0x91, 3, 0b11]);
+/// for _ in 0..4 {
+///     vm.cycle().unwrap();
+/// }
+/// assert_eq!(*vm.get_ram_region(I2C_DATA, 3), [21, 0x1c, 0b01]);
+/// ```
+pub fn connect_i2c(mut vm_desc: VmDescription) -> Result<(Vm, Arc<Mutex<I2c>>), BError> {
+    let i2c = mount_i2c(&mut vm_desc);
+    let vm = Vm::new(vm_desc)?;
+    Ok((vm, i2c))
+}
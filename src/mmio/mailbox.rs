@@ -0,0 +1,251 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    BError,
+    mmio::Device,
+    vm::{Ram, Vm, VmDescription, WriteEvent},
+};
+
+/// Output register. The word is sent to the other end by [`MB_SEND`]
+pub const MB_OUT: u16 = 0x6800;
+/// Input register. Holds the word received by [`MB_RECV`], read only
+pub const MB_IN: u16 = 0x6801;
+/// Status register, read only. See the status flags
+pub const MB_STATUS: u16 = 0x6802;
+/// Control register. Writing a combination of the control flags runs them in the order send, receive, ring, acknowledge
+pub const MB_CTRL: u16 = 0x6803;
+
+// control flags
+/// Send the word in MB_OUT to the other end
+pub const MB_SEND: u16 = 1;
+/// Move the next received word into MB_IN
+pub const MB_RECV: u16 = 1 << 1;
+/// Ring the doorbell of the other end
+pub const MB_RING: u16 = 1 << 2;
+/// Clear the own doorbell
+pub const MB_ACK: u16 = 1 << 3;
+
+// status flags
+/// Received words are available
+pub const MB_DA: u16 = 1;
+/// The FIFO towards the other end is full, words sent now are dropped
+pub const MB_FULL: u16 = 1 << 1;
+/// The other end rang the doorbell
+pub const MB_BELL: u16 = 1 << 2;
+
+/// Amount of words each FIFO holds
+pub const MAILBOX_LEN: usize = 0xff;
+
+/// The FIFOs and doorbells shared by both ends. Index 0 belongs to the first end of the pair
+#[derive(Default)]
+struct Link {
+    fifos: [VecDeque<u16>; 2],
+    bells: [bool; 2],
+}
+
+/// One end of a pair of mailboxes created by [`Mailbox::pair()`]. Each end has a FIFO of incoming words and a
+/// doorbell the other end rings.
+pub struct Mailbox {
+    link: Arc<Mutex<Link>>,
+    side: usize,
+    write_reg: u16,
+    read_reg: u16,
+}
+
+impl Mailbox {
+    /// Create two connected ends
+    pub fn pair() -> (Self, Self) {
+        let link = Arc::new(Mutex::new(Link::default()));
+        let end = |side| Self {
+            link: link.clone(),
+            side,
+            write_reg: 0,
+            read_reg: 0,
+        };
+        (end(0), end(1))
+    }
+
+    /// called when the MB_OUT register is written to
+    pub fn write_reg_changed(&mut self, reg_content: u16) {
+        self.write_reg = reg_content;
+    }
+
+    /// called when the MB_CTRL register is written to
+    pub fn control_changed(&mut self, reg_content: u16) {
+        let (own, other) = (self.side, 1 - self.side);
+        let mut link = self.link.lock().unwrap();
+        if reg_content & MB_SEND != 0 && link.fifos[other].len() < MAILBOX_LEN {
+            link.fifos[other].push_back(self.write_reg);
+        }
+        if reg_content & MB_RECV != 0 {
+            self.read_reg = link.fifos[own].pop_front().unwrap_or(0);
+        }
+        if reg_content & MB_RING != 0 {
+            link.bells[other] = true;
+        }
+        if reg_content & MB_ACK != 0 {
+            link.bells[own] = false;
+        }
+    }
+
+    /// Get the MB_STATUS register
+    pub fn get_status(&self) -> u16 {
+        let (own, other) = (self.side, 1 - self.side);
+        let link = self.link.lock().unwrap();
+        let mut status = 0;
+        if !link.fifos[own].is_empty() {
+            status |= MB_DA;
+        }
+        if link.fifos[other].len() >= MAILBOX_LEN {
+            status |= MB_FULL;
+        }
+        if link.bells[own] {
+            status |= MB_BELL;
+        }
+        status
+    }
+
+    /// Get the MB_IN register
+    pub fn get_input(&self) -> u16 {
+        self.read_reg
+    }
+
+    /// Amount of received words the guest didn't read yet
+    pub fn available(&self) -> usize {
+        self.link.lock().unwrap().fifos[self.side].len()
+    }
+
+    /// Save the state of this end. Laid out as: MB_OUT, MB_IN (2 bytes each), doorbell (1 byte), then the received
+    /// words (2 bytes each). Big endian. The FIFO towards the other end is saved by the other end
+    pub fn save(&self) -> Vec<u8> {
+        let link = self.link.lock().unwrap();
+        let mut out = self.write_reg.to_be_bytes().to_vec();
+        out.extend_from_slice(&self.read_reg.to_be_bytes());
+        out.push(link.bells[self.side] as u8);
+        for word in &link.fifos[self.side] {
+            out.extend_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+
+    /// Restore the state of this end from the output of [`Mailbox::save()`]
+    /// ## Errors
+    /// [`BError::DeserializationError`] if the state is malformed
+    pub fn load(&mut self, state: &[u8]) -> Result<(), BError> {
+        if state.len() < 5 || state.len() % 2 != 1 || (state.len() - 5) / 2 > MAILBOX_LEN {
+            return Err(BError::DeserializationError(
+                "Invalid mailbox state length".into(),
+            ));
+        }
+        self.write_reg = u16::from_be_bytes([state[0], state[1]]);
+        self.read_reg = u16::from_be_bytes([state[2], state[3]]);
+        let mut link = self.link.lock().unwrap();
+        link.bells[self.side] = state[4] != 0;
+        link.fifos[self.side] = state[5..]
+            .chunks(2)
+            .map(|w| u16::from_be_bytes([w[0], w[1]]))
+            .collect();
+        Ok(())
+    }
+}
+
+/// Synchronizes the registers of a shared [`Mailbox`] with RAM on every tick
+struct MailboxDevice(Arc<Mutex<Mailbox>>);
+
+impl Device for MailboxDevice {
+    fn tick(&mut self, _cycles: u64, ram: &mut Ram) {
+        let mailbox = self.0.lock().unwrap();
+        ram.set_ram(MB_IN, mailbox.get_input());
+        ram.set_ram(MB_STATUS, mailbox.get_status());
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.0.lock().unwrap().save()
+    }
+
+    fn load(&mut self, state: &[u8]) -> Result<(), BError> {
+        self.0.lock().unwrap().load(state)
+    }
+}
+
+/// Modifies a VmDescription to mount one end of a mailbox pair. The received word and the status register are updated
+/// by the VM after every cycle.
+pub fn mount_mailbox(vm_desc: &mut VmDescription, mailbox: Mailbox) -> Arc<Mutex<Mailbox>> {
+    let mailbox = Arc::new(Mutex::new(mailbox));
+    let out_mailbox = mailbox.clone();
+    let ctrl_mailbox = mailbox.clone();
+
+    vm_desc.callbacks.push((
+        MB_OUT,
+        Box::new(move |write: WriteEvent| {
+            out_mailbox.lock().unwrap().write_reg_changed(write.value)
+        }),
+    ));
+    vm_desc.callbacks.push((
+        MB_CTRL,
+        Box::new(move |write: WriteEvent| {
+            ctrl_mailbox.lock().unwrap().control_changed(write.value)
+        }),
+    ));
+    vm_desc.rom_blocks.push((MB_IN, 2));
+    vm_desc
+        .devices
+        .push(Box::new(MailboxDevice(mailbox.clone())));
+    mailbox
+}
+
+/// Builds two VMs connected by a pair of mailboxes. See [`mount_mailbox()`]
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler, mmio::mailbox::{MB_IN, connect_mailboxes}};
+///
+/// // send 0x42 and ring the doorbell
+/// let sender = r"[text]
+/// A = 0x42
+/// D = add, 0, A
+/// A = 0x6800
+/// *A = add, 0, D
+/// A = 5
+/// D = add, 0, A
+/// A = 0x6803
+/// *A = add, 0, D
+/// [consts 0x100]";
+/// // wait for the doorbell, then receive the word and acknowledge the doorbell
+/// let receiver = r"[text]
+/// label wait:
+/// A = 0x6802
+/// D = add, 0, *A
+/// A = 4
+/// D = and, D, A
+/// A = wait
+/// add, 0, D; JEQ
+/// A = 10
+/// D = add, 0, A
+/// A = 0x6803
+/// *A = add, 0, D
+/// [consts 0x100]";
+/// let (mut a, mut b) = connect_mailboxes(
+///     assembler::run(sender).unwrap(),
+///     assembler::run(receiver).unwrap(),
+/// )
+/// .unwrap();
+/// for _ in 0..8 {
+///     a.cycle().unwrap();
+/// }
+/// for _ in 0..10 {
+///     b.cycle().unwrap();
+/// }
+/// assert_eq!(*b.get_ram_region(MB_IN, 2), [0x42, 0]);
+/// ```
+pub fn connect_mailboxes(
+    mut first: VmDescription,
+    mut second: VmDescription,
+) -> Result<(Vm, Vm), BError> {
+    let (a, b) = Mailbox::pair();
+    mount_mailbox(&mut first, a);
+    mount_mailbox(&mut second, b);
+    Ok((Vm::new(first)?, Vm::new(second)?))
+}
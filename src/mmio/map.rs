@@ -0,0 +1,204 @@
+//! The default addresses of the registers of the standard devices, in one place. Most devices can only be mounted at
+//! these addresses, the UART can also be mounted elsewhere. The addresses are listed here even for devices that need
+//! the `std` feature, so tools like the disassembler can name them in any build.
+
+/// A register, or a block of registers, of a standard device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapEntry {
+    /// Name of the register as used in the docs and the register constants
+    pub name: &'static str,
+    /// Kind of the device, named like the built-in kinds of the [`crate::mmio::registry::DeviceRegistry`]
+    pub device: &'static str,
+    pub address: u16,
+    /// Amount of words, 1 for everything but blocks like the screen
+    pub length: u16,
+    pub description: &'static str,
+}
+
+impl MapEntry {
+    const fn new(
+        name: &'static str,
+        device: &'static str,
+        address: u16,
+        description: &'static str,
+    ) -> Self {
+        Self {
+            name,
+            device,
+            address,
+            length: 1,
+            description,
+        }
+    }
+
+    /// Whether `address` belongs to this entry
+    pub fn contains(&self, address: u16) -> bool {
+        address >= self.address && address - self.address < self.length
+    }
+}
+
+/// Every standard register, ordered by address
+pub const MAP: &[MapEntry] = &[
+    MapEntry {
+        name: "SCREEN",
+        device: "screen",
+        address: 0x5000,
+        length: 80 * 25,
+        description: "character cells",
+    },
+    MapEntry::new("U_BAUD", "uart", 0x6000, "cycles per bit"),
+    MapEntry::new(
+        "U_OUT",
+        "uart",
+        0x6001,
+        "write one byte into the output FIFO",
+    ),
+    MapEntry::new("U_IN", "uart", 0x6002, "read one byte from the input FIFO"),
+    MapEntry::new("U_IFL", "uart", 0x6003, "input flags"),
+    MapEntry::new("U_OFL", "uart", 0x6004, "output flags"),
+    MapEntry::new("GPIO_DIR", "gpio", 0x6100, "pin directions"),
+    MapEntry::new("GPIO_OUT", "gpio", 0x6101, "output pins"),
+    MapEntry::new("GPIO_IN", "gpio", 0x6102, "input pins"),
+    MapEntry::new("RNG_DATA", "rng", 0x6200, "next random number"),
+    MapEntry::new(
+        "SH_OP",
+        "semihost",
+        0x6300,
+        "operation, runs it when written",
+    ),
+    MapEntry::new("SH_ARG0", "semihost", 0x6301, "first argument"),
+    MapEntry::new("SH_ARG1", "semihost", 0x6302, "second argument"),
+    MapEntry::new("SH_ARG2", "semihost", 0x6303, "third argument"),
+    MapEntry::new(
+        "SH_RESULT",
+        "semihost",
+        0x6304,
+        "result of the last operation",
+    ),
+    MapEntry::new("SPI_DATA", "spi", 0x6400, "byte to send, byte received"),
+    MapEntry::new("SPI_CTRL", "spi", 0x6401, "control"),
+    MapEntry::new("SPI_STATUS", "spi", 0x6402, "status"),
+    MapEntry::new("I2C_DATA", "i2c", 0x6500, "byte to send, byte received"),
+    MapEntry::new("I2C_CTRL", "i2c", 0x6501, "control"),
+    MapEntry::new("I2C_STATUS", "i2c", 0x6502, "status"),
+    MapEntry::new("NET_OUT", "net", 0x6600, "write one byte to the socket"),
+    MapEntry::new("NET_IN", "net", 0x6601, "read one byte from the socket"),
+    MapEntry::new("NET_STATUS", "net", 0x6602, "status"),
+    MapEntry::new("NET_CTRL", "net", 0x6603, "control"),
+    MapEntry::new(
+        "CYCLE_LOW",
+        "cycle_counter",
+        0x6700,
+        "low word of the cycle counter",
+    ),
+    MapEntry::new(
+        "CYCLE_HIGH",
+        "cycle_counter",
+        0x6701,
+        "high word of the cycle counter",
+    ),
+    MapEntry::new(
+        "MB_OUT",
+        "mailbox",
+        0x6800,
+        "write one word to the other mailbox",
+    ),
+    MapEntry::new("MB_IN", "mailbox", 0x6801, "read one word from the mailbox"),
+    MapEntry::new("MB_STATUS", "mailbox", 0x6802, "status"),
+    MapEntry::new("MB_CTRL", "mailbox", 0x6803, "control"),
+    MapEntry::new("KBD_DATA", "ps2_keyboard", 0x6900, "next scan code"),
+    MapEntry::new("KBD_STATUS", "ps2_keyboard", 0x6901, "status"),
+    MapEntry::new("KBD_CTRL", "ps2_keyboard", 0x6902, "control"),
+    MapEntry::new("BLK_SECTOR", "block", 0x6a00, "selected sector"),
+    MapEntry::new("BLK_OFFSET", "block", 0x6a01, "selected word in the sector"),
+    MapEntry::new(
+        "BLK_DATA",
+        "block",
+        0x6a02,
+        "read or write the selected word",
+    ),
+    MapEntry::new("BLK_SECTORS", "block", 0x6a03, "amount of sectors"),
+    MapEntry::new("PANEL_SWITCHES", "panel", 0x7ffe, "switches"),
+    MapEntry::new("PANEL_LAMPS", "panel", 0x7fff, "lamps"),
+];
+
+/// Find the register at `address`
+/// ## Examples
+/// ```rust
+/// use bric_vm::mmio::map;
+///
+/// assert_eq!(map::lookup(0x6001).unwrap().name, "U_OUT");
+/// assert_eq!(map::lookup(0x5010).unwrap().name, "SCREEN");
+/// assert!(map::lookup(0x100).is_none());
+/// ```
+pub fn lookup(address: u16) -> Option<&'static MapEntry> {
+    MAP.iter().find(|entry| entry.contains(address))
+}
+
+/// Find a register by its name
+pub fn by_name(name: &str) -> Option<&'static MapEntry> {
+    MAP.iter().find(|entry| entry.name == name)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::mmio::{
+        block, counter, gpio, i2c, mailbox, net, panel, ps2, rng, screen, semihost, spi, uart,
+    };
+
+    #[test]
+    fn test_map_matches_devices() {
+        let constants = [
+            ("SCREEN", screen::SCREEN_BASE),
+            ("U_BAUD", uart::U_BAUD),
+            ("U_OUT", uart::U_OUT),
+            ("U_IN", uart::U_IN),
+            ("U_IFL", uart::U_IFL),
+            ("U_OFL", uart::U_OFL),
+            ("GPIO_DIR", gpio::GPIO_DIR),
+            ("GPIO_OUT", gpio::GPIO_OUT),
+            ("GPIO_IN", gpio::GPIO_IN),
+            ("RNG_DATA", rng::RNG_DATA),
+            ("SH_OP", semihost::SH_OP),
+            ("SH_ARG0", semihost::SH_ARG0),
+            ("SH_ARG1", semihost::SH_ARG1),
+            ("SH_ARG2", semihost::SH_ARG2),
+            ("SH_RESULT", semihost::SH_RESULT),
+            ("SPI_DATA", spi::SPI_DATA),
+            ("SPI_CTRL", spi::SPI_CTRL),
+            ("SPI_STATUS", spi::SPI_STATUS),
+            ("I2C_DATA", i2c::I2C_DATA),
+            ("I2C_CTRL", i2c::I2C_CTRL),
+            ("I2C_STATUS", i2c::I2C_STATUS),
+            ("NET_OUT", net::NET_OUT),
+            ("NET_IN", net::NET_IN),
+            ("NET_STATUS", net::NET_STATUS),
+            ("NET_CTRL", net::NET_CTRL),
+            ("CYCLE_LOW", counter::CYCLE_LOW),
+            ("CYCLE_HIGH", counter::CYCLE_HIGH),
+            ("MB_OUT", mailbox::MB_OUT),
+            ("MB_IN", mailbox::MB_IN),
+            ("MB_STATUS", mailbox::MB_STATUS),
+            ("MB_CTRL", mailbox::MB_CTRL),
+            ("KBD_DATA", ps2::KBD_DATA),
+            ("KBD_STATUS", ps2::KBD_STATUS),
+            ("KBD_CTRL", ps2::KBD_CTRL),
+            ("BLK_SECTOR", block::BLK_SECTOR),
+            ("BLK_OFFSET", block::BLK_OFFSET),
+            ("BLK_DATA", block::BLK_DATA),
+            ("BLK_SECTORS", block::BLK_SECTORS),
+            ("PANEL_SWITCHES", panel::PANEL_SWITCHES),
+            ("PANEL_LAMPS", panel::PANEL_LAMPS),
+        ];
+        assert_eq!(constants.len(), MAP.len());
+        for (name, address) in constants {
+            assert_eq!(by_name(name).unwrap().address, address, "{}", name);
+        }
+        assert_eq!(by_name("SCREEN").unwrap().length, screen::SCREEN_LEN);
+        // ordered and not overlapping
+        for pair in MAP.windows(2) {
+            assert!(pair[0].address + pair[0].length <= pair[1].address);
+        }
+    }
+}
@@ -1,7 +1,76 @@
+use alloc::vec::Vec;
+
+use crate::{BError, vm::Ram};
+
+/// A memory mapped device that advances with the clock of the guest. Devices are handed to the VM using
+/// [`crate::vm::VmDescription`] or [`crate::vm::Vm::register_device()`] and are ticked after every cycle.
+/// Devices must be `Send` so the VM can run on a different thread than the one it was created on.
+pub trait Device: Send {
+    /// Advance the device by `cycles` clock cycles. `ram` can be used to update the registers of the device.
+    fn tick(&mut self, cycles: u64, ram: &mut Ram);
+
+    /// Save the internal state of the device, e.g. for [`crate::vm::Vm::snapshot()`].
+    /// Devices without internal state don't need to implement this.
+    fn save(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restore the internal state of the device from the output of [`Device::save()`]
+    /// ## Errors
+    /// [`BError::DeserializationError`] if the state is malformed
+    fn load(&mut self, _state: &[u8]) -> Result<(), BError> {
+        Ok(())
+    }
+
+    /// Whether the device behaves the same in every run, given the same guest and the same input from the embedder.
+    /// Devices that depend on the host, e.g. its network or file system, return `false` and can't be used by
+    /// deterministic VMs. See [`crate::vm::VmDescription::deterministic`]
+    fn deterministic(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(feature = "std")]
+pub mod block;
+
+pub mod counter;
+
 pub mod display {}
 
+#[cfg(feature = "std")]
+pub mod gpio;
+
+#[cfg(feature = "std")]
+pub mod i2c;
+
+#[cfg(feature = "std")]
+pub mod mailbox;
+
+pub mod map;
+
+#[cfg(feature = "std")]
+pub mod net;
+
+#[cfg(feature = "std")]
+pub mod panel;
+
+pub mod registry;
+
+pub mod rng;
+
+#[cfg(feature = "std")]
+pub mod ps2;
+
+#[cfg(feature = "std")]
+pub mod screen;
+
+#[cfg(feature = "std")]
+pub mod semihost;
+
+#[cfg(feature = "std")]
+pub mod spi;
+
+#[cfg(feature = "std")]
 pub mod uart;
 
 pub mod keyboard {}
-
-pub mod network {}
@@ -0,0 +1,251 @@
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crate::{
+    BError,
+    mmio::Device,
+    vm::{Ram, Vm, VmDescription, WriteEvent},
+};
+
+/// Output register. The low byte is sent by [`NET_SEND`]
+pub const NET_OUT: u16 = 0x6600;
+/// Input register. Holds the byte received by [`NET_RECV`], read only
+pub const NET_IN: u16 = 0x6601;
+/// Status register, read only. See the status flags
+pub const NET_STATUS: u16 = 0x6602;
+/// Control register. See the control flags
+pub const NET_CTRL: u16 = 0x6603;
+
+// control flags
+/// Send the byte in NET_OUT
+pub const NET_SEND: u16 = 1;
+/// Move the next received byte into NET_IN
+pub const NET_RECV: u16 = 1 << 1;
+
+// status flags
+/// A connection is established
+pub const NET_CONNECTED: u16 = 1;
+/// Received bytes are available
+pub const NET_DA: u16 = 1 << 1;
+/// The connection was closed or failed
+pub const NET_CLOSED: u16 = 1 << 2;
+
+/// How the bridge gets its connection
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NetMode {
+    /// Listen on an address, e.g. `127.0.0.1:4000`, and accept the first connection
+    Listen(String),
+    /// Connect to an address, e.g. `example.com:80`
+    Connect(String),
+}
+
+/// Represents a bridge between a byte FIFO of the guest and a TCP connection of the host. Bytes are received by a
+/// background thread. Bytes sent while no connection is established are dropped.
+#[derive(Default)]
+pub struct Net {
+    stream: Option<TcpStream>,
+    input: VecDeque<u8>,
+    write_reg: u8,
+    read_reg: u8,
+    closed: bool,
+    local_addr: Option<SocketAddr>,
+}
+
+impl Net {
+    /// called when the NET_OUT register is written to
+    pub fn write_reg_changed(&mut self, reg_content: u16) {
+        self.write_reg = reg_content as u8;
+    }
+
+    /// called when the NET_CTRL register is written to
+    pub fn control_changed(&mut self, reg_content: u16) {
+        if reg_content & NET_SEND != 0
+            && let Some(stream) = &mut self.stream
+            && stream.write_all(&[self.write_reg]).is_err()
+        {
+            self.close();
+        }
+        if reg_content & NET_RECV != 0 {
+            self.read_reg = self.input.pop_front().unwrap_or(0);
+        }
+    }
+
+    fn close(&mut self) {
+        self.stream = None;
+        self.closed = true;
+    }
+
+    /// Get the NET_STATUS register
+    pub fn get_status(&self) -> u16 {
+        let mut status = 0;
+        if self.stream.is_some() {
+            status |= NET_CONNECTED;
+        }
+        if !self.input.is_empty() {
+            status |= NET_DA;
+        }
+        if self.closed {
+            status |= NET_CLOSED;
+        }
+        status
+    }
+
+    /// Get the NET_IN register
+    pub fn get_input(&self) -> u16 {
+        self.read_reg as u16
+    }
+
+    /// Amount of received bytes the guest didn't read yet
+    pub fn available(&self) -> usize {
+        self.input.len()
+    }
+
+    /// Whether a connection is established
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Get the address the bridge listens on in [`NetMode::Listen`], e.g. to find the port when listening on port 0
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+}
+
+/// Receives bytes from `stream` into `net` until the connection closes
+fn receive(net: Arc<Mutex<Net>>, mut stream: TcpStream) {
+    let mut buf = [0; 0x100];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => {
+                net.lock().unwrap().close();
+                return;
+            }
+            Ok(len) => net.lock().unwrap().input.extend(&buf[..len]),
+        }
+    }
+}
+
+/// Hands a new connection to `net` and starts receiving
+fn start(net: &Arc<Mutex<Net>>, stream: TcpStream) -> Result<(), BError> {
+    let reader = stream
+        .try_clone()
+        .map_err(|e| BError::IoError(e.to_string()))?;
+    net.lock().unwrap().stream = Some(stream);
+    let recv_net = net.clone();
+    thread::spawn(move || receive(recv_net, reader));
+    Ok(())
+}
+
+/// Synchronizes the registers of a shared [`Net`] with RAM on every tick
+struct NetDevice(Arc<Mutex<Net>>);
+
+impl Device for NetDevice {
+    fn tick(&mut self, _cycles: u64, ram: &mut Ram) {
+        let net = self.0.lock().unwrap();
+        ram.set_ram(NET_IN, net.get_input());
+        ram.set_ram(NET_STATUS, net.get_status());
+    }
+
+    fn deterministic(&self) -> bool {
+        false
+    }
+}
+
+/// Modifies a VmDescription to mount a TCP bridge and creates it. In [`NetMode::Connect`] the connection is
+/// established before returning, in [`NetMode::Listen`] the first connection is accepted by a background thread.
+/// The connection is not part of VM snapshots.
+/// ## Errors
+/// [`BError::IoError`] if the address can't be listened on or connected to
+pub fn mount_net(vm_desc: &mut VmDescription, mode: NetMode) -> Result<Arc<Mutex<Net>>, BError> {
+    let net = Arc::new(Mutex::new(Net::default()));
+    match mode {
+        NetMode::Connect(address) => {
+            let stream = TcpStream::connect(address).map_err(|e| BError::IoError(e.to_string()))?;
+            start(&net, stream)?;
+        }
+        NetMode::Listen(address) => {
+            let listener =
+                TcpListener::bind(address).map_err(|e| BError::IoError(e.to_string()))?;
+            net.lock().unwrap().local_addr = listener.local_addr().ok();
+            let accept_net = net.clone();
+            thread::spawn(move || match listener.accept() {
+                Ok((stream, _)) => {
+                    if start(&accept_net, stream).is_err() {
+                        accept_net.lock().unwrap().close();
+                    }
+                }
+                Err(_) => accept_net.lock().unwrap().close(),
+            });
+        }
+    }
+
+    let out_net = net.clone();
+    let ctrl_net = net.clone();
+    vm_desc.callbacks.push((
+        NET_OUT,
+        Box::new(move |write: WriteEvent| out_net.lock().unwrap().write_reg_changed(write.value)),
+    ));
+    vm_desc.callbacks.push((
+        NET_CTRL,
+        Box::new(move |write: WriteEvent| ctrl_net.lock().unwrap().control_changed(write.value)),
+    ));
+    vm_desc.rom_blocks.push((NET_IN, 2));
+    vm_desc.devices.push(Box::new(NetDevice(net.clone())));
+    Ok(net)
+}
+
+/// Modifies a VmDescription to mount a TCP bridge, creates it and builds the VM. See [`mount_net()`]
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler, mmio::net::{NET_IN, NetMode, connect_net}};
+/// use std::{io::{Read, Write}, net::TcpListener, thread, time::Duration};
+///
+/// // send 'h', then receive a byte
+/// let code = r"[text]
+/// A = 0x68
+/// D = add, 0, A
+/// A = 0x6600
+/// *A = add, 0, D
+/// A = 1
+/// D = add, 0, A
+/// A = 0x6603
+/// *A = add, 0, D
+/// A = 2
+/// D = add, 0, A
+/// A = 0x6603
+/// *A = add, 0, D
+/// [consts 0x100]";
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let address = listener.local_addr().unwrap().to_string();
+/// let (mut vm, net) = connect_net(assembler::run(code).unwrap(), NetMode::Connect(address)).unwrap();
+/// let (mut peer, _) = listener.accept().unwrap();
+///
+/// for _ in 0..8 {
+///     vm.cycle().unwrap();
+/// }
+/// let mut received = [0];
+/// peer.read_exact(&mut received).unwrap();
+/// assert_eq!(&received, b"h");
+///
+/// peer.write_all(b"x").unwrap();
+/// while net.lock().unwrap().available() == 0 {
+///     thread::sleep(Duration::from_millis(1));
+/// }
+/// for _ in 0..4 {
+///     vm.cycle().unwrap();
+/// }
+/// assert_eq!(*vm.get_ram_region(NET_IN, 1), [b'x' as u16]);
+/// ```
+pub fn connect_net(
+    mut vm_desc: VmDescription,
+    mode: NetMode,
+) -> Result<(Vm, Arc<Mutex<Net>>), BError> {
+    let net = mount_net(&mut vm_desc, mode)?;
+    let vm = Vm::new(vm_desc)?;
+    Ok((vm, net))
+}
@@ -0,0 +1,171 @@
+#[cfg(feature = "cli")]
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "cli")]
+use crossterm::{
+    cursor::MoveToColumn,
+    queue,
+    style::Print,
+    terminal::{Clear, ClearType},
+};
+
+use crate::{
+    BError,
+    mmio::Device,
+    vm::{Ram, Vm, VmDescription, WriteEvent},
+};
+
+/// Lamp register. One lamp per bit, the lamp of bit 0 is at the same address as the lamp in the nandgame
+pub const PANEL_LAMPS: u16 = 0x7fff;
+/// Switch register. One switch per bit, read only
+pub const PANEL_SWITCHES: u16 = 0x7ffe;
+
+/// Represents a panel with 16 lamps the guest turns on and off and 16 switches the user flips
+#[derive(Debug, Default)]
+pub struct Panel {
+    lamps: u16,
+    switches: u16,
+    dirty: bool,
+}
+
+impl Panel {
+    /// called when the PANEL_LAMPS register is written to
+    pub fn lamps_changed(&mut self, reg_content: u16) {
+        if self.lamps != reg_content {
+            self.lamps = reg_content;
+            self.dirty = true;
+        }
+    }
+
+    /// Get the lamps, one per bit
+    pub fn get_lamps(&self) -> u16 {
+        self.lamps
+    }
+
+    /// Get whether `lamp` is on
+    pub fn lamp(&self, lamp: u8) -> bool {
+        self.lamps & (1 << (lamp & 0xf)) != 0
+    }
+
+    /// Get the switches, one per bit
+    pub fn get_switches(&self) -> u16 {
+        self.switches
+    }
+
+    /// Flip `switch` to `on`
+    pub fn set_switch(&mut self, switch: u8, on: bool) {
+        let mask = 1 << (switch & 0xf);
+        let switches = if on {
+            self.switches | mask
+        } else {
+            self.switches & !mask
+        };
+        if switches != self.switches {
+            self.switches = switches;
+            self.dirty = true;
+        }
+    }
+
+    /// Flip `switch` to the other position
+    pub fn toggle_switch(&mut self, switch: u8) {
+        let on = self.switches & (1 << (switch & 0xf)) == 0;
+        self.set_switch(switch, on);
+    }
+
+    /// Whether the panel changed since it was last rendered
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Draw the panel into the current line of a terminal. Lamps and switches are drawn from 15 down to 0
+    #[cfg(feature = "cli")]
+    pub fn render(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let lamps: String = (0..16)
+            .rev()
+            .map(|i| if self.lamp(i) { '●' } else { '○' })
+            .collect();
+        let switches: String = (0..16)
+            .rev()
+            .map(|i| {
+                if self.switches & (1 << i) != 0 {
+                    '1'
+                } else {
+                    '0'
+                }
+            })
+            .collect();
+        queue!(
+            out,
+            MoveToColumn(0),
+            Clear(ClearType::CurrentLine),
+            Print(format!("lamps {} switches {}", lamps, switches))
+        )?;
+        out.flush()?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Save the state of the panel. Laid out as: lamps, switches. 2 bytes each, big endian.
+    pub fn save(&self) -> Vec<u8> {
+        [self.lamps, self.switches]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect()
+    }
+
+    /// Restore the state of the panel from the output of [`Panel::save()`]
+    /// ## Errors
+    /// [`BError::DeserializationError`] if the state is malformed
+    pub fn load(&mut self, state: &[u8]) -> Result<(), BError> {
+        if state.len() != 4 {
+            return Err(BError::DeserializationError(
+                "Invalid panel state length".into(),
+            ));
+        }
+        self.lamps = u16::from_be_bytes([state[0], state[1]]);
+        self.switches = u16::from_be_bytes([state[2], state[3]]);
+        self.dirty = true;
+        Ok(())
+    }
+}
+
+/// Synchronizes the switch register of a shared [`Panel`] with RAM on every tick
+struct PanelDevice(Arc<Mutex<Panel>>);
+
+impl Device for PanelDevice {
+    fn tick(&mut self, _cycles: u64, ram: &mut Ram) {
+        ram.set_ram(PANEL_SWITCHES, self.0.lock().unwrap().get_switches());
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.0.lock().unwrap().save()
+    }
+
+    fn load(&mut self, state: &[u8]) -> Result<(), BError> {
+        self.0.lock().unwrap().load(state)
+    }
+}
+
+/// Modifies a VmDescription to mount a lamp and switch panel and creates the panel.
+/// The switch register is updated by the VM after every cycle.
+pub fn mount_panel(vm_desc: &mut VmDescription) -> Arc<Mutex<Panel>> {
+    let panel = Arc::new(Mutex::new(Panel::default()));
+    let lamp_panel = panel.clone();
+
+    vm_desc.callbacks.push((
+        PANEL_LAMPS,
+        Box::new(move |write: WriteEvent| lamp_panel.lock().unwrap().lamps_changed(write.value)),
+    ));
+    vm_desc.rom_blocks.push((PANEL_SWITCHES, 1));
+    vm_desc.devices.push(Box::new(PanelDevice(panel.clone())));
+    panel
+}
+
+/// Modifies a VmDescription to mount a lamp and switch panel, creates the panel and builds the VM.
+/// See [`mount_panel()`]
+pub fn connect_panel(mut vm_desc: VmDescription) -> Result<(Vm, Arc<Mutex<Panel>>), BError> {
+    let panel = mount_panel(&mut vm_desc);
+    let vm = Vm::new(vm_desc)?;
+    Ok((vm, panel))
+}
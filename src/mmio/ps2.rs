@@ -0,0 +1,260 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    BError,
+    mmio::Device,
+    vm::{Ram, Vm, VmDescription, WriteEvent},
+};
+
+/// Data register. Holds the scancode byte received by [`KBD_RECV`], read only
+pub const KBD_DATA: u16 = 0x6900;
+/// Status register, read only. See the status flags
+pub const KBD_STATUS: u16 = 0x6901;
+/// Control register. See the control flags
+pub const KBD_CTRL: u16 = 0x6902;
+
+// control flags
+/// Move the next scancode byte into KBD_DATA
+pub const KBD_RECV: u16 = 1;
+/// Drop all pending scancode bytes and clear KBD_OVERFLOW
+pub const KBD_CLEAR: u16 = 1 << 1;
+
+// status flags
+/// Scancode bytes are available
+pub const KBD_DA: u16 = 1;
+/// The FIFO overflowed and scancode bytes were dropped
+pub const KBD_OVERFLOW: u16 = 1 << 1;
+
+/// Amount of scancode bytes the FIFO holds
+pub const KBD_FIFO_LEN: usize = 16;
+
+/// Prefix of extended scancodes
+pub const EXTENDED: u8 = 0xe0;
+/// Prefix of break codes
+pub const BREAK: u8 = 0xf0;
+
+// make codes of scancode set 2. Extended keys carry the 0xe0 prefix in the high byte
+pub const KEY_LSHIFT: u16 = 0x12;
+pub const KEY_RSHIFT: u16 = 0x59;
+pub const KEY_LCTRL: u16 = 0x14;
+pub const KEY_RCTRL: u16 = 0xe014;
+pub const KEY_LALT: u16 = 0x11;
+pub const KEY_RALT: u16 = 0xe011;
+pub const KEY_ENTER: u16 = 0x5a;
+pub const KEY_BACKSPACE: u16 = 0x66;
+pub const KEY_ESCAPE: u16 = 0x76;
+pub const KEY_TAB: u16 = 0x0d;
+pub const KEY_SPACE: u16 = 0x29;
+pub const KEY_UP: u16 = 0xe075;
+pub const KEY_DOWN: u16 = 0xe072;
+pub const KEY_LEFT: u16 = 0xe06b;
+pub const KEY_RIGHT: u16 = 0xe074;
+
+/// Make codes of the letters a-z
+const LETTERS: [u8; 26] = [
+    0x1c, 0x32, 0x21, 0x23, 0x24, 0x2b, 0x34, 0x33, 0x43, 0x3b, 0x42, 0x4b, 0x3a, 0x31, 0x44, 0x4d,
+    0x15, 0x2d, 0x1b, 0x2c, 0x3c, 0x2a, 0x1d, 0x22, 0x35, 0x1a,
+];
+/// Make codes of the digits 0-9
+const DIGITS: [u8; 10] = [0x45, 0x16, 0x1e, 0x26, 0x25, 0x2e, 0x36, 0x3d, 0x3e, 0x46];
+
+/// Get the make code of the key that types `c` on a US layout and whether shift has to be held.
+/// Supports letters, digits, space, enter, tab and backspace
+pub fn key_for_char(c: char) -> Option<(u16, bool)> {
+    match c {
+        'a'..='z' => Some((LETTERS[c as usize - 'a' as usize] as u16, false)),
+        'A'..='Z' => Some((LETTERS[c as usize - 'A' as usize] as u16, true)),
+        '0'..='9' => Some((DIGITS[c as usize - '0' as usize] as u16, false)),
+        ' ' => Some((KEY_SPACE, false)),
+        '\n' => Some((KEY_ENTER, false)),
+        '\t' => Some((KEY_TAB, false)),
+        '\x08' => Some((KEY_BACKSPACE, false)),
+        _ => None,
+    }
+}
+
+/// Represents a PS/2 keyboard sending scancode set 2 through a FIFO. The embedder presses and releases keys, the guest
+/// receives the make and break codes byte by byte and has to track modifiers itself.
+#[derive(Default)]
+pub struct Ps2Keyboard {
+    fifo: VecDeque<u8>,
+    data: u16,
+    overflow: bool,
+}
+
+impl Ps2Keyboard {
+    /// Send a raw scancode byte. Sets KBD_OVERFLOW and drops the byte if the FIFO is full
+    pub fn send(&mut self, byte: u8) {
+        if self.fifo.len() >= KBD_FIFO_LEN {
+            self.overflow = true;
+        } else {
+            self.fifo.push_back(byte);
+        }
+    }
+
+    /// Send the make code of `key`, e.g. [`KEY_LSHIFT`]
+    pub fn press(&mut self, key: u16) {
+        if key >> 8 == EXTENDED as u16 {
+            self.send(EXTENDED);
+        }
+        self.send(key as u8);
+    }
+
+    /// Send the break code of `key`
+    pub fn release(&mut self, key: u16) {
+        if key >> 8 == EXTENDED as u16 {
+            self.send(EXTENDED);
+        }
+        self.send(BREAK);
+        self.send(key as u8);
+    }
+
+    /// Press and release the keys that type `c`, holding shift if necessary. See [`key_for_char()`]
+    /// ## Errors
+    /// [`BError::IoError`] if no key types `c`
+    pub fn type_char(&mut self, c: char) -> Result<(), BError> {
+        let (key, shift) =
+            key_for_char(c).ok_or_else(|| BError::IoError(format!("no key types {:?}", c)))?;
+        if shift {
+            self.press(KEY_LSHIFT);
+        }
+        self.press(key);
+        self.release(key);
+        if shift {
+            self.release(KEY_LSHIFT);
+        }
+        Ok(())
+    }
+
+    /// called when the KBD_CTRL register is written to
+    pub fn control_changed(&mut self, reg_content: u16) {
+        if reg_content & KBD_RECV != 0 {
+            self.data = self.fifo.pop_front().unwrap_or(0) as u16;
+        }
+        if reg_content & KBD_CLEAR != 0 {
+            self.fifo.clear();
+            self.overflow = false;
+        }
+    }
+
+    /// Get the KBD_STATUS register
+    pub fn get_status(&self) -> u16 {
+        let mut status = 0;
+        if !self.fifo.is_empty() {
+            status |= KBD_DA;
+        }
+        if self.overflow {
+            status |= KBD_OVERFLOW;
+        }
+        status
+    }
+
+    /// Get the KBD_DATA register
+    pub fn get_data(&self) -> u16 {
+        self.data
+    }
+
+    /// Save the state of the keyboard. Laid out as: KBD_DATA (1 byte), overflow (1 byte), then the pending bytes
+    pub fn save(&self) -> Vec<u8> {
+        let mut out = vec![self.data as u8, self.overflow as u8];
+        out.extend(&self.fifo);
+        out
+    }
+
+    /// Restore the state of the keyboard from the output of [`Ps2Keyboard::save()`]
+    /// ## Errors
+    /// [`BError::DeserializationError`] if the state is malformed
+    pub fn load(&mut self, state: &[u8]) -> Result<(), BError> {
+        if state.len() < 2 || state.len() - 2 > KBD_FIFO_LEN {
+            return Err(BError::DeserializationError(
+                "Invalid keyboard state length".into(),
+            ));
+        }
+        self.data = state[0] as u16;
+        self.overflow = state[1] != 0;
+        self.fifo = state[2..].iter().copied().collect();
+        Ok(())
+    }
+}
+
+/// Synchronizes the registers of a shared [`Ps2Keyboard`] with RAM on every tick
+struct Ps2KeyboardDevice(Arc<Mutex<Ps2Keyboard>>);
+
+impl Device for Ps2KeyboardDevice {
+    fn tick(&mut self, _cycles: u64, ram: &mut Ram) {
+        let keyboard = self.0.lock().unwrap();
+        ram.set_ram(KBD_DATA, keyboard.get_data());
+        ram.set_ram(KBD_STATUS, keyboard.get_status());
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.0.lock().unwrap().save()
+    }
+
+    fn load(&mut self, state: &[u8]) -> Result<(), BError> {
+        self.0.lock().unwrap().load(state)
+    }
+}
+
+/// Modifies a VmDescription to mount a PS/2 keyboard and creates it. The data and status registers are updated by the
+/// VM after every cycle.
+pub fn mount_ps2_keyboard(vm_desc: &mut VmDescription) -> Arc<Mutex<Ps2Keyboard>> {
+    let keyboard = Arc::new(Mutex::new(Ps2Keyboard::default()));
+    let ctrl_keyboard = keyboard.clone();
+
+    vm_desc.callbacks.push((
+        KBD_CTRL,
+        Box::new(move |write: WriteEvent| {
+            ctrl_keyboard.lock().unwrap().control_changed(write.value)
+        }),
+    ));
+    vm_desc.rom_blocks.push((KBD_DATA, 2));
+    vm_desc
+        .devices
+        .push(Box::new(Ps2KeyboardDevice(keyboard.clone())));
+    keyboard
+}
+
+/// Modifies a VmDescription to mount a PS/2 keyboard, creates it and builds the VM. See [`mount_ps2_keyboard()`]
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler, mmio::ps2::{KBD_DATA, KEY_LSHIFT, connect_ps2_keyboard}};
+///
+/// // receive two scancode bytes into 0x100 and 0x101
+/// let code = r"[text]
+/// A = 1
+/// D = add, 0, A
+/// A = 0x6902
+/// *A = add, 0, D
+/// A = 0x6900
+/// D = add, 0, *A
+/// A = 0x100
+/// *A = add, 0, D
+/// A = 1
+/// D = add, 0, A
+/// A = 0x6902
+/// *A = add, 0, D
+/// A = 0x6900
+/// D = add, 0, *A
+/// A = 0x101
+/// *A = add, 0, D
+/// [consts 0x200]";
+/// let (mut vm, keyboard) = connect_ps2_keyboard(assembler::run(code).unwrap()).unwrap();
+/// keyboard.lock().unwrap().release(KEY_LSHIFT);
+///
+/// for _ in 0..16 {
+///     vm.cycle().unwrap();
+/// }
+/// assert_eq!(*vm.get_ram_region(0x100, 2), [0xf0, 0x12]);
+/// assert_eq!(*vm.get_ram_region(KBD_DATA, 2), [0x12, 0]);
+/// ```
+pub fn connect_ps2_keyboard(
+    mut vm_desc: VmDescription,
+) -> Result<(Vm, Arc<Mutex<Ps2Keyboard>>), BError> {
+    let keyboard = mount_ps2_keyboard(&mut vm_desc);
+    let vm = Vm::new(vm_desc)?;
+    Ok((vm, keyboard))
+}
@@ -0,0 +1,321 @@
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::any::Any;
+
+use crate::{
+    BError,
+    vm::{Vm, VmDescription},
+};
+
+/// A named device instance listed in a [`VmDescription`]. Instances are mounted by the factory registered for their
+/// `kind` in a [`DeviceRegistry`].
+/// - `kind`: Name of the factory, e.g. `uart`
+/// - `name`: Name of the instance, unique in a description
+/// - `base`: Address of the first register of the device
+/// - `config`: Configuration interpreted by the factory
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceConfig {
+    pub kind: String,
+    pub name: String,
+    pub base: u16,
+    pub config: Vec<u8>,
+}
+
+impl DeviceConfig {
+    /// Create a device instance without configuration
+    pub fn new(kind: &str, name: &str, base: u16) -> Self {
+        Self {
+            kind: kind.to_string(),
+            name: name.to_string(),
+            base,
+            config: Vec::new(),
+        }
+    }
+
+    /// Serialize a list of device instances. Laid out as: count (2 bytes), then per instance kind length (1 byte),
+    /// kind, name length (1 byte), name, base (2 bytes), config length (2 bytes), config. Big endian.
+    pub(crate) fn serialize_all(configs: &[Self]) -> Result<Vec<u8>, BError> {
+        if configs.len() > 0xffff {
+            return Err(BError::SerializationError(
+                "The number of devices is to large".into(),
+            ));
+        }
+        let mut output = (configs.len() as u16).to_be_bytes().to_vec();
+        for device in configs {
+            if device.kind.len() > 0xff || device.name.len() > 0xff || device.config.len() > 0xffff
+            {
+                return Err(BError::SerializationError(
+                    format!("The device {} is to large", device.name).into(),
+                ));
+            }
+            for text in [&device.kind, &device.name] {
+                output.push(text.len() as u8);
+                output.extend_from_slice(text.as_bytes());
+            }
+            output.extend_from_slice(&device.base.to_be_bytes());
+            output.extend_from_slice(&(device.config.len() as u16).to_be_bytes());
+            output.extend_from_slice(&device.config);
+        }
+        Ok(output)
+    }
+
+    /// Deserialize the output of [`DeviceConfig::serialize_all()`]
+    pub(crate) fn deserialize_all(input: &[u8]) -> Result<Vec<Self>, BError> {
+        use crate::util::check_slice;
+        let invalid = || BError::DeserializationError("Invalid device segment".into());
+
+        let count = check_slice(input, 2)?;
+        let count = u16::from_be_bytes([count[0], count[1]]);
+        let mut current = &input[2..];
+        let mut configs = Vec::new();
+        for _ in 0..count {
+            let mut texts = Vec::with_capacity(2);
+            for _ in 0..2 {
+                let len = *check_slice(current, 1)?.first().unwrap() as usize;
+                let text = check_slice(&current[1..], len)?;
+                texts.push(String::from_utf8(text.to_vec()).map_err(|_| invalid())?);
+                current = &current[1 + len..];
+            }
+            let header = check_slice(current, 4)?;
+            let base = u16::from_be_bytes([header[0], header[1]]);
+            let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+            let config = check_slice(&current[4..], len)?.to_vec();
+            current = &current[4 + len..];
+            let name = texts.pop().unwrap();
+            let kind = texts.pop().unwrap();
+            configs.push(Self {
+                kind,
+                name,
+                base,
+                config,
+            });
+        }
+        if !current.is_empty() {
+            return Err(invalid());
+        }
+        Ok(configs)
+    }
+}
+
+/// Returned by a [`DeviceFactory`] to give the embedder access to the device, e.g. an `Arc<Mutex<Uart>>`.
+/// Use [`Box::downcast()`] to get the concrete type
+pub type DeviceHandle = Box<dyn Any + Send>;
+
+/// Mounts a device instance onto a VmDescription, e.g. by pushing callbacks and a [`crate::mmio::Device`]
+pub type DeviceFactory =
+    Box<dyn Fn(&mut VmDescription, &DeviceConfig) -> Result<DeviceHandle, BError> + Send + Sync>;
+
+/// Maps device kinds to the factories that mount them. [`DeviceRegistry::default()`] knows the built-in devices,
+/// third-party devices are added with [`DeviceRegistry::register()`].
+/// ## Examples
+/// ```rust
+/// use bric_vm::{
+///     mmio::{registry::{DeviceConfig, DeviceHandle, DeviceRegistry}, uart::Uart},
+///     vm::VmDescription,
+/// };
+/// use std::sync::{Arc, Mutex};
+///
+/// let mut registry = DeviceRegistry::default();
+/// // a third-party device: a constant word at `base` taken from the config
+/// registry.register(
+///     "constant",
+///     Box::new(|vm_desc, device| {
+///         let value = u16::from_be_bytes([device.config[0], device.config[1]]);
+///         vm_desc.mem[device.base as usize] = value;
+///         vm_desc.rom_blocks.push((device.base, 1));
+///         Ok(Box::new(()) as DeviceHandle)
+///     }),
+/// );
+///
+/// let vm_desc = VmDescription {
+///     device_configs: vec![
+///         DeviceConfig::new("uart", "console", 0x6000),
+///         DeviceConfig::new("uart", "modem", 0x6010),
+///         DeviceConfig { config: vec![0x12, 0x34], ..DeviceConfig::new("constant", "id", 0x7000) },
+///     ],
+///     ..Default::default()
+/// };
+/// let (vm, mut handles) = registry.build(vm_desc).unwrap();
+/// let modem = handles.remove("modem").unwrap().downcast::<Arc<Mutex<Uart>>>().unwrap();
+/// modem.lock().unwrap().put_input(b'a');
+/// assert_eq!(*vm.get_ram_region(0x7000, 1), [0x1234]);
+/// assert_eq!(vm.to_vm_desc().device_configs.len(), 3);
+/// ```
+pub struct DeviceRegistry {
+    factories: BTreeMap<String, DeviceFactory>,
+}
+
+impl DeviceRegistry {
+    /// Create a registry without any factories
+    pub fn new() -> Self {
+        Self {
+            factories: BTreeMap::new(),
+        }
+    }
+
+    /// Register the factory of a device kind. Replaces an existing factory of the same kind
+    pub fn register(&mut self, kind: &str, factory: DeviceFactory) {
+        self.factories.insert(kind.to_string(), factory);
+    }
+
+    /// Get the device kinds the registry knows
+    pub fn kinds(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(String::as_str)
+    }
+
+    /// Mount all device instances listed in `vm_desc.device_configs`. Returns the handles by instance name
+    /// ## Errors
+    /// - [`BError::MapError`] if a kind is unknown or two instances have the same name
+    /// - Any error of a factory
+    pub fn mount(
+        &self,
+        vm_desc: &mut VmDescription,
+    ) -> Result<BTreeMap<String, DeviceHandle>, BError> {
+        let mut handles = BTreeMap::new();
+        for device in vm_desc.device_configs.clone() {
+            let factory = self.factories.get(&device.kind).ok_or_else(|| {
+                BError::MapError(format!(
+                    "unknown kind {} of device {}",
+                    device.kind, device.name
+                ))
+            })?;
+            if handles.contains_key(&device.name) {
+                return Err(BError::MapError(format!(
+                    "two devices are named {}",
+                    device.name
+                )));
+            }
+            let handle = factory(vm_desc, &device)?;
+            handles.insert(device.name, handle);
+        }
+        Ok(handles)
+    }
+
+    /// Mount all device instances listed in `vm_desc.device_configs` and build the VM. See [`DeviceRegistry::mount()`]
+    pub fn build(
+        &self,
+        mut vm_desc: VmDescription,
+    ) -> Result<(Vm, BTreeMap<String, DeviceHandle>), BError> {
+        let handles = self.mount(&mut vm_desc)?;
+        let vm = Vm::new(vm_desc)?;
+        Ok((vm, handles))
+    }
+}
+
+/// Built-in devices. Only the UART can be moved, the other devices require `base` to be their usual address
+#[cfg(feature = "std")]
+mod builtin {
+    use super::{DeviceConfig, DeviceFactory, DeviceHandle};
+    use crate::{BError, mmio, vm::VmDescription};
+    use alloc::{boxed::Box, format};
+
+    fn fixed(
+        base: u16,
+        mount: fn(&mut VmDescription) -> DeviceHandle,
+    ) -> impl Fn(&mut VmDescription, &DeviceConfig) -> Result<DeviceHandle, BError> {
+        move |vm_desc, device| {
+            if device.base != base {
+                return Err(BError::MapError(format!(
+                    "the {} device {} can only be mounted at {:#06x}",
+                    device.kind, device.name, base
+                )));
+            }
+            Ok(mount(vm_desc))
+        }
+    }
+
+    pub(super) fn factories() -> [(&'static str, DeviceFactory); 8] {
+        [
+            (
+                "uart",
+                Box::new(|vm_desc, device| {
+                    Ok(Box::new(mmio::uart::mount_uart_at(vm_desc, device.base)?) as DeviceHandle)
+                }),
+            ),
+            (
+                "gpio",
+                Box::new(fixed(mmio::gpio::GPIO_DIR, |vm_desc| {
+                    Box::new(mmio::gpio::mount_gpio(vm_desc))
+                })),
+            ),
+            (
+                "panel",
+                Box::new(fixed(mmio::panel::PANEL_SWITCHES, |vm_desc| {
+                    Box::new(mmio::panel::mount_panel(vm_desc))
+                })),
+            ),
+            (
+                "screen",
+                Box::new(fixed(mmio::screen::SCREEN_BASE, |vm_desc| {
+                    Box::new(mmio::screen::mount_screen(vm_desc))
+                })),
+            ),
+            (
+                "spi",
+                Box::new(fixed(mmio::spi::SPI_DATA, |vm_desc| {
+                    Box::new(mmio::spi::mount_spi(vm_desc))
+                })),
+            ),
+            (
+                "i2c",
+                Box::new(fixed(mmio::i2c::I2C_DATA, |vm_desc| {
+                    Box::new(mmio::i2c::mount_i2c(vm_desc))
+                })),
+            ),
+            (
+                "ps2_keyboard",
+                Box::new(fixed(mmio::ps2::KBD_DATA, |vm_desc| {
+                    Box::new(mmio::ps2::mount_ps2_keyboard(vm_desc))
+                })),
+            ),
+            (
+                "cycle_counter",
+                Box::new(fixed(mmio::counter::CYCLE_LOW, |vm_desc| {
+                    Box::new(mmio::counter::mount_cycle_counter(vm_desc))
+                })),
+            ),
+        ]
+    }
+}
+
+impl Default for DeviceRegistry {
+    /// Create a registry that knows the built-in devices: `uart`, `gpio`, `panel`, `screen`, `spi`, `i2c`,
+    /// `ps2_keyboard` and `cycle_counter`. Without the `std` feature it is empty. Semihosting and the network bridge
+    /// are not built in since they give the guest access to the host, register them explicitly if a program should be
+    /// allowed to use them.
+    fn default() -> Self {
+        #[allow(unused_mut)]
+        let mut registry = Self::new();
+        #[cfg(feature = "std")]
+        for (kind, factory) in builtin::factories() {
+            registry.register(kind, factory);
+        }
+        registry
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serde_round_trip() {
+        let configs = alloc::vec![
+            DeviceConfig::new("uart", "console", 0x6000),
+            DeviceConfig {
+                config: alloc::vec![1, 2, 0xff],
+                ..DeviceConfig::new("sensor", "thermometer", 0x7000)
+            },
+        ];
+        let json = serde_json::to_string(&configs).unwrap();
+        assert!(json.contains(r#""kind":"sensor""#));
+        let back: Vec<DeviceConfig> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, configs);
+    }
+}
@@ -0,0 +1,90 @@
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    BError,
+    mmio::Device,
+    vm::{Ram, VmDescription},
+};
+
+/// Data register. Every read returns the next random value, read only
+pub const RNG_DATA: u16 = 0x6200;
+
+const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// A SplitMix64 pseudo random number generator. The same seed always produces the same sequence.
+/// Clones share their state.
+#[derive(Clone, Debug, Default)]
+pub struct Rng {
+    state: Arc<AtomicU64>,
+}
+
+impl Rng {
+    /// Create a generator from `seed`
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: Arc::new(AtomicU64::new(seed)),
+        }
+    }
+
+    /// Advance the generator and get the next 64 bit value
+    pub fn next_u64(&self) -> u64 {
+        let mut z = self
+            .state
+            .fetch_add(GAMMA, Ordering::Relaxed)
+            .wrapping_add(GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Advance the generator and get the next 16 bit value, the value of the RNG_DATA register
+    pub fn next_u16(&self) -> u16 {
+        (self.next_u64() >> 48) as u16
+    }
+
+    /// Save the state of the generator. 8 bytes, big endian.
+    pub fn save(&self) -> Vec<u8> {
+        self.state.load(Ordering::Relaxed).to_be_bytes().to_vec()
+    }
+
+    /// Restore the state of the generator from the output of [`Rng::save()`]
+    /// ## Errors
+    /// [`BError::DeserializationError`] if the state is malformed
+    pub fn load(&self, state: &[u8]) -> Result<(), BError> {
+        let state: [u8; 8] = state
+            .try_into()
+            .map_err(|_| BError::DeserializationError("Invalid RNG state length".into()))?;
+        self.state
+            .store(u64::from_be_bytes(state), Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Makes the state of the generator part of snapshots. The generator itself only advances on reads
+struct RngDevice(Rng);
+
+impl Device for RngDevice {
+    fn tick(&mut self, _cycles: u64, _ram: &mut Ram) {}
+
+    fn save(&self) -> Vec<u8> {
+        self.0.save()
+    }
+
+    fn load(&mut self, state: &[u8]) -> Result<(), BError> {
+        self.0.load(state)
+    }
+}
+
+/// Modifies a VmDescription to mount a random number generator seeded with `seed` at [`RNG_DATA`].
+/// [`crate::vm::Vm::new()`] does this for descriptions with an `rng_seed`.
+pub fn mount_rng(vm_desc: &mut VmDescription, seed: u64) -> Rng {
+    let rng = Rng::new(seed);
+    let read_rng = rng.clone();
+    vm_desc
+        .read_callbacks
+        .push((RNG_DATA, Box::new(move |_| read_rng.next_u16())));
+    vm_desc.rom_blocks.push((RNG_DATA, 1));
+    vm_desc.devices.push(Box::new(RngDevice(rng.clone())));
+    rng
+}
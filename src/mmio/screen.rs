@@ -0,0 +1,149 @@
+#[cfg(feature = "cli")]
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "cli")]
+use crossterm::{
+    cursor::MoveTo,
+    queue,
+    style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
+};
+
+use crate::{
+    BError,
+    vm::{Vm, VmDescription, WriteEvent},
+};
+
+/// Columns of the screen
+pub const SCREEN_WIDTH: u16 = 80;
+/// Rows of the screen
+pub const SCREEN_HEIGHT: u16 = 25;
+/// Amount of cells of the screen
+pub const SCREEN_LEN: u16 = SCREEN_WIDTH * SCREEN_HEIGHT;
+/// First cell of the screen. Cells are laid out row by row
+pub const SCREEN_BASE: u16 = 0x5000;
+
+/// Represents a character cell screen. Every cell is one word: the low byte is an ASCII character, the low nibble
+/// of the high byte is the foreground color and the high nibble the background color. Color 0 is the default color of
+/// the terminal, colors 1-15 are the ANSI colors 1-15.
+pub struct TextScreen {
+    cells: Vec<u16>,
+    dirty: bool,
+}
+
+impl TextScreen {
+    /// called when a cell of the screen is written to
+    pub fn write_cell(&mut self, offset: u16, value: u16) {
+        if let Some(cell) = self.cells.get_mut(offset as usize)
+            && *cell != value
+        {
+            *cell = value;
+            self.dirty = true;
+        }
+    }
+
+    /// Get the cell in column `x` and row `y`, `None` outside of the screen
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::mmio::screen::TextScreen;
+    ///
+    /// let mut screen = TextScreen::default();
+    /// screen.write_cell(81, b'x' as u16);
+    /// assert_eq!(screen.get_cell(1, 1), Some(b'x' as u16));
+    /// assert_eq!(screen.get_cell(80, 0), None);
+    /// assert_eq!(screen.get_cell(0, 1000), None);
+    /// ```
+    pub fn get_cell(&self, x: u16, y: u16) -> Option<u16> {
+        if x >= SCREEN_WIDTH {
+            return None;
+        }
+        self.cells
+            .get((y as usize) * (SCREEN_WIDTH as usize) + x as usize)
+            .copied()
+    }
+
+    /// Get the characters of the screen row by row. Characters that aren't printable ASCII are replaced by spaces
+    pub fn lines(&self) -> Vec<String> {
+        self.cells
+            .chunks(SCREEN_WIDTH as usize)
+            .map(|row| row.iter().map(|cell| cell_char(*cell)).collect())
+            .collect()
+    }
+
+    /// Whether the screen changed since it was last rendered
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Draw the screen into the top left corner of a terminal using crossterm
+    #[cfg(feature = "cli")]
+    pub fn render(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let mut current_attributes = None;
+        for (y, row) in self.cells.chunks(SCREEN_WIDTH as usize).enumerate() {
+            queue!(out, MoveTo(0, y as u16))?;
+            for cell in row.iter() {
+                let [attributes, _] = cell.to_be_bytes();
+                if current_attributes != Some(attributes) {
+                    queue!(
+                        out,
+                        SetForegroundColor(cell_color(attributes & 0xf)),
+                        SetBackgroundColor(cell_color(attributes >> 4))
+                    )?;
+                    current_attributes = Some(attributes);
+                }
+                queue!(out, Print(cell_char(*cell)))?;
+            }
+        }
+        queue!(out, ResetColor)?;
+        out.flush()?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl Default for TextScreen {
+    fn default() -> Self {
+        Self {
+            cells: vec![0; SCREEN_LEN as usize],
+            dirty: true,
+        }
+    }
+}
+
+fn cell_char(cell: u16) -> char {
+    match (cell & 0xff) as u8 {
+        c @ 0x20..=0x7e => c as char,
+        _ => ' ',
+    }
+}
+
+#[cfg(feature = "cli")]
+fn cell_color(color: u8) -> Color {
+    match color {
+        0 => Color::Reset,
+        c => Color::AnsiValue(c),
+    }
+}
+
+/// Modifies a VmDescription to mount a text screen at [`SCREEN_BASE`] and creates the screen.
+/// The screen is updated by a region callback whenever the guest writes to it, so creating the VM fails if another
+/// region callback overlaps the screen.
+pub fn mount_screen(vm_desc: &mut VmDescription) -> Arc<Mutex<TextScreen>> {
+    let screen = Arc::new(Mutex::new(TextScreen::default()));
+    let cb_screen = screen.clone();
+    vm_desc.region_callbacks.push((
+        SCREEN_BASE,
+        SCREEN_LEN,
+        Box::new(move |offset: u16, write: WriteEvent| {
+            cb_screen.lock().unwrap().write_cell(offset, write.value);
+        }),
+    ));
+    screen
+}
+
+/// Modifies a VmDescription to mount a text screen, creates the screen and builds the VM. See [`mount_screen()`]
+pub fn connect_screen(mut vm_desc: VmDescription) -> Result<(Vm, Arc<Mutex<TextScreen>>), BError> {
+    let screen = mount_screen(&mut vm_desc);
+    let vm = Vm::new(vm_desc)?;
+    Ok((vm, screen))
+}
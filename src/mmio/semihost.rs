@@ -0,0 +1,273 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    BError,
+    mmio::Device,
+    vm::{RAM_LEN, Ram, Vm, VmDescription, WriteEvent},
+};
+
+/// Operation register. Writing an operation code starts the operation
+pub const SH_OP: u16 = 0x6300;
+/// First argument of the operation
+pub const SH_ARG0: u16 = 0x6301;
+/// Second argument of the operation
+pub const SH_ARG1: u16 = 0x6302;
+/// Third argument of the operation
+pub const SH_ARG2: u16 = 0x6303;
+/// Result of the last operation, read only. [`SH_ERROR`] if it failed
+pub const SH_RESULT: u16 = 0x6304;
+
+// operations
+/// Open the file at the zero terminated path at ARG0 with the mode in ARG1. Results in a handle
+pub const SH_OPEN: u16 = 1;
+/// Close the handle in ARG0
+pub const SH_CLOSE: u16 = 2;
+/// Read up to ARG2 bytes from the handle in ARG0 into ARG1, one byte per word. Results in the amount of bytes read
+pub const SH_READ: u16 = 3;
+/// Write the low bytes of ARG2 words at ARG1 to the handle in ARG0. Results in the amount of bytes written
+pub const SH_WRITE: u16 = 4;
+
+// open modes
+/// Open an existing file for reading
+pub const MODE_READ: u16 = 0;
+/// Create or truncate a file for writing
+pub const MODE_WRITE: u16 = 1;
+/// Create a file or append to it
+pub const MODE_APPEND: u16 = 2;
+
+/// Result of a failed operation
+pub const SH_ERROR: u16 = 0xffff;
+
+/// Maximum length of a path in words
+const MAX_PATH_LEN: usize = 0x100;
+
+/// Gives the guest access to the files in a directory of the host. Paths are relative to that directory and may not
+/// leave it.
+pub struct Semihosting {
+    root: PathBuf,
+    files: Vec<Option<File>>,
+    pending: Option<u16>,
+}
+
+impl Semihosting {
+    /// Create a semihosting device for the files in `root`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            files: Vec::new(),
+            pending: None,
+        }
+    }
+
+    /// called when the SH_OP register is written to
+    pub fn op_changed(&mut self, reg_content: u16) {
+        self.pending = Some(reg_content);
+    }
+
+    /// Get the directory the guest has access to
+    pub fn get_root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Close all files opened by the guest
+    pub fn close_all(&mut self) {
+        self.files.clear();
+    }
+
+    /// Run the pending operation, if any, and write its result to SH_RESULT
+    fn run(&mut self, ram: &mut Ram) {
+        let Some(op) = self.pending.take() else {
+            return;
+        };
+        let [arg0, arg1, arg2] = <[u16; 3]>::try_from(&*ram.get_ram_region(SH_ARG0, 3)).unwrap();
+        let result = match op {
+            SH_OPEN => self.open(ram, arg0, arg1),
+            SH_CLOSE => self
+                .files
+                .get_mut(arg0 as usize)
+                .and_then(Option::take)
+                .map(|_| 0),
+            SH_READ => self.read(ram, arg0, arg1, arg2),
+            SH_WRITE => self.write(ram, arg0, arg1, arg2),
+            _ => None,
+        };
+        ram.set_ram(SH_RESULT, result.unwrap_or(SH_ERROR));
+    }
+
+    fn open(&mut self, ram: &Ram, path: u16, mode: u16) -> Option<u16> {
+        let path: String = ram
+            .get_ram_region(path, MAX_PATH_LEN)
+            .iter()
+            .take_while(|c| **c != 0)
+            .map(|c| (*c & 0xff) as u8 as char)
+            .collect();
+        let path = self.resolve(&path)?;
+        let file = match mode {
+            MODE_READ => File::open(path),
+            MODE_WRITE => File::create(path),
+            MODE_APPEND => OpenOptions::new().append(true).create(true).open(path),
+            _ => return None,
+        }
+        .ok()?;
+
+        let handle = match self.files.iter().position(Option::is_none) {
+            Some(handle) => handle,
+            None => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+        };
+        if handle >= SH_ERROR as usize {
+            return None;
+        }
+        self.files[handle] = Some(file);
+        Some(handle as u16)
+    }
+
+    fn read(&mut self, ram: &mut Ram, handle: u16, buffer: u16, length: u16) -> Option<u16> {
+        let file = self.files.get_mut(handle as usize)?.as_mut()?;
+        let length = (length as usize).min(RAM_LEN - buffer as usize);
+        let mut bytes = vec![0; length];
+        let read = file.read(&mut bytes).ok()?;
+        let words: Vec<u16> = bytes[..read].iter().map(|b| *b as u16).collect();
+        ram.set_ram_region(buffer, &words).ok()?;
+        Some(read as u16)
+    }
+
+    fn write(&mut self, ram: &Ram, handle: u16, buffer: u16, length: u16) -> Option<u16> {
+        let file = self.files.get_mut(handle as usize)?.as_mut()?;
+        let bytes: Vec<u8> = ram
+            .get_ram_region(buffer, length as usize)
+            .iter()
+            .map(|w| (*w & 0xff) as u8)
+            .collect();
+        file.write_all(&bytes).ok()?;
+        Some(bytes.len() as u16)
+    }
+
+    /// Resolve a path of the guest to a path in the root. Fails if the path is absolute, contains `..`, leads out of
+    /// the root through a symlinked directory or is a symlink itself, which could be dangling
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let path = Path::new(path);
+        if path.as_os_str().is_empty()
+            || !path
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return None;
+        }
+        let root = self.root.canonicalize().ok()?;
+        let full = root.join(path);
+        let parent = full.parent()?.canonicalize().ok()?;
+        if !parent.starts_with(&root) {
+            return None;
+        }
+        // creating a file through a dangling symlink would follow it out of the root
+        if full
+            .symlink_metadata()
+            .is_ok_and(|metadata| metadata.file_type().is_symlink())
+        {
+            return None;
+        }
+        Some(full)
+    }
+}
+
+/// Runs the operations of a shared [`Semihosting`] device. Open files are not part of snapshots
+struct SemihostingDevice(Arc<Mutex<Semihosting>>);
+
+impl Device for SemihostingDevice {
+    fn tick(&mut self, _cycles: u64, ram: &mut Ram) {
+        self.0.lock().unwrap().run(ram);
+    }
+
+    fn deterministic(&self) -> bool {
+        false
+    }
+}
+
+/// Modifies a VmDescription to mount a semihosting device with access to the files in `root` and creates the device.
+/// Operations run at the end of the cycle in which SH_OP is written, so the result can be read by the next instruction.
+pub fn mount_semihosting(
+    vm_desc: &mut VmDescription,
+    root: impl Into<PathBuf>,
+) -> Arc<Mutex<Semihosting>> {
+    let semihosting = Arc::new(Mutex::new(Semihosting::new(root)));
+    let op_semihosting = semihosting.clone();
+
+    vm_desc.callbacks.push((
+        SH_OP,
+        Box::new(move |write: WriteEvent| op_semihosting.lock().unwrap().op_changed(write.value)),
+    ));
+    vm_desc.rom_blocks.push((SH_RESULT, 1));
+    vm_desc
+        .devices
+        .push(Box::new(SemihostingDevice(semihosting.clone())));
+    semihosting
+}
+
+/// Modifies a VmDescription to mount a semihosting device, creates the device and builds the VM.
+/// See [`mount_semihosting()`]
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler, mmio::semihost::connect_semihosting};
+///
+/// // open out.txt for writing and write the two bytes at 0x200 into it
+/// let code = r"[text]
+/// A = 0x100
+/// D = add, 0, A
+/// A = 0x6301
+/// *A = add, 0, D
+/// A = 1
+/// D = add, 0, A
+/// A = 0x6302
+/// *A = add, 0, D
+/// A = 1
+/// D = add, 0, A
+/// A = 0x6300
+/// *A = add, 0, D
+/// A = 0x6304
+/// D = add, 0, *A
+/// A = 0x6301
+/// *A = add, 0, D
+/// A = 0x200
+/// D = add, 0, A
+/// A = 0x6302
+/// *A = add, 0, D
+/// A = 2
+/// D = add, 0, A
+/// A = 0x6303
+/// *A = add, 0, D
+/// A = 4
+/// D = add, 0, A
+/// A = 0x6300
+/// *A = add, 0, D
+/// [consts 0x1000]";
+/// let mut vm_desc = assembler::run(code).unwrap();
+/// for (i, c) in "out.txt".bytes().enumerate() {
+///     vm_desc.mem[0x100 + i] = c as u16;
+/// }
+/// vm_desc.mem[0x200..0x202].copy_from_slice(&[b'h' as u16, b'i' as u16]);
+///
+/// let root = std::env::temp_dir().join("bric_vm_semihost_example");
+/// std::fs::create_dir_all(&root).unwrap();
+/// let (mut vm, _) = connect_semihosting(vm_desc, &root).unwrap();
+/// for _ in 0..28 {
+///     vm.cycle().unwrap();
+/// }
+/// assert_eq!(*vm.get_ram_region(0x6304, 1), [2]);
+/// assert_eq!(std::fs::read_to_string(root.join("out.txt")).unwrap(), "hi");
+/// ```
+pub fn connect_semihosting(
+    mut vm_desc: VmDescription,
+    root: impl Into<PathBuf>,
+) -> Result<(Vm, Arc<Mutex<Semihosting>>), BError> {
+    let semihosting = mount_semihosting(&mut vm_desc, root);
+    let vm = Vm::new(vm_desc)?;
+    Ok((vm, semihosting))
+}
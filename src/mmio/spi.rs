@@ -0,0 +1,205 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    BError,
+    mmio::Device,
+    vm::{Ram, Vm, VmDescription, WriteEvent},
+};
+
+/// Data register. The low byte is sent when a transfer is started and replaced by the received byte afterwards
+pub const SPI_DATA: u16 = 0x6400;
+/// Control register. See the control flags
+pub const SPI_CTRL: u16 = 0x6401;
+/// Status register, read only. See the status flags
+pub const SPI_STATUS: u16 = 0x6402;
+
+// control flags
+/// Assert the chip select line of the slave selected in bits 8-11
+pub const SPI_CS: u16 = 1;
+/// Start a transfer of the byte in SPI_DATA
+pub const SPI_START: u16 = 1 << 1;
+
+// status flags
+/// A transfer completed and SPI_DATA holds the received byte. Cleared by writing SPI_CTRL without SPI_START
+pub const SPI_DONE: u16 = 1;
+
+/// A simulated peripheral on the SPI bus. Transfers are full duplex: the slave receives one byte and answers with one
+/// byte at the same time.
+pub trait SpiSlave: Send {
+    /// Called when the chip select line of the slave is asserted
+    fn select(&mut self) {}
+
+    /// Called when the chip select line of the slave is released
+    fn deselect(&mut self) {}
+
+    /// Exchange one byte with the master. `mosi` is the byte sent by the master, the returned byte is the answer
+    fn transfer(&mut self, mosi: u8) -> u8;
+}
+
+/// Represents an SPI master with up to 16 slaves. Transfers complete instantly.
+/// Bytes transferred without a selected slave read as 0xff.
+#[derive(Default)]
+pub struct Spi {
+    slaves: Vec<Box<dyn SpiSlave>>,
+    selected: Option<usize>,
+    tx: u8,
+    rx: Option<u8>,
+    status: u16,
+}
+
+impl Spi {
+    /// Attach a slave to the bus. Returns its number, which selects it in bits 8-11 of SPI_CTRL
+    /// ## Errors
+    /// [`BError::MapError`] if 16 slaves are already attached
+    pub fn attach(&mut self, slave: Box<dyn SpiSlave>) -> Result<u8, BError> {
+        if self.slaves.len() >= 16 {
+            return Err(BError::MapError(
+                "an SPI bus holds at most 16 slaves".to_string(),
+            ));
+        }
+        self.slaves.push(slave);
+        Ok((self.slaves.len() - 1) as u8)
+    }
+
+    /// called when the SPI_DATA register is written to
+    pub fn data_changed(&mut self, reg_content: u16) {
+        self.tx = reg_content as u8;
+    }
+
+    /// called when the SPI_CTRL register is written to
+    pub fn control_changed(&mut self, reg_content: u16) {
+        let selected = (reg_content & SPI_CS != 0).then_some(((reg_content >> 8) & 0xf) as usize);
+        if selected != self.selected {
+            if let Some(slave) = self.selected_slave() {
+                slave.deselect();
+            }
+            self.selected = selected;
+            if let Some(slave) = self.selected_slave() {
+                slave.select();
+            }
+        }
+
+        if reg_content & SPI_START != 0 {
+            let tx = self.tx;
+            let rx = self
+                .selected_slave()
+                .map_or(0xff, |slave| slave.transfer(tx));
+            self.rx = Some(rx);
+            self.status |= SPI_DONE;
+        } else {
+            self.status &= !SPI_DONE;
+        }
+    }
+
+    fn selected_slave(&mut self) -> Option<&mut Box<dyn SpiSlave>> {
+        self.slaves.get_mut(self.selected?)
+    }
+
+    /// Get the SPI_STATUS register
+    pub fn get_status(&self) -> u16 {
+        self.status
+    }
+
+    /// Save the state of the master. Laid out as: transmit byte, status (2 bytes, big endian).
+    /// The state of the slaves is not saved
+    pub fn save(&self) -> Vec<u8> {
+        let mut out = vec![self.tx];
+        out.extend_from_slice(&self.status.to_be_bytes());
+        out
+    }
+
+    /// Restore the state of the master from the output of [`Spi::save()`]
+    /// ## Errors
+    /// [`BError::DeserializationError`] if the state is malformed
+    pub fn load(&mut self, state: &[u8]) -> Result<(), BError> {
+        if state.len() != 3 {
+            return Err(BError::DeserializationError(
+                "Invalid SPI state length".into(),
+            ));
+        }
+        self.tx = state[0];
+        self.status = u16::from_be_bytes([state[1], state[2]]);
+        self.rx = None;
+        Ok(())
+    }
+}
+
+/// Synchronizes the registers of a shared [`Spi`] with RAM on every tick
+struct SpiDevice(Arc<Mutex<Spi>>);
+
+impl Device for SpiDevice {
+    fn tick(&mut self, _cycles: u64, ram: &mut Ram) {
+        let mut spi = self.0.lock().unwrap();
+        if let Some(rx) = spi.rx.take() {
+            ram.set_ram(SPI_DATA, rx as u16);
+        }
+        ram.set_ram(SPI_STATUS, spi.get_status());
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.0.lock().unwrap().save()
+    }
+
+    fn load(&mut self, state: &[u8]) -> Result<(), BError> {
+        self.0.lock().unwrap().load(state)
+    }
+}
+
+/// Modifies a VmDescription to mount an SPI master and creates it. Slaves are attached with [`Spi::attach()`].
+/// The received byte and the status register are updated by the VM after every cycle.
+pub fn mount_spi(vm_desc: &mut VmDescription) -> Arc<Mutex<Spi>> {
+    let spi = Arc::new(Mutex::new(Spi::default()));
+    let data_spi = spi.clone();
+    let ctrl_spi = spi.clone();
+
+    vm_desc.callbacks.push((
+        SPI_DATA,
+        Box::new(move |write: WriteEvent| data_spi.lock().unwrap().data_changed(write.value)),
+    ));
+    vm_desc.callbacks.push((
+        SPI_CTRL,
+        Box::new(move |write: WriteEvent| ctrl_spi.lock().unwrap().control_changed(write.value)),
+    ));
+    vm_desc.rom_blocks.push((SPI_STATUS, 1));
+    vm_desc.devices.push(Box::new(SpiDevice(spi.clone())));
+    spi
+}
+
+/// Modifies a VmDescription to mount an SPI master, creates it and builds the VM. See [`mount_spi()`]
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler, mmio::spi::{SPI_DATA, SpiSlave, connect_spi}};
+///
+/// /// Answers every byte with the byte incremented by one
+/// struct Incrementer;
+///
+/// impl SpiSlave for Incrementer {
+///     fn transfer(&mut self, mosi: u8) -> u8 {
+///         mosi.wrapping_add(1)
+///     }
+/// }
+///
+/// // send 0x41 to slave 0
+/// let code = r"[text]
+/// A = 0x41
+/// D = add, 0, A
+/// A = 0x6400
+/// *A = add, 0, D
+/// A = 3
+/// D = add, 0, A
+/// A = 0x6401
+/// *A = add, 0, D
+/// [consts 0x100]";
+/// let (mut vm, spi) = connect_spi(assembler::run(code).unwrap()).unwrap();
+/// assert_eq!(spi.lock().unwrap().attach(Box::new(Incrementer)).unwrap(), 0);
+///
+/// for _ in 0..8 {
+///     vm.cycle().unwrap();
+/// }
+/// assert_eq!(*vm.get_ram_region(SPI_DATA, 3), [0x42, 3, 1]);
+/// ```
+pub fn connect_spi(mut vm_desc: VmDescription) -> Result<(Vm, Arc<Mutex<Spi>>), BError> {
+    let spi = mount_spi(&mut vm_desc);
+    let vm = Vm::new(vm_desc)?;
+    Ok((vm, spi))
+}
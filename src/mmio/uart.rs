@@ -1,11 +1,16 @@
 use std::{
     collections::VecDeque,
-    sync::{Arc, Mutex},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, Sender},
+    },
 };
 
 use crate::{
     BError,
-    vm::{Vm, VmDescription},
+    mmio::Device,
+    util::Region,
+    vm::{AccessLevels, RAM_LEN, Ram, Vm, VmDescription, WriteEvent},
 };
 
 // input flags
@@ -15,6 +20,8 @@ pub const IO: u16 = 1;
 pub const DA: u16 = 1 << 1;
 /// Output FIFO ready
 pub const OR: u16 = 1 << 2;
+/// Clear to send: the other end accepts output. Always set without flow control
+pub const CTS: u16 = 1 << 3;
 
 // output flags
 /// Output written
@@ -23,31 +30,227 @@ pub const OW: u16 = 1;
 pub const IR: u16 = 1 << 1;
 /// Reset
 pub const RU: u16 = 1 << 2;
+/// Request to send: the guest accepts input. Only used with flow control, every write of U_OFL updates it
+pub const RTS: u16 = 1 << 3;
+
+/// Bits on the line per byte: start bit, 8 data bits, stop bit
+pub const FRAME_BITS: u64 = 10;
 
 pub const INPUT_BUF_LEN: usize = 0xff;
 pub const OUTPUT_BUF_LEN: usize = 0xff;
 
-/// Baud rate register. Baud = 40_000_000 / U_BAUD
-pub const U_BAUD: u16 = 0x6000;
+/// Default base address of the UART registers. The register constants below are at this base
+pub const UART_BASE: u16 = 0x6000;
+/// Amount of registers of one UART
+pub const UART_LEN: u16 = 5;
+
+/// Baud rate register. Baud = 40_000_000 / U_BAUD, so U_BAUD is the amount of cycles per bit. 0 transfers instantly
+pub const U_BAUD: u16 = UART_BASE;
 /// UART output register
-pub const U_OUT: u16 = 0x6001;
+pub const U_OUT: u16 = UART_BASE + 1;
 /// UART input register
-pub const U_IN: u16 = 0x6002;
+pub const U_IN: u16 = UART_BASE + 2;
 /// UART i flags register dbg -> vm
-pub const U_IFL: u16 = 0x6003;
+pub const U_IFL: u16 = UART_BASE + 3;
 /// UART o flags register vm -> dbg
-pub const U_OFL: u16 = 0x6004;
+pub const U_OFL: u16 = UART_BASE + 4;
+
+/// A [`Uart`] shared between the VM and the embedder
+pub type SharedUart = Arc<Mutex<Uart>>;
+
+/// Called with the RTS line whenever it changes
+pub type RtsHandler = Box<dyn FnMut(bool) + Send>;
+
+/// Called with every byte once it has been transmitted
+pub type OutputHandler = Box<dyn FnMut(u8) + Send>;
+
+/// The streams of a UART mounted by [`connect_uart()`]
+/// - `uart`: The UART itself, e.g. to configure flow control
+/// - `output`: Receives the bytes written by the guest once they have been transmitted
+/// - `input`: Bytes sent here are put on the line to the guest. They are picked up after every cycle
+pub struct UartIo {
+    pub uart: SharedUart,
+    pub output: Receiver<u8>,
+    pub input: Sender<u8>,
+}
 
 /// Represents the UART.
+/// Bytes take [`FRAME_BITS`] times U_BAUD cycles to be transmitted or received. Bytes written by the guest become
+/// available to [`Uart::get_output()`] and bytes passed to [`Uart::put_input()`] arrive in the input FIFO once they
+/// have been on the line for that long.
+///
+/// With flow control enabled the UART only transmits while the embedder asserts CTS, see [`Uart::set_cts()`], and the
+/// embedder should only send while [`Uart::is_rts()`], e.g. using [`Uart::try_put_input()`].
 pub struct Uart {
     input: VecDeque<u8>,
     output: VecDeque<u8>,
     write_reg: u8,
     read_reg: u8,
     in_flags: u16,
+    baud: u16,
+    /// Amount of the oldest bytes in `output` that have been transmitted
+    sent: usize,
+    tx_cycles: u64,
+    line: VecDeque<u8>,
+    rx_cycles: u64,
+    flow_control: bool,
+    rts: bool,
+    cts: bool,
+    last_rts: bool,
+    rts_handler: Option<RtsHandler>,
+    output_handler: Option<OutputHandler>,
+    input_stream: Option<Receiver<u8>>,
 }
 
 impl Uart {
+    /// called when the U_BAUD register is written to
+    pub fn baud_changed(&mut self, reg_content: u16) {
+        self.baud = reg_content;
+    }
+
+    /// Amount of cycles it takes to transfer one byte. 0 if transfers are instant
+    pub fn frame_cycles(&self) -> u64 {
+        self.baud as u64 * FRAME_BITS
+    }
+
+    /// Enable or disable RTS/CTS flow control. Disabled after creation
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::mmio::uart::{CTS, OW, RTS, Uart};
+    ///
+    /// let mut uart = Uart::default();
+    /// uart.set_flow_control(true);
+    /// // the guest didn't request input yet
+    /// assert!(!uart.try_put_input(b'a'));
+    /// uart.output_flags_changed(RTS);
+    /// assert!(uart.try_put_input(b'a'));
+    ///
+    /// // output stays in the UART while CTS is deasserted
+    /// uart.set_cts(false);
+    /// uart.write_reg_changed(b'b' as u16);
+    /// uart.output_flags_changed(OW | RTS);
+    /// assert_eq!(uart.get_in_flags() & CTS, 0);
+    /// assert_eq!(uart.get_output(), None);
+    /// uart.set_cts(true);
+    /// assert_eq!(uart.get_output(), Some(b'b'));
+    /// ```
+    pub fn set_flow_control(&mut self, enabled: bool) {
+        self.flow_control = enabled;
+        self.notify_rts();
+    }
+
+    /// Set whether the embedder is clear to receive output. Transmission pauses while CTS is deasserted
+    pub fn set_cts(&mut self, cts: bool) {
+        self.cts = cts;
+    }
+
+    fn clear_to_send(&self) -> bool {
+        !self.flow_control || self.cts
+    }
+
+    /// Whether the guest requests input: it set RTS and the input FIFO has room for another byte.
+    /// Always true without flow control
+    pub fn is_rts(&self) -> bool {
+        !self.flow_control || (self.rts && self.input.len() + self.line.len() < INPUT_BUF_LEN)
+    }
+
+    /// Set a handler that is called with the RTS line whenever it changes. See [`Uart::is_rts()`]
+    pub fn set_rts_handler(&mut self, handler: RtsHandler) {
+        self.rts_handler = Some(handler);
+    }
+
+    fn notify_rts(&mut self) {
+        let rts = self.is_rts();
+        if rts != self.last_rts {
+            self.last_rts = rts;
+            if let Some(handler) = &mut self.rts_handler {
+                handler(rts);
+            }
+        }
+    }
+
+    /// Set a handler that is called with every byte once it has been transmitted. Transmitted bytes are no longer
+    /// returned by [`Uart::get_output()`]
+    pub fn set_output_handler(&mut self, handler: OutputHandler) {
+        self.output_handler = Some(handler);
+        self.deliver();
+    }
+
+    /// Stream the output and input of the UART through channels instead of [`Uart::get_output()`] and
+    /// [`Uart::put_input()`]. Returns the receiver of the transmitted bytes and the sender of the input.
+    /// With flow control input is only taken from the channel while [`Uart::is_rts()`]
+    pub fn streams(&mut self) -> (Receiver<u8>, Sender<u8>) {
+        let (out_tx, out_rx) = mpsc::channel();
+        let (in_tx, in_rx) = mpsc::channel();
+        self.set_output_handler(Box::new(move |byte| {
+            let _ = out_tx.send(byte);
+        }));
+        self.input_stream = Some(in_rx);
+        (out_rx, in_tx)
+    }
+
+    /// Hand the transmitted bytes to the output handler
+    fn deliver(&mut self) {
+        if let Some(mut handler) = self.output_handler.take() {
+            while let Some(byte) = self.get_output() {
+                handler(byte);
+            }
+            self.output_handler = Some(handler);
+        }
+    }
+
+    /// Take input from the input stream
+    fn pull_input(&mut self) {
+        if let Some(stream) = self.input_stream.take() {
+            while self.is_rts()
+                && let Ok(byte) = stream.try_recv()
+            {
+                self.put_input(byte);
+            }
+            self.input_stream = Some(stream);
+        }
+    }
+
+    /// Advance the transfers by `cycles` clock cycles
+    pub fn advance(&mut self, cycles: u64) {
+        self.pull_input();
+        self.transfer(cycles);
+        self.deliver();
+    }
+
+    fn transfer(&mut self, cycles: u64) {
+        let frame = self.frame_cycles();
+        if frame == 0 {
+            while let Some(byte) = self.line.pop_front() {
+                self.receive(byte);
+            }
+            return;
+        }
+
+        if self.sent < self.output.len() && self.clear_to_send() {
+            self.tx_cycles += cycles;
+            let done = ((self.tx_cycles / frame) as usize).min(self.output.len() - self.sent);
+            self.sent += done;
+            self.tx_cycles -= done as u64 * frame;
+        }
+        if self.sent == self.output.len() {
+            self.tx_cycles = 0;
+        }
+
+        if !self.line.is_empty() {
+            self.rx_cycles += cycles;
+        }
+        while self.rx_cycles >= frame
+            && let Some(byte) = self.line.pop_front()
+        {
+            self.receive(byte);
+            self.rx_cycles -= frame;
+        }
+        if self.line.is_empty() {
+            self.rx_cycles = 0;
+        }
+    }
+
     /// called when the U_OUT register is written to
     pub fn write_reg_changed(&mut self, reg_content: u16) {
         self.write_reg = (reg_content & !(0xff << 8)) as u8;
@@ -61,6 +264,7 @@ impl Uart {
             let out_len = self.output.len();
             if out_len > OUTPUT_BUF_LEN {
                 self.output.pop_back();
+                self.sent = self.sent.saturating_sub(1);
             } else if out_len == OUTPUT_BUF_LEN {
                 self.in_flags &= !OR;
             } else {
@@ -84,11 +288,38 @@ impl Uart {
             self.write_reg = 0;
             self.read_reg = 0;
             self.in_flags = 0b100;
+            self.sent = 0;
+            self.tx_cycles = 0;
+            self.line = VecDeque::new();
+            self.rx_cycles = 0;
         }
+        self.rts = reg_content & RTS != 0;
+        self.notify_rts();
+        self.deliver();
     }
 
-    /// write an input to the U_IN FIFO
+    /// write an input to the U_IN FIFO. It arrives after one frame, see [`Uart::frame_cycles()`]
     pub fn put_input(&mut self, input_byte: u8) {
+        if self.frame_cycles() == 0 {
+            self.receive(input_byte);
+        } else {
+            self.line.push_back(input_byte);
+            self.notify_rts();
+        }
+    }
+
+    /// write an input to the U_IN FIFO if the guest requests input. Returns whether the byte was sent.
+    /// See [`Uart::is_rts()`]
+    pub fn try_put_input(&mut self, input_byte: u8) -> bool {
+        if self.is_rts() {
+            self.put_input(input_byte);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn receive(&mut self, input_byte: u8) {
         self.input.push_front(input_byte);
         self.in_flags |= DA;
         let inp_len = self.input.len();
@@ -97,11 +328,16 @@ impl Uart {
         } else if inp_len == INPUT_BUF_LEN {
             self.in_flags |= IO;
         }
+        self.notify_rts();
     }
 
-    /// get the U_OFL flags
+    /// get the U_IFL flags
     pub fn get_in_flags(&self) -> u16 {
-        self.in_flags
+        if self.clear_to_send() {
+            self.in_flags | CTS
+        } else {
+            self.in_flags & !CTS
+        }
     }
 
     /// get the U_IN register
@@ -109,9 +345,101 @@ impl Uart {
         self.read_reg as u16
     }
 
-    /// get the U_OUT register
+    /// get the oldest byte written to the U_OUT register that has been transmitted
     pub fn get_output(&mut self) -> Option<u8> {
-        self.output.pop_back()
+        if !self.clear_to_send() && self.sent == 0 {
+            return None;
+        }
+        if self.frame_cycles() != 0 {
+            if self.sent == 0 {
+                return None;
+            }
+            self.sent -= 1;
+        } else {
+            self.sent = self.sent.saturating_sub(1);
+        }
+        let byte = self.output.pop_back();
+        if byte.is_some() {
+            self.in_flags |= OR;
+        }
+        byte
+    }
+
+    /// Save the state of the UART. Laid out as: write register, read register, input flags (2 bytes),
+    /// input FIFO length (2 bytes), input FIFO, output FIFO length (2 bytes), output FIFO, U_BAUD (2 bytes),
+    /// transmitted output bytes (2 bytes), cycles of the current transmission (8 bytes), cycles of the current
+    /// reception (8 bytes), length of the bytes on the line (4 bytes), bytes on the line, flow control, RTS, CTS
+    /// (1 byte each). Big endian. The RTS handler is not saved
+    pub fn save(&self) -> Vec<u8> {
+        let mut out = vec![self.write_reg, self.read_reg];
+        out.extend_from_slice(&self.in_flags.to_be_bytes());
+        for fifo in [&self.input, &self.output] {
+            // the FIFOs never hold more than 0xff bytes
+            out.extend_from_slice(&(fifo.len() as u16).to_be_bytes());
+            out.extend(fifo.iter());
+        }
+        out.extend_from_slice(&self.baud.to_be_bytes());
+        out.extend_from_slice(&(self.sent as u16).to_be_bytes());
+        out.extend_from_slice(&self.tx_cycles.to_be_bytes());
+        out.extend_from_slice(&self.rx_cycles.to_be_bytes());
+        out.extend_from_slice(&(self.line.len() as u32).to_be_bytes());
+        out.extend(self.line.iter());
+        out.extend([self.flow_control, self.rts, self.cts].map(u8::from));
+        out
+    }
+
+    /// Restore the state of the UART from the output of [`Uart::save()`]
+    /// ## Errors
+    /// [`BError::DeserializationError`] if the state is malformed
+    pub fn load(&mut self, state: &[u8]) -> Result<(), BError> {
+        use crate::util::check_slice;
+        let header = check_slice(state, 4)?;
+        let (write_reg, read_reg) = (header[0], header[1]);
+        let in_flags = u16::from_be_bytes([header[2], header[3]]);
+        let mut current = &state[4..];
+        let mut fifos: Vec<VecDeque<u8>> = Vec::with_capacity(2);
+        for _ in 0..2 {
+            let len_bytes = check_slice(current, 2)?;
+            let len = u16::from_be_bytes([len_bytes[0], len_bytes[1]]) as usize;
+            current = &current[2..];
+            fifos.push(check_slice(current, len)?.iter().copied().collect());
+            current = &current[len..];
+        }
+        let timing = check_slice(current, 24)?;
+        let baud = u16::from_be_bytes([timing[0], timing[1]]);
+        let sent = u16::from_be_bytes([timing[2], timing[3]]) as usize;
+        let tx_cycles = u64::from_be_bytes(timing[4..12].try_into().unwrap());
+        let rx_cycles = u64::from_be_bytes(timing[12..20].try_into().unwrap());
+        let line_len = u32::from_be_bytes(timing[20..24].try_into().unwrap()) as usize;
+        current = &current[24..];
+        let line = check_slice(current, line_len)?.iter().copied().collect();
+        current = &current[line_len..];
+        let flow = check_slice(current, 3)?;
+        let (flow_control, rts, cts) = (flow[0] != 0, flow[1] != 0, flow[2] != 0);
+        current = &current[3..];
+        if !current.is_empty() {
+            return Err(BError::DeserializationError("UART state too long".into()));
+        }
+        if sent > fifos[1].len() {
+            return Err(BError::DeserializationError(
+                "UART state has more transmitted bytes than output".into(),
+            ));
+        }
+        self.output = fifos.pop().unwrap();
+        self.input = fifos.pop().unwrap();
+        self.write_reg = write_reg;
+        self.read_reg = read_reg;
+        self.in_flags = in_flags;
+        self.baud = baud;
+        self.sent = sent;
+        self.tx_cycles = tx_cycles;
+        self.rx_cycles = rx_cycles;
+        self.line = line;
+        self.flow_control = flow_control;
+        self.rts = rts;
+        self.cts = cts;
+        self.notify_rts();
+        Ok(())
     }
 }
 
@@ -123,35 +451,195 @@ impl Default for Uart {
             write_reg: 0,
             read_reg: 0,
             in_flags: 0b100,
+            baud: 0,
+            sent: 0,
+            tx_cycles: 0,
+            line: VecDeque::new(),
+            rx_cycles: 0,
+            flow_control: false,
+            rts: false,
+            cts: true,
+            last_rts: true,
+            rts_handler: None,
+            output_handler: None,
+            input_stream: None,
         }
     }
 }
 
-/// Modifies a VmDescription to mount a UART, creates a UART
-/// The UARTs registers must manually be updated when running the VM
-/// The UART object itself can also be used on another thread.
-pub fn connect_uart(mut vm_desc: VmDescription) -> Result<(Vm, Arc<Mutex<Uart>>), BError> {
-    // Build Uart
+/// Synchronizes the registers of a shared [`Uart`] at `base` with RAM on every tick
+struct UartDevice {
+    uart: Arc<Mutex<Uart>>,
+    base: u16,
+}
+
+impl Device for UartDevice {
+    fn tick(&mut self, cycles: u64, ram: &mut Ram) {
+        let mut uart = self.uart.lock().unwrap();
+        uart.advance(cycles);
+        ram.set_ram(self.base + (U_IN - UART_BASE), uart.get_input());
+        ram.set_ram(self.base + (U_IFL - UART_BASE), uart.get_in_flags());
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.uart.lock().unwrap().save()
+    }
+
+    fn load(&mut self, state: &[u8]) -> Result<(), BError> {
+        self.uart.lock().unwrap().load(state)
+    }
+}
+
+/// Modifies a VmDescription to mount a UART with its registers at `base` instead of [`UART_BASE`] and creates the
+/// UART. The registers keep their order, e.g. the input register is at `base + 2`. Every call mounts another UART
+/// with its own buffers, so several UARTs can be mounted at different bases.
+/// ## Errors
+/// [`BError::OutOfBoundsError`] if the registers don't fit into RAM
+pub fn mount_uart_at(vm_desc: &mut VmDescription, base: u16) -> Result<Arc<Mutex<Uart>>, BError> {
+    if base as usize + UART_LEN as usize > RAM_LEN {
+        return Err(BError::OutOfBoundsError(base, UART_LEN as usize, RAM_LEN));
+    }
     let uart = Arc::new(Mutex::new(Uart::default()));
-    // Modify VmDescription
+    let baud_uart = uart.clone();
     let wc_uart = uart.clone();
     let of_uart = uart.clone();
 
-    let write_change = Box::new(move |input: u16| {
-        wc_uart.lock().unwrap().write_reg_changed(input);
+    let write_change = Box::new(move |write: WriteEvent| {
+        wc_uart.lock().unwrap().write_reg_changed(write.value);
+    });
+
+    let of_change = Box::new(move |write: WriteEvent| {
+        of_uart.lock().unwrap().output_flags_changed(write.value);
     });
 
-    let of_change = Box::new(move |input: u16| {
-        of_uart.lock().unwrap().output_flags_changed(input);
+    let baud_change = Box::new(move |write: WriteEvent| {
+        baud_uart.lock().unwrap().baud_changed(write.value);
     });
 
-    vm_desc.rom_blocks.push((U_IN, 1));
+    vm_desc.rom_blocks.push((base + (U_IN - UART_BASE), 1));
+    let out = base + (U_OUT - UART_BASE);
+    vm_desc
+        .access_regions
+        .push(Region::new(out, out, AccessLevels::WriteOnly));
 
-    vm_desc.callbacks.push((U_OUT, write_change));
-    vm_desc.callbacks.push((U_OFL, of_change));
+    vm_desc.callbacks.push((base, baud_change));
 
-    vm_desc.mem[U_IFL as usize] = 0b100;
-    // Build Vm
+    vm_desc
+        .callbacks
+        .push((base + (U_OUT - UART_BASE), write_change));
+    vm_desc
+        .callbacks
+        .push((base + (U_OFL - UART_BASE), of_change));
+
+    vm_desc.devices.push(Box::new(UartDevice {
+        uart: uart.clone(),
+        base,
+    }));
+
+    vm_desc.mem[(base + (U_IFL - UART_BASE)) as usize] = 0b100;
+    Ok(uart)
+}
+
+/// Modifies a VmDescription to mount a UART at [`UART_BASE`] and creates the UART. See [`mount_uart_at()`]
+pub fn mount_uart(vm_desc: &mut VmDescription) -> Arc<Mutex<Uart>> {
+    mount_uart_at(vm_desc, UART_BASE).expect("the default UART fits into RAM")
+}
+
+/// Modifies a VmDescription to mount a UART at [`UART_BASE`], creates the UART and streams its output and input.
+/// See [`Uart::streams()`]
+pub fn mount_uart_io(vm_desc: &mut VmDescription) -> UartIo {
+    uart_io(mount_uart(vm_desc))
+}
+
+/// Modifies a VmDescription to mount a UART, creates a UART and streams its output and input.
+/// The UARTs registers are updated by the VM after every cycle. See [`Uart::streams()`]
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler, mmio::uart::connect_uart};
+///
+/// // echo the first input byte
+/// let code = r"[text]
+/// label wait:
+/// A = 0x6003
+/// D = add, 0, *A
+/// A = 2
+/// D = and, D, A
+/// A = wait
+/// add, 0, D; JEQ
+/// A = 2
+/// D = add, 0, A
+/// A = 0x6004
+/// *A = add, 0, D
+/// A = 0x6002
+/// D = add, 0, *A
+/// A = 0x6001
+/// *A = add, 0, D
+/// A = 1
+/// D = add, 0, A
+/// A = 0x6004
+/// *A = add, 0, D
+/// [consts 0x100]";
+/// let (mut vm, io) = connect_uart(assembler::run(code).unwrap()).unwrap();
+/// io.input.send(b'a').unwrap();
+/// for _ in 0..18 {
+///     vm.cycle().unwrap();
+/// }
+/// assert_eq!(io.output.try_recv(), Ok(b'a'));
+/// ```
+pub fn connect_uart(mut vm_desc: VmDescription) -> Result<(Vm, UartIo), BError> {
+    let uart = mount_uart_io(&mut vm_desc);
     let vm = Vm::new(vm_desc)?;
     Ok((vm, uart))
 }
+
+fn uart_io(uart: SharedUart) -> UartIo {
+    let (output, input) = uart.lock().unwrap().streams();
+    UartIo {
+        uart,
+        output,
+        input,
+    }
+}
+
+/// Modifies a VmDescription to mount one UART per base address, creates the UARTs, streams their output and input and
+/// builds the VM. The UARTs are returned in the order of `bases`, which must be at least [`UART_LEN`] apart.
+/// See [`mount_uart_at()`]
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler, mmio::uart::connect_uarts};
+///
+/// // write 'a' to the UART at 0x6000 and 'b' to the UART at 0x6010
+/// let code = r"[text]
+/// A = 0x61
+/// D = add, 0, A
+/// A = 0x6001
+/// *A = add, 0, D
+/// A = 0x62
+/// D = add, 0, A
+/// A = 0x6011
+/// *A = add, 0, D
+/// A = 1
+/// D = add, 0, A
+/// A = 0x6004
+/// *A = add, 0, D
+/// A = 0x6014
+/// *A = add, 0, D
+/// [consts 0x100]";
+/// let (mut vm, uarts) = connect_uarts(assembler::run(code).unwrap(), &[0x6000, 0x6010]).unwrap();
+/// for _ in 0..14 {
+///     vm.cycle().unwrap();
+/// }
+/// assert_eq!(uarts[0].output.try_recv(), Ok(b'a'));
+/// assert_eq!(uarts[1].output.try_recv(), Ok(b'b'));
+/// ```
+pub fn connect_uarts(
+    mut vm_desc: VmDescription,
+    bases: &[u16],
+) -> Result<(Vm, Vec<UartIo>), BError> {
+    let uarts = bases
+        .iter()
+        .map(|base| mount_uart_at(&mut vm_desc, *base))
+        .collect::<Result<Vec<_>, _>>()?;
+    let vm = Vm::new(vm_desc)?;
+    Ok((vm, uarts.into_iter().map(uart_io).collect()))
+}
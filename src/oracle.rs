@@ -0,0 +1,155 @@
+//! Differential execution. Two [`Engine`]s run the same program in lockstep and the first cycle after which their state
+//! differs is reported, so a new backend, e.g. a predecoding interpreter, can be checked against [`Vm`] as the
+//! reference. Two versions of the crate can't be linked into one program, compare the output of `bric run --trace`
+//! of both instead.
+use std::{borrow::Cow, fmt};
+
+use crate::{
+    BError,
+    vm::{CpuState, RAM_LEN, Vm},
+};
+
+/// Something that executes BRIC programs cycle by cycle
+pub trait Engine {
+    /// Execute one cycle. Returns [`BError::ExecutionHaltedError`] when the program ran past the end of ROM
+    fn cycle(&mut self) -> Result<(), BError>;
+    /// Get the registers and the program counter
+    fn cpu_state(&self) -> CpuState;
+    /// Get the whole RAM, [`RAM_LEN`] words
+    fn ram(&self) -> Cow<'_, [u16]>;
+}
+
+impl Engine for Vm {
+    fn cycle(&mut self) -> Result<(), BError> {
+        Vm::cycle(self)
+    }
+
+    fn cpu_state(&self) -> CpuState {
+        Vm::cpu_state(self)
+    }
+
+    fn ram(&self) -> Cow<'_, [u16]> {
+        self.get_ram_region(0, RAM_LEN)
+    }
+}
+
+/// How the engines differ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// The result of the cycle differs, holds the errors as text, `None` if the cycle succeeded
+    Outcome {
+        left: Option<String>,
+        right: Option<String>,
+    },
+    /// The registers or the program counter differ
+    Cpu { left: CpuState, right: CpuState },
+    /// The RAM word at `address` differs. Only the lowest differing address is reported
+    Memory { address: u16, left: u16, right: u16 },
+}
+
+/// The first difference between two engines
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Amount of cycles executed by both engines, including the diverging one
+    pub cycle: u64,
+    /// The state of the left engine before the diverging cycle
+    pub before: CpuState,
+    pub difference: Difference,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "divergence in cycle {} at pc {:#06x}: ",
+            self.cycle, self.before.pc
+        )?;
+        match &self.difference {
+            Difference::Outcome { left, right } => {
+                let describe =
+                    |outcome: &Option<String>| outcome.clone().unwrap_or("ok".to_string());
+                write!(f, "{} vs {}", describe(left), describe(right))
+            }
+            Difference::Cpu { left, right } => write!(f, "{:x?} vs {:x?}", left, right),
+            Difference::Memory {
+                address,
+                left,
+                right,
+            } => write!(
+                f,
+                "RAM[{:#06x}] is {:#06x} vs {:#06x}",
+                address, left, right
+            ),
+        }
+    }
+}
+
+/// Run both engines in lockstep for up to `max_cycles` cycles and compare the result of every cycle, the CPU state
+/// and the RAM after it. Stops without a divergence when both halt in the same cycle.
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler, oracle::{Difference, lockstep}, vm::Vm};
+///
+/// let code = r"[text]
+/// A = 5
+/// D = add, 0, A
+/// A = 0x100
+/// *A = add, 0, D
+/// [consts 0x200]";
+/// let mut reference = Vm::new(assembler::run(code).unwrap()).unwrap();
+/// let mut copy = Vm::new(assembler::run(code).unwrap()).unwrap();
+/// assert_eq!(lockstep(&mut reference, &mut copy, 100), None);
+///
+/// // storing the wrong register is caught in the cycle of the store
+/// let mut reference = Vm::new(assembler::run(code).unwrap()).unwrap();
+/// let mut broken = Vm::new(assembler::run(&code.replace("0, D", "0, A")).unwrap()).unwrap();
+/// let divergence = lockstep(&mut reference, &mut broken, 100).unwrap();
+/// assert_eq!(divergence.cycle, 4);
+/// assert_eq!(divergence.difference, Difference::Memory { address: 0x100, left: 5, right: 0x100 });
+/// ```
+pub fn lockstep(
+    left: &mut impl Engine,
+    right: &mut impl Engine,
+    max_cycles: u64,
+) -> Option<Divergence> {
+    for cycle in 1..=max_cycles {
+        let before = left.cpu_state();
+        let [left_outcome, right_outcome] =
+            [left.cycle(), right.cycle()].map(|result| result.err().map(|e| e.to_string()));
+        let divergence = |difference| {
+            Some(Divergence {
+                cycle,
+                before,
+                difference,
+            })
+        };
+        if left_outcome != right_outcome {
+            return divergence(Difference::Outcome {
+                left: left_outcome,
+                right: right_outcome,
+            });
+        }
+        let (left_cpu, right_cpu) = (left.cpu_state(), right.cpu_state());
+        if left_cpu != right_cpu {
+            return divergence(Difference::Cpu {
+                left: left_cpu,
+                right: right_cpu,
+            });
+        }
+        let (left_ram, right_ram) = (left.ram(), right.ram());
+        if left_ram != right_ram
+            && let Some(address) = (0..left_ram.len().min(right_ram.len()))
+                .find(|idx| left_ram[*idx] != right_ram[*idx])
+        {
+            return divergence(Difference::Memory {
+                address: address as u16,
+                left: left_ram[address],
+                right: right_ram[address],
+            });
+        }
+        if left_outcome.is_some() {
+            return None;
+        }
+    }
+    None
+}
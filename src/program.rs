@@ -0,0 +1,269 @@
+//! Building programs from Rust code instead of assembly text, e.g. in unit tests and code generators. A
+//! [`ProgramBuilder`] lays out ROM the same way the assembler does: the instructions, a padding word, and the consts
+//! aligned behind them, which are mapped into RAM.
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use crate::{
+    BError,
+    vm::{Instruction, InstructionBuilder, VmDescription},
+};
+
+/// Where the consts are mapped into RAM unless [`ProgramBuilder::consts_at()`] is used, like in the assembler
+pub const DEFAULT_CONSTS_MOUNT: u16 = 0xfff0;
+
+/// Emits instructions and consts and resolves labels into a [`VmDescription`]. Errors of the emitted instructions are
+/// kept until [`ProgramBuilder::build()`], so calls can be chained
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler, program::ProgramBuilder, vm::{InstructionBuilder, Jump, Register}};
+///
+/// let mut program = ProgramBuilder::new();
+/// program
+///     .load_label("five")
+///     .alu(InstructionBuilder::add(Register::None, Register::MA).target(Register::D))
+///     .label("loop")
+///     .load_label("loop")
+///     .alu(InstructionBuilder::dec(Register::D).target(Register::D).jump(Jump::Jgt))
+///     .consts_at(0x100)
+///     .const_label("five")
+///     .constant(5);
+/// let vm_desc = program.build().unwrap();
+///
+/// let code = r"[text]
+/// A = five
+/// D = add, 0, *A
+/// label loop:
+/// A = loop
+/// D = dec, D; JGT
+/// [consts 0x100]
+/// label five:
+/// M = 5";
+/// let assembled = assembler::run(code).unwrap();
+/// assert_eq!(vm_desc.rom, assembled.rom);
+/// assert_eq!(vm_desc.rom_mappings, assembled.rom_mappings);
+/// ```
+#[derive(Debug)]
+pub struct ProgramBuilder {
+    rom: Vec<u16>,
+    consts: Vec<u16>,
+    consts_mount: u16,
+    /// Labels of instructions and their ROM address
+    labels: BTreeMap<String, u16>,
+    /// Labels of consts and their index in `consts`
+    const_labels: BTreeMap<String, usize>,
+    /// ROM addresses of the loads of labels
+    label_uses: Vec<(usize, String)>,
+    error: Option<BError>,
+}
+
+impl Default for ProgramBuilder {
+    fn default() -> Self {
+        Self {
+            rom: Vec::new(),
+            consts: Vec::new(),
+            consts_mount: DEFAULT_CONSTS_MOUNT,
+            labels: BTreeMap::new(),
+            const_labels: BTreeMap::new(),
+            label_uses: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep the first error
+    fn fail(&mut self, message: String) {
+        if self.error.is_none() {
+            self.error = Some(BError::AsmParseError(message.into()));
+        }
+    }
+
+    fn define(&mut self, name: &str) -> bool {
+        if self.labels.contains_key(name) || self.const_labels.contains_key(name) {
+            self.fail(format!("label {} already in use", name));
+            return false;
+        }
+        true
+    }
+
+    /// Emit an ALU instruction
+    pub fn alu(&mut self, inst: InstructionBuilder) -> &mut Self {
+        match inst.build() {
+            Ok(inst) => self.rom.push(Instruction::Alu(inst).to_u16()),
+            Err(e) => {
+                if self.error.is_none() {
+                    self.error = Some(e);
+                }
+                // keep the addresses of following labels right
+                self.rom.push(0);
+            }
+        }
+        self
+    }
+
+    /// Emit `A = value`. Values above `0x7fff` can't be loaded
+    pub fn load(&mut self, value: u16) -> &mut Self {
+        if value > 0x7fff {
+            self.fail(format!("{} is to large to load into A", value));
+        }
+        self.rom.push(Instruction::Data(value & 0x7fff).to_u16());
+        self
+    }
+
+    /// Emit `A = name`. The label may be defined later
+    pub fn load_label(&mut self, name: &str) -> &mut Self {
+        self.label_uses.push((self.rom.len(), name.to_string()));
+        self.rom.push(Instruction::Data(0).to_u16());
+        self
+    }
+
+    /// Emit a raw word
+    pub fn word(&mut self, word: u16) -> &mut Self {
+        self.rom.push(word);
+        self
+    }
+
+    /// Define a label at the next instruction
+    pub fn label(&mut self, name: &str) -> &mut Self {
+        if self.define(name) {
+            self.labels.insert(name.to_string(), self.rom.len() as u16);
+        }
+        self
+    }
+
+    /// Map the consts into RAM at `address`
+    pub fn consts_at(&mut self, address: u16) -> &mut Self {
+        self.consts_mount = address;
+        self
+    }
+
+    /// Define a label at the next const. Its value is the RAM address of the const
+    pub fn const_label(&mut self, name: &str) -> &mut Self {
+        if self.define(name) {
+            self.const_labels
+                .insert(name.to_string(), self.consts.len());
+        }
+        self
+    }
+
+    /// Place a const
+    pub fn constant(&mut self, value: u16) -> &mut Self {
+        self.consts.push(value);
+        self
+    }
+
+    /// Place several consts
+    pub fn constants(&mut self, values: &[u16]) -> &mut Self {
+        self.consts.extend_from_slice(values);
+        self
+    }
+
+    /// Get the ROM address of the next instruction
+    pub fn position(&self) -> u16 {
+        self.rom.len() as u16
+    }
+
+    /// Resolve the labels and lay out ROM
+    /// ## Errors
+    /// The first error of the emitted instructions, unknown or duplicate labels, labels above `0x7fff` and programs
+    /// that don't fit into ROM
+    pub fn build(self) -> Result<VmDescription, BError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let mut rom = self.rom;
+        // the padding word, so a label at the very end points at an instruction
+        rom.push(Instruction::Data(0).to_u16());
+        let more = 0xf - (rom.len() % 0x10);
+        rom.append(&mut vec![0; more]);
+        let consts_start = rom.len();
+        rom.extend_from_slice(&self.consts);
+        if rom.len() > 0xffff {
+            return Err(BError::AsmParseError(
+                format!("your program is to large: {} words", rom.len()).into(),
+            ));
+        }
+        for (position, name) in &self.label_uses {
+            let value = match (self.labels.get(name), self.const_labels.get(name)) {
+                (Some(address), _) => *address as usize,
+                (None, Some(idx)) => self.consts_mount as usize + idx,
+                (None, None) => {
+                    return Err(BError::AsmParseError(
+                        format!("unknown label {}", name).into(),
+                    ));
+                }
+            };
+            if value > 0x7fff {
+                return Err(BError::AsmParseError(
+                    format!("label {} is to large: {}", name, value).into(),
+                ));
+            }
+            rom[*position] |= value as u16;
+        }
+        Ok(VmDescription {
+            rom,
+            rom_mappings: vec![(
+                consts_start as u16,
+                self.consts.len() as u16,
+                self.consts_mount,
+            )],
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{Jump, Register, Vm};
+
+    #[test]
+    fn test_program_builder() {
+        use Register::*;
+
+        // E = 2 * count
+        let mut program = ProgramBuilder::new();
+        program
+            .load_label("count")
+            .alu(InstructionBuilder::add(None, MA).target(D))
+            .label("loop")
+            .load(2)
+            .alu(InstructionBuilder::add(E, A).target(E))
+            .load_label("loop")
+            .alu(InstructionBuilder::dec(D).target(D).jump(Jump::Jgt))
+            .load(0x10)
+            .alu(InstructionBuilder::add(None, E).target(MA))
+            .consts_at(0x200)
+            .const_label("count")
+            .constant(3);
+        let mut vm = Vm::new(program.build().unwrap()).unwrap();
+        for _ in 0..16 {
+            vm.cycle().unwrap();
+        }
+        assert_eq!(*vm.get_ram_region(0x10, 1), [6]);
+
+        let mut program = ProgramBuilder::new();
+        program.load_label("nowhere");
+        assert!(program.build().is_err());
+        let mut program = ProgramBuilder::new();
+        program.label("x").const_label("x");
+        assert!(program.build().is_err());
+        // the first error is reported
+        let mut program = ProgramBuilder::new();
+        program.alu(InstructionBuilder::add(D, E)).load(0x8000);
+        assert!(matches!(
+            program.build(),
+            Err(BError::InstParseError { .. })
+        ));
+    }
+}
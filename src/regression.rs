@@ -0,0 +1,298 @@
+//! Regression checks of guest programs. The old and the new build of a program run to the end with the same UART
+//! input and their UART output and final RAM are compared, so a refactoring can be checked for changes in behavior.
+//! Unlike [`crate::oracle`] the builds don't have to run in lockstep, only the result counts: the cycles and the
+//! registers are allowed to differ.
+use std::{fmt, ops::RangeInclusive};
+
+use crate::{
+    BError,
+    mmio::uart::mount_uart_io,
+    vm::{BudgetOutcome, BudgetRun, RAM_LEN, Vm, VmDescription},
+};
+
+/// Amount of cycles a build may run unless [`Options::max_cycles`] is set
+pub const DEFAULT_MAX_CYCLES: u64 = 10_000_000;
+
+/// Words of a differing memory range shown by [`Difference`]'s `Display`
+const SHOWN_WORDS: usize = 8;
+
+/// What both builds run with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Bytes in the UART input FIFO when the builds start
+    pub input: Vec<u8>,
+    /// Cycles after which a build is stopped
+    pub max_cycles: u64,
+    /// RAM that isn't compared, e.g. a stack. The RAM the ROM of either build is mapped to is never compared
+    pub ignore: Vec<RangeInclusive<u16>>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            input: Vec::new(),
+            max_cycles: DEFAULT_MAX_CYCLES,
+            ignore: Vec::new(),
+        }
+    }
+}
+
+/// How a build stopped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    /// It ran past the end of ROM
+    Halted,
+    /// It reached [`Options::max_cycles`]
+    CycleLimit,
+    /// It failed, holds the error as text
+    Failed(String),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Halted => write!(f, "halted"),
+            Self::CycleLimit => write!(f, "reached the cycle limit"),
+            Self::Failed(message) => write!(f, "failed: {}", message),
+        }
+    }
+}
+
+/// The result of running one build
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub stop: Stop,
+    pub cycles: u64,
+    /// Everything written to the UART
+    pub uart: Vec<u8>,
+    /// The whole RAM at the end, [`RAM_LEN`] words
+    pub ram: Vec<u16>,
+    /// RAM the ROM is mapped to, inclusive
+    pub mapped: Vec<(u16, u16)>,
+}
+
+/// A change in behavior between the builds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// The builds stopped in different ways
+    Stop { old: Stop, new: Stop },
+    /// The UART output differs, starting at byte `offset`
+    Uart {
+        offset: usize,
+        old: Vec<u8>,
+        new: Vec<u8>,
+    },
+    /// The RAM from `start` to `end`, inclusive, differs in every word
+    Memory {
+        start: u16,
+        end: u16,
+        old: Vec<u16>,
+        new: Vec<u16>,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stop { old, new } => write!(f, "the old build {}, the new build {}", old, new),
+            Self::Uart { offset, old, new } => write!(
+                f,
+                "the UART output differs from byte {}: old {:?}, new {:?}",
+                offset,
+                String::from_utf8_lossy(old),
+                String::from_utf8_lossy(new)
+            ),
+            Self::Memory {
+                start,
+                end,
+                old,
+                new,
+            } => {
+                let words = |words: &[u16]| {
+                    let mut text = words
+                        .iter()
+                        .take(SHOWN_WORDS)
+                        .map(|word| format!("{:04x}", word))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    if words.len() > SHOWN_WORDS {
+                        text.push_str(" ...");
+                    }
+                    text
+                };
+                write!(
+                    f,
+                    "RAM {:#06x}-{:#06x} differs: old {}, new {}",
+                    start,
+                    end,
+                    words(old),
+                    words(new)
+                )
+            }
+        }
+    }
+}
+
+/// The result of [`compare()`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Regression {
+    pub old: Outcome,
+    pub new: Outcome,
+    /// Empty if the builds behave the same
+    pub differences: Vec<Difference>,
+}
+
+impl Regression {
+    pub fn passed(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+impl fmt::Display for Regression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for difference in &self.differences {
+            writeln!(f, "{}", difference)?;
+        }
+        if self.passed() {
+            write!(f, "no differences, ")?;
+        }
+        write!(
+            f,
+            "the old build ran {} cycles, the new build {}",
+            self.old.cycles, self.new.cycles
+        )
+    }
+}
+
+/// Run a build with a UART mounted at its default address until it stops. The VM is deterministic, so the outcome
+/// is the same on every run
+/// ## Errors
+/// Errors of creating the VM, see [`Vm::new()`]
+pub fn run(mut vm_desc: VmDescription, options: &Options) -> Result<Outcome, BError> {
+    vm_desc.deterministic = true;
+    let mapped = vm_desc
+        .rom_mappings
+        .iter()
+        .filter(|(_, length, _)| *length > 0)
+        .map(|(_, length, address)| (*address, address.saturating_add(length - 1)))
+        .collect();
+    let io = mount_uart_io(&mut vm_desc);
+    let mut vm = Vm::new(vm_desc)?;
+    for byte in &options.input {
+        // the receiver lives as long as the VM
+        io.input.send(*byte).unwrap();
+    }
+    let stop = match vm.run_with_budget(options.max_cycles) {
+        Ok(BudgetRun {
+            outcome: BudgetOutcome::Halted,
+            ..
+        }) => Stop::Halted,
+        Ok(_) => Stop::CycleLimit,
+        Err(e) => Stop::Failed(e.to_string()),
+    };
+    Ok(Outcome {
+        stop,
+        cycles: vm.get_cycles(),
+        uart: io.output.try_iter().collect(),
+        ram: vm.get_ram_region(0, RAM_LEN).to_vec(),
+        mapped,
+    })
+}
+
+/// Run the old and the new build of a program and compare how they stop, their UART output and their final RAM
+/// ## Errors
+/// Errors of creating either VM
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler, regression::{Difference, Options, compare}};
+///
+/// let old = r"[text]
+/// A = 5
+/// D = add, 0, A
+/// D = add, D, A
+/// A = 0x100
+/// *A = add, 0, D
+/// [consts 0x200]";
+/// // shorter, same result
+/// let new = r"[text]
+/// A = 10
+/// D = add, 0, A
+/// A = 0x100
+/// *A = add, 0, D
+/// [consts 0x200]";
+/// let options = Options { max_cycles: 100, ..Default::default() };
+/// let regression = compare(assembler::run(old).unwrap(), assembler::run(new).unwrap(), &options).unwrap();
+/// assert!(regression.passed());
+///
+/// let broken = new.replace("A = 10", "A = 11");
+/// let regression = compare(assembler::run(old).unwrap(), assembler::run(&broken).unwrap(), &options).unwrap();
+/// assert!(matches!(regression.differences[..], [Difference::Memory { start: 0x100, end: 0x100, .. }]));
+/// assert!(regression.to_string().starts_with("RAM 0x0100-0x0100 differs: old 000a, new 000b"));
+/// ```
+pub fn compare(
+    old: VmDescription,
+    new: VmDescription,
+    options: &Options,
+) -> Result<Regression, BError> {
+    let old = run(old, options)?;
+    let new = run(new, options)?;
+    let mut differences = Vec::new();
+
+    if old.stop != new.stop {
+        differences.push(Difference::Stop {
+            old: old.stop.clone(),
+            new: new.stop.clone(),
+        });
+    }
+    if old.uart != new.uart {
+        let offset = old
+            .uart
+            .iter()
+            .zip(&new.uart)
+            .take_while(|(old, new)| old == new)
+            .count();
+        differences.push(Difference::Uart {
+            offset,
+            old: old.uart.clone(),
+            new: new.uart.clone(),
+        });
+    }
+
+    let compared = |address: u16| {
+        let inside = |(start, end): &(u16, u16)| (*start..=*end).contains(&address);
+        !old.mapped.iter().chain(&new.mapped).any(inside)
+            && !options.ignore.iter().any(|range| range.contains(&address))
+    };
+    let mut current: Option<(u16, u16)> = None;
+    for address in 0..RAM_LEN {
+        let differs = old.ram[address] != new.ram[address] && compared(address as u16);
+        match (differs, current) {
+            (true, Some((start, _))) => current = Some((start, address as u16)),
+            (true, None) => current = Some((address as u16, address as u16)),
+            (false, Some(range)) => {
+                differences.push(memory_difference(&old, &new, range));
+                current = None;
+            }
+            (false, None) => {}
+        }
+    }
+    if let Some(range) = current {
+        differences.push(memory_difference(&old, &new, range));
+    }
+
+    Ok(Regression {
+        old,
+        new,
+        differences,
+    })
+}
+
+fn memory_difference(old: &Outcome, new: &Outcome, (start, end): (u16, u16)) -> Difference {
+    let range = start as usize..=end as usize;
+    Difference::Memory {
+        start,
+        end,
+        old: old.ram[range.clone()].to_vec(),
+        new: new.ram[range].to_vec(),
+    }
+}
@@ -0,0 +1,471 @@
+//! Tests shipped with guest code. A `[tests]` section at the end of a .basm file holds test cases, which set up the
+//! registers and memory, run the program for a limited amount of cycles and check the registers, memory and UART
+//! output afterwards. The assembler ignores the section. See [tests](../docs/assembly.md#the-tests-section)
+//! ```text
+//! [tests]
+//! begin doubles_five
+//!     set D 5
+//!     cycles 100
+//!     expect *result 10
+//! end
+//! ```
+use std::fmt;
+
+use crate::{
+    AsmError, BError,
+    assembler::{self, debug_info::DebugInfo},
+    mmio::uart::mount_uart_io,
+    util::number_literal_to_u16,
+    vm::{Coverage, Register, StateDump, Vm},
+};
+
+pub mod snapshot;
+
+/// Amount of cycles a test runs unless it sets `cycles`
+pub const DEFAULT_CYCLES: u64 = 10_000;
+
+/// Something a test can set or check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Pc,
+    /// One of A and D - H
+    Register(Register),
+    Memory(u16),
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pc => write!(f, "pc"),
+            Self::Register(register) => write!(f, "{}", register),
+            Self::Memory(address) => write!(f, "*{:#06x}", address),
+        }
+    }
+}
+
+/// A check made after the run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expectation {
+    Value {
+        location: Location,
+        value: u16,
+    },
+    /// Everything the program wrote to the UART
+    Uart(Vec<u8>),
+}
+
+/// One test of a `[tests]` section
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestCase {
+    pub name: String,
+    /// Line of the `begin`, starting at 1
+    pub line: usize,
+    pub setup: Vec<(Location, u16)>,
+    /// Bytes in the UART input FIFO when the test starts
+    pub input: Vec<u8>,
+    /// Maximum amount of cycles, the test also ends when the program runs past the end of ROM
+    pub cycles: u64,
+    pub expectations: Vec<Expectation>,
+}
+
+/// The outcome of one test
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestResult {
+    pub name: String,
+    /// Amount of cycles the program ran
+    pub cycles: u64,
+    /// Failed expectations or the error that stopped the program. Empty if the test passed
+    pub failures: Vec<String>,
+    /// Instructions executed by the test
+    pub coverage: Coverage,
+    /// State of the VM when the test ended
+    pub state: StateDump,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// The outcome of all tests of a program
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TestReport {
+    pub results: Vec<TestResult>,
+    /// Instructions executed by any of the tests, `None` if there are no tests
+    pub coverage: Option<Coverage>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(TestResult::passed)
+    }
+
+    /// Amount of failed tests
+    pub fn failed(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|result| !result.passed())
+            .count()
+    }
+}
+
+impl fmt::Display for TestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            let outcome = if result.passed() { "ok" } else { "FAILED" };
+            writeln!(
+                f,
+                "test {} ... {} ({} cycles)",
+                result.name, outcome, result.cycles
+            )?;
+            for failure in &result.failures {
+                writeln!(f, "    {}", failure)?;
+            }
+        }
+        write!(
+            f,
+            "{} passed, {} failed",
+            self.results.len() - self.failed(),
+            self.failed()
+        )
+    }
+}
+
+/// Parse a number or the name of a label. Labels of the \[text\] section are ROM addresses, labels of the \[consts\]
+/// section RAM addresses
+fn parse_value(input: &str, info: &DebugInfo) -> Option<u16> {
+    number_literal_to_u16(input)
+        .ok()
+        .or_else(|| info.labels.get(input).copied())
+        .or_else(|| info.consts.get(input).copied())
+}
+
+fn parse_location(input: &str, info: &DebugInfo) -> Option<Location> {
+    if input == "pc" {
+        return Some(Location::Pc);
+    }
+    if let Some(address) = input.strip_prefix('*') {
+        return parse_value(address, info).map(Location::Memory);
+    }
+    match Register::from_str(input)? {
+        Register::MA | Register::None => None,
+        register => Some(Location::Register(register)),
+    }
+}
+
+/// Parse a string in double quotes. Supports the escapes `\n`, `\t`, `\\` and `\"`
+fn parse_string(input: &str) -> Option<Vec<u8>> {
+    let inner = input.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                c @ ('\\' | '"') => c,
+                _ => return None,
+            },
+            c => c,
+        };
+        let mut buf = [0; 4];
+        out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+    Some(out)
+}
+
+/// Parse the `[tests]` section. `first_line` is the line `tests` starts on, labels are resolved with `info`
+/// ## Errors
+/// [`BError::AsmParseError`] with the line of the first malformed statement
+pub fn parse_tests(
+    tests: &str,
+    first_line: usize,
+    info: &DebugInfo,
+) -> Result<Vec<TestCase>, BError> {
+    let mut cases = Vec::new();
+    let mut current: Option<TestCase> = None;
+    for (idx, text) in tests.lines().enumerate() {
+        let line = first_line + idx;
+        let trimmed = text.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| BError::AsmParseError(AsmError::at(line, text, message));
+        let (keyword, rest) = trimmed.split_once(' ').unwrap_or((trimmed, ""));
+        let rest = rest.trim();
+        let Some(case) = current.as_mut() else {
+            match keyword {
+                "begin" if !rest.is_empty() && !rest.contains(' ') => {
+                    current = Some(TestCase {
+                        name: rest.to_string(),
+                        line,
+                        setup: Vec::new(),
+                        input: Vec::new(),
+                        cycles: DEFAULT_CYCLES,
+                        expectations: Vec::new(),
+                    });
+                    continue;
+                }
+                _ => return Err(error("expected `begin NAME`")),
+            }
+        };
+        match keyword {
+            "end" => cases.push(current.take().unwrap()),
+            "set" | "expect" => {
+                let (location, value) = rest
+                    .split_once(' ')
+                    .ok_or_else(|| error("expected a location and a value"))?;
+                let value = value.trim();
+                if keyword == "expect" && location == "uart" {
+                    let output = parse_string(value).ok_or_else(|| error("invalid string"))?;
+                    case.expectations.push(Expectation::Uart(output));
+                    continue;
+                }
+                let location =
+                    parse_location(location, info).ok_or_else(|| error("invalid location"))?;
+                let value = parse_value(value, info).ok_or_else(|| error("invalid value"))?;
+                if keyword == "set" {
+                    case.setup.push((location, value));
+                } else {
+                    case.expectations
+                        .push(Expectation::Value { location, value });
+                }
+            }
+            "input" => {
+                let input = parse_string(rest).ok_or_else(|| error("invalid string"))?;
+                case.input.extend(input);
+            }
+            "cycles" => {
+                case.cycles = rest
+                    .parse()
+                    .map_err(|_| error("invalid amount of cycles"))?;
+            }
+            _ => return Err(error("unknown statement")),
+        }
+    }
+    match current {
+        Some(case) => Err(BError::AsmParseError(
+            format!("test {} is missing its `end`", case.name).into(),
+        )),
+        None => Ok(cases),
+    }
+}
+
+fn read(vm: &Vm, location: Location) -> u16 {
+    match location {
+        Location::Pc => vm.get_pc(),
+        Location::Register(register) => vm.get_reg(register),
+        Location::Memory(address) => vm.get_ram_region(address, 1)[0],
+    }
+}
+
+/// Assemble `assembly` and run one test on it in a deterministic VM, with a UART mounted at its default address
+/// ## Errors
+/// Errors of assembling the program and creating the VM. Errors of the program fail the test instead
+/// ## Examples
+/// ```rust
+/// use bric_vm::testing::{DEFAULT_CYCLES, Expectation, TestCase, run_test};
+///
+/// // write 'a' to the UART
+/// let code = r"[text]
+/// A = 0x61
+/// D = add, 0, A
+/// A = 0x6001
+/// *A = add, 0, D
+/// A = 1
+/// D = add, 0, A
+/// A = 0x6004
+/// *A = add, 0, D
+/// [consts 0x100]";
+/// let mut case = TestCase {
+///     name: "prints_a".to_string(),
+///     line: 1,
+///     setup: Vec::new(),
+///     input: Vec::new(),
+///     cycles: DEFAULT_CYCLES,
+///     expectations: vec![Expectation::Uart(b"a".to_vec())],
+/// };
+/// assert!(run_test(code, &case).unwrap().passed());
+/// case.expectations = vec![Expectation::Uart(b"b".to_vec())];
+/// assert_eq!(
+///     run_test(code, &case).unwrap().failures,
+///     [r#"expected the UART output "b", found "a""#]
+/// );
+/// ```
+pub fn run_test(assembly: &str, case: &TestCase) -> Result<TestResult, BError> {
+    let mut vm_desc = assembler::run(assembly)?;
+    vm_desc.deterministic = true;
+    let io = mount_uart_io(&mut vm_desc);
+    let mut vm = Vm::new(vm_desc)?;
+    vm.enable_coverage();
+    for byte in &case.input {
+        // the receiver lives as long as the VM
+        io.input.send(*byte).unwrap();
+    }
+    for (location, value) in &case.setup {
+        match location {
+            Location::Pc => vm.set_pc(*value),
+            Location::Register(register) => vm.set_reg(*register, *value),
+            Location::Memory(address) => vm.set_ram(*address, *value),
+        }
+    }
+
+    let mut failures = Vec::new();
+    let cycles = match vm.run_with_budget(case.cycles) {
+        Ok(run) => run.cycles,
+        Err(e) => {
+            failures.push(format!("cycle {}: {}", vm.get_cycles(), e));
+            vm.get_cycles()
+        }
+    };
+
+    let output: Vec<u8> = io.output.try_iter().collect();
+    for expectation in &case.expectations {
+        match expectation {
+            Expectation::Value { location, value } => {
+                let actual = read(&vm, *location);
+                if actual != *value {
+                    failures.push(format!(
+                        "expected {} to be {:#06x}, found {:#06x}",
+                        location, value, actual
+                    ));
+                }
+            }
+            Expectation::Uart(expected) if *expected != output => failures.push(format!(
+                "expected the UART output {:?}, found {:?}",
+                String::from_utf8_lossy(expected),
+                String::from_utf8_lossy(&output)
+            )),
+            Expectation::Uart(_) => {}
+        }
+    }
+    Ok(TestResult {
+        name: case.name.clone(),
+        cycles,
+        failures,
+        // enabled above
+        coverage: vm.coverage().unwrap(),
+        state: vm.dump_state(),
+    })
+}
+
+/// Assemble a program and parse the tests of its `[tests]` section, if it has one
+/// ## Errors
+/// Errors of assembling the program or parsing the tests
+pub fn load_tests(assembly: &str) -> Result<Vec<TestCase>, BError> {
+    let (_, info) = assembler::run_with_debug_info(assembly)?;
+    match assembler::split_tests(assembly) {
+        (_, Some((tests, line))) => parse_tests(tests, line, &info),
+        (_, None) => Ok(Vec::new()),
+    }
+}
+
+/// Assemble a program and run every test of its `[tests]` section
+/// ## Errors
+/// Errors of assembling the program or parsing the tests
+/// ## Examples
+/// ```rust
+/// use bric_vm::testing::run_tests;
+///
+/// let code = r#"[text]
+/// A = 0x10
+/// D = add, 0, *A
+/// D = add, D, A
+/// *A = add, 0, D
+/// [consts 0x100]
+/// [tests]
+/// begin adds_sixteen
+///     set *0x10 2
+///     cycles 4
+///     expect *0x10 0x12
+///     expect D 0x12
+/// end
+/// begin wrong
+///     cycles 4
+///     expect *0x10 1
+/// end"#;
+/// let report = run_tests(code).unwrap();
+/// assert!(report.results[0].passed());
+/// assert_eq!(report.failed(), 1);
+/// assert_eq!(report.results[1].failures, ["expected *0x0010 to be 0x0001, found 0x0010"]);
+/// assert!(report.to_string().ends_with("1 passed, 1 failed"));
+/// assert_eq!(report.coverage.unwrap().percent(), 100.0);
+/// ```
+pub fn run_tests(assembly: &str) -> Result<TestReport, BError> {
+    let cases = load_tests(assembly)?;
+    let results: Vec<TestResult> = cases
+        .iter()
+        .map(|case| run_test(assembly, case))
+        .collect::<Result<_, _>>()?;
+    let coverage =
+        results
+            .iter()
+            .map(|result| result.coverage.clone())
+            .reduce(|mut all, coverage| {
+                all.merge(&coverage);
+                all
+            });
+    Ok(TestReport { results, coverage })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tests() {
+        let code = r#"[text]
+label start:
+A = 0x61
+D = add, 0, A
+A = 0x6001
+*A = add, 0, D
+A = 1
+D = add, 0, A
+A = 0x6004
+*A = add, 0, D
+[consts 0x100]
+label value:
+M = 7
+[tests]
+# comment
+begin prints_a
+    input "x\n"
+    set E 3
+    set *value 8
+    cycles 8
+    expect uart "a"
+    expect *value 8
+    expect pc 8
+end"#;
+        let (_, info) = assembler::run_with_debug_info(code).unwrap();
+        let (tests, line) = assembler::split_tests(code).1.unwrap();
+        assert_eq!(line, 14);
+        let cases = parse_tests(tests, line, &info).unwrap();
+        assert_eq!(cases.len(), 1);
+        assert_eq!(cases[0].line, 16);
+        assert_eq!(cases[0].input, b"x\n");
+        assert_eq!(
+            cases[0].setup,
+            [
+                (Location::Register(Register::E), 3),
+                (Location::Memory(0x100), 8)
+            ]
+        );
+
+        for bad in [
+            "set D 1",
+            "begin x\nset *nowhere 1\nend",
+            "begin x\nexpect uart abc\nend",
+            "begin x\nfrobnicate\nend",
+            "begin x",
+        ] {
+            assert!(parse_tests(bad, 1, &info).is_err(), "{}", bad);
+        }
+        let Err(BError::AsmParseError(e)) = parse_tests("begin x\nset *A 1\nend", 20, &info) else {
+            panic!("`*A` is not a location");
+        };
+        assert_eq!(e.line, Some(21));
+    }
+}
@@ -0,0 +1,187 @@
+//! Golden file tests of the execution of guest programs. The state of the VM is rendered as text at a list of cycles
+//! and compared against a file checked in next to the tests, so changes to the assembler or the VM that alter what a
+//! program does show up as a diff. Missing golden files are written, setting the environment variable
+//! [`UPDATE_VAR`] to `1` rewrites them.
+//! ```rust,no_run
+//! use bric_vm::{assembler, testing::snapshot, vm::Vm};
+//!
+//! let mut vm = Vm::new(assembler::run("[text]\nA = 5\n[consts 0x100]").unwrap()).unwrap();
+//! snapshot::assert_snapshot(&mut vm, &[1, 100], "tests/golden/five.snap");
+//! ```
+use std::{fmt::Write, fs, io, path::Path};
+
+use crate::{
+    BError,
+    vm::{RAM_LEN, Vm},
+};
+
+/// Set to `1` to rewrite the golden files instead of comparing against them
+pub const UPDATE_VAR: &str = "BRIC_UPDATE_SNAPSHOTS";
+
+/// Words per line of the RAM dump
+const ROW_LEN: usize = 8;
+
+/// Render the registers, the flags and every row of RAM that isn't all zero
+pub fn render(vm: &Vm) -> String {
+    let mut out = vm.dump_state().to_string();
+    out.push('\n');
+    let ram = vm.get_ram_region(0, RAM_LEN);
+    for (idx, row) in ram.chunks(ROW_LEN).enumerate() {
+        if row.iter().all(|word| *word == 0) {
+            continue;
+        }
+        // writing to a String doesn't fail
+        let _ = write!(out, "{:#06x}:", idx * ROW_LEN);
+        for word in row {
+            let _ = write!(out, " {:04x}", word);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Run the VM and render its state once it executed each amount of cycles in `checkpoints`, which are ascending. Running
+/// past the end of ROM ends the capture
+/// ## Errors
+/// Errors of [`Vm::cycle()`] other than [`BError::ExecutionHaltedError`]
+pub fn capture(vm: &mut Vm, checkpoints: &[u64]) -> Result<String, BError> {
+    let mut out = String::new();
+    for checkpoint in checkpoints {
+        while vm.get_cycles() < *checkpoint {
+            match vm.cycle() {
+                Ok(()) => {}
+                Err(BError::ExecutionHaltedError { .. }) => {
+                    let _ = writeln!(out, "== halted after {} cycles", vm.get_cycles());
+                    out.push_str(&render(vm));
+                    return Ok(out);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let _ = writeln!(out, "== checkpoint {}", checkpoint);
+        out.push_str(&render(vm));
+    }
+    Ok(out)
+}
+
+/// Show the lines only in `expected` with `-` and the lines only in `actual` with `+`, with up to two lines of context
+/// ## Examples
+/// ```rust
+/// use bric_vm::testing::snapshot::diff;
+///
+/// assert_eq!(diff("a\nb\nc\n", "a\nx\nc\n"), " a\n-b\n+x\n c\n");
+/// ```
+pub fn diff(expected: &str, actual: &str) -> String {
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+    // length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(('-', old[i]));
+            i += 1;
+        } else {
+            lines.push(('+', new[j]));
+            j += 1;
+        }
+    }
+    let changed: Vec<usize> = (0..lines.len())
+        .filter(|idx| lines[*idx].0 != ' ')
+        .collect();
+    let mut out = String::new();
+    let mut last = None;
+    for (idx, (sign, line)) in lines.iter().enumerate() {
+        if !changed.iter().any(|c| c.abs_diff(idx) <= 2) {
+            continue;
+        }
+        if last.is_some_and(|last| last + 1 != idx) {
+            out.push_str("...\n");
+        }
+        let _ = writeln!(out, "{}{}", sign, line);
+        last = Some(idx);
+    }
+    out
+}
+
+/// Compare `actual` against the golden file at `path`. Writes the file instead if it doesn't exist or [`UPDATE_VAR`]
+/// is set. Returns the diff if they differ
+/// ## Errors
+/// Errors of reading or writing the golden file
+pub fn check(actual: &str, path: impl AsRef<Path>) -> io::Result<Option<String>> {
+    let path = path.as_ref();
+    let update = std::env::var(UPDATE_VAR).is_ok_and(|value| value == "1");
+    if update || !path.exists() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, actual)?;
+        return Ok(None);
+    }
+    let expected = fs::read_to_string(path)?;
+    Ok((expected != actual).then(|| diff(&expected, actual)))
+}
+
+/// Capture the VM at `checkpoints` and compare against the golden file at `path`, see [`capture()`] and [`check()`]
+/// ## Panics
+/// If the VM fails, the golden file can't be accessed or the states differ. The message holds the diff
+pub fn assert_snapshot(vm: &mut Vm, checkpoints: &[u64], path: impl AsRef<Path>) {
+    let path = path.as_ref();
+    let actual = capture(vm, checkpoints).unwrap_or_else(|e| panic!("the VM failed: {}", e));
+    match check(&actual, path) {
+        Ok(None) => {}
+        Ok(Some(diff)) => panic!(
+            "snapshot {} differs, run with {}=1 to update it\n{}",
+            path.display(),
+            UPDATE_VAR,
+            diff
+        ),
+        Err(e) => panic!("unable to access {}: {}", path.display(), e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    #[test]
+    fn test_snapshot() {
+        let code = r"[text]
+A = 5
+D = add, 0, A
+A = 0x100
+*A = add, 0, D
+[consts 0x200]";
+        let mut vm = Vm::new(assembler::run(code).unwrap()).unwrap();
+        let text = capture(&mut vm, &[2, 4, 1000]).unwrap();
+        assert!(text.starts_with("== checkpoint 2\ncycle 2, pc 0x0002: A = 256\n"));
+        assert!(text.contains("== checkpoint 4\n"));
+        assert!(text.contains("0x0100: 0005 0000 0000 0000 0000 0000 0000 0000\n"));
+        assert!(text.contains("== halted after 15 cycles\n"));
+
+        let path = std::env::temp_dir().join(format!("bric_snapshot_{}.snap", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert_eq!(check(&text, &path).unwrap(), None);
+        assert_eq!(check(&text, &path).unwrap(), None);
+        let changed = text.replace("0x0100: 0005", "0x0100: 0006");
+        let diff = check(&changed, &path).unwrap().unwrap();
+        assert!(diff.contains("-0x0100: 0005"));
+        assert!(diff.contains("+0x0100: 0006"));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,6 +1,13 @@
-use std::fmt::Debug;
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt::Debug;
 
-/// Custom Error class including all errors for the VM and associated tools
+/// Custom Error class including all errors for the VM and associated tools. Every variant has a stable code, see
+/// [`BError::code()`]
 #[derive(Debug)]
 pub enum BError {
     /// Instruction Parsing Error
@@ -16,15 +23,59 @@ pub enum BError {
     /// IO Error
     IoError(String),
     /// Assembly Parse Error
-    AsmParseError(String),
+    AsmParseError(AsmError),
     /// Serialization Error
-    SerializationError(String),
+    SerializationError(FormatError),
     /// Deserialization Error
-    DeserializationError(String),
+    DeserializationError(FormatError),
+    /// Write to read only memory by the instruction at `pc`
+    AccessViolation { pc: u16, address: u16 },
 }
 
-impl std::fmt::Display for BError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl BError {
+    /// Get the stable code of the error. Codes are never reused or changed
+    /// | Code    | Variant                                |
+    /// | ------- | -------------------------------------- |
+    /// | `B0001` | [`BError::InstParseError`]             |
+    /// | `B0002` | [`BError::ExecutionHaltedError`]       |
+    /// | `B0003` | [`BError::InvalidInstructionError`]    |
+    /// | `B0004` | [`BError::MapError`]                   |
+    /// | `B0005` | [`BError::OutOfBoundsError`]           |
+    /// | `B0006` | [`BError::IoError`]                    |
+    /// | `B0007` | [`BError::AsmParseError`]              |
+    /// | `B0008` | [`BError::SerializationError`]         |
+    /// | `B0009` | [`BError::DeserializationError`]       |
+    /// | `B0010` | [`BError::AccessViolation`]            |
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::{BError, assembler, vm::VmDescription};
+    ///
+    /// let Err(error) = assembler::run("[text]\nD = add, D, E\n[consts 0x100]") else { unreachable!() };
+    /// assert_eq!(error.code(), "B0007");
+    /// let BError::AsmParseError(error) = error else { unreachable!() };
+    /// assert_eq!((error.line, error.message.as_str()), (Some(1), "one or both operands invalid"));
+    ///
+    /// let Err(BError::DeserializationError(error)) = VmDescription::deserialize(b"ELF\x04") else { unreachable!() };
+    /// assert_eq!((error.expected.unwrap(), error.found.unwrap()), ("BVM".to_string(), "ELF".to_string()));
+    /// ```
+    pub const fn code(&self) -> &'static str {
+        match self {
+            BError::InstParseError { .. } => "B0001",
+            BError::ExecutionHaltedError { .. } => "B0002",
+            BError::InvalidInstructionError { .. } => "B0003",
+            BError::MapError(_) => "B0004",
+            BError::OutOfBoundsError(..) => "B0005",
+            BError::IoError(_) => "B0006",
+            BError::AsmParseError(_) => "B0007",
+            BError::SerializationError(_) => "B0008",
+            BError::DeserializationError(_) => "B0009",
+            BError::AccessViolation { .. } => "B0010",
+        }
+    }
+}
+
+impl core::fmt::Display for BError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             BError::InstParseError { value, message } => {
                 write!(f, "unable to parse {value}: {message}")
@@ -47,37 +98,188 @@ impl std::fmt::Display for BError {
             BError::IoError(message) => {
                 write!(f, "input / output error: {}", message)
             }
-            BError::AsmParseError(message) => {
-                write!(f, "unable to parse assembly: {message}")
+            BError::AsmParseError(error) => {
+                write!(f, "unable to parse assembly: {error}")
             }
-            BError::SerializationError(message) => {
-                write!(f, "error serializing: {message}")
+            BError::SerializationError(error) => {
+                write!(f, "error serializing: {error}")
             }
-            BError::DeserializationError(message) => {
-                write!(f, "error deserializing: {message}")
+            BError::DeserializationError(error) => {
+                write!(f, "error deserializing: {error}")
             }
+            BError::AccessViolation { pc, address } => {
+                write!(
+                    f,
+                    "forbidden write on {address:#06x} by the instruction at {pc:#06x}"
+                )
+            }
+        }
+    }
+}
+
+impl core::error::Error for BError {}
+
+/// An error in assembly source
+/// - `line`: The line the error was found on, if it belongs to one
+/// - `text`: The text of that line
+/// - `message`: What is wrong
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: Option<usize>,
+    pub text: Option<String>,
+    pub message: String,
+}
+
+impl AsmError {
+    /// An error on line `line` reading `text`
+    pub fn at(line: usize, text: &str, message: impl Into<String>) -> Self {
+        Self {
+            line: Some(line),
+            text: Some(text.to_string()),
+            message: message.into(),
         }
     }
 }
 
-/// Represents a labeled interval between two numbers
+impl From<String> for AsmError {
+    fn from(message: String) -> Self {
+        Self {
+            line: None,
+            text: None,
+            message,
+        }
+    }
+}
+
+impl From<&str> for AsmError {
+    fn from(message: &str) -> Self {
+        message.to_string().into()
+    }
+}
+
+impl core::fmt::Display for AsmError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match (self.line, &self.text) {
+            (Some(line), Some(text)) => {
+                write!(f, "error on line {}: {}\n{}", line, text, self.message)
+            }
+            (Some(line), None) => write!(f, "error on line {}: {}", line, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// An error in a binary format, e.g. a .bvm file
+/// - `offset`: Byte offset into the input where the error was found, if known
+/// - `expected`: What should have been there
+/// - `found`: What was there instead
+/// - `message`: What is wrong
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatError {
+    pub offset: Option<usize>,
+    pub expected: Option<String>,
+    pub found: Option<String>,
+    pub message: String,
+}
+
+impl FormatError {
+    /// Add what was expected and what was found instead
+    pub fn expected(mut self, expected: impl ToString, found: impl ToString) -> Self {
+        self.expected = Some(expected.to_string());
+        self.found = Some(found.to_string());
+        self
+    }
+
+    /// Add the byte offset into the input
+    pub fn at(mut self, offset: usize) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+}
+
+impl From<String> for FormatError {
+    fn from(message: String) -> Self {
+        Self {
+            offset: None,
+            expected: None,
+            found: None,
+            message,
+        }
+    }
+}
+
+impl From<&str> for FormatError {
+    fn from(message: &str) -> Self {
+        message.to_string().into()
+    }
+}
+
+impl core::fmt::Display for FormatError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(offset) = self.offset {
+            write!(f, " at byte {:#x}", offset)?;
+        }
+        if let (Some(expected), Some(found)) = (&self.expected, &self.found) {
+            write!(f, " (expected {}, found {})", expected, found)?;
+        }
+        Ok(())
+    }
+}
+
+/// Represents a labeled interval between two numbers. Regions with a higher priority take precedence over regions with a
+/// lower priority where they overlap in a [`RegionMap`].
 #[derive(Debug, Clone)]
 pub struct Region<K, V> {
     start: K,
     end: K,
     label: V,
+    priority: u8,
 }
 
 impl<K, V> Region<K, V> {
+    /// Create a region with priority 0
     pub fn new(start: K, end: K, label: V) -> Self {
-        Self { start, end, label }
+        Self::with_priority(start, end, label, 0)
+    }
+
+    /// Create a region with an explicit priority
+    pub fn with_priority(start: K, end: K, label: V, priority: u8) -> Self {
+        Self {
+            start,
+            end,
+            label,
+            priority,
+        }
+    }
+
+    /// Get the first position of the region
+    pub fn start(&self) -> &K {
+        &self.start
+    }
+
+    /// Get the last position of the region, it is part of the region
+    pub fn end(&self) -> &K {
+        &self.end
+    }
+
+    /// Get the label of the region
+    pub fn label(&self) -> &V {
+        &self.label
+    }
+
+    /// Get the priority of the region
+    pub fn priority(&self) -> u8 {
+        self.priority
     }
 }
 
-/// Represents a map of regions that are labeled that can be easily searched
+/// Represents a map of regions that are labeled that can be easily searched.
+/// Regions of the same priority may not overlap, regions of different priorities may.
 #[derive(Debug, Clone)]
 pub struct RegionMap<K, V> {
-    regions: Vec<Region<K, V>>,
+    /// Non-overlapping regions sorted by their start, grouped by priority. Ordered from highest to lowest priority.
+    layers: Vec<Vec<Region<K, V>>>,
 }
 
 impl<K, V> TryFrom<Vec<Region<K, V>>> for RegionMap<K, V>
@@ -97,20 +299,29 @@ where
             }
         }
 
-        value.sort_by(|a, b| a.start.cmp(&b.start));
+        // sort by priority (highest first) and then start
+        value.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.start.cmp(&b.start)));
 
-        // verify no overlaps
-        for i in 1..value.len() {
-            if value[i].start <= value[i - 1].end {
-                return Err(BError::MapError(format!(
-                    "regions {:?} and {:?} overlap",
-                    value[i - 1],
-                    value[i]
-                )));
+        let mut layers: Vec<Vec<Region<K, V>>> = Vec::new();
+        for region in value {
+            match layers.last_mut() {
+                Some(layer) if layer[0].priority == region.priority => {
+                    // verify no overlaps within a priority
+                    // the layer is never empty
+                    let previous = layer.last().unwrap();
+                    if region.start <= previous.end {
+                        return Err(BError::MapError(format!(
+                            "regions {:?} and {:?} overlap",
+                            previous, region
+                        )));
+                    }
+                    layer.push(region);
+                }
+                _ => layers.push(vec![region]),
             }
         }
 
-        Ok(Self { regions: value })
+        Ok(Self { layers })
     }
 }
 
@@ -120,13 +331,20 @@ where
     V: Clone + Debug,
 {
     /// Uses bisection search to find the label associated with the position given.
+    /// If multiple regions contain the position, the label of the region with the highest priority is returned.
     pub fn find_region(&self, position: K) -> Option<&V> {
+        self.layers
+            .iter()
+            .find_map(|layer| Self::find_in_layer(layer, position))
+    }
+
+    fn find_in_layer(regions: &[Region<K, V>], position: K) -> Option<&V> {
         let mut low = 0;
-        let mut high = self.regions.len();
+        let mut high = regions.len();
 
         while low < high {
             let mid = (low + high) / 2;
-            let region = &self.regions[mid];
+            let region = &regions[mid];
 
             if position < region.start {
                 high = mid;
@@ -139,12 +357,55 @@ where
 
         None
     }
+
+    /// Iterate over all regions, from the highest to the lowest priority and ordered by their start within a priority
+    pub fn iter(&self) -> impl Iterator<Item = &Region<K, V>> {
+        self.layers.iter().flatten()
+    }
+}
+
+impl<V> RegionMap<u16, V> {
+    /// Get the ranges of addresses that are not covered by any region, as inclusive start and end
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::util::{Region, RegionMap};
+    ///
+    /// let map = RegionMap::try_from(vec![
+    ///     Region::new(0x100, 0x1ff, ()),
+    ///     Region::with_priority(0x180, 0x2ff, (), 1),
+    /// ])
+    /// .unwrap();
+    /// assert_eq!(map.gaps(), vec![(0, 0xff), (0x300, 0xffff)]);
+    /// ```
+    pub fn gaps(&self) -> Vec<(u16, u16)> {
+        let mut covered: Vec<(u16, u16)> = self
+            .layers
+            .iter()
+            .flatten()
+            .map(|region| (region.start, region.end))
+            .collect();
+        covered.sort_unstable();
+        let mut gaps = Vec::new();
+        // first address that may be uncovered
+        let mut next = 0u32;
+        for (start, end) in covered {
+            if start as u32 > next {
+                gaps.push((next as u16, start - 1));
+            }
+            next = next.max(end as u32 + 1);
+        }
+        if next <= u16::MAX as u32 {
+            gaps.push((next as u16, u16::MAX));
+        }
+        gaps
+    }
 }
 
 pub(crate) fn check_slice(input: &[u8], len: usize) -> Result<&[u8], BError> {
     if len > input.len() {
-        Err(BError::SerializationError(
-            "File to short or part missing".to_string(),
+        Err(BError::DeserializationError(
+            FormatError::from("File to short or part missing")
+                .expected(format!("{} bytes", len), format!("{} bytes", input.len())),
         ))
     } else {
         Ok(&input[..len])
@@ -157,13 +418,79 @@ pub(crate) fn check_slice(input: &[u8], len: usize) -> Result<&[u8], BError> {
 pub(crate) fn extract_number(slice: &[u8]) -> Result<u16, BError> {
     let out = u16::from_be_bytes([slice[0], slice[1]]);
     if slice[2] != 0x00 {
-        return Err(BError::SerializationError(
-            "Invalid region separators".to_string(),
+        return Err(BError::DeserializationError(
+            FormatError::from("Invalid region separators").expected(0, slice[2]),
         ));
     }
     Ok(out)
 }
 
+/// A segment of a segmented container: type tag and payload
+pub(crate) type Segment<'a> = ([u8; 4], &'a [u8]);
+
+/// Write a segment table followed by the payloads of `segments`. Offsets are relative to the start of the output
+/// ## Errors
+/// When there are too many segments or the output gets larger than 4GiB
+pub(crate) fn write_segments(segments: &[Segment]) -> Result<Vec<u8>, BError> {
+    if segments.len() > 0xffff {
+        return Err(BError::SerializationError(
+            "The number of segments is to large".into(),
+        ));
+    }
+    let mut output = Vec::new();
+    output.extend_from_slice(&(segments.len() as u16).to_be_bytes());
+
+    let mut offset = 2 + 12 * segments.len();
+    for (tag, payload) in segments.iter() {
+        let (Ok(start), Ok(length)) = (u32::try_from(offset), u32::try_from(payload.len())) else {
+            return Err(BError::SerializationError(
+                "The file to be written is to large".into(),
+            ));
+        };
+        output.extend_from_slice(tag);
+        output.extend_from_slice(&start.to_be_bytes());
+        output.extend_from_slice(&length.to_be_bytes());
+        offset += payload.len();
+    }
+    for (_, payload) in segments.iter() {
+        output.extend_from_slice(payload);
+    }
+    Ok(output)
+}
+
+/// Read the segment table written by [`write_segments()`] and return the segments in table order
+/// ## Errors
+/// When the table is cut off or a segment lies outside of `input`
+pub(crate) fn read_segments(input: &[u8]) -> Result<Vec<Segment<'_>>, BError> {
+    let count = check_slice(input, 2)?;
+    let count = u16::from_be_bytes([count[0], count[1]]) as usize;
+    let table = check_slice(&input[2..], 12 * count)?;
+
+    let mut segments = Vec::with_capacity(count);
+    for entry in table.chunks(12) {
+        let tag = [entry[0], entry[1], entry[2], entry[3]];
+        let start = u32::from_be_bytes([entry[4], entry[5], entry[6], entry[7]]) as usize;
+        let length = u32::from_be_bytes([entry[8], entry[9], entry[10], entry[11]]) as usize;
+        let payload = input
+            .get(start..)
+            .and_then(|rest| rest.get(..length))
+            .ok_or_else(|| {
+                BError::DeserializationError(
+                    FormatError::from(format!(
+                        "segment {} lies outside of the file",
+                        String::from_utf8_lossy(&tag)
+                    ))
+                    .expected(
+                        format!("at most {} bytes", input.len()),
+                        start.saturating_add(length),
+                    ),
+                )
+            })?;
+        segments.push((tag, payload));
+    }
+    Ok(segments)
+}
+
 /// Get a number from a string looking like `0xabc`, `0b01` or `10`
 /// Error when conversion fails
 pub fn number_literal_to_u16(input: &str) -> Result<u16, ()> {
@@ -0,0 +1,117 @@
+//! Export of executions as VCD waveforms, e.g. to inspect them in GTKWave next to the waveforms of an HDL simulation.
+//! The PC, the registers and a selection of RAM words are 16 bit signals of the module `bric`, sampled after every
+//! cycle. One cycle is one time unit of `1 ns`.
+use std::io::{self, Write};
+
+use crate::vm::Vm;
+
+/// Writes the signals of a [`Vm`] as a VCD file
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler, vcd::VcdWriter, vm::Vm};
+///
+/// let code = r"[text]
+/// A = 5
+/// D = add, 0, A
+/// A = 0x100
+/// *A = add, 0, D
+/// [consts 0x200]";
+/// let mut vm = Vm::new(assembler::run(code).unwrap()).unwrap();
+/// let mut vcd = VcdWriter::new(Vec::new(), &[0x100]).unwrap();
+/// vcd.sample(&vm).unwrap();
+/// for _ in 0..4 {
+///     vm.cycle().unwrap();
+///     vcd.sample(&vm).unwrap();
+/// }
+/// let output = String::from_utf8(vcd.into_inner()).unwrap();
+/// assert!(output.contains("$var wire 16 ( mem_0100 $end"));
+/// // only changed signals are written
+/// assert!(output.ends_with("#4\nb0000000000000100 !\nb0000000000000101 (\n"));
+/// ```
+pub struct VcdWriter<W: Write> {
+    out: W,
+    addresses: Vec<u16>,
+    /// Last written value of every signal, `None` before the first sample
+    last: Vec<Option<u16>>,
+}
+
+/// Names of the signals before the watched RAM words
+const REGISTERS: [&str; 7] = ["pc", "a", "d", "e", "f", "g", "h"];
+
+/// Get the VCD identifier of the signal at `index`, made of the printable characters `!` to `~`
+fn identifier(mut index: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (index % 94) as u8) as char);
+        index /= 94;
+        if index == 0 {
+            return id;
+        }
+        index -= 1;
+    }
+}
+
+impl<W: Write> VcdWriter<W> {
+    /// Write the header declaring the PC, the registers and the RAM words at `addresses`
+    /// ## Errors
+    /// Errors of writing to `out`
+    pub fn new(mut out: W, addresses: &[u16]) -> io::Result<Self> {
+        writeln!(out, "$version bric_vm {} $end", env!("CARGO_PKG_VERSION"))?;
+        writeln!(out, "$timescale 1 ns $end")?;
+        writeln!(out, "$scope module bric $end")?;
+        let names = REGISTERS.iter().map(|name| name.to_string()).chain(
+            addresses
+                .iter()
+                .map(|address| format!("mem_{:04x}", address)),
+        );
+        for (index, name) in names.enumerate() {
+            writeln!(out, "$var wire 16 {} {} $end", identifier(index), name)?;
+        }
+        writeln!(out, "$upscope $end")?;
+        writeln!(out, "$enddefinitions $end")?;
+        Ok(Self {
+            out,
+            addresses: addresses.to_vec(),
+            last: vec![None; REGISTERS.len() + addresses.len()],
+        })
+    }
+
+    /// Write the signals that changed since the last sample at the current cycle of `vm`
+    /// ## Errors
+    /// Errors of writing to the output
+    pub fn sample(&mut self, vm: &Vm) -> io::Result<()> {
+        let cpu = vm.cpu_state();
+        let values = [cpu.pc, cpu.a, cpu.d, cpu.e, cpu.f, cpu.g, cpu.h]
+            .into_iter()
+            .chain(
+                self.addresses
+                    .iter()
+                    .map(|address| vm.get_ram_region(*address, 1)[0]),
+            );
+        let mut time_written = false;
+        for (index, value) in values.enumerate() {
+            if self.last[index] == Some(value) {
+                continue;
+            }
+            if !time_written {
+                writeln!(self.out, "#{}", vm.get_cycles())?;
+                time_written = true;
+            }
+            writeln!(self.out, "b{:016b} {}", value, identifier(index))?;
+            self.last[index] = Some(value);
+        }
+        Ok(())
+    }
+
+    /// Flush the output
+    /// ## Errors
+    /// Errors of flushing the output
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    /// Get the output back
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
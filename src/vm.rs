@@ -1,10 +1,43 @@
-use crate::util::{BError, Region, RegionMap};
-use core::fmt;
+use crate::mmio::{Device, registry::DeviceConfig, rng::mount_rng};
+use crate::util::{BError, FormatError, Region, RegionMap, Segment, read_segments, write_segments};
+use alloc::{
+    borrow::Cow,
+    boxed::Box,
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{
+    convert::TryFrom,
+    fmt,
+    num::Wrapping,
+    ops::{Range, RangeInclusive},
+};
 use num_enum::TryFromPrimitive;
-use std::{collections::HashMap, convert::TryFrom, num::Wrapping};
+
+pub mod alu;
+pub mod backing;
+pub mod boot;
+pub mod heat;
+pub mod inject;
+pub mod profile;
 
 /// The length of RAM for the BRIC
 pub const RAM_LEN: usize = 65536;
+
+/// Allocate zeroed RAM directly on the heap. `Box::new([0; RAM_LEN])` builds the array on the stack first
+/// ## Examples
+/// ```rust
+/// use bric_vm::vm::{RAM_LEN, empty_ram};
+///
+/// assert_eq!(empty_ram().len(), RAM_LEN);
+/// ```
+pub fn empty_ram() -> Box<[u16; RAM_LEN]> {
+    // the length is right, so the conversion can't fail
+    vec![0; RAM_LEN].into_boxed_slice().try_into().unwrap()
+}
 pub(crate) const BIT_15: u16 = 0b1000000000000000;
 
 fn new_parse_error(value: u8) -> BError {
@@ -44,8 +77,8 @@ impl Register {
     }
 }
 
-impl std::fmt::Display for Register {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::None => write!(f, "None"),
             Self::A => write!(f, "A"),
@@ -64,77 +97,494 @@ impl std::fmt::Display for Register {
 pub enum AccessLevels {
     ReadWrite,
     Read,
+    /// Writes proceed and trigger callbacks, reads return the open bus value like [`AccessLevels::None`]. Models
+    /// output-only registers
+    WriteOnly,
     None,
 }
 
+/// Where the CPU fetches its instructions from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExecutionMode {
+    /// Fetch instructions from the separate ROM
+    #[default]
+    Harvard,
+    /// Fetch instructions from RAM. The ROM is copied into RAM starting at address 0 when the VM is created,
+    /// which allows programs to modify their own code. Execution never halts by running out of instructions.
+    VonNeumann,
+}
+
+/// The kind of a [`Fault`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    /// Write to memory that isn't writable
+    ForbiddenWrite = 1,
+    /// Read from memory with [`AccessLevels::None`] or [`AccessLevels::WriteOnly`]
+    ForbiddenRead = 2,
+}
+
+impl FaultKind {
+    /// Get the kind from its numeric value
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Self::ForbiddenWrite),
+            2 => Some(Self::ForbiddenRead),
+            _ => None,
+        }
+    }
+}
+
+/// Describes a memory access that violated the memory protections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    /// The address of the instruction that caused the fault
+    pub pc: u16,
+    /// The address that was accessed
+    pub address: u16,
+    /// The value that was written or, for reads, the value the CPU read
+    pub value: u16,
+    pub kind: FaultKind,
+}
+
+/// How the PC left the ROM, see [`Vm::halt_cause()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltCause {
+    /// The instruction at `pc`, the last one in ROM, was executed and the PC moved on behind it
+    FellOffEnd { pc: u16 },
+    /// The `instruction` at `pc` jumped to `target`, which is outside of ROM
+    WildJump {
+        pc: u16,
+        instruction: u16,
+        target: u16,
+    },
+    /// The PC was placed at `pc` without executing an instruction, e.g. by the description or [`Vm::set_pc()`]
+    Placed { pc: u16 },
+}
+
+/// The value the CPU reads from memory with [`AccessLevels::None`] or [`AccessLevels::WriteOnly`]
+pub enum OpenBus {
+    /// Always read 0
+    Zero,
+    /// Always read 0xffff
+    Ones,
+    /// Read the last value that was read from or written to memory
+    LastValue,
+    /// Read the value returned by the callback. The callback receives the address that was read.
+    Callback(Box<dyn FnMut(u16) -> u16 + Send>),
+}
+
+impl fmt::Debug for OpenBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Zero => write!(f, "Zero"),
+            Self::Ones => write!(f, "Ones"),
+            Self::LastValue => write!(f, "LastValue"),
+            Self::Callback(_) => write!(f, "Callback"),
+        }
+    }
+}
+
+/// What happens when the CPU writes to memory it is not allowed to write to
+pub enum ForbiddenWritePolicy {
+    /// Drop the write silently
+    Ignore,
+    /// Drop the write and call the handler with the address and the value of the write
+    Handler(Box<dyn FnMut(u16, u16) + Send>),
+    /// Drop the write and make [`Vm::cycle()`] return a [`BError::AccessViolation`]
+    Error,
+}
+
+impl fmt::Debug for ForbiddenWritePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ignore => write!(f, "Ignore"),
+            Self::Handler(_) => write!(f, "Handler"),
+            Self::Error => write!(f, "Error"),
+        }
+    }
+}
+
+impl Default for ForbiddenWritePolicy {
+    /// Print a warning to stdout. Without the `std` feature forbidden writes are ignored.
+    fn default() -> Self {
+        #[cfg(feature = "std")]
+        {
+            Self::Handler(Box::new(|address, _| {
+                println!("[!] forbidden write on {}", address);
+            }))
+        }
+        #[cfg(not(feature = "std"))]
+        {
+            Self::Ignore
+        }
+    }
+}
+
+// Callbacks and devices are `Send` so a `Vm` can be moved to another thread. State shared with other threads can be
+// accessed through an `Arc<Mutex<_>>` as done by the UART.
+
+/// A write of the CPU as seen by write callbacks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteEvent {
+    /// The newly written value
+    pub value: u16,
+    /// The value of the cell before the write
+    pub old: u16,
+    /// Address of the instruction that made the write. 0 for writes made outside of [`Vm::cycle()`]
+    pub pc: u16,
+}
+
+impl WriteEvent {
+    /// Whether the write changed the value of the cell
+    pub fn changed(&self) -> bool {
+        self.value != self.old
+    }
+}
+
+/// A callback that runs when the CPU writes to a memory address. Receives the write, see [`WriteEvent`].
+pub type WriteCallback = Box<dyn FnMut(WriteEvent) + Send>;
+/// A callback that runs when the CPU reads from a memory address. Receives the value stored in RAM and returns the value
+/// the CPU reads.
+pub type ReadCallback = Box<dyn FnMut(u16) -> u16 + Send>;
+/// A callback that runs when the CPU writes into a range of memory. Receives the offset of the written address from the
+/// start of the range and the write.
+pub type RegionWriteCallback = Box<dyn FnMut(u16, WriteEvent) + Send>;
+/// A callback that runs when the CPU reads from a range of memory. Receives the offset of the read address from the
+/// start of the range and the value stored in RAM, returns the value the CPU reads.
+pub type RegionReadCallback = Box<dyn FnMut(u16, u16) -> u16 + Send>;
+
 /// Represents the RAM of the VM. This includes the MMIO. Callbacks can be registered using [`Ram::register_callback()`]
+/// and [`Ram::register_read_callback()`], or for entire ranges of memory using [`Ram::register_region_callback()`] and
+/// [`Ram::register_region_read_callback()`]
 pub struct Ram {
-    ram: [u16; RAM_LEN],
-    write_callbacks: HashMap<u16, Box<dyn FnMut(u16)>>,
+    ram: backing::Store,
+    write_callbacks: BTreeMap<u16, WriteCallback>,
+    read_callbacks: BTreeMap<u16, ReadCallback>,
+    region_write_callbacks: Vec<(u16, u16, RegionWriteCallback)>,
+    region_read_callbacks: Vec<(u16, u16, RegionReadCallback)>,
     memory_regions: RegionMap<u16, AccessLevels>,
+    /// Ordered: start, inclusive end, mirrored address
+    mirrors: Vec<(u16, u16, u16)>,
+    forbidden_write_policy: ForbiddenWritePolicy,
+    /// Address, value and kind of the last forbidden access since the last call to [`Ram::take_fault()`]
+    pending_fault: Option<(u16, u16, FaultKind)>,
+    /// Whether the pending fault is a forbidden write under [`ForbiddenWritePolicy::Error`]
+    raise_fault: bool,
+    /// Ordered: start, inclusive end, open bus behavior. Regions without an entry read [`OpenBus::Zero`]
+    open_bus: Vec<(u16, u16, OpenBus)>,
+    /// The last value that was read from or written to memory
+    last_bus_value: u16,
+    /// Address of the instruction being executed, handed to write callbacks
+    pc: u16,
 }
 
 impl fmt::Debug for Ram {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Ram")
             .field("ram", &self.ram)
             .field("write_callbacks", &self.write_callbacks.keys())
+            .field("read_callbacks", &self.read_callbacks.keys())
+            .field(
+                "region_write_callbacks",
+                &self
+                    .region_write_callbacks
+                    .iter()
+                    .map(|(start, end, _)| (start, end))
+                    .collect::<Vec<_>>(),
+            )
+            .field(
+                "region_read_callbacks",
+                &self
+                    .region_read_callbacks
+                    .iter()
+                    .map(|(start, end, _)| (start, end))
+                    .collect::<Vec<_>>(),
+            )
             .field("memory_regions", &self.memory_regions)
+            .field("mirrors", &self.mirrors)
+            .field("forbidden_write_policy", &self.forbidden_write_policy)
+            .field("pending_fault", &self.pending_fault)
+            .field("raise_fault", &self.raise_fault)
+            .field("open_bus", &self.open_bus)
+            .field("last_bus_value", &self.last_bus_value)
+            .field("pc", &self.pc)
             .finish()
     }
 }
 
 impl Ram {
-    fn read_ram(&self, position: u16) -> u16 {
+    /// Translate an address inside a mirrored range to the address it mirrors
+    fn resolve_mirror(&self, position: u16) -> u16 {
+        match self
+            .mirrors
+            .iter()
+            .find(|(start, end, _)| (*start..=*end).contains(&position))
+        {
+            Some((start, _, target)) => target + (position - start),
+            None => position,
+        }
+    }
+
+    /// Read memory the way the CPU does. Runs the read callback registered on `position` if there is one.
+    fn read_ram(&mut self, position: u16) -> u16 {
+        let position = self.resolve_mirror(position);
+        let value = match self
+            .memory_regions
+            .find_region(position)
+            .unwrap_or(&AccessLevels::ReadWrite)
+        {
+            AccessLevels::None | AccessLevels::WriteOnly => {
+                let last_bus_value = self.last_bus_value;
+                let value = match self.find_open_bus(position) {
+                    Some(OpenBus::Ones) => 0xffff,
+                    Some(OpenBus::LastValue) => last_bus_value,
+                    Some(OpenBus::Callback(cb)) => cb(position),
+                    Some(OpenBus::Zero) | None => 0,
+                };
+                self.pending_fault = Some((position, value, FaultKind::ForbiddenRead));
+                value
+            }
+            _ => {
+                let mut value = self.ram[position as usize];
+                if let Some(cb) = self.read_callbacks.get_mut(&position) {
+                    value = (*cb)(value);
+                }
+                if let Some((start, _, cb)) = self
+                    .region_read_callbacks
+                    .iter_mut()
+                    .find(|(start, end, _)| (*start..=*end).contains(&position))
+                {
+                    value = (*cb)(position - *start, value);
+                }
+                value
+            }
+        };
+        self.last_bus_value = value;
+        value
+    }
+
+    fn find_open_bus(&mut self, position: u16) -> Option<&mut OpenBus> {
+        self.open_bus
+            .iter_mut()
+            .find(|(start, end, _)| (*start..=*end).contains(&position))
+            .map(|(_, _, open_bus)| open_bus)
+    }
+
+    /// Read memory while respecting access levels but without triggering read callbacks or [`OpenBus::Callback`]s
+    fn peek_ram(&self, position: u16) -> u16 {
+        let position = self.resolve_mirror(position);
         match self
             .memory_regions
             .find_region(position)
             .unwrap_or(&AccessLevels::ReadWrite)
         {
-            AccessLevels::None => 0,
+            AccessLevels::None | AccessLevels::WriteOnly => match self
+                .open_bus
+                .iter()
+                .find(|(start, end, _)| (*start..=*end).contains(&position))
+            {
+                Some((_, _, OpenBus::Ones)) => 0xffff,
+                Some((_, _, OpenBus::LastValue)) => self.last_bus_value,
+                _ => 0,
+            },
             // doesn't panic because 16 < RAM_LEN
             _ => self.ram[position as usize],
         }
     }
 
     fn write_ram(&mut self, position: u16, value: u16) {
+        let position = self.resolve_mirror(position);
         match self
             .memory_regions
             .find_region(position)
             .unwrap_or(&AccessLevels::ReadWrite)
         {
-            AccessLevels::ReadWrite => {
+            AccessLevels::ReadWrite | AccessLevels::WriteOnly => {
+                let write = WriteEvent {
+                    value,
+                    old: self.ram[position as usize],
+                    pc: self.pc,
+                };
                 self.set_ram(position, value);
+                self.last_bus_value = value;
 
                 if let Some(cb) = self.write_callbacks.get_mut(&position) {
-                    (*cb)(value);
+                    (*cb)(write);
+                }
+                if let Some((start, _, cb)) = self
+                    .region_write_callbacks
+                    .iter_mut()
+                    .find(|(start, end, _)| (*start..=*end).contains(&position))
+                {
+                    (*cb)(position - *start, write);
                 }
             }
             _ => {
-                println!("[!] forbidden write on {}", position);
+                self.pending_fault = Some((position, value, FaultKind::ForbiddenWrite));
+                match &mut self.forbidden_write_policy {
+                    ForbiddenWritePolicy::Ignore => {}
+                    ForbiddenWritePolicy::Handler(handler) => handler(position, value),
+                    ForbiddenWritePolicy::Error => self.raise_fault = true,
+                }
             }
         }
     }
 
     /// Create new RAM. The `ram` argument is written directly to the newly created object.
-    pub fn new(ram: [u16; RAM_LEN], memory_regions: RegionMap<u16, AccessLevels>) -> Self {
+    pub fn new(ram: Box<[u16; RAM_LEN]>, memory_regions: RegionMap<u16, AccessLevels>) -> Self {
         Self {
-            ram,
-            write_callbacks: HashMap::new(),
+            ram: backing::Store::new(ram, backing::RamBacking::Flat),
+            write_callbacks: BTreeMap::new(),
+            read_callbacks: BTreeMap::new(),
+            region_write_callbacks: Vec::new(),
+            region_read_callbacks: Vec::new(),
             memory_regions,
+            mirrors: Vec::new(),
+            forbidden_write_policy: ForbiddenWritePolicy::default(),
+            pending_fault: None,
+            raise_fault: false,
+            open_bus: Vec::new(),
+            last_bus_value: 0,
+            pc: 0,
+        }
+    }
+
+    /// Set the value the CPU reads from addresses with [`AccessLevels::None`] or [`AccessLevels::WriteOnly`] in the `length` addresses starting at
+    /// `address`. Addresses without an open bus behavior read [`OpenBus::Zero`].
+    /// ## Errors
+    /// - [`BError::OutOfBoundsError`] if the range is empty or does not fit into RAM
+    /// - [`BError::MapError`] if the range overlaps with another open bus range
+    pub fn set_open_bus(
+        &mut self,
+        address: u16,
+        length: u16,
+        open_bus: OpenBus,
+    ) -> Result<(), BError> {
+        let end = check_callback_range(
+            address,
+            length,
+            self.open_bus.iter().map(|(s, e, _)| (*s, *e)),
+        )?;
+        self.open_bus.push((address, end, open_bus));
+        Ok(())
+    }
+
+    /// Set what happens when the CPU writes to memory it is not allowed to write to
+    pub fn set_forbidden_write_policy(&mut self, policy: ForbiddenWritePolicy) {
+        self.forbidden_write_policy = policy;
+    }
+
+    /// Take the address, value and kind of the last forbidden access and whether it should raise an error
+    fn take_fault(&mut self) -> Option<(u16, u16, FaultKind, bool)> {
+        let raise = core::mem::take(&mut self.raise_fault);
+        self.pending_fault
+            .take()
+            .map(|(address, value, kind)| (address, value, kind, raise))
+    }
+
+    /// Make the `length` addresses starting at `address` mirror the addresses starting at `target`. Reads and writes
+    /// (including [`Ram::set_ram()`]) on the mirror are forwarded to the target, access levels and callbacks of the
+    /// target apply. [`Ram::get_ram_region()`] returns the underlying memory and does not follow mirrors.
+    /// ## Errors
+    /// - [`BError::OutOfBoundsError`] if either range is empty or does not fit into RAM
+    /// - [`BError::MapError`] if the mirror overlaps with another mirror or a mirror's target, or the target lies inside
+    ///   a mirror
+    pub fn add_mirror(&mut self, address: u16, length: u16, target: u16) -> Result<(), BError> {
+        if target as usize + length as usize > RAM_LEN {
+            return Err(BError::OutOfBoundsError(target, length as usize, RAM_LEN));
+        }
+        let end = check_callback_range(
+            address,
+            length,
+            self.mirrors.iter().map(|(s, e, _)| (*s, *e)),
+        )?;
+        let target_end = target + (length - 1);
+        if let Some((start, end, _)) = self
+            .mirrors
+            .iter()
+            .chain(core::iter::once(&(address, end, target)))
+            .find(|(s, e, _)| target <= *e && *s <= target_end)
+        {
+            return Err(BError::MapError(format!(
+                "mirror target {:#06x}-{:#06x} lies inside the mirror {:#06x}-{:#06x}",
+                target, target_end, start, end
+            )));
+        }
+        if let Some((start, mirror_end, t)) = self
+            .mirrors
+            .iter()
+            .find(|(s, e, t)| address <= *t + (*e - *s) && *t <= end)
+        {
+            return Err(BError::MapError(format!(
+                "mirror {:#06x}-{:#06x} lies inside the target {:#06x}-{:#06x} of the mirror at {:#06x}",
+                address,
+                end,
+                t,
+                *t + (*mirror_end - *start),
+                start
+            )));
         }
+        self.mirrors.push((address, end, target));
+        Ok(())
     }
 
     /// Registers a callback on a certain address. Only one callback will be registered,
     /// new callbacks on the same address will lead to the old one being overwritten.
-    pub fn register_callback(&mut self, address: u16, cb: Box<dyn FnMut(u16)>) {
+    pub fn register_callback(&mut self, address: u16, cb: WriteCallback) {
         let _ = self.write_callbacks.insert(address, cb);
     }
 
+    /// Registers a callback that runs when the CPU reads from a certain address. The callback receives the value
+    /// stored in RAM and returns the value the CPU will see. The stored value is not changed.
+    /// Only one read callback will be registered per address, new callbacks on the same address overwrite the old one.
+    pub fn register_read_callback(&mut self, address: u16, cb: ReadCallback) {
+        let _ = self.read_callbacks.insert(address, cb);
+    }
+
+    /// Registers one callback for writes on the `length` addresses starting at `address`. The callback is run after
+    /// a callback registered on the single address using [`Ram::register_callback()`].
+    /// ## Errors
+    /// - [`BError::OutOfBoundsError`] if the range is empty or does not fit into RAM
+    /// - [`BError::MapError`] if the range overlaps with the range of another region write callback
+    pub fn register_region_callback(
+        &mut self,
+        address: u16,
+        length: u16,
+        cb: RegionWriteCallback,
+    ) -> Result<(), BError> {
+        let end = check_callback_range(
+            address,
+            length,
+            self.region_write_callbacks.iter().map(|(s, e, _)| (*s, *e)),
+        )?;
+        self.region_write_callbacks.push((address, end, cb));
+        Ok(())
+    }
+
+    /// Registers one callback for reads on the `length` addresses starting at `address`. The callback is run after
+    /// a callback registered on the single address using [`Ram::register_read_callback()`] and receives its result.
+    /// ## Errors
+    /// See [`Ram::register_region_callback()`]
+    pub fn register_region_read_callback(
+        &mut self,
+        address: u16,
+        length: u16,
+        cb: RegionReadCallback,
+    ) -> Result<(), BError> {
+        let end = check_callback_range(
+            address,
+            length,
+            self.region_read_callbacks.iter().map(|(s, e, _)| (*s, *e)),
+        )?;
+        self.region_read_callbacks.push((address, end, cb));
+        Ok(())
+    }
+
     /// Set the memory at an address in RAM without the usual checks
     /// ## Panics
     /// If address is not the ragne (0, RAM_SIZE)
     pub fn set_ram(&mut self, address: u16, value: u16) {
+        let address = self.resolve_mirror(address);
         self.ram[address as usize] = value;
     }
 
@@ -147,24 +597,102 @@ impl Ram {
             return Err(BError::OutOfBoundsError(address, values.len(), RAM_LEN));
         }
 
-        for i in 0..values.len() {
-            self.ram[i + address as usize] = values[i]
-        }
+        let start = address as usize;
+        self.ram.write(start, values);
+
+        Ok(())
+    }
 
+    /// Copy `length` words from `source` to `destination` in one go, the regions may overlap. Like
+    /// [`Ram::set_ram_region()`] this performs no checks
+    /// ## Errors
+    /// [`BError::OutOfBoundsError`] if either region goes beyond the RAM
+    pub fn copy_ram_region(
+        &mut self,
+        source: u16,
+        destination: u16,
+        length: usize,
+    ) -> Result<(), BError> {
+        for address in [source, destination] {
+            if address as usize + length > RAM_LEN {
+                return Err(BError::OutOfBoundsError(address, length, RAM_LEN));
+            }
+        }
+        let source = source as usize;
+        self.ram
+            .copy_within(source..source + length, destination as usize);
         Ok(())
     }
 
     /// Gets a RAM region. If the region goes beyond the RAM, the resulting vec will only contain as many entries as the
     /// overlap between the specified region of RAM and RAM
-    pub fn get_ram_region(&self, address: u16, length: usize) -> &[u16] {
-        let start = address as usize;
-        let end = start + length;
+    pub fn get_ram_region(&self, address: u16, length: usize) -> Cow<'_, [u16]> {
+        self.view(address as usize..address as usize + length)
+    }
+
+    /// Borrow the words of RAM in `range` without copying them, except when paged RAM is read across pages. Parts of
+    /// the range beyond the RAM are cut off. Like [`Ram::get_ram_region()`] this doesn't follow mirrors or call read
+    /// callbacks
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::vm::{Vm, VmDescription};
+    ///
+    /// let mut vm = Vm::new(VmDescription::default()).unwrap();
+    /// vm.set_ram_region(0xfffe, &[1, 2]).unwrap();
+    /// assert_eq!(*vm.ram_view(0xfffe..0x10005), [1, 2]);
+    /// ```
+    pub fn view(&self, range: Range<usize>) -> Cow<'_, [u16]> {
+        let range = clip_range(range, RAM_LEN);
+        self.ram.read(range)
+    }
+
+    /// Mutably borrow the words of RAM in `range`, cut off like [`Ram::view()`]. Writes bypass access levels, mirrors
+    /// and callbacks. Borrowing paged RAM across pages turns it into [`backing::RamBacking::Flat`]
+    pub fn view_mut(&mut self, range: Range<usize>) -> &mut [u16] {
+        let range = clip_range(range, RAM_LEN);
+        self.ram.slice_mut(range)
+    }
+
+    /// How the words of RAM are stored
+    pub fn backing(&self) -> backing::RamBacking {
+        self.ram.backing()
+    }
+
+    /// Store the words of RAM another way, keeping their values
+    pub fn set_backing(&mut self, backing: backing::RamBacking) {
+        self.ram.set_backing(backing);
+    }
+
+    /// Allocated pages of [`backing::PAGE_LEN`] words. Flat RAM always has all of them
+    pub fn resident_pages(&self) -> usize {
+        self.ram.resident_pages()
+    }
+}
 
-        let end = if end > RAM_LEN { RAM_LEN } else { end };
-        let start = if start > end { end } else { start };
+/// Cut `range` off at `len`. An empty range is returned if it starts beyond `len`
+fn clip_range(range: Range<usize>, len: usize) -> Range<usize> {
+    let end = range.end.min(len);
+    range.start.min(end)..end
+}
 
-        &self.ram[start..end]
+/// Checks that a callback, mirror or open bus range lies in RAM and doesn't overlap with any of the `existing` inclusive ranges.
+/// Returns the inclusive end of the range.
+fn check_callback_range(
+    address: u16,
+    length: u16,
+    mut existing: impl Iterator<Item = (u16, u16)>,
+) -> Result<u16, BError> {
+    if length == 0 || address as usize + length as usize > RAM_LEN {
+        return Err(BError::OutOfBoundsError(address, length as usize, RAM_LEN));
+    }
+    let end = address + (length - 1);
+    if let Some((start, other_end)) = existing.find(|(s, e)| address <= *e && *s <= end) {
+        return Err(BError::MapError(format!(
+            "callback range {:#06x}-{:#06x} overlaps with {:#06x}-{:#06x}",
+            address, end, start, other_end
+        )));
     }
+    Ok(end)
 }
 
 /// A unit containing the RAM and the registers (except for the program counter register)
@@ -180,12 +708,13 @@ pub struct MemoryUnit {
 }
 
 impl MemoryUnit {
-    /// Get the value currently in the register specified by `reg`
+    /// Get the value currently in the register specified by `reg`. Reading `*A` does not trigger read callbacks,
+    /// use [`MemoryUnit::read_reg()`] for that.
     pub fn get_reg(&self, reg: Register) -> u16 {
         match reg {
             Register::None => 0,
             Register::A => self.a,
-            Register::MA => self.ram.read_ram(self.a),
+            Register::MA => self.ram.peek_ram(self.a),
             Register::D => self.d,
             Register::E => self.e,
             Register::F => self.f,
@@ -194,6 +723,14 @@ impl MemoryUnit {
         }
     }
 
+    /// Read the register specified by `reg` the way the CPU does. Reading `*A` triggers read callbacks.
+    pub fn read_reg(&mut self, reg: Register) -> u16 {
+        match reg {
+            Register::MA => self.ram.read_ram(self.a),
+            other => self.get_reg(other),
+        }
+    }
+
     /// Set the value of the register specified by `reg`
     pub fn set_reg(&mut self, reg: Register, value: u16) {
         match reg {
@@ -218,7 +755,7 @@ impl MemoryUnit {
         f: u16,
         g: u16,
         h: u16,
-        ram: [u16; 65536],
+        ram: Box<[u16; RAM_LEN]>,
         regions: Vec<Region<u16, AccessLevels>>,
     ) -> Result<Self, BError> {
         Ok(Self {
@@ -233,10 +770,40 @@ impl MemoryUnit {
     }
 
     /// Register a callback on the write of a specific memory address in RAM
-    pub fn register_callback(&mut self, address: u16, cb: Box<dyn FnMut(u16)>) {
+    pub fn register_callback(&mut self, address: u16, cb: WriteCallback) {
         self.ram.register_callback(address, cb);
     }
 
+    /// Register a callback on the read of a specific memory address in RAM
+    pub fn register_read_callback(&mut self, address: u16, cb: ReadCallback) {
+        self.ram.register_read_callback(address, cb);
+    }
+
+    /// Wraps [`Ram::register_region_callback()`]
+    pub fn register_region_callback(
+        &mut self,
+        address: u16,
+        length: u16,
+        cb: RegionWriteCallback,
+    ) -> Result<(), BError> {
+        self.ram.register_region_callback(address, length, cb)
+    }
+
+    /// Wraps [`Ram::add_mirror()`]
+    pub fn add_mirror(&mut self, address: u16, length: u16, target: u16) -> Result<(), BError> {
+        self.ram.add_mirror(address, length, target)
+    }
+
+    /// Wraps [`Ram::register_region_read_callback()`]
+    pub fn register_region_read_callback(
+        &mut self,
+        address: u16,
+        length: u16,
+        cb: RegionReadCallback,
+    ) -> Result<(), BError> {
+        self.ram.register_region_read_callback(address, length, cb)
+    }
+
     /// Wraps [`Ram::set_ram()`]
     pub fn set_ram(&mut self, address: u16, value: u16) {
         self.ram.set_ram(address, value);
@@ -248,9 +815,14 @@ impl MemoryUnit {
     }
 
     /// Wraps [`Ram::get_ram_region()`]
-    pub fn get_ram_region(&self, address: u16, length: usize) -> &[u16] {
+    pub fn get_ram_region(&self, address: u16, length: usize) -> Cow<'_, [u16]> {
         self.ram.get_ram_region(address, length)
     }
+
+    /// Wraps [`Ram::view()`]
+    pub fn ram_view(&self, range: Range<usize>) -> Cow<'_, [u16]> {
+        self.ram.view(range)
+    }
 }
 
 impl Default for MemoryUnit {
@@ -263,7 +835,7 @@ impl Default for MemoryUnit {
             g: 0,
             h: 0,
             ram: Ram::new(
-                [0; 65536],
+                empty_ram(),
                 RegionMap::try_from(vec![Region::new(0, 65535, AccessLevels::ReadWrite)]).unwrap(),
             ),
         }
@@ -294,14 +866,19 @@ impl Rom {
         self.program_text.get(address as usize).map(|v| *v)
     }
 
+    /// Set the value of ROM at the `address`. Grows the ROM with zeroes if `address` is past its end
+    fn set_address(&mut self, address: u16, value: u16) {
+        let address = address as usize;
+        if address >= self.program_text.len() {
+            self.program_text.resize(address + 1, 0);
+        }
+        self.program_text[address] = value;
+    }
+
     pub(crate) fn get_rom_region(&self, address: u16, length: usize) -> &[u16] {
         let start = address as usize;
-        let end = start + length;
-        let rom_len = self.program_text.len();
-        let end = if end > rom_len { rom_len } else { end };
-        let start = if start > end { end } else { start };
-
-        &self.program_text[start..end]
+        let range = clip_range(start..start + length, self.program_text.len());
+        &self.program_text[range]
     }
 }
 
@@ -371,23 +948,254 @@ impl Instruction {
     }
 }
 
+/// Jump conditions of an ALU instruction. The result of the instruction is compared against 0 as a signed number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jump {
+    Jlt,
+    Jeq,
+    Jgt,
+    Jle,
+    Jge,
+    Jmp,
+    Jne,
+}
+
+impl Jump {
+    /// The lt, eq and gt bits of the condition
+    fn bits(self) -> (bool, bool, bool) {
+        match self {
+            Self::Jlt => (true, false, false),
+            Self::Jeq => (false, true, false),
+            Self::Jgt => (false, false, true),
+            Self::Jle => (true, true, false),
+            Self::Jge => (false, true, true),
+            Self::Jmp => (true, true, true),
+            Self::Jne => (true, false, true),
+        }
+    }
+}
+
+/// Builds correctly encoded [`AluInstruction`]s, e.g. to generate ROM from host code. Operands are registers,
+/// [`Register::None`] stands for the constant `0`. The operands follow the rules of the assembler: one operand of a two
+/// operand operation is `A`, or the left one is `0`. Invalid operands are reported by [`InstructionBuilder::build()`]
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler::text_processor::assemble_at, vm::{Instruction, InstructionBuilder, Jump, Register}};
+///
+/// let inst = InstructionBuilder::add(Register::None, Register::A)
+///     .target(Register::D)
+///     .build()
+///     .unwrap();
+/// assert_eq!(inst.0, assemble_at("D = add, 0, A", 0).unwrap()[0]);
+///
+/// let inst = InstructionBuilder::sub(Register::D, Register::A)
+///     .jump(Jump::Jeq)
+///     .build()
+///     .unwrap();
+/// assert_eq!(Instruction::Alu(inst).to_u16(), assemble_at("sub, D, A; JEQ", 0).unwrap()[0]);
+///
+/// // neither operand is A
+/// assert!(InstructionBuilder::and(Register::D, Register::E).build().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstructionBuilder {
+    u: bool,
+    op: u8,
+    x: Register,
+    /// Right operand of two operand operations
+    y: Option<Register>,
+    target: Register,
+    jump: Option<Jump>,
+}
+
+impl InstructionBuilder {
+    fn two(u: bool, op: u8, x: Register, y: Register) -> Self {
+        Self {
+            u,
+            op,
+            x,
+            y: Some(y),
+            target: Register::None,
+            jump: None,
+        }
+    }
+
+    fn one(u: bool, op: u8, x: Register) -> Self {
+        Self {
+            u,
+            op,
+            x,
+            y: None,
+            target: Register::None,
+            jump: None,
+        }
+    }
+
+    /// `x + y`
+    pub fn add(x: Register, y: Register) -> Self {
+        Self::two(true, 0b000, x, y)
+    }
+
+    /// `x - y`
+    pub fn sub(x: Register, y: Register) -> Self {
+        Self::two(true, 0b001, x, y)
+    }
+
+    /// `x & y`
+    pub fn and(x: Register, y: Register) -> Self {
+        Self::two(false, 0b000, x, y)
+    }
+
+    /// `x | y`
+    pub fn or(x: Register, y: Register) -> Self {
+        Self::two(false, 0b001, x, y)
+    }
+
+    /// `x ^ y`
+    pub fn xor(x: Register, y: Register) -> Self {
+        Self::two(false, 0b010, x, y)
+    }
+
+    /// `x + 1`
+    pub fn inc(x: Register) -> Self {
+        Self::one(true, 0b010, x)
+    }
+
+    /// `x - 1`
+    pub fn dec(x: Register) -> Self {
+        Self::one(true, 0b011, x)
+    }
+
+    /// Arithmetic shift right of `x`
+    pub fn asr(x: Register) -> Self {
+        Self::one(true, 0b100, x)
+    }
+
+    /// `!x`
+    pub fn not(x: Register) -> Self {
+        Self::one(false, 0b011, x)
+    }
+
+    /// `x << 1`
+    pub fn lsl(x: Register) -> Self {
+        Self::one(false, 0b100, x)
+    }
+
+    /// `x >> 1`
+    pub fn lsr(x: Register) -> Self {
+        Self::one(false, 0b101, x)
+    }
+
+    /// Rotate `x` left by one bit
+    pub fn rol(x: Register) -> Self {
+        Self::one(false, 0b110, x)
+    }
+
+    /// Rotate `x` right by one bit
+    pub fn ror(x: Register) -> Self {
+        Self::one(false, 0b111, x)
+    }
+
+    /// Write the result to `target`. Without a target the result is only used for the jump
+    pub fn target(mut self, target: Register) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Jump to the address in A if the result meets the condition
+    pub fn jump(mut self, jump: Jump) -> Self {
+        self.jump = Some(jump);
+        self
+    }
+
+    /// Encode the instruction
+    /// ## Errors
+    /// [`BError::InstParseError`] if the operands can't be encoded
+    pub fn build(self) -> Result<AluInstruction, BError> {
+        let mut inst = AluInstruction(0);
+        inst.set_u(self.u);
+        inst.set_op(self.op);
+        inst.set_target(self.target as u8);
+        if let Some(jump) = self.jump {
+            let (lt, eq, gt) = jump.bits();
+            inst.set_lt(lt);
+            inst.set_eq(eq);
+            inst.set_gt(gt);
+        }
+        // the source is x unless x is A or 0, then the switch puts A or the zeroed A on the left
+        let (source, sw) = match (self.x, self.y) {
+            (x, None) => (x, false),
+            (Register::None | Register::A, Some(y)) if y != Register::None => (y, true),
+            (x, Some(Register::A)) if x != Register::None => (x, false),
+            (x, Some(y)) => {
+                return Err(BError::InstParseError {
+                    value: inst.0,
+                    message: format!("invalid operands {} and {}, one of them has to be A", x, y),
+                });
+            }
+        };
+        inst.set_sw(sw);
+        inst.set_zx(self.x == Register::None);
+        inst.set_source(source as u8);
+        Ok(inst)
+    }
+}
+
 /// Describes the VM. This is mainly used for initialization of the VM but can also be used for serialization in order to capture the state of the VM.
 /// ## Fields
 /// - `pc`: The value of the program counter
 /// - `rom`: The program text
 /// - `mem`: The initial state of ram
-/// - `callbacks`: Callbacks for reads on memory addresses. Ordered address: callback
+/// - `callbacks`: Callbacks for writes on memory addresses. Ordered address: callback. See [`WriteCallback`]
+/// - `read_callbacks`: Callbacks for reads on memory addresses. Ordered address: callback. See [`ReadCallback`]
+/// - `region_callbacks`: Callbacks for writes on ranges of memory. Ordered address, length, callback. See [`RegionWriteCallback`]
+/// - `region_read_callbacks`: Callbacks for reads on ranges of memory. Ordered address, length, callback. See [`RegionReadCallback`]
+/// - `devices`: Devices that are ticked after every cycle. See [`Device`]
+/// - `mirrors`: RAM regions that mirror other RAM regions. Ordered: ram_address, length, mirrored_address. Results in
+///   reads and writes on the length addresses starting at ram_address being forwarded to mirrored_address
+/// - `execution_mode`: Whether instructions are fetched from ROM or RAM. See [`ExecutionMode`]
+/// - `rom_window`: RAM address of a two word window into ROM. The first word selects a ROM address, the second word
+///   reads the ROM at that address. See [ROM window](../docs/rom_window.md)
+/// - `rom_writable`: Whether writes to the second word of the ROM window patch the ROM. Otherwise the window is read only
+/// - `perf_counters`: RAM address of a block of seven words exposing the [`PerfCounters`] to the guest. Disabled if
+///   `None`. See [performance counters](../docs/perf_counters.md)
+/// - `reset_register`: RAM address of a word that resets the VM to this description on the cycle after it is written.
+///   Disabled if `None`. See [`Vm::reset()`]
+/// - `open_bus`: What the CPU reads from regions with [`AccessLevels::None`] or [`AccessLevels::WriteOnly`]. Ordered: ram_address, length, open bus.
+///   See [`OpenBus`]
+/// - `forbidden_write_policy`: What happens when the CPU writes to read only memory. See [`ForbiddenWritePolicy`]
+/// - `access_regions`: Additional access levels for regions of RAM. ROM mappings and ROM blocks have priority 0, regions
+///   with a higher priority take precedence over them where they overlap (e.g. a writable MMIO window inside a ROM mapping)
 /// - `rom_mappings`: Mappings from ROM to RAM. Ordered: rom_address, length, ram_address. Results in length bytes of ROM starting from rom_address being
 ///     copied into RAM at ram_address
 /// - `regs`: A-H registers in alphabetical order
 /// - `rom_blocks`: Rom regions to make read only for the processor Ordered: ram_address, length
+/// - `metadata`: Information about the program, e.g. written by the assembler. See [`Metadata`]
+/// - `source`: The .basm source of the program. Compressed when serialized if the `compression` feature is enabled
+/// - `rng_seed`: Seed of a random number generator mounted at [`crate::mmio::rng::RNG_DATA`]. No generator is mounted
+///   if this is `None`. See [random number generator](../docs/rng.md)
+/// - `deterministic`: Guarantee that every run of the VM executes the same way. [`Vm::new()`] rejects read callbacks,
+///   which hand host values to the guest, and devices that depend on the host, see [`Device::deterministic()`]. Use
+///   `rng_seed` for random numbers
+/// - `latency`: Extra cycles of accesses to RAM, ROM and MMIO, which advance the cycle count, the devices and the
+///   performance counters. See [`Latency`]
+/// - `ram_backing`: Whether RAM is one allocation or pages allocated when they are first written. Paged RAM makes VMs
+///   that touch little memory cheap to create and keep around. See [`backing::RamBacking`]
+/// - `host_env`: Host values like environment variables that [`Vm::new()`] writes into a read only block of RAM for the
+///   guest. Disabled if `None`. See [`crate::args::HostEnv`]
+/// - `boot_rom`: Start the built-in [`boot::BOOT_ROM`], which loads a program from the
+///   [block storage device](../docs/boot.md). [`Vm::new()`] puts it at [`boot::BOOT_ROM_BASE`] behind the ROM, starts the
+///   PC there and opens a writable ROM window at [`boot::BOOT_WINDOW`]. When the boot ROM jumps to the program the
+///   window is closed, `rom_window` takes its place and the RAM the boot ROM used gets its initial values back
+/// - `device_configs`: Named device instances. They are mounted by a [`crate::mmio::registry::DeviceRegistry`], [`Vm::new()`] only keeps them
+///   so [`Vm::to_vm_desc()`] returns them. See [`DeviceConfig`]
 /// ## Examples
 /// This example instantiates a new VmDescription that maps the region from 0x0500 to 0x0600 into RAM at 0xf000 and has a callback at memory address 0x0123.
 /// ```rust
-/// use bric_vm::vm::VmDescription;
+/// use bric_vm::vm::{VmDescription, WriteEvent};
 ///
-/// let callback = |new_value: u16| {
-///     println!("got new value at 0x123: {}", new_value);
+/// let callback = |write: WriteEvent| {
+///     println!("instruction {:#06x} wrote {} at 0x123", write.pc, write.value);
 /// };
 ///
 /// let vm_desc = VmDescription{
@@ -400,10 +1208,32 @@ pub struct VmDescription {
     pub pc: u16,
     pub rom: Vec<u16>,
     pub mem: Box<[u16; RAM_LEN]>,
-    pub callbacks: Vec<(u16, Box<dyn FnMut(u16)>)>,
+    pub callbacks: Vec<(u16, WriteCallback)>,
+    pub read_callbacks: Vec<(u16, ReadCallback)>,
+    pub region_callbacks: Vec<(u16, u16, RegionWriteCallback)>,
+    pub region_read_callbacks: Vec<(u16, u16, RegionReadCallback)>,
+    pub devices: Vec<Box<dyn Device>>,
     pub rom_mappings: Vec<(u16, u16, u16)>,
     pub regs: [u16; 6],
     pub rom_blocks: Vec<(u16, u16)>,
+    pub access_regions: Vec<Region<u16, AccessLevels>>,
+    pub mirrors: Vec<(u16, u16, u16)>,
+    pub forbidden_write_policy: ForbiddenWritePolicy,
+    pub open_bus: Vec<(u16, u16, OpenBus)>,
+    pub execution_mode: ExecutionMode,
+    pub rom_window: Option<u16>,
+    pub rom_writable: bool,
+    pub perf_counters: Option<u16>,
+    pub reset_register: Option<u16>,
+    pub metadata: Option<Metadata>,
+    pub source: Option<String>,
+    pub rng_seed: Option<u64>,
+    pub deterministic: bool,
+    pub latency: Latency,
+    pub ram_backing: backing::RamBacking,
+    pub host_env: Option<crate::args::HostEnv>,
+    pub boot_rom: bool,
+    pub device_configs: Vec<DeviceConfig>,
 }
 
 impl Default for VmDescription {
@@ -411,8 +1241,30 @@ impl Default for VmDescription {
         Self {
             pc: 0,
             rom: vec![],
-            mem: Box::new([0; RAM_LEN]),
+            mem: empty_ram(),
             callbacks: Vec::new(),
+            read_callbacks: Vec::new(),
+            region_callbacks: Vec::new(),
+            region_read_callbacks: Vec::new(),
+            devices: Vec::new(),
+            access_regions: Vec::new(),
+            mirrors: Vec::new(),
+            forbidden_write_policy: ForbiddenWritePolicy::default(),
+            open_bus: Vec::new(),
+            execution_mode: ExecutionMode::default(),
+            rom_window: None,
+            rom_writable: false,
+            perf_counters: None,
+            reset_register: None,
+            metadata: None,
+            source: None,
+            rng_seed: None,
+            deterministic: false,
+            latency: Latency::default(),
+            ram_backing: backing::RamBacking::default(),
+            host_env: None,
+            boot_rom: false,
+            device_configs: Vec::new(),
             rom_mappings: Vec::new(),
             regs: [0; 6],
             rom_blocks: vec![],
@@ -420,95 +1272,514 @@ impl Default for VmDescription {
     }
 }
 
-// this implements serialization and deserialization
-// could also be done using serde https://serde.rs/data-format.html
-impl VmDescription {
-    /// Serialize a VMDescription according to spec
-    /// Currently the serialization does not support rom_blocks
-    /// ## Errors
-    /// - When the number of mappings is too large
-    /// - When ROM is too large
-    pub fn serialize(&self) -> Result<Vec<u8>, BError> {
-        let mut output = Vec::new();
-
-        // Magic
-        output.append(&mut b"BVM\x00".to_vec());
-
-        // PC
-        output.append(&mut self.pc.to_be_bytes().to_vec());
-        output.push(0x00);
+/// Version of the .bvm format written by [`VmDescription::serialize()`]
+pub const BVM_VERSION: u8 = 4;
+
+// Segment types of the .bvm container
+const SEG_CPU: [u8; 4] = *b"CPU\x00";
+const SEG_ROM_MAPPINGS: [u8; 4] = *b"RMP\x00";
+const SEG_ROM: [u8; 4] = *b"ROM\x00";
+const SEG_RAM: [u8; 4] = *b"RAM\x00";
+const SEG_METADATA: [u8; 4] = *b"MTA\x00";
+const SEG_SOURCE: [u8; 4] = *b"SRC\x00";
+const SEG_DEVICES: [u8; 4] = *b"DEV\x00";
+
+// Encodings of the source segment
+const SOURCE_STORED: u8 = 0x00;
+const SOURCE_DEFLATE: u8 = 0x01;
+
+/// Header flag of .bvm files whose ROM and RAM segments are deflate compressed
+const FLAG_COMPRESSED: u8 = 0x01;
+
+/// Compress `bytes` with deflate
+#[cfg(feature = "compression")]
+fn deflate(bytes: &[u8]) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec(bytes, 6)
+}
 
-        // Regs
-        // Regs are already in the correct order
-        for reg in self.regs.iter() {
-            output.append(&mut reg.to_be_bytes().to_vec())
-        }
-        output.push(0x00);
+/// Largest decompressed ROM or RAM segment, the start address of a RAM segment and every word of RAM
+const SEGMENT_LIMIT: usize = 2 + RAM_LEN * 2;
+
+/// Largest decompressed source, far more than the source of any program that fits into ROM
+const SOURCE_LIMIT: usize = 1 << 24;
+
+/// Decompress deflate compressed `bytes` into at most `limit` bytes
+/// ## Errors
+/// When the data is corrupted, decompresses to more than `limit` bytes or the `compression` feature is disabled
+fn inflate(bytes: &[u8], limit: usize) -> Result<Vec<u8>, BError> {
+    #[cfg(feature = "compression")]
+    {
+        use miniz_oxide::inflate::TINFLStatus;
+        miniz_oxide::inflate::decompress_to_vec_with_limit(bytes, limit).map_err(|e| {
+            if e.status == TINFLStatus::HasMoreOutput {
+                BError::DeserializationError(
+                    format!("Compressed data is larger than {} bytes", limit).into(),
+                )
+            } else {
+                BError::DeserializationError("Corrupted compressed data".into())
+            }
+        })
+    }
+    #[cfg(not(feature = "compression"))]
+    {
+        let _ = (bytes, limit);
+        Err(BError::DeserializationError(
+            "The file is compressed, enable the `compression` feature to read it".into(),
+        ))
+    }
+}
 
-        // Mappings
-        // Magic
-        output.append(&mut b"RMP\x00".to_vec());
+/// Encode the source segment, compressing the source if possible
+fn encode_source(source: &str) -> Vec<u8> {
+    #[cfg(feature = "compression")]
+    {
+        let mut output = vec![SOURCE_DEFLATE];
+        output.append(&mut deflate(source.as_bytes()));
+        output
+    }
+    #[cfg(not(feature = "compression"))]
+    {
+        let mut output = vec![SOURCE_STORED];
+        output.extend_from_slice(source.as_bytes());
+        output
+    }
+}
 
-        // Amount of Mappings
-        let map_no = self.rom_mappings.len();
-        if map_no > 0xffff {
-            return Err(BError::SerializationError(
-                "The number of ROM mappings to be written is to large".to_string(),
+/// Decode the source segment
+/// ## Errors
+/// When the encoding is unknown, the source is compressed without the `compression` feature, inflates to more than
+/// [`SOURCE_LIMIT`] bytes or is not UTF-8
+fn decode_source(payload: &[u8]) -> Result<String, BError> {
+    let bytes = match payload.split_first() {
+        Some((&SOURCE_STORED, stored)) => stored.to_vec(),
+        Some((&SOURCE_DEFLATE, compressed)) => inflate(compressed, SOURCE_LIMIT)?,
+        _ => {
+            return Err(BError::DeserializationError(
+                "Unknown source encoding".into(),
             ));
         }
-        output.append(&mut (map_no as u16).to_be_bytes().to_vec());
-        output.push(0x00);
+    };
+    String::from_utf8(bytes)
+        .map_err(|_| BError::DeserializationError("Source is not valid UTF-8".into()))
+}
 
-        // write Mappings
-        for (rom_addr, length, ram_addr) in self.rom_mappings.iter() {
-            output.append(&mut rom_addr.to_be_bytes().to_vec());
-            output.append(&mut length.to_be_bytes().to_vec());
-            output.append(&mut ram_addr.to_be_bytes().to_vec());
-            output.push(0x00);
+/// Amount of words in a RAM segment written by [`VmDescription::serialize()`]
+const RAM_CHUNK_LEN: usize = 0x100;
+
+/// Information about the program stored in a .bvm file
+/// - `name`: Name of the program
+/// - `entry_point`: Symbol of the entry point
+/// - `assembler_version`: Version of the assembler that built the program
+/// - `build_timestamp`: Time of the build in seconds since the unix epoch
+/// - `entries`: Free-form key/value pairs. Keys used by the fields above are reserved
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Metadata {
+    pub name: Option<String>,
+    pub entry_point: Option<String>,
+    pub assembler_version: Option<String>,
+    pub build_timestamp: Option<u64>,
+    pub entries: Vec<(String, String)>,
+}
+
+impl Metadata {
+    const NAME: &str = "name";
+    const ENTRY_POINT: &str = "entry_point";
+    const ASSEMBLER_VERSION: &str = "assembler_version";
+    const BUILD_TIMESTAMP: &str = "build_timestamp";
+
+    /// All fields as key/value pairs, the fields with a reserved key first
+    pub fn to_entries(&self) -> Vec<(String, String)> {
+        let mut entries = Vec::new();
+        let fields = [
+            (Self::NAME, self.name.clone()),
+            (Self::ENTRY_POINT, self.entry_point.clone()),
+            (Self::ASSEMBLER_VERSION, self.assembler_version.clone()),
+            (
+                Self::BUILD_TIMESTAMP,
+                self.build_timestamp.map(|t| t.to_string()),
+            ),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                entries.push((key.to_string(), value));
+            }
         }
+        entries.extend(self.entries.iter().cloned());
+        entries
+    }
 
-        // ROM
-        // Magic
-        output.append(&mut b"\x00ROM\x00".to_vec());
+    /// Serialize the metadata segment according to spec
+    /// ## Errors
+    /// When there are too many entries or a key or value is too long
+    fn serialize(&self) -> Result<Vec<u8>, BError> {
+        let mut output = Vec::new();
 
-        // ROM length
-        let rom_len = self.rom.len();
-        if rom_len > 0xffff {
+        let entries = self.to_entries();
+        if entries.len() > 0xffff {
             return Err(BError::SerializationError(
-                "The ROM to be written is to large".to_string(),
+                "The number of metadata entries is to large".into(),
             ));
         }
-        output.append(&mut (rom_len as u16).to_be_bytes().to_vec());
+        output.append(&mut (entries.len() as u16).to_be_bytes().to_vec());
         output.push(0x00);
 
-        // write ROM
-        for val in self.rom.iter() {
-            output.append(&mut val.to_be_bytes().to_vec());
-        }
-
-        // RAM
-        output.append(&mut b"\x00RAM\x00".to_vec());
-        for val in self.mem.iter() {
-            output.append(&mut val.to_be_bytes().to_vec());
+        for (key, value) in entries.iter() {
+            for text in [key, value] {
+                if text.len() > 0xffff {
+                    return Err(BError::SerializationError(
+                        format!("The metadata entry {key} is to large").into(),
+                    ));
+                }
+                output.append(&mut (text.len() as u16).to_be_bytes().to_vec());
+                output.extend_from_slice(text.as_bytes());
+            }
+            output.push(0x00);
         }
-
         Ok(output)
     }
 
-    /// Deserialize a VMDescription according to spec
-    /// Currently the serialization does not support rom_blocks
+    /// Deserialize a metadata segment. Returns the metadata and the rest of the input
     /// ## Errors
     /// When the spec is not correctly respected
-    pub fn deserialize(input: &[u8]) -> Result<Self, BError> {
+    fn deserialize(input: &[u8]) -> Result<(Self, &[u8]), BError> {
         use crate::util::{check_slice, extract_number};
         let current = input;
-        // Check file magic
-        if check_slice(current, 4)? != b"BVM\x00" {
+        let amount = extract_number(check_slice(current, 3)?)?;
+        let mut current = &current[3..];
+
+        let mut metadata = Self::default();
+        for _ in 0..amount {
+            let mut texts = [String::new(), String::new()];
+            for text in texts.iter_mut() {
+                let len_bytes = check_slice(current, 2)?;
+                let len = u16::from_be_bytes([len_bytes[0], len_bytes[1]]) as usize;
+                let bytes = check_slice(&current[2..], len)?;
+                *text = String::from_utf8(bytes.to_vec()).map_err(|_| {
+                    BError::DeserializationError("Metadata is not valid UTF-8".into())
+                })?;
+                current = &current[2 + len..];
+            }
+            if check_slice(current, 1)?[0] != 0x00 {
+                return Err(BError::DeserializationError(
+                    "Invalid region separators".into(),
+                ));
+            }
+            current = &current[1..];
+
+            let [key, value] = texts;
+            match key.as_str() {
+                Self::NAME => metadata.name = Some(value),
+                Self::ENTRY_POINT => metadata.entry_point = Some(value),
+                Self::ASSEMBLER_VERSION => metadata.assembler_version = Some(value),
+                Self::BUILD_TIMESTAMP => {
+                    metadata.build_timestamp = Some(value.parse().map_err(|_| {
+                        BError::DeserializationError("Invalid build timestamp".into())
+                    })?)
+                }
+                _ => metadata.entries.push((key, value)),
+            }
+        }
+        Ok((metadata, current))
+    }
+}
+
+/// Byte order of the words in a flat binary image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endianness {
+    #[default]
+    Big,
+    Little,
+}
+
+impl Endianness {
+    /// Decode `bytes` into words
+    /// ## Errors
+    /// A [`BError::DeserializationError`] if the amount of bytes is odd
+    fn words(self, bytes: &[u8]) -> Result<Vec<u16>, BError> {
+        if !bytes.len().is_multiple_of(2) {
             return Err(BError::DeserializationError(
-                "Invalid file format".to_string(),
+                FormatError::from("Image has an odd amount of bytes").at(bytes.len() - 1),
             ));
         }
-        let current = &current[4..];
+        Ok(bytes
+            .chunks(2)
+            .map(|w| match self {
+                Endianness::Big => u16::from_be_bytes([w[0], w[1]]),
+                Endianness::Little => u16::from_le_bytes([w[0], w[1]]),
+            })
+            .collect())
+    }
+}
+
+// loaders for programs that are not wrapped in a .bvm file
+impl VmDescription {
+    /// Create a VmDescription running `rom` from address 0 with everything else zeroed
+    pub fn from_rom_words(rom: &[u16]) -> Self {
+        Self {
+            rom: rom.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    /// Create a VmDescription from a flat binary image of ROM
+    /// ## Errors
+    /// - A [`BError::DeserializationError`] if the image has an odd amount of bytes
+    /// - A [`BError::OutOfBoundsError`] if the image holds more than 0x10000 words
+    pub fn from_rom_image(image: &[u8], endianness: Endianness) -> Result<Self, BError> {
+        let rom = endianness.words(image)?;
+        if rom.len() > RAM_LEN {
+            return Err(BError::OutOfBoundsError(0, rom.len(), RAM_LEN));
+        }
+        Ok(Self {
+            rom,
+            ..Default::default()
+        })
+    }
+
+    /// Copy a flat binary image into RAM starting at `address`
+    /// ## Errors
+    /// - A [`BError::DeserializationError`] if the image has an odd amount of bytes
+    /// - A [`BError::OutOfBoundsError`] if the image does not fit into RAM
+    pub fn load_ram_image(
+        &mut self,
+        address: u16,
+        image: &[u8],
+        endianness: Endianness,
+    ) -> Result<(), BError> {
+        let words = endianness.words(image)?;
+        let start = address as usize;
+        if start + words.len() > RAM_LEN {
+            return Err(BError::OutOfBoundsError(address, words.len(), RAM_LEN));
+        }
+        self.mem[start..start + words.len()].copy_from_slice(&words);
+        Ok(())
+    }
+}
+
+// this implements serialization and deserialization
+// could also be done using serde https://serde.rs/data-format.html
+impl VmDescription {
+    /// Get the metadata of the program, if there is any
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Serialize a VMDescription according to spec. RAM is written in chunks, chunks that only hold zeroes are left out
+    /// Currently the serialization does not support rom_blocks
+    /// ## Errors
+    /// - When the number of segments is too large
+    /// - When ROM is too large
+    pub fn serialize(&self) -> Result<Vec<u8>, BError> {
+        self.serialize_with(false)
+    }
+
+    /// Like [`VmDescription::serialize()`], but compresses ROM and RAM with deflate. Only readers built with the
+    /// `compression` feature can read the result
+    /// ## Errors
+    /// See [`VmDescription::serialize()`]
+    #[cfg(feature = "compression")]
+    pub fn serialize_compressed(&self) -> Result<Vec<u8>, BError> {
+        self.serialize_with(true)
+    }
+
+    fn serialize_with(&self, compress: bool) -> Result<Vec<u8>, BError> {
+        let mut segments: Vec<([u8; 4], Vec<u8>)> = Vec::new();
+
+        // PC and regs. Regs are already in the correct order
+        let mut cpu = self.pc.to_be_bytes().to_vec();
+        for reg in self.regs.iter() {
+            cpu.extend_from_slice(&reg.to_be_bytes());
+        }
+        segments.push((SEG_CPU, cpu));
+
+        // Mappings
+        let mut mappings = Vec::with_capacity(6 * self.rom_mappings.len());
+        for (rom_addr, length, ram_addr) in self.rom_mappings.iter() {
+            mappings.extend_from_slice(&rom_addr.to_be_bytes());
+            mappings.extend_from_slice(&length.to_be_bytes());
+            mappings.extend_from_slice(&ram_addr.to_be_bytes());
+        }
+        segments.push((SEG_ROM_MAPPINGS, mappings));
+
+        // ROM
+        if self.rom.len() > 0xffff {
+            return Err(BError::SerializationError(
+                "The ROM to be written is to large".into(),
+            ));
+        }
+        segments.push((
+            SEG_ROM,
+            self.rom.iter().flat_map(|v| v.to_be_bytes()).collect(),
+        ));
+
+        // RAM. Compression takes care of the zeroes, so a compressed file holds one chunk
+        let chunk_len = if compress { RAM_LEN } else { RAM_CHUNK_LEN };
+        for (idx, chunk) in self.mem.chunks(chunk_len).enumerate() {
+            if chunk.iter().all(|v| *v == 0) {
+                continue;
+            }
+            let mut ram = ((idx * chunk_len) as u16).to_be_bytes().to_vec();
+            ram.extend(chunk.iter().flat_map(|v| v.to_be_bytes()));
+            segments.push((SEG_RAM, ram));
+        }
+
+        // Metadata
+        if let Some(metadata) = &self.metadata {
+            segments.push((SEG_METADATA, metadata.serialize()?));
+        }
+
+        // Source
+        if let Some(source) = &self.source {
+            segments.push((SEG_SOURCE, encode_source(source)));
+        }
+
+        // Devices
+        if !self.device_configs.is_empty() {
+            segments.push((
+                SEG_DEVICES,
+                DeviceConfig::serialize_all(&self.device_configs)?,
+            ));
+        }
+
+        #[cfg(feature = "compression")]
+        if compress {
+            for (tag, payload) in segments.iter_mut() {
+                if matches!(*tag, SEG_ROM | SEG_RAM) {
+                    *payload = deflate(payload);
+                }
+            }
+        }
+
+        let segments: Vec<Segment> = segments
+            .iter()
+            .map(|(tag, payload)| (*tag, payload.as_slice()))
+            .collect();
+        let mut output = b"BVM".to_vec();
+        output.push(BVM_VERSION);
+        output.push(if compress { FLAG_COMPRESSED } else { 0x00 });
+        output.append(&mut write_segments(&segments)?);
+        Ok(output)
+    }
+
+    /// Deserialize a VMDescription according to spec. Dispatches on the format version following the magic
+    /// Currently the serialization does not support rom_blocks. A source that can't be decoded, e.g. a compressed one
+    /// without the `compression` feature, is left out
+    /// ## Errors
+    /// When the spec is not correctly respected or the file was written in a version newer than [`BVM_VERSION`]
+    pub fn deserialize(input: &[u8]) -> Result<Self, BError> {
+        use crate::util::check_slice;
+        // Check file magic
+        let magic = check_slice(input, 4)?;
+        if &magic[..3] != b"BVM" {
+            return Err(BError::DeserializationError(
+                FormatError::from("Invalid file format")
+                    .at(0)
+                    .expected("BVM", String::from_utf8_lossy(&magic[..3])),
+            ));
+        }
+        match magic[3] {
+            // version 0 files predate the version byte and share the layout of version 1
+            0..=2 => Self::deserialize_sections(&input[4..], magic[3]),
+            3 => Self::deserialize_segments(&input[4..], 0x00),
+            4 => {
+                let flags = check_slice(&input[4..], 1)?[0];
+                if flags & !FLAG_COMPRESSED != 0 {
+                    return Err(BError::DeserializationError(
+                        FormatError::from(format!("Unsupported header flags {flags:#04x}")).at(4),
+                    ));
+                }
+                Self::deserialize_segments(&input[5..], flags)
+            }
+            version => Err(BError::DeserializationError(
+                FormatError::from("Unsupported format version")
+                    .at(3)
+                    .expected(format!("at most {BVM_VERSION}"), version),
+            )),
+        }
+    }
+
+    /// Deserialize the segments of a file following the header. Unknown segments are skipped
+    fn deserialize_segments(input: &[u8], flags: u8) -> Result<Self, BError> {
+        let invalid = |tag: &[u8; 4]| {
+            BError::DeserializationError(
+                format!("Invalid length of segment {}", String::from_utf8_lossy(tag)).into(),
+            )
+        };
+        let words = |payload: &[u8]| -> Vec<u16> {
+            payload
+                .chunks(2)
+                .map(|w| u16::from_be_bytes([w[0], w[1]]))
+                .collect()
+        };
+
+        let mut description = Self::default();
+        for (tag, payload) in read_segments(input)? {
+            let decompressed;
+            let payload = if flags & FLAG_COMPRESSED != 0 && matches!(tag, SEG_ROM | SEG_RAM) {
+                decompressed = inflate(payload, SEGMENT_LIMIT)?;
+                decompressed.as_slice()
+            } else {
+                payload
+            };
+            match tag {
+                SEG_CPU => {
+                    if payload.len() != 14 {
+                        return Err(invalid(&tag));
+                    }
+                    let values = words(payload);
+                    description.pc = values[0];
+                    description.regs.copy_from_slice(&values[1..]);
+                }
+                SEG_ROM_MAPPINGS => {
+                    if payload.len() % 6 != 0 {
+                        return Err(invalid(&tag));
+                    }
+                    description.rom_mappings = words(payload)
+                        .chunks(3)
+                        .map(|m| (m[0], m[1], m[2]))
+                        .collect();
+                }
+                SEG_ROM => {
+                    if payload.len() % 2 != 0 {
+                        return Err(invalid(&tag));
+                    }
+                    description.rom = words(payload);
+                }
+                SEG_RAM => {
+                    if payload.len() < 2 || payload.len() % 2 != 0 {
+                        return Err(invalid(&tag));
+                    }
+                    let values = words(payload);
+                    let start = values[0] as usize;
+                    let chunk = &values[1..];
+                    if start + chunk.len() > RAM_LEN {
+                        return Err(BError::OutOfBoundsError(values[0], chunk.len(), RAM_LEN));
+                    }
+                    description.mem[start..start + chunk.len()].copy_from_slice(chunk);
+                }
+                SEG_METADATA => {
+                    let (metadata, rest) = Metadata::deserialize(payload)?;
+                    if !rest.is_empty() {
+                        return Err(invalid(&tag));
+                    }
+                    description.metadata = Some(metadata);
+                }
+                // the source is optional, a program is still usable without it
+                SEG_SOURCE => description.source = decode_source(payload).ok(),
+                SEG_DEVICES => description.device_configs = DeviceConfig::deserialize_all(payload)?,
+                // written by a newer version, safe to skip
+                _ => {}
+            }
+        }
+        Ok(description)
+    }
+
+    /// Deserialize the part of a file following the magic in the layout used before version 3.
+    /// Version 2 added the metadata section
+    fn deserialize_sections(input: &[u8], version: u8) -> Result<Self, BError> {
+        use crate::util::{check_slice, extract_number};
+        let current = input;
 
         // Get pc
         let pc_nums = check_slice(current, 3)?;
@@ -520,7 +1791,7 @@ impl VmDescription {
         let reg_num = check_slice(current, REG_FIELD_SIZE + 1)?;
         if reg_num[REG_FIELD_SIZE] != 0x00 {
             return Err(BError::DeserializationError(
-                "Invalid region separators".to_string(),
+                "Invalid region separators".into(),
             ));
         }
         let mut regs = [0; 6];
@@ -532,7 +1803,7 @@ impl VmDescription {
         // ROM mappings
         // Magic check
         if check_slice(current, 4)? != b"RMP\x00" {
-            return Err(BError::DeserializationError("No ROM mappings".to_string()));
+            return Err(BError::DeserializationError("No ROM mappings".into()));
         }
         let current = &current[4..];
 
@@ -547,7 +1818,7 @@ impl VmDescription {
         let rom_map_region = check_slice(current, rrlen)?;
         if rom_map_region[rrlen - 1] != 0x00 {
             return Err(BError::DeserializationError(
-                "Invalid region separators".to_string(),
+                "Invalid region separators".into(),
             ));
         }
 
@@ -558,7 +1829,7 @@ impl VmDescription {
             let ram_addr = u16::from_be_bytes([rom_map_region[j + 4], rom_map_region[j + 5]]);
             if rom_map_region[j + 6] != 0x00 {
                 return Err(BError::DeserializationError(
-                    "Invalid region separators".to_string(),
+                    "Invalid region separators".into(),
                 ));
             }
             mappings.push((rom_addr, length, ram_addr));
@@ -568,7 +1839,7 @@ impl VmDescription {
         // ROM
         // Magic check
         if check_slice(current, 4)? != b"ROM\x00" {
-            return Err(BError::DeserializationError("No ROM".to_string()));
+            return Err(BError::DeserializationError("No ROM".into()));
         }
         let current = &current[4..];
 
@@ -582,7 +1853,7 @@ impl VmDescription {
         let rom_region = check_slice(current, romlen)?;
         if rom_region[romlen - 1] != 0x00 {
             return Err(BError::DeserializationError(
-                "Invalid region separators".to_string(),
+                "Invalid region separators".into(),
             ));
         }
         let mut rom = Vec::with_capacity(rom_amnt);
@@ -596,13 +1867,16 @@ impl VmDescription {
         // RAM
         // Magic check
         if check_slice(current, 4)? != b"RAM\x00" {
-            return Err(BError::DeserializationError("No RAM".to_string()));
+            return Err(BError::DeserializationError("No RAM".into()));
         }
         let current = &current[4..];
 
-        if current.len() != RAM_LEN * 2 {
+        if current.len() < RAM_LEN * 2 || (version < 2 && current.len() != RAM_LEN * 2) {
             return Err(BError::DeserializationError(
-                "Invalid RAM length".to_string(),
+                FormatError::from("Invalid RAM length").expected(
+                    format!("{} bytes", RAM_LEN * 2),
+                    format!("{} bytes", current.len()),
+                ),
             ));
         }
         let mut ram = Vec::with_capacity(RAM_LEN * 2);
@@ -613,12 +1887,51 @@ impl VmDescription {
 
         // shouldn't fail. we set the size before
         let mem: Box<[u16; RAM_LEN]> = ram.into_boxed_slice().try_into().unwrap();
+        let current = &current[RAM_LEN * 2..];
+
+        // Metadata
+        let (metadata, current) = if current.is_empty() {
+            (None, current)
+        } else {
+            if check_slice(current, 4)? != b"MTA\x00" {
+                return Err(BError::DeserializationError("No metadata".into()));
+            }
+            let (metadata, current) = Metadata::deserialize(&current[4..])?;
+            (Some(metadata), current)
+        };
+        if !current.is_empty() {
+            return Err(BError::DeserializationError(
+                "Trailing data after the last section".into(),
+            ));
+        }
 
         Ok(Self {
             pc,
             rom,
             mem,
             callbacks: vec![],
+            read_callbacks: vec![],
+            region_callbacks: vec![],
+            region_read_callbacks: vec![],
+            devices: vec![],
+            access_regions: vec![],
+            mirrors: vec![],
+            forbidden_write_policy: ForbiddenWritePolicy::default(),
+            open_bus: vec![],
+            execution_mode: ExecutionMode::default(),
+            rom_window: None,
+            rom_writable: false,
+            perf_counters: None,
+            reset_register: None,
+            metadata,
+            source: None,
+            rng_seed: None,
+            deterministic: false,
+            latency: Latency::default(),
+            ram_backing: backing::RamBacking::default(),
+            host_env: None,
+            boot_rom: false,
+            device_configs: Vec::new(),
             rom_mappings: mappings,
             regs,
             rom_blocks: vec![],
@@ -626,423 +1939,3433 @@ impl VmDescription {
     }
 }
 
-/// Represents the VM.
-/// ### Examples
-/// Setting up a VM with the default VmDescription. This will result in an error when calling [`Vm::cycle()`] because there is no code to run.
-/// ```rust
-/// use bric_vm::vm::{Vm, VmDescription};
-///
-/// let vm_desc = VmDescription::default();
-/// let mut vm = Vm::new(vm_desc).unwrap();
-///
-/// // This is because `assert_matches!()` is in nightly.
-/// let v = match vm.cycle() {
-///     Err(_) => 1,
-///     Ok(_) => 2
-/// };
-///
-/// assert!(v == 1);
-/// ```
-#[derive(Debug)]
-pub struct Vm {
-    pc: Pc,
-    rom: Rom,
-    mem: MemoryUnit,
+/// The registers and program counter of the CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuState {
+    pub pc: u16,
+    pub a: u16,
+    pub d: u16,
+    pub e: u16,
+    pub f: u16,
+    pub g: u16,
+    pub h: u16,
 }
 
-impl Vm {
-    /// Create a new VM from a [`VmDescription`]
-    /// Copies the values in the description into the correct places in the computer and sets up mappings and callbacks
-    /// ## Errors
-    /// Results in a [`BError::OutOfBoundsError`] if a memory mapped region of ROM is not in RAM
-    pub fn new(description: VmDescription) -> Result<Self, BError> {
-        let pc = Pc::new(description.pc);
-
-        let mut ram = *description.mem;
+/// A summary of the state of a [`Vm`] for humans, see [`Vm::dump_state()`]. The instruction is disassembled if the
+/// `std` feature is enabled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateDump {
+    pub cpu: CpuState,
+    pub cycles: u64,
+    /// The instruction at the PC, `None` if the PC is past the end of ROM
+    pub instruction: Option<u16>,
+    /// Result of the last ALU instruction
+    pub last_result: Option<u16>,
+}
 
-        // Set up memory mapped regions
-        let mut regions = Vec::new();
-        for (source_low, length, addr) in description.rom_mappings {
-            let sl = source_low as usize;
-            let ad = addr as usize;
-            if ad + length as usize > RAM_LEN {
-                return Err(BError::OutOfBoundsError(addr, length as usize, RAM_LEN));
+impl fmt::Display for StateDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cycle {}, pc {:#06x}: ", self.cycles, self.cpu.pc)?;
+        match self.instruction {
+            #[cfg(feature = "std")]
+            Some(word) => writeln!(f, "{}", Instruction::from_u16(word))?,
+            #[cfg(not(feature = "std"))]
+            Some(word) => writeln!(f, "{:#06x}", word)?,
+            None => writeln!(f, "past the end of ROM")?,
+        }
+        let cpu = &self.cpu;
+        for (name, value) in [
+            ("A", cpu.a),
+            ("D", cpu.d),
+            ("E", cpu.e),
+            ("F", cpu.f),
+            ("G", cpu.g),
+            ("H", cpu.h),
+        ] {
+            writeln!(
+                f,
+                "{}  {:#06x}  {:>5}  {}",
+                name, value, value, value as i16
+            )?;
+        }
+        match self.last_result {
+            Some(result) => {
+                let flag = match (result as i16).signum() {
+                    -1 => "lt",
+                    0 => "eq",
+                    _ => "gt",
+                };
+                write!(f, "flags {} (last result {:#06x})", flag, result)
             }
+            None => write!(f, "flags none"),
+        }
+    }
+}
 
-            for i in 0..length as usize {
-                if let Some(v) = description.rom.get(i + sl) {
-                    ram[ad + i] = *v;
-                } else {
-                    return Err(BError::MapError(format!(
-                        "invalid span in rom: {:?} with length {:?}",
-                        sl, length
-                    )));
-                }
+/// Which instructions of the program were executed, see [`Vm::coverage()`]. Uncovered instructions are disassembled
+/// if the `std` feature is enabled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coverage {
+    /// The instructions from address 0, without the consts and the padding in front of them
+    pub rom: Vec<u16>,
+    /// Whether the instruction at each address of `rom` was executed
+    pub executed: Vec<bool>,
+}
+
+impl Coverage {
+    /// Amount of executed instructions
+    pub fn covered(&self) -> usize {
+        self.executed.iter().filter(|hit| **hit).count()
+    }
+
+    /// Executed instructions in percent, 100 for an empty program
+    pub fn percent(&self) -> f64 {
+        if self.rom.is_empty() {
+            return 100.0;
+        }
+        self.covered() as f64 * 100.0 / self.rom.len() as f64
+    }
+
+    /// The inclusive address ranges of instructions that were never executed
+    pub fn uncovered(&self) -> Vec<(u16, u16)> {
+        let mut ranges: Vec<(u16, u16)> = Vec::new();
+        for (address, _) in self.executed.iter().enumerate().filter(|(_, hit)| !**hit) {
+            let address = address as u16;
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == address => *end = address,
+                _ => ranges.push((address, address)),
             }
-            regions.push(Region::new(addr, addr + length, AccessLevels::Read));
         }
+        ranges
+    }
 
-        for (addr, length) in description.rom_blocks {
-            let start = addr as usize;
-            let end = start + (length as usize);
+    /// Add the instructions executed in another run of the same program
+    pub fn merge(&mut self, other: &Coverage) {
+        for (hit, other) in self.executed.iter_mut().zip(&other.executed) {
+            *hit |= *other;
+        }
+    }
+}
 
-            if start > RAM_LEN || end > RAM_LEN {
-                return Err(BError::OutOfBoundsError(addr, length as usize, RAM_LEN));
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "coverage: {}/{} instructions ({:.1}%)",
+            self.covered(),
+            self.rom.len(),
+            self.percent()
+        )?;
+        for (start, end) in self.uncovered() {
+            write!(f, "\nuncovered {:#06x}-{:#06x}:", start, end)?;
+            for address in start..=end {
+                let word = self.rom[address as usize];
+                #[cfg(feature = "std")]
+                write!(f, "\n  {:#06x}: {}", address, Instruction::from_u16(word))?;
+                #[cfg(not(feature = "std"))]
+                write!(f, "\n  {:#06x}: {:#06x}", address, word)?;
             }
+        }
+        Ok(())
+    }
+}
+
+/// Extra cycles memory accesses cost on top of the one cycle of every instruction. All zero by default. See
+/// [`VmDescription::latency`]
+/// ## Examples
+/// ```rust
+/// use bric_vm::{assembler, vm::{Latency, Vm}};
+///
+/// let mut vm_desc = assembler::run(r"[text]
+/// A = 0x6000
+/// *A = add, 0, A
+/// A = 0x100
+/// D = add, 0, *A
+/// [consts 0x200]").unwrap();
+/// vm_desc.latency = Latency { ram: 2, rom: 1, mmio: 10, mmio_regions: vec![0x6000..=0x60ff] };
+/// let mut vm = Vm::new(vm_desc).unwrap();
+/// for _ in 0..4 {
+///     vm.cycle().unwrap();
+/// }
+/// // four instructions fetched from ROM, a write to MMIO and a read of RAM
+/// assert_eq!(vm.get_cycles(), 4 * 2 + 10 + 2);
+/// assert_eq!(vm.perf_counters().instructions, 4);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Latency {
+    /// Cycles of every read or write of RAM, including fetching an instruction in [`ExecutionMode::VonNeumann`]
+    pub ram: u64,
+    /// Cycles of fetching an instruction from ROM in [`ExecutionMode::Harvard`]
+    pub rom: u64,
+    /// Cycles of every access to `mmio_regions`, instead of `ram`
+    pub mmio: u64,
+    /// RAM of devices, inclusive
+    pub mmio_regions: Vec<RangeInclusive<u16>>,
+}
 
-            regions.push(Region::new(start as u16, end as u16, AccessLevels::Read));
+impl Latency {
+    /// Cycles of a RAM access at `address`
+    fn access(&self, address: u16) -> u64 {
+        if self
+            .mmio_regions
+            .iter()
+            .any(|region| region.contains(&address))
+        {
+            self.mmio
+        } else {
+            self.ram
         }
+    }
+}
 
-        let rom = Rom::new(description.rom);
-        let regs = description.regs;
-        // Set up memory
-        let mut mem = MemoryUnit::new(
-            regs[0], regs[1], regs[2], regs[3], regs[4], regs[5], ram, regions,
-        )?;
+/// What executing an instruction costs, in a unit of your choice like picojoules or points. An instruction costs its
+/// class, plus `jump` if it jumps and the costs of its RAM accesses. See [`Vm::enable_cost()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CostTable {
+    /// Loading a constant into A
+    pub data: u64,
+    /// add, sub, inc, dec and asr
+    pub arithmetic: u64,
+    /// and, or, xor and not
+    pub logic: u64,
+    /// lsl, lsr, rol and ror
+    pub shift: u64,
+    /// Taken jumps, on top of the class of the instruction
+    pub jump: u64,
+    pub ram_read: u64,
+    pub ram_write: u64,
+}
 
-        for (idx, callback) in description.callbacks {
-            mem.register_callback(idx, callback);
+impl Default for CostTable {
+    /// Every instruction costs 1, jumps and RAM accesses cost 2 more
+    fn default() -> Self {
+        Self {
+            data: 1,
+            arithmetic: 1,
+            logic: 1,
+            shift: 1,
+            jump: 2,
+            ram_read: 2,
+            ram_write: 2,
         }
+    }
+}
+
+/// The cost accumulated since [`Vm::enable_cost()`], split by what it was spent on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CostReport {
+    pub data: u64,
+    pub arithmetic: u64,
+    pub logic: u64,
+    pub shift: u64,
+    pub jump: u64,
+    pub ram_read: u64,
+    pub ram_write: u64,
+    /// Instructions executed since the cost is recorded
+    pub instructions: u64,
+    /// Cycles elapsed since the cost is recorded
+    pub cycles: u64,
+}
 
-        Ok(Self { pc, rom, mem })
+impl CostReport {
+    pub fn total(&self) -> u64 {
+        self.data
+            + self.arithmetic
+            + self.logic
+            + self.shift
+            + self.jump
+            + self.ram_read
+            + self.ram_write
     }
+}
 
-    /// Cycles the CPU. Interprets the instruction and increments the PC.
-    /// ## Errors
-    /// - A [`BError::ExecutionHaltedError`] if there are no more instructions to run
-    /// - A [`BError::AsmParseError`] if there has been an error parseing the instruction
-    pub fn cycle(&mut self) -> Result<(), BError> {
-        let pcval = self.pc.get_val();
-        let inst = self
-            .rom
-            .get_address(pcval)
-            .ok_or(BError::ExecutionHaltedError { value: pcval })?;
-        self.interpret_instruction(inst)?;
-        self.pc.inc();
-        Ok(())
+impl fmt::Display for CostReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cost: {} for {} instructions in {} cycles (data {}, arithmetic {}, logic {}, shift {}, jumps {}, RAM reads {}, RAM writes {})",
+            self.total(),
+            self.instructions,
+            self.cycles,
+            self.data,
+            self.arithmetic,
+            self.logic,
+            self.shift,
+            self.jump,
+            self.ram_read,
+            self.ram_write
+        )
     }
+}
 
-    fn interpret_instruction(&mut self, instruction: u16) -> Result<(), BError> {
-        match Instruction::from_u16(instruction) {
-            Instruction::Alu(inst) => {
-                let source = Register::try_from(inst.get_source())?;
+/// Counts events since the VM was created. Exposed to the guest by [`VmDescription::perf_counters`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PerfCounters {
+    /// Elapsed clock cycles
+    pub cycles: u64,
+    /// Retired instructions
+    pub instructions: u64,
+    /// Taken jumps
+    pub branches: u64,
+}
 
-                // Switch order of operands in ALU
-                let (x, y) = if inst.get_sw() {
-                    (Register::A, source)
-                } else {
-                    (source, Register::A)
-                };
+/// How often an ALU instruction reads and writes RAM through `*A`
+fn ram_accesses(alu: &AluInstruction) -> (bool, bool) {
+    let ma = Register::MA as u8;
+    // with zx and without sw the source is replaced by 0 and never read
+    let reads = alu.get_source() == ma && (alu.get_sw() || !alu.get_zx());
+    (reads, alu.get_target() == ma)
+}
 
-                let target = Register::try_from(inst.get_target())?;
+/// Add the cost of the executed instruction `inst` to `report`
+fn add_cost(table: &CostTable, report: &mut CostReport, inst: u16, jumped: bool) {
+    report.instructions += 1;
+    let alu = match Instruction::from_u16(inst) {
+        Instruction::Data(_) => {
+            report.data += table.data;
+            return;
+        }
+        Instruction::Alu(alu) => alu,
+    };
+    match (alu.get_u(), alu.get_op()) {
+        (true, _) => report.arithmetic += table.arithmetic,
+        (false, 0b100..) => report.shift += table.shift,
+        (false, _) => report.logic += table.logic,
+    }
+    if jumped {
+        report.jump += table.jump;
+    }
+    let (reads, writes) = ram_accesses(&alu);
+    report.ram_read += reads as u64 * table.ram_read;
+    report.ram_write += writes as u64 * table.ram_write;
+}
 
-                // Apply the Zero X flag
-                let x = if inst.get_zx() {
-                    0
-                } else {
-                    self.mem.get_reg(x)
-                };
-                let y = self.mem.get_reg(y);
+/// How [`Vm::run_with_budget()`] stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetOutcome {
+    /// The program ran past the end of ROM within the budget
+    Halted,
+    /// The budget was used up before the program halted
+    Exhausted,
+}
 
-                // Do the computation
-                let output = if inst.get_u() {
-                    // Arithmetic instructions
-                    match inst.get_op() {
-                        0b000 => x.wrapping_add(y),
-                        0b001 => x.wrapping_sub(y),
-                        0b010 => x.wrapping_add(1),
-                        0b011 => x.wrapping_sub(1),
-                        0b100 => {
-                            let t = x & BIT_15;
-                            t | (x << 1)
-                        }
-                        _ => return Err(BError::InvalidInstructionError { instruction }),
-                    }
-                } else {
-                    // Logic instructions
-                    match inst.get_op() {
-                        0b000 => x & y,
-                        0b001 => x | y,
-                        0b010 => x ^ y,
-                        0b011 => !x,
-                        0b100 => x.wrapping_shl(1),
-                        0b101 => x.wrapping_shr(1),
-                        0b110 => x.rotate_left(1),
-                        0b111 => x.rotate_right(1),
-                        _ => return Err(BError::InvalidInstructionError { instruction }),
-                    }
-                };
+/// The result of [`Vm::run_with_budget()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BudgetRun {
+    /// Cycles consumed. At most the budget, unless the last instruction stalled on memory past it, see [`Latency`]
+    pub cycles: u64,
+    pub outcome: BudgetOutcome,
+}
 
-                // Set the condition flags
-                let ioutput = output as i16;
-                let lt = ioutput < 0;
-                let gt = ioutput > 0;
-                let eq = output == 0;
+/// How [`execute_sandboxed()`] ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SandboxOutcome {
+    /// The input isn't a ROM image, holds the reason
+    Rejected(String),
+    /// The program ran past the end of ROM
+    Halted,
+    /// The program was still running after the maximum amount of cycles
+    CycleLimit,
+    /// The program failed, e.g. on an invalid instruction. Holds the code of the error, see [`BError::code()`], and
+    /// its text
+    Failed { code: &'static str, message: String },
+}
 
-                if (lt & inst.get_lt()) | (gt & inst.get_gt()) | (eq & inst.get_eq()) {
-                    // apply jump. We set the PC to A - 1, because we will increment after.
-                    self.pc.set(self.mem.a - 1);
+/// The result of [`execute_sandboxed()`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SandboxResult {
+    pub outcome: SandboxOutcome,
+    pub cycles: u64,
+    /// The registers and the PC at the end, all zero if the input was rejected
+    pub cpu: CpuState,
+}
+
+/// Run a flat big endian ROM image, see [`VmDescription::from_rom_image()`], for at most `max_cycles` cycles. Never
+/// panics, whatever the bytes are: malformed images are rejected and invalid instructions end the run, so this is a
+/// safe entry point for untrusted programs and fuzzers. RAM is [`backing::RamBacking::Paged`], so short runs only
+/// allocate the pages they write
+/// ## Examples
+/// ```rust
+/// use bric_vm::vm::{SandboxOutcome, execute_sandboxed};
+///
+/// // A = 5, then an invalid arithmetic operation
+/// let result = execute_sandboxed(&[0x80, 0x05, 0x0f, 0x00], 100);
+/// assert_eq!(result.cycles, 1);
+/// assert_eq!(result.cpu.a, 5);
+/// assert!(matches!(result.outcome, SandboxOutcome::Failed { code: "B0003", .. }));
+///
+/// assert!(matches!(execute_sandboxed(&[0x80], 100).outcome, SandboxOutcome::Rejected(_)));
+/// assert_eq!(execute_sandboxed(&[0x80, 0x05], 100).outcome, SandboxOutcome::Halted);
+/// // A = 1, then jump to it forever
+/// assert_eq!(execute_sandboxed(&[0x80, 0x01, 0x00, 0x07], 100).outcome, SandboxOutcome::CycleLimit);
+/// ```
+pub fn execute_sandboxed(rom: &[u8], max_cycles: u64) -> SandboxResult {
+    let rejected = |e: BError| SandboxResult {
+        outcome: SandboxOutcome::Rejected(e.to_string()),
+        cycles: 0,
+        cpu: CpuState::default(),
+    };
+    let vm_desc = match VmDescription::from_rom_image(rom, Endianness::Big) {
+        Ok(vm_desc) => VmDescription {
+            ram_backing: backing::RamBacking::Paged,
+            ..vm_desc
+        },
+        Err(e) => return rejected(e),
+    };
+    let mut vm = match Vm::new(vm_desc) {
+        Ok(vm) => vm,
+        Err(e) => return rejected(e),
+    };
+    let outcome = match vm.run_with_budget(max_cycles) {
+        Ok(BudgetRun {
+            outcome: BudgetOutcome::Halted,
+            ..
+        }) => SandboxOutcome::Halted,
+        Ok(_) => SandboxOutcome::CycleLimit,
+        Err(e) => SandboxOutcome::Failed {
+            code: e.code(),
+            message: e.to_string(),
+        },
+    };
+    SandboxResult {
+        outcome,
+        cycles: vm.get_cycles(),
+        cpu: vm.cpu_state(),
+    }
+}
+
+/// The result of [`Vm::benchmark()`]
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BenchmarkReport {
+    /// Retired instructions
+    pub instructions: u64,
+    /// Elapsed clock cycles
+    pub cycles: u64,
+    /// How often the program halted and was restarted
+    pub restarts: u64,
+    /// Wall clock time spent running
+    pub elapsed: std::time::Duration,
+}
+
+#[cfg(feature = "std")]
+impl BenchmarkReport {
+    /// Get the retired instructions per second
+    pub fn instructions_per_second(&self) -> f64 {
+        self.instructions as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Get the time taken by one million instructions
+    pub fn time_per_million(&self) -> std::time::Duration {
+        self.elapsed
+            .mul_f64(1_000_000.0 / self.instructions.max(1) as f64)
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for BenchmarkReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} instructions in {} cycles, {} restarts, {:.3} s",
+            self.instructions,
+            self.cycles,
+            self.restarts,
+            self.elapsed.as_secs_f64()
+        )?;
+        write!(
+            f,
+            "{:.0} instructions/s, {:?} per 1M instructions",
+            self.instructions_per_second(),
+            self.time_per_million()
+        )
+    }
+}
+
+/// Keeps a VM at a clock rate by sleeping whenever it gets ahead of the wall clock. See [`Vm::run_realtime()`]
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct Pacer {
+    hz: u64,
+    began: std::time::Instant,
+    cycles: u64,
+}
+
+#[cfg(feature = "std")]
+impl Pacer {
+    /// Start pacing at `hz` cycles per second, at least 1
+    pub fn new(hz: u64) -> Self {
+        Self {
+            hz: hz.max(1),
+            began: std::time::Instant::now(),
+            cycles: 0,
+        }
+    }
+
+    /// Cycles worth a hundredth of a second. Sleeping after every batch keeps the amount of sleeps low while the VM
+    /// still looks smooth
+    pub fn batch(&self) -> u64 {
+        (self.hz / 100).max(1)
+    }
+
+    /// Account for `cycles` executed cycles and sleep until the wall clock has caught up with them. A VM slower than
+    /// the clock rate never sleeps and doesn't make up for the lost time later
+    pub fn pace(&mut self, cycles: u64) {
+        self.cycles += cycles;
+        let due = std::time::Duration::from_secs_f64(self.cycles as f64 / self.hz as f64);
+        let elapsed = self.began.elapsed();
+        match due.checked_sub(elapsed) {
+            Some(ahead) => std::thread::sleep(ahead),
+            None => {
+                // restart the clock so a stall isn't followed by a burst
+                self.began = std::time::Instant::now();
+                self.cycles = 0;
+            }
+        }
+    }
+}
+
+/// Control word of the performance counter block. Writing it latches the counters into the block
+pub const PERF_LATCH: u16 = 1;
+/// Control word of the performance counter block. Writing it resets the counters before latching
+pub const PERF_RESET: u16 = 1 << 1;
+/// Amount of words of the performance counter block: the control word and three 32 bit counters
+pub const PERF_LEN: u16 = 7;
+
+/// A copy of the state of a [`Vm`] that can be restored using [`Vm::restore()`]. Unlike [`Vm::to_vm_desc()`] this
+/// includes the state of devices and the cycle count, but no memory protections, mappings or callbacks.
+#[derive(Debug, Clone)]
+pub struct VmState {
+    pub cpu: CpuState,
+    pub ram: Box<[u16; RAM_LEN]>,
+    pub cycles: u64,
+    pub instructions: u64,
+    pub branches: u64,
+    pub fault: Option<Fault>,
+    /// The value last seen on the bus, which reads of [`OpenBus::LastValue`] regions return
+    pub last_bus_value: u16,
+    /// The output of [`Device::save()`] for each device in the order they were registered
+    pub devices: Vec<Vec<u8>>,
+}
+
+/// Represents the VM.
+/// ### Examples
+/// Setting up a VM with the default VmDescription. This will result in an error when calling [`Vm::cycle()`] because there is no code to run.
+/// ```rust
+/// use bric_vm::vm::{Vm, VmDescription};
+///
+/// let vm_desc = VmDescription::default();
+/// let mut vm = Vm::new(vm_desc).unwrap();
+///
+/// // This is because `assert_matches!()` is in nightly.
+/// let v = match vm.cycle() {
+///     Err(_) => 1,
+///     Ok(_) => 2
+/// };
+///
+/// assert!(v == 1);
+/// ```
+/// Running a VM on a background thread while another thread inspects it.
+/// ```rust
+/// use bric_vm::vm::{Vm, VmDescription};
+/// use std::sync::{Arc, Mutex};
+///
+/// let vm_desc = VmDescription {
+///     rom: vec![0x8000; 100],
+///     ..Default::default()
+/// };
+/// let vm = Arc::new(Mutex::new(Vm::new(vm_desc).unwrap()));
+///
+/// let worker_vm = vm.clone();
+/// let worker = std::thread::spawn(move || while worker_vm.lock().unwrap().cycle().is_ok() {});
+/// worker.join().unwrap();
+///
+/// assert_eq!(vm.lock().unwrap().get_pc(), 100);
+/// ```
+pub struct Vm {
+    pc: Pc,
+    rom: Rom,
+    mem: MemoryUnit,
+    devices: Vec<Box<dyn Device>>,
+    cycles: u64,
+    instructions: u64,
+    branches: u64,
+    fault: Option<Fault>,
+    execution_mode: ExecutionMode,
+    rom_window: Option<u16>,
+    rom_writable: bool,
+    rom_window_address: u16,
+    /// See [`VmDescription::boot_rom`]
+    handover: Option<boot::Handover>,
+    /// Whether the boot ROM runs, i.e. the boot window is open
+    booting: bool,
+    perf_counters: Option<u16>,
+    reset_register: Option<u16>,
+    initial: Option<(VmState, Vec<u16>)>,
+    device_configs: Vec<DeviceConfig>,
+    last_result: Option<u16>,
+    /// Ordered: rom_address, length, ram_address. Only kept for [`Vm::memory_map_report()`]
+    rom_mappings: Vec<(u16, u16, u16)>,
+    /// Executed ROM addresses, see [`Vm::enable_coverage()`]
+    coverage: Option<Vec<bool>>,
+    deterministic: bool,
+    /// Injections that haven't happened yet, ordered by cycle
+    injections: Vec<inject::Injection>,
+    latency: Latency,
+    /// Extra cycles the last executed instruction spent on memory accesses
+    stall: u64,
+    /// See [`Vm::enable_cost()`]. The cycles of the report are the cycle count when it was enabled
+    cost: Option<(CostTable, CostReport)>,
+    profiler: Option<profile::Profiler>,
+    heat: Option<heat::HeatMap>,
+    /// Address and word of the last executed instruction, whether it jumped and the PC after it
+    last_executed: Option<(u16, u16, bool, u16)>,
+}
+
+impl fmt::Debug for Vm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Vm")
+            .field("pc", &self.pc)
+            .field("rom", &self.rom)
+            .field("mem", &self.mem)
+            .field("devices", &self.devices.len())
+            .field("cycles", &self.cycles)
+            .field("fault", &self.fault)
+            .field("execution_mode", &self.execution_mode)
+            .field("rom_window", &self.rom_window)
+            .field("rom_writable", &self.rom_writable)
+            .field("perf_counters", &self.perf_counters)
+            .field("reset_register", &self.reset_register)
+            .finish()
+    }
+}
+
+impl Vm {
+    /// Create a new VM from a [`VmDescription`]
+    /// Copies the values in the description into the correct places in the computer and sets up mappings and callbacks
+    /// ## Errors
+    /// Results in a [`BError::OutOfBoundsError`] if a memory mapped region of ROM is not in RAM
+    /// or a region callback, mirror, the ROM window, the performance counters or the host environment do not fit into
+    /// RAM or the ROM doesn't end in front of the boot ROM and a [`BError::MapError`] if region callbacks or mirrors
+    /// overlap or a deterministic description has read callbacks or devices that depend on the host
+    pub fn new(mut description: VmDescription) -> Result<Self, BError> {
+        if description.deterministic {
+            if !description.read_callbacks.is_empty()
+                || !description.region_read_callbacks.is_empty()
+            {
+                return Err(BError::MapError(
+                    "deterministic VMs can't have read callbacks, use rng_seed for random numbers"
+                        .to_string(),
+                ));
+            }
+            if description
+                .devices
+                .iter()
+                .any(|device| !device.deterministic())
+            {
+                return Err(BError::MapError(
+                    "deterministic VMs can't have devices that depend on the host".to_string(),
+                ));
+            }
+        }
+        let mut handover = None;
+        if description.boot_rom {
+            if description.rom.len() > boot::BOOT_ROM_BASE as usize {
+                return Err(BError::OutOfBoundsError(
+                    0,
+                    description.rom.len(),
+                    boot::BOOT_ROM_BASE as usize,
+                ));
+            }
+            let window = boot::BOOT_WINDOW as usize;
+            handover = Some(boot::Handover {
+                rom_window: description.rom_window,
+                rom_writable: description.rom_writable,
+                ram: core::array::from_fn(|i| description.mem[window + i]),
+            });
+            description.rom.resize(boot::BOOT_ROM_BASE as usize, 0);
+            description.rom.extend_from_slice(&boot::BOOT_ROM);
+            description.pc = boot::BOOT_ROM_BASE;
+        }
+        if let Some(seed) = description.rng_seed {
+            mount_rng(&mut description, seed);
+        }
+        if let Some(addr) = description.perf_counters {
+            if addr as usize + PERF_LEN as usize > RAM_LEN {
+                return Err(BError::OutOfBoundsError(addr, PERF_LEN as usize, RAM_LEN));
+            }
+            description.rom_blocks.push((addr + 1, PERF_LEN - 1));
+        }
+        if let Some(env) = &description.host_env {
+            let block = env.encode();
+            let start = env.address as usize;
+            if start + block.len() > RAM_LEN {
+                return Err(BError::OutOfBoundsError(env.address, block.len(), RAM_LEN));
+            }
+            description.mem[start..start + block.len()].copy_from_slice(&block);
+            description
+                .rom_blocks
+                .push((env.address, block.len() as u16));
+        }
+        let pc = Pc::new(description.pc);
+
+        let mut ram = description.mem;
+
+        // Load the program into memory
+        if description.execution_mode == ExecutionMode::VonNeumann {
+            if description.rom.len() > RAM_LEN {
+                return Err(BError::OutOfBoundsError(0, description.rom.len(), RAM_LEN));
+            }
+            ram[..description.rom.len()].copy_from_slice(&description.rom);
+        }
+
+        // Set up memory mapped regions
+        let mut regions = Vec::new();
+        let rom_mappings = description.rom_mappings.clone();
+        for (source_low, length, addr) in description.rom_mappings {
+            let sl = source_low as usize;
+            let ad = addr as usize;
+            if ad + length as usize > RAM_LEN {
+                return Err(BError::OutOfBoundsError(addr, length as usize, RAM_LEN));
+            }
+
+            for i in 0..length as usize {
+                if let Some(v) = description.rom.get(i + sl) {
+                    ram[ad + i] = *v;
+                } else {
+                    return Err(BError::MapError(format!(
+                        "invalid span in rom: {:?} with length {:?}",
+                        sl, length
+                    )));
                 }
-                self.mem.set_reg(target, output);
             }
-            Instruction::Data(val) => {
-                self.mem.a = val;
+            if length > 0 {
+                regions.push(Region::new(addr, addr + (length - 1), AccessLevels::Read));
             }
-        };
-        Ok(())
-    }
+        }
 
-    /// Serializes the current state of the VM. Currently serialization of access levels and ROM maps is not supported
-    /// The ROM Memory will already be mapped into Memory at the point of serialization so no difference in runtime behavior is expected
-    /// The only difference is that access levels of memory are now all R/W.
-    pub fn to_vm_desc(&self) -> VmDescription {
-        let pc = self.pc.get_val();
-        let rom = self.rom.program_text.clone();
-        let mem = Box::new(self.mem.ram.ram);
-        /*let callbacks = self
-        .mem
-        .ram
-        .write_callbacks
-        .iter()
-        .map(|(k, v)| (*k, *v))
-        .collect();*/
-        let rom_mappings = vec![];
-        let regs = self.mem.get_regs().into();
+        for (addr, length) in description.rom_blocks {
+            let start = addr as usize;
+            let end = start + (length as usize);
 
-        VmDescription {
+            if start > RAM_LEN || end > RAM_LEN {
+                return Err(BError::OutOfBoundsError(addr, length as usize, RAM_LEN));
+            }
+
+            if length > 0 {
+                regions.push(Region::new(
+                    start as u16,
+                    (end - 1) as u16,
+                    AccessLevels::Read,
+                ));
+            }
+        }
+
+        regions.extend(description.access_regions);
+
+        let rom = Rom::new(description.rom);
+        let regs = description.regs;
+        // Set up memory
+        let mut mem = MemoryUnit::new(
+            regs[0], regs[1], regs[2], regs[3], regs[4], regs[5], ram, regions,
+        )?;
+        mem.ram.set_backing(description.ram_backing);
+
+        for (idx, callback) in description.callbacks {
+            mem.register_callback(idx, callback);
+        }
+
+        for (idx, callback) in description.read_callbacks {
+            mem.register_read_callback(idx, callback);
+        }
+
+        mem.ram
+            .set_forbidden_write_policy(description.forbidden_write_policy);
+
+        for (addr, length, open_bus) in description.open_bus {
+            mem.ram.set_open_bus(addr, length, open_bus)?;
+        }
+
+        for (addr, length, target) in description.mirrors {
+            mem.add_mirror(addr, length, target)?;
+        }
+
+        for (addr, length, callback) in description.region_callbacks {
+            mem.register_region_callback(addr, length, callback)?;
+        }
+
+        for (addr, length, callback) in description.region_read_callbacks {
+            mem.register_region_read_callback(addr, length, callback)?;
+        }
+
+        if let Some(addr) = description.rom_window
+            && addr as usize + 2 > RAM_LEN
+        {
+            return Err(BError::OutOfBoundsError(addr, 2, RAM_LEN));
+        }
+
+        let mut vm = Self {
             pc,
             rom,
             mem,
-            callbacks: vec![],
+            devices: description.devices,
+            cycles: 0,
+            instructions: 0,
+            branches: 0,
+            last_result: None,
+            fault: None,
+            execution_mode: description.execution_mode,
+            rom_window: if handover.is_some() {
+                Some(boot::BOOT_WINDOW)
+            } else {
+                description.rom_window
+            },
+            rom_writable: description.rom_writable || handover.is_some(),
+            rom_window_address: 0,
+            handover,
+            booting: handover.is_some(),
+            perf_counters: description.perf_counters,
+            reset_register: description.reset_register,
+            initial: None,
+            device_configs: description.device_configs,
             rom_mappings,
-            regs,
-            rom_blocks: vec![],
+            coverage: None,
+            deterministic: description.deterministic,
+            injections: Vec::new(),
+            latency: description.latency,
+            stall: 0,
+            cost: None,
+            profiler: None,
+            heat: None,
+            last_executed: None,
+        };
+        vm.refresh_rom_window();
+        if let Some(addr) = vm.reset_register {
+            let mut state = vm.snapshot();
+            state.ram[addr as usize] = 0;
+            vm.initial = Some((state, vm.rom.program_text.clone()));
         }
+        Ok(vm)
     }
 
-    /// Wraps [`Ram::set_ram()`]
-    pub fn set_ram(&mut self, address: u16, value: u16) {
-        self.mem.set_ram(address, value);
+    /// Cycles the CPU. Interprets the instruction, increments the PC and then ticks all registered devices.
+    /// ## Errors
+    /// - A [`BError::ExecutionHaltedError`] if there are no more instructions to run, see [`Vm::halt_cause()`] for why
+    /// - A [`BError::AsmParseError`] if there has been an error parseing the instruction
+    /// - A [`BError::AccessViolation`] if the instruction wrote to read only memory and the
+    ///   [`ForbiddenWritePolicy::Error`] policy is set. The PC is left on the offending instruction.
+    pub fn cycle(&mut self) -> Result<(), BError> {
+        if let Some(addr) = self.reset_register
+            && self.mem.ram.ram[addr as usize] != 0
+        {
+            return self.reset();
+        }
+        while let Some(injection) = self.injections.first()
+            && injection.cycle <= self.cycles
+        {
+            let injection = self.injections.remove(0);
+            self.inject(injection.target, injection.effect);
+        }
+        self.execute()?;
+        let cycles = 1 + core::mem::take(&mut self.stall);
+        self.cycles += cycles;
+        self.sync_perf_counters();
+        self.tick_devices(cycles);
+        Ok(())
     }
 
-    /// Wraps [`MemoryUnit::get_regs()`]
-    pub fn get_regs(&self) -> (u16, u16, u16, u16, u16, u16) {
-        self.mem.get_regs()
+    /// Fetch and interpret one instruction without advancing the clock
+    fn execute(&mut self) -> Result<(), BError> {
+        let pcval = self.pc.get_val();
+        let inst = match self.execution_mode {
+            ExecutionMode::Harvard => self
+                .rom
+                .get_address(pcval)
+                .ok_or(BError::ExecutionHaltedError { value: pcval })?,
+            ExecutionMode::VonNeumann => self.mem.ram.ram[pcval as usize],
+        };
+        // forget about accesses made outside of the CPU
+        let _ = self.mem.ram.take_fault();
+        self.mem.ram.pc = pcval;
+        // the slowest core of a lockstep multi-core VM holds up the others
+        self.stall = self.stall.max(self.access_cycles(pcval, inst));
+        if let Some(heat) = self.heat.as_mut()
+            && let Instruction::Alu(alu) = Instruction::from_u16(inst)
+        {
+            let (reads, writes) = ram_accesses(&alu);
+            heat.record(self.mem.a, reads, writes);
+        }
+        let branches = self.branches;
+        if let Some(executed) = self.coverage.as_mut()
+            && let Some(hit) = executed.get_mut(pcval as usize)
+        {
+            *hit = true;
+        }
+        self.interpret_instruction(inst)?;
+        if let Some((address, value, kind, raise)) = self.mem.ram.take_fault() {
+            self.fault = Some(Fault {
+                pc: pcval,
+                address,
+                value,
+                kind,
+            });
+            if raise {
+                self.pc.set(pcval);
+                return Err(BError::AccessViolation { pc: pcval, address });
+            }
+        }
+        self.pc.inc();
+        self.instructions += 1;
+        if let Some((table, report)) = self.cost.as_mut() {
+            add_cost(table, report, inst, self.branches != branches);
+        }
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(pcval, self.pc.get_val(), self.branches != branches);
+        }
+        self.last_executed = Some((pcval, inst, self.branches != branches, self.pc.get_val()));
+        self.sync_rom_window();
+        if self.booting && self.pc.get_val() < boot::BOOT_ROM_BASE {
+            self.hand_over();
+        }
+        Ok(())
     }
 
-    /// Wraps [`MemoryUnit::set_reg()`]
-    pub fn set_reg(&mut self, reg: Register, val: u16) {
-        self.mem.set_reg(reg, val);
+    /// Close the boot window once the boot ROM jumped to the program. The ROM window of the description takes its place
+    /// and the RAM the boot ROM used gets its initial values back, so booted programs start like loaded ones
+    fn hand_over(&mut self) {
+        let Some(handover) = self.handover else {
+            return;
+        };
+        for (i, word) in handover.ram.into_iter().enumerate() {
+            self.mem.ram.ram[boot::BOOT_WINDOW as usize + i] = word;
+        }
+        self.rom_window = handover.rom_window;
+        self.rom_writable = handover.rom_writable;
+        self.booting = false;
+        self.refresh_rom_window();
     }
 
-    /// Wraps [`MemoryUnit::get_reg()`]
-    pub fn get_reg(&self, reg: Register) -> u16 {
-        self.mem.get_reg(reg)
+    /// Extra cycles of fetching and executing `inst` at `pc`, see [`Latency`]. Must be called before executing it
+    fn access_cycles(&self, pc: u16, inst: u16) -> u64 {
+        let mut cycles = match self.execution_mode {
+            ExecutionMode::Harvard => self.latency.rom,
+            ExecutionMode::VonNeumann => self.latency.access(pc),
+        };
+        if let Instruction::Alu(alu) = Instruction::from_u16(inst) {
+            let (reads, writes) = ram_accesses(&alu);
+            cycles += (reads as u64 + writes as u64) * self.latency.access(self.mem.a);
+        }
+        cycles
     }
 
-    /// Wraps [`Ram::get_ram_region()`]
-    pub fn get_ram_region(&self, address: u16, length: usize) -> &[u16] {
-        self.mem.get_ram_region(address, length)
+    /// Run a command written to the control word of the performance counter block
+    fn sync_perf_counters(&mut self) {
+        let Some(addr) = self.perf_counters else {
+            return;
+        };
+        let control = self.mem.ram.ram[addr as usize];
+        if control == 0 {
+            return;
+        }
+        if control & PERF_RESET != 0 {
+            self.cycles = 0;
+            self.instructions = 0;
+            self.branches = 0;
+        }
+        let mut words = [0; PERF_LEN as usize];
+        for (i, counter) in [self.cycles, self.instructions, self.branches]
+            .into_iter()
+            .enumerate()
+        {
+            words[1 + 2 * i] = counter as u16;
+            words[2 + 2 * i] = (counter >> 16) as u16;
+        }
+        let start = addr as usize;
+        self.mem.ram.ram.write(start, &words);
+    }
+
+    /// Reset the VM to the description it was created from, including ROM patches, the cycle count and the state of
+    /// devices. Only available if the description has a `reset_register`, the guest resets the VM by writing to it.
+    /// ## Errors
+    /// - [`BError::MapError`] if the VM has no reset register
+    /// - [`BError::DeserializationError`] if a device fails to load its initial state
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::vm::{Vm, VmDescription};
+    ///
+    /// let vm_desc = VmDescription {
+    ///     rom: vec![0x8000; 100],
+    ///     reset_register: Some(0x7000),
+    ///     ..Default::default()
+    /// };
+    /// let mut vm = Vm::new(vm_desc).unwrap();
+    /// vm.cycle().unwrap();
+    /// vm.set_ram(0x100, 1);
+    /// // the next cycle resets the VM instead of executing an instruction
+    /// vm.set_ram(0x7000, 1);
+    /// vm.cycle().unwrap();
+    /// assert_eq!((vm.get_pc(), vm.get_cycles()), (0, 0));
+    /// assert_eq!(*vm.get_ram_region(0x100, 1), [0]);
+    /// ```
+    pub fn reset(&mut self) -> Result<(), BError> {
+        let Some((state, rom)) = self.initial.take() else {
+            return Err(BError::MapError("the VM has no reset register".to_string()));
+        };
+        self.rom.program_text.clone_from(&rom);
+        let result = self.restore(&state);
+        self.initial = Some((state, rom));
+        result
+    }
+
+    /// Get the performance counters
+    pub fn perf_counters(&self) -> PerfCounters {
+        PerfCounters {
+            cycles: self.cycles,
+            instructions: self.instructions,
+            branches: self.branches,
+        }
+    }
+
+    /// Start counting how often each ROM address is executed, forgetting earlier counts. Calls to the `functions`,
+    /// e.g. the labels of [`crate::assembler::debug_info::DebugInfo`], are tracked as described in [`profile`]. See
+    /// [`Vm::profile()`]
+    pub fn enable_profile(&mut self, functions: &[u16]) {
+        self.profiler = Some(profile::Profiler::new(
+            self.rom.program_text.len(),
+            functions,
+        ));
+    }
+
+    /// Get what was executed since [`Vm::enable_profile()`], `None` if profiling isn't enabled. See
+    /// [`profile::Profile::by_symbol()`] for totals by label
+    pub fn profile(&self) -> Option<profile::Profile> {
+        let profiler = self.profiler.as_ref()?;
+        Some(profiler.report(&self.rom.program_text, self.program_len()))
+    }
+
+    /// Start counting the reads and writes of every RAM address, forgetting earlier counts. See [`Vm::heat_map()`]
+    pub fn enable_heat_map(&mut self) {
+        self.heat = Some(heat::HeatMap::default());
+    }
+
+    /// Get the accesses since [`Vm::enable_heat_map()`], `None` if it isn't enabled
+    pub fn heat_map(&self) -> Option<&heat::HeatMap> {
+        self.heat.as_ref()
+    }
+
+    /// Start adding up the cost of the executed instructions, forgetting earlier costs. See [`Vm::cost()`]
+    pub fn enable_cost(&mut self, table: CostTable) {
+        let report = CostReport {
+            cycles: self.cycles,
+            ..Default::default()
+        };
+        self.cost = Some((table, report));
+    }
+
+    /// Get the cost of the instructions executed since [`Vm::enable_cost()`], `None` if it isn't enabled
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::{assembler, vm::{CostTable, Vm}};
+    ///
+    /// let code = r"[text]
+    /// A = 0x100
+    /// D = add, 0, *A
+    /// D = lsl, D
+    /// *A = add, 0, D
+    /// [consts 0x200]";
+    /// let mut vm = Vm::new(assembler::run(code).unwrap()).unwrap();
+    /// vm.enable_cost(CostTable { ram_read: 5, ram_write: 10, ..Default::default() });
+    /// for _ in 0..4 {
+    ///     vm.cycle().unwrap();
+    /// }
+    /// let cost = vm.cost().unwrap();
+    /// assert_eq!((cost.data, cost.arithmetic, cost.shift), (1, 2, 1));
+    /// assert_eq!(cost.total(), 4 + 5 + 10);
+    /// assert!(cost.to_string().starts_with("cost: 19 for 4 instructions in 4 cycles"));
+    /// ```
+    pub fn cost(&self) -> Option<CostReport> {
+        let (_, report) = self.cost.as_ref()?;
+        Some(CostReport {
+            cycles: self.cycles.saturating_sub(report.cycles),
+            ..*report
+        })
+    }
+
+    /// Start recording which ROM addresses are executed, forgetting earlier records. See [`Vm::coverage()`]
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(vec![false; self.rom.program_text.len()]);
+    }
+
+    /// Get the instructions executed since [`Vm::enable_coverage()`], `None` if coverage isn't enabled. The program
+    /// ends in front of the first word mapped into RAM by [`VmDescription::rom_mappings`], the consts and the padding
+    /// the assembler puts in front of them aren't counted
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::{assembler, vm::Vm};
+    ///
+    /// let code = r"[text]
+    /// A = 5
+    /// D = add, 0, A
+    /// A = skip
+    /// JMP
+    /// D = inc, D
+    /// D = inc, D
+    /// label skip:
+    /// A = 0x100
+    /// [consts 0x200]
+    /// M = 7";
+    /// let mut vm = Vm::new(assembler::run(code).unwrap()).unwrap();
+    /// vm.enable_coverage();
+    /// for _ in 0..5 {
+    ///     vm.cycle().unwrap();
+    /// }
+    /// let coverage = vm.coverage().unwrap();
+    /// assert_eq!((coverage.covered(), coverage.rom.len()), (5, 7));
+    /// assert_eq!(coverage.uncovered(), vec![(4, 5)]);
+    /// assert!(coverage.to_string().starts_with("coverage: 5/7 instructions (71.4%)\nuncovered 0x0004-0x0005:"));
+    /// ```
+    pub fn coverage(&self) -> Option<Coverage> {
+        let executed = self.coverage.as_ref()?;
+        let rom = &self.rom.program_text;
+        let end = self.program_len();
+        let mut executed: Vec<bool> = executed.iter().copied().take(end).collect();
+        executed.resize(end, false);
+        let rom = rom[..end].to_vec();
+        Some(Coverage { rom, executed })
+    }
+
+    /// Length of the program in ROM: up to the first word mapped into RAM, without the padding the assembler puts in
+    /// front of the consts
+    fn program_len(&self) -> usize {
+        let rom = &self.rom.program_text;
+        let Some(start) = self
+            .rom_mappings
+            .iter()
+            .map(|(address, _, _)| *address as usize)
+            .min()
+        else {
+            return rom.len();
+        };
+        let mut end = start.min(rom.len());
+        // the alignment and the padding word in front of the consts
+        while end > 0 && start - end < 0xf && rom[end - 1] == 0 {
+            end -= 1;
+        }
+        if end > 0 && rom[end - 1] == Instruction::Data(0).to_u16() {
+            end -= 1;
+        }
+        end
+    }
+
+    /// Apply a write to the data word of the ROM window and load the ROM word at the selected address into it
+    fn sync_rom_window(&mut self) {
+        let Some(addr) = self.rom_window else {
+            return;
+        };
+        let data = self.mem.ram.ram[addr as usize + 1];
+        let current = self.rom.get_address(self.rom_window_address).unwrap_or(0);
+        if self.rom_writable && data != current {
+            self.rom.set_address(self.rom_window_address, data);
+        }
+        self.refresh_rom_window();
+    }
+
+    /// Load the ROM word at the address selected in the ROM window into its data word
+    fn refresh_rom_window(&mut self) {
+        let Some(addr) = self.rom_window else {
+            return;
+        };
+        self.rom_window_address = self.mem.ram.ram[addr as usize];
+        self.mem.ram.ram[addr as usize + 1] =
+            self.rom.get_address(self.rom_window_address).unwrap_or(0);
+    }
+
+    /// Set the value of ROM at `address`, growing the ROM if necessary. This ignores [`VmDescription::rom_writable`]
+    pub fn set_rom(&mut self, address: u16, value: u16) {
+        self.rom.set_address(address, value);
+        self.refresh_rom_window();
+    }
+
+    /// Advance all registered devices by `cycles` clock cycles
+    fn tick_devices(&mut self, cycles: u64) {
+        for device in self.devices.iter_mut() {
+            device.tick(cycles, &mut self.mem.ram);
+        }
+    }
+
+    /// Get the registers and the program counter at once
+    pub fn cpu_state(&self) -> CpuState {
+        CpuState {
+            pc: self.pc.get_val(),
+            a: self.mem.a,
+            d: self.mem.d,
+            e: self.mem.e,
+            f: self.mem.f,
+            g: self.mem.g,
+            h: self.mem.h,
+        }
+    }
+
+    /// Set the registers and the program counter at once
+    pub fn set_cpu_state(&mut self, state: CpuState) {
+        self.pc.set(state.pc);
+        self.mem.a = state.a;
+        self.mem.d = state.d;
+        self.mem.e = state.e;
+        self.mem.f = state.f;
+        self.mem.g = state.g;
+        self.mem.h = state.h;
+    }
+
+    /// Run the program as fast as possible for `seconds` and measure the throughput of the interpreter. The program is
+    /// restored to the state it had on the call whenever it halts, so short programs run over and over. The counts in the
+    /// report are accumulated over all restarts.
+    /// ## Errors
+    /// Errors of [`Vm::cycle()`] other than [`BError::ExecutionHaltedError`]
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::vm::{Vm, VmDescription};
+    ///
+    /// let vm_desc = VmDescription { rom: vec![0x8005; 0x100], ..Default::default() };
+    /// let mut vm = Vm::new(vm_desc).unwrap();
+    /// let report = vm.benchmark(0.01).unwrap();
+    /// assert!(report.instructions > 0x100 && report.restarts > 0);
+    /// assert_eq!(report.instructions, report.cycles);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn benchmark(&mut self, seconds: f64) -> Result<BenchmarkReport, BError> {
+        use std::time::{Duration, Instant};
+        /// Cycles run between checks of the clock
+        const CHUNK: u32 = 0x4000;
+
+        let start = self.snapshot();
+        let duration = Duration::from_secs_f64(seconds);
+        let mut report = BenchmarkReport::default();
+        let add_counters = |vm: &Self, report: &mut BenchmarkReport| {
+            report.instructions += vm.instructions - start.instructions;
+            report.cycles += vm.cycles - start.cycles;
+        };
+        let began = Instant::now();
+        while began.elapsed() < duration {
+            for _ in 0..CHUNK {
+                match self.cycle() {
+                    Ok(()) => {}
+                    Err(BError::ExecutionHaltedError { .. }) => {
+                        add_counters(self, &mut report);
+                        report.restarts += 1;
+                        self.restore(&start)?;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        report.elapsed = began.elapsed();
+        add_counters(self, &mut report);
+        Ok(report)
+    }
+
+    /// Run the program until it halts at a clock rate of `hz`, so interactive and timing dependent programs run as fast
+    /// as the hardware would. Returns the amount of cycles run, see [`Pacer`] for how the time is kept
+    /// ## Errors
+    /// Errors of [`Vm::cycle()`] other than [`BError::ExecutionHaltedError`]
+    /// ## Examples
+    /// ```rust
+    /// use std::time::{Duration, Instant};
+    /// use bric_vm::vm::{Vm, VmDescription};
+    ///
+    /// let vm_desc = VmDescription { rom: vec![0x8005; 500], ..Default::default() };
+    /// let mut vm = Vm::new(vm_desc).unwrap();
+    /// let began = Instant::now();
+    /// assert_eq!(vm.run_realtime(10_000).unwrap(), 500);
+    /// assert!(began.elapsed() >= Duration::from_millis(50));
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn run_realtime(&mut self, hz: u64) -> Result<u64, BError> {
+        let mut pacer = Pacer::new(hz);
+        let mut cycles = 0;
+        loop {
+            let run = self.run_with_budget(pacer.batch())?;
+            cycles += run.cycles;
+            if run.outcome == BudgetOutcome::Halted {
+                return Ok(cycles);
+            }
+            pacer.pace(run.cycles);
+        }
+    }
+
+    /// Run the program until it halts, but for at most `budget` cycles
+    /// ## Errors
+    /// Errors of [`Vm::cycle()`] other than [`BError::ExecutionHaltedError`]. The cycles consumed until then are
+    /// counted by [`Vm::get_cycles()`]
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::vm::{BudgetOutcome, BudgetRun, Vm, VmDescription};
+    ///
+    /// let vm_desc = VmDescription { rom: vec![0x8005; 10], ..Default::default() };
+    /// let mut vm = Vm::new(vm_desc).unwrap();
+    /// assert_eq!(
+    ///     vm.run_with_budget(4).unwrap(),
+    ///     BudgetRun { cycles: 4, outcome: BudgetOutcome::Exhausted }
+    /// );
+    /// assert_eq!(
+    ///     vm.run_with_budget(100).unwrap(),
+    ///     BudgetRun { cycles: 6, outcome: BudgetOutcome::Halted }
+    /// );
+    /// ```
+    pub fn run_with_budget(&mut self, budget: u64) -> Result<BudgetRun, BError> {
+        let mut cycles = 0;
+        while cycles < budget {
+            let before = self.cycles;
+            match self.cycle() {
+                // the cycle count goes back when the guest resets the counters
+                Ok(()) => cycles += self.cycles.saturating_sub(before).max(1),
+                Err(BError::ExecutionHaltedError { .. }) => {
+                    return Ok(BudgetRun {
+                        cycles,
+                        outcome: BudgetOutcome::Halted,
+                    });
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(BudgetRun {
+            cycles,
+            outcome: BudgetOutcome::Exhausted,
+        })
+    }
+
+    /// Apply a fault to RAM, ROM, a register or the PC right away. See [`inject`]
+    pub fn inject(&mut self, target: inject::Target, effect: inject::Effect) {
+        use inject::Target;
+        match target {
+            Target::Ram(address) => {
+                let word = &mut self.mem.ram.ram[address as usize];
+                *word = effect.apply(*word);
+                self.refresh_rom_window();
+            }
+            Target::Rom(address) => {
+                if let Some(word) = self.rom.get_address(address) {
+                    self.set_rom(address, effect.apply(word));
+                }
+            }
+            Target::Register(register) => {
+                let value = effect.apply(self.get_reg(register));
+                self.set_reg(register, value);
+            }
+            Target::Pc => self.pc.set(effect.apply(self.pc.get_val())),
+        }
+    }
+
+    /// Apply a fault when the VM reaches the cycle of the injection, right before the instruction of that cycle.
+    /// Injections for past cycles happen on the next cycle
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::{assembler, vm::{Register, Vm, inject::{Effect, Injection, Target}}};
+    ///
+    /// // store the value loaded into A twice
+    /// let code = r"[text]
+    /// A = 5
+    /// D = add, 0, A
+    /// A = 0x100
+    /// *A = add, 0, D
+    /// A = 0x101
+    /// *A = add, 0, D
+    /// [consts 0x200]";
+    /// let mut vm = Vm::new(assembler::run(code).unwrap()).unwrap();
+    /// vm.schedule_injection(Injection { cycle: 5, target: Target::Ram(0x100), effect: Effect::Flip(0b10) });
+    /// vm.schedule_injection(Injection { cycle: 1, target: Target::Register(Register::A), effect: Effect::Force(6) });
+    /// for _ in 0..6 {
+    ///     vm.cycle().unwrap();
+    /// }
+    /// // the copies differ
+    /// assert_eq!(*vm.get_ram_region(0x100, 2), [4, 6]);
+    /// assert!(vm.pending_injections().is_empty());
+    /// ```
+    pub fn schedule_injection(&mut self, injection: inject::Injection) {
+        let idx = self
+            .injections
+            .partition_point(|pending| pending.cycle <= injection.cycle);
+        self.injections.insert(idx, injection);
+    }
+
+    /// Get the injections that haven't happened yet, ordered by cycle
+    pub fn pending_injections(&self) -> &[inject::Injection] {
+        &self.injections
+    }
+
+    /// Forget the injections that haven't happened yet
+    pub fn clear_injections(&mut self) {
+        self.injections.clear();
+    }
+
+    /// Take a snapshot of the state of the VM. See [`VmState`]
+    pub fn snapshot(&self) -> VmState {
+        VmState {
+            cpu: self.cpu_state(),
+            ram: self.mem.ram.ram.to_flat(),
+            cycles: self.cycles,
+            instructions: self.instructions,
+            branches: self.branches,
+            fault: self.fault,
+            last_bus_value: self.mem.ram.last_bus_value,
+            devices: self.save_devices(),
+        }
+    }
+
+    /// Save the state of every device in the order they were registered. See [`Device::save()`]
+    pub fn save_devices(&self) -> Vec<Vec<u8>> {
+        self.devices.iter().map(|d| d.save()).collect()
+    }
+
+    /// Restore the state of the devices from the output of [`Vm::save_devices()`]
+    /// ## Errors
+    /// [`BError::DeserializationError`] if the amount of devices differs or a device fails to load its state. The
+    /// devices loaded before the failing one are put back into their previous state.
+    pub fn load_devices(&mut self, states: &[Vec<u8>]) -> Result<(), BError> {
+        if states.len() != self.devices.len() {
+            return Err(BError::DeserializationError(
+                FormatError::from("the snapshot holds a different amount of devices than the VM")
+                    .expected(self.devices.len(), states.len()),
+            ));
+        }
+        let previous = self.save_devices();
+        for (i, device_state) in states.iter().enumerate() {
+            if let Err(e) = self.devices[i].load(device_state) {
+                for (device, device_state) in self.devices.iter_mut().zip(previous.iter()).take(i) {
+                    // a device can load what it saved itself
+                    let _ = device.load(device_state);
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Restore a snapshot taken with [`Vm::snapshot()`]
+    /// ## Errors
+    /// - [`BError::DeserializationError`] if the amount of devices differs from the snapshot or a device fails to
+    ///   load its state. The VM is left unchanged in that case, including the devices that loaded their state before.
+    pub fn restore(&mut self, state: &VmState) -> Result<(), BError> {
+        self.load_devices(&state.devices)?;
+        self.set_cpu_state(state.cpu);
+        self.mem.ram.ram.load(&state.ram);
+        if let Some(handover) = self.handover {
+            // the boot window is open while the boot ROM runs
+            self.booting = self.pc.get_val() >= boot::BOOT_ROM_BASE;
+            (self.rom_window, self.rom_writable) = if self.booting {
+                (Some(boot::BOOT_WINDOW), true)
+            } else {
+                (handover.rom_window, handover.rom_writable)
+            };
+        }
+        self.refresh_rom_window();
+        self.cycles = state.cycles;
+        self.instructions = state.instructions;
+        self.branches = state.branches;
+        self.fault = state.fault;
+        self.mem.ram.last_bus_value = state.last_bus_value;
+        self.last_result = None;
+        self.last_executed = None;
+        Ok(())
+    }
+
+    /// Get the result of the last ALU instruction, `None` if there was none since the VM was created or restored
+    pub fn last_result(&self) -> Option<u16> {
+        self.last_result
+    }
+
+    /// The instruction at the PC, which the next cycle executes. `None` if the PC is beyond the ROM
+    pub fn current_instruction(&self) -> Option<u16> {
+        let pc = self.pc.get_val();
+        match self.execution_mode {
+            ExecutionMode::Harvard => self.rom.get_address(pc),
+            ExecutionMode::VonNeumann => Some(self.mem.ram.ram[pc as usize]),
+        }
+    }
+
+    /// Summarize the registers, the instruction at the PC and the flags of the last ALU result. See [`StateDump`]
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::{assembler, vm::Vm};
+    ///
+    /// let code = r"[text]
+    /// A = 5
+    /// D = sub, 0, A
+    /// A = 0x100
+    /// [consts 0x200]";
+    /// let mut vm = Vm::new(assembler::run(code).unwrap()).unwrap();
+    /// vm.cycle().unwrap();
+    /// vm.cycle().unwrap();
+    /// let dump = vm.dump_state().to_string();
+    /// assert!(dump.contains("0x0002: A = 256"));
+    /// assert!(dump.contains("D  0xfffb  65531  -5"));
+    /// assert!(dump.contains("lt"));
+    /// ```
+    pub fn dump_state(&self) -> StateDump {
+        StateDump {
+            cpu: self.cpu_state(),
+            cycles: self.cycles,
+            instruction: self.current_instruction(),
+            last_result: self.last_result,
+        }
+    }
+
+    /// List the access levels, ROM mappings, mirrors, open bus behaviors, callbacks and devices by address in a table.
+    /// Addresses without an access level are listed as `unprotected`, they are readable and writable
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::vm::{Vm, VmDescription};
+    ///
+    /// let vm_desc = VmDescription {
+    ///     rom: vec![0; 0x20],
+    ///     rom_mappings: vec![(0x10, 0x10, 0xf000)],
+    ///     callbacks: vec![(0x123, Box::new(|_| {}))],
+    ///     ..Default::default()
+    /// };
+    /// let report = Vm::new(vm_desc).unwrap().memory_map_report();
+    /// assert!(report.contains("0x0000  0xefff  unprotected"));
+    /// assert!(report.contains("0xf000  0xf00f  read only       priority 0"));
+    /// assert!(report.contains("0xf000  0xf00f  ROM mapping     ROM 0x0010-0x001f"));
+    /// assert!(report.contains("0x0123  0x0123  write callback"));
+    /// ```
+    pub fn memory_map_report(&self) -> String {
+        let mut report = String::from("start   end     kind            details\n");
+        for (start, end, kind, detail) in self.memory_map_rows() {
+            let line = format!("{:#06x}  {:#06x}  {:<14}  {}", start, end, kind, detail);
+            report.push_str(line.trim_end());
+            report.push('\n');
+        }
+        report
+    }
+
+    /// Describe what lives at `address`: the standard register from [`crate::mmio::map`], if any, followed by every
+    /// row of [`Vm::memory_map_report()`] covering the address
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::vm::{Vm, VmDescription};
+    ///
+    /// let vm_desc = VmDescription {
+    ///     rom: vec![0; 0x20],
+    ///     rom_mappings: vec![(0x10, 0x10, 0xf000)],
+    ///     ..Default::default()
+    /// };
+    /// let vm = Vm::new(vm_desc).unwrap();
+    /// assert_eq!(vm.describe_address(0x100), "0x0100: RAM\nunprotected");
+    /// assert_eq!(
+    ///     vm.describe_address(0xf001),
+    ///     "0xf001: RAM\nread only       priority 0\nROM mapping     ROM 0x0010-0x001f"
+    /// );
+    /// assert!(vm.describe_address(0x6001).starts_with("0x6001: U_OUT (uart)"));
+    /// ```
+    pub fn describe_address(&self, address: u16) -> String {
+        let mut description = match crate::mmio::map::lookup(address) {
+            Some(entry) => format!(
+                "{:#06x}: {} ({}), {}",
+                address, entry.name, entry.device, entry.description
+            ),
+            None => format!("{:#06x}: RAM", address),
+        };
+        for (_, _, kind, detail) in self
+            .memory_map_rows()
+            .into_iter()
+            .filter(|(start, end, _, _)| (*start..=*end).contains(&address))
+        {
+            let line = format!("\n{:<14}  {}", kind, detail);
+            description.push_str(line.trim_end());
+        }
+        description
+    }
+
+    /// The rows of [`Vm::memory_map_report()`]: start, inclusive end, kind and details, sorted by address
+    fn memory_map_rows(&self) -> Vec<(u16, u16, &'static str, String)> {
+        let ram = &self.mem.ram;
+        let mut rows = Vec::new();
+        for region in ram.memory_regions.iter() {
+            let kind = match region.label() {
+                AccessLevels::ReadWrite => "read write",
+                AccessLevels::Read => "read only",
+                AccessLevels::WriteOnly => "write only",
+                AccessLevels::None => "no access",
+            };
+            let detail = format!("priority {}", region.priority());
+            rows.push((*region.start(), *region.end(), kind, detail));
+        }
+        for (start, end) in ram.memory_regions.gaps() {
+            rows.push((start, end, "unprotected", String::new()));
+        }
+        for (rom_address, length, ram_address) in &self.rom_mappings {
+            if *length > 0 {
+                let detail = format!(
+                    "ROM {:#06x}-{:#06x}",
+                    rom_address,
+                    rom_address.wrapping_add(length - 1)
+                );
+                rows.push((
+                    *ram_address,
+                    ram_address + (length - 1),
+                    "ROM mapping",
+                    detail,
+                ));
+            }
+        }
+        for (start, end, target) in &ram.mirrors {
+            rows.push((*start, *end, "mirror", format!("of {:#06x}", target)));
+        }
+        for (start, end, open_bus) in &ram.open_bus {
+            rows.push((*start, *end, "open bus", format!("{:?}", open_bus)));
+        }
+        for address in ram.write_callbacks.keys() {
+            rows.push((*address, *address, "write callback", String::new()));
+        }
+        for address in ram.read_callbacks.keys() {
+            rows.push((*address, *address, "read callback", String::new()));
+        }
+        for (start, end, _) in &ram.region_write_callbacks {
+            rows.push((*start, *end, "write callback", String::new()));
+        }
+        for (start, end, _) in &ram.region_read_callbacks {
+            rows.push((*start, *end, "read callback", String::new()));
+        }
+        if let Some(address) = self.rom_window {
+            rows.push((address, address + 1, "ROM window", String::new()));
+        }
+        if let Some(address) = self.perf_counters {
+            let end = address + (PERF_LEN - 1);
+            rows.push((address, end, "perf counters", String::new()));
+        }
+        if let Some(address) = self.reset_register {
+            rows.push((address, address, "reset register", String::new()));
+        }
+        for config in &self.device_configs {
+            let detail = format!("{} ({})", config.name, config.kind);
+            rows.push((config.base, config.base, "device", detail));
+        }
+        rows.sort_by_key(|(start, end, _, _)| (*start, *end));
+        rows
+    }
+
+    /// Get the last memory access that violated the memory protections
+    pub fn get_fault(&self) -> Option<Fault> {
+        self.fault
+    }
+
+    /// Set or clear the last fault, used when restoring a coredump
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn set_fault(&mut self, fault: Option<Fault>) {
+        self.fault = fault;
+    }
+
+    /// Wraps [`Ram::set_forbidden_write_policy()`]
+    pub fn set_forbidden_write_policy(&mut self, policy: ForbiddenWritePolicy) {
+        self.mem.ram.set_forbidden_write_policy(policy);
+    }
+
+    /// Wraps [`Ram::set_open_bus()`]
+    pub fn set_open_bus(
+        &mut self,
+        address: u16,
+        length: u16,
+        open_bus: OpenBus,
+    ) -> Result<(), BError> {
+        self.mem.ram.set_open_bus(address, length, open_bus)
+    }
+
+    /// Register a device that gets ticked after every cycle
+    /// ## Errors
+    /// [`BError::MapError`] if the VM is deterministic and the device is not, see [`VmDescription::deterministic`]
+    pub fn register_device(&mut self, device: Box<dyn Device>) -> Result<(), BError> {
+        if self.deterministic && !device.deterministic() {
+            return Err(BError::MapError(
+                "deterministic VMs can't have devices that depend on the host".to_string(),
+            ));
+        }
+        self.devices.push(device);
+        Ok(())
+    }
+
+    /// Whether every run of the VM executes the same way, see [`VmDescription::deterministic`]
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::vm::{Vm, VmDescription};
+    ///
+    /// let vm_desc = VmDescription {
+    ///     rng_seed: Some(1),
+    ///     deterministic: true,
+    ///     ..Default::default()
+    /// };
+    /// assert!(Vm::new(vm_desc).unwrap().is_deterministic());
+    /// // the values of read callbacks come from the host
+    /// let vm_desc = VmDescription {
+    ///     read_callbacks: vec![(0x100, Box::new(|_| 1))],
+    ///     deterministic: true,
+    ///     ..Default::default()
+    /// };
+    /// assert!(Vm::new(vm_desc).is_err());
+    /// ```
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// Get the amount of cycles executed since the VM was created
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    fn interpret_instruction(&mut self, instruction: u16) -> Result<(), BError> {
+        match Instruction::from_u16(instruction) {
+            Instruction::Alu(inst) => {
+                let source = Register::try_from(inst.get_source())?;
+
+                // Switch order of operands in ALU
+                let (x, y) = if inst.get_sw() {
+                    (Register::A, source)
+                } else {
+                    (source, Register::A)
+                };
+
+                let target = Register::try_from(inst.get_target())?;
+
+                // Apply the Zero X flag
+                let x = if inst.get_zx() {
+                    0
+                } else {
+                    self.mem.read_reg(x)
+                };
+                let y = self.mem.read_reg(y);
+
+                // Do the computation
+                let (output, flags) = alu::compute(inst.get_op(), inst.get_u(), x, y)
+                    .ok_or(BError::InvalidInstructionError { instruction })?;
+
+                self.last_result = Some(output);
+                if flags.jumps(inst.get_lt(), inst.get_eq(), inst.get_gt()) {
+                    // apply jump. We set the PC to A - 1, because we will increment after.
+                    self.pc.set(self.mem.a.wrapping_sub(1));
+                    self.branches += 1;
+                }
+                self.mem.set_reg(target, output);
+            }
+            Instruction::Data(val) => {
+                self.mem.a = val;
+            }
+        };
+        Ok(())
+    }
+
+    /// Serializes the current state of the VM. Currently serialization of access levels and ROM maps is not supported
+    /// The ROM Memory will already be mapped into Memory at the point of serialization so no difference in runtime behavior is expected
+    /// The only difference is that access levels of memory are now all R/W.
+    pub fn to_vm_desc(&self) -> VmDescription {
+        let pc = self.pc.get_val();
+        let rom = self.rom.program_text.clone();
+        let mem = self.mem.ram.ram.to_flat();
+        /*let callbacks = self
+        .mem
+        .ram
+        .write_callbacks
+        .iter()
+        .map(|(k, v)| (*k, *v))
+        .collect();*/
+        let rom_mappings = vec![];
+        let regs = self.mem.get_regs().into();
+
+        VmDescription {
+            pc,
+            rom,
+            mem,
+            callbacks: vec![],
+            read_callbacks: vec![],
+            region_callbacks: vec![],
+            region_read_callbacks: vec![],
+            devices: vec![],
+            access_regions: vec![],
+            mirrors: vec![],
+            forbidden_write_policy: ForbiddenWritePolicy::default(),
+            open_bus: vec![],
+            execution_mode: ExecutionMode::default(),
+            rom_window: None,
+            rom_writable: false,
+            perf_counters: None,
+            reset_register: None,
+            metadata: None,
+            source: None,
+            rng_seed: None,
+            deterministic: self.deterministic,
+            latency: self.latency.clone(),
+            ram_backing: self.mem.ram.backing(),
+            // the block is part of the RAM
+            host_env: None,
+            // and the boot ROM part of the ROM
+            boot_rom: false,
+            device_configs: self.device_configs.clone(),
+            rom_mappings,
+            regs,
+            rom_blocks: vec![],
+        }
+    }
+
+    /// Wraps [`Ram::set_ram()`]. Writes to the ROM window take effect immediately
+    pub fn set_ram(&mut self, address: u16, value: u16) {
+        self.mem.set_ram(address, value);
+        self.sync_rom_window();
+    }
+
+    /// Wraps [`MemoryUnit::get_regs()`]. See [`Vm::cpu_state()`] for a more convenient alternative.
+    pub fn get_regs(&self) -> (u16, u16, u16, u16, u16, u16) {
+        self.mem.get_regs()
+    }
+
+    /// Wraps [`MemoryUnit::set_reg()`]
+    pub fn set_reg(&mut self, reg: Register, val: u16) {
+        self.mem.set_reg(reg, val);
+    }
+
+    /// Wraps [`MemoryUnit::get_reg()`]
+    pub fn get_reg(&self, reg: Register) -> u16 {
+        self.mem.get_reg(reg)
+    }
+
+    /// Wraps [`Ram::get_ram_region()`]
+    pub fn get_ram_region(&self, address: u16, length: usize) -> Cow<'_, [u16]> {
+        self.mem.get_ram_region(address, length)
+    }
+
+    /// Wraps [`Ram::view()`]
+    pub fn ram_view(&self, range: Range<usize>) -> Cow<'_, [u16]> {
+        self.mem.ram_view(range)
+    }
+
+    /// Wraps [`Ram::resident_pages()`]
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::vm::{Vm, VmDescription, backing::RamBacking};
+    ///
+    /// let mut vm = Vm::new(VmDescription {
+    ///     ram_backing: RamBacking::Paged,
+    ///     ..Default::default()
+    /// })
+    /// .unwrap();
+    /// assert_eq!(vm.resident_pages(), 0);
+    /// vm.set_ram(0x4000, 1);
+    /// assert_eq!(vm.resident_pages(), 1);
+    /// // reading across pages copies them instead of allocating all of RAM
+    /// assert_eq!(vm.get_ram_region(0x3fff, 2)[1], 1);
+    /// vm.set_ram(0x4100, 2);
+    /// assert_eq!(vm.resident_pages(), 2);
+    /// ```
+    pub fn resident_pages(&self) -> usize {
+        self.mem.ram.resident_pages()
+    }
+
+    /// Wraps [`Ram::set_ram_region()`]. Writes to the ROM window take effect immediately
+    /// ## Errors
+    /// [`BError::OutOfBoundsError`] if the region goes beyond the RAM
+    pub fn set_ram_region(&mut self, address: u16, values: &[u16]) -> Result<(), BError> {
+        self.mem.ram.set_ram_region(address, values)?;
+        self.sync_rom_window();
+        Ok(())
+    }
+
+    /// Wraps [`Ram::copy_ram_region()`]. Writes to the ROM window take effect immediately
+    /// ## Errors
+    /// [`BError::OutOfBoundsError`] if either region goes beyond the RAM
+    pub fn copy_ram_region(
+        &mut self,
+        source: u16,
+        destination: u16,
+        length: usize,
+    ) -> Result<(), BError> {
+        self.mem.ram.copy_ram_region(source, destination, length)?;
+        self.sync_rom_window();
+        Ok(())
+    }
+
+    /// Returns a segment of ROM between `address` and `address + length`. If part of the specified segment is outside
+    /// the ROM, it gets cut off
+    pub fn get_rom_region(&self, address: u16, length: usize) -> &[u16] {
+        self.rom.get_rom_region(address, length)
+    }
+
+    /// Borrow the whole ROM
+    pub fn rom(&self) -> &[u16] {
+        &self.rom.program_text
+    }
+
+    /// The regions of ROM copied into RAM on start, see [`VmDescription`]
+    pub fn rom_mappings(&self) -> &[(u16, u16, u16)] {
+        &self.rom_mappings
+    }
+
+    /// Set the PC. The VM forgets the last executed instruction, see [`Vm::halt_cause()`]
+    pub fn set_pc(&mut self, new: u16) {
+        self.pc.set(new);
+        self.last_executed = None;
+    }
+
+    /// Get PC
+    pub fn get_pc(&self) -> u16 {
+        self.pc.get_val()
+    }
+
+    /// Find out how the PC left the ROM after [`Vm::cycle()`] returned a [`BError::ExecutionHaltedError`]: by running
+    /// past the last instruction or by a jump. `None` while the PC is in ROM and in [`ExecutionMode::VonNeumann`],
+    /// which never halts
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::{assembler, vm::{HaltCause, Vm}};
+    ///
+    /// let code = r"[text]
+    /// A = 0x7000
+    /// JMP
+    /// [consts 0x100]";
+    /// let mut vm = Vm::new(assembler::run(code).unwrap()).unwrap();
+    /// assert_eq!(vm.halt_cause(), None);
+    /// while vm.cycle().is_ok() {}
+    /// assert!(matches!(vm.halt_cause(), Some(HaltCause::WildJump { pc: 1, target: 0x7000, .. })));
+    ///
+    /// vm.set_pc(0x8000);
+    /// assert_eq!(vm.halt_cause(), Some(HaltCause::Placed { pc: 0x8000 }));
+    /// // also where the jump led to
+    /// vm.set_pc(0x7000);
+    /// assert_eq!(vm.halt_cause(), Some(HaltCause::Placed { pc: 0x7000 }));
+    /// ```
+    pub fn halt_cause(&self) -> Option<HaltCause> {
+        let pc = self.pc.get_val();
+        if self.execution_mode == ExecutionMode::VonNeumann || self.rom.get_address(pc).is_some() {
+            return None;
+        }
+        Some(match self.last_executed {
+            Some((last, instruction, true, next)) if next == pc => HaltCause::WildJump {
+                pc: last,
+                instruction,
+                target: pc,
+            },
+            Some((last, _, false, next)) if next == pc => HaltCause::FellOffEnd { pc: last },
+            _ => HaltCause::Placed { pc },
+        })
+    }
+}
+
+/// How a [`MultiCoreVm`] interleaves its cores
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scheduler {
+    /// Every core executes one instruction per cycle, in order of their index
+    #[default]
+    Lockstep,
+    /// One core at a time executes `quantum` instructions, one per cycle, before the next core is scheduled
+    RoundRobin { quantum: u32 },
+}
+
+/// Describes an additional core of a [`MultiCoreVm`]
+/// - `pc`: Program counter the core starts at
+/// - `rom`: The program of the core
+/// - `regs`: A-H registers in alphabetical order
+#[derive(Debug, Clone, Default)]
+pub struct CoreDescription {
+    pub pc: u16,
+    pub rom: Vec<u16>,
+    pub regs: [u16; 6],
+}
+
+/// Describes a [`MultiCoreVm`]
+/// - `vm`: The shared RAM, devices and memory layout, as well as the first core
+/// - `cores`: The cores after the first one
+/// - `scheduler`: How cores are interleaved. See [`Scheduler`]
+/// - `mailbox`: RAM address of the mailbox used for signaling between cores. See [multi-core](../docs/multicore.md)
+#[derive(Default)]
+pub struct MultiCoreDescription {
+    pub vm: VmDescription,
+    pub cores: Vec<CoreDescription>,
+    pub scheduler: Scheduler,
+    pub mailbox: Option<u16>,
+}
+
+/// State of a core that is not loaded into the shared [`Vm`]
+#[derive(Debug)]
+struct Core {
+    cpu: CpuState,
+    rom: Rom,
+    fault: Option<Fault>,
+    halted: bool,
+}
+
+/// Several BRIC cores sharing one [`Ram`] and its devices. Every core has its own registers, PC and ROM.
+/// The active core is loaded into a shared [`Vm`], the other cores are swapped in by the [`Scheduler`].
+/// Devices are ticked once per cycle, no matter how many cores executed an instruction in that cycle.
+/// ## Examples
+/// ```rust
+/// use bric_vm::vm::{CoreDescription, MultiCoreDescription, MultiCoreVm, Scheduler, VmDescription};
+///
+/// let mc_desc = MultiCoreDescription {
+///     vm: VmDescription {
+///         rom: vec![0x8001],
+///         ..Default::default()
+///     },
+///     cores: vec![CoreDescription {
+///         rom: vec![0x8002],
+///         ..Default::default()
+///     }],
+///     scheduler: Scheduler::Lockstep,
+///     mailbox: None,
+/// };
+/// let mut mc = MultiCoreVm::new(mc_desc).unwrap();
+/// mc.cycle().unwrap();
+///
+/// assert_eq!(mc.core_state(0).a, 1);
+/// assert_eq!(mc.core_state(1).a, 2);
+/// ```
+#[derive(Debug)]
+pub struct MultiCoreVm {
+    vm: Vm,
+    cores: Vec<Core>,
+    active: usize,
+    scheduler: Scheduler,
+    slice: u32,
+    mailbox: Option<u16>,
+}
+
+impl MultiCoreVm {
+    /// Create a new multi-core VM from a [`MultiCoreDescription`]
+    /// ## Errors
+    /// - The errors of [`Vm::new()`]
+    /// - A [`BError::OutOfBoundsError`] if the mailbox does not fit into RAM
+    pub fn new(description: MultiCoreDescription) -> Result<Self, BError> {
+        let core_count = description.cores.len() + 1;
+        if let Some(addr) = description.mailbox
+            && addr as usize + 2 + 2 * core_count > RAM_LEN
+        {
+            return Err(BError::OutOfBoundsError(addr, 2 + 2 * core_count, RAM_LEN));
+        }
+
+        let vm = Vm::new(description.vm)?;
+        // the first core is loaded into the VM, its slot is only filled when it is swapped out
+        let mut cores = vec![Core {
+            cpu: vm.cpu_state(),
+            rom: Rom::new(vec![]),
+            fault: None,
+            halted: false,
+        }];
+        for core in description.cores {
+            let regs = core.regs;
+            cores.push(Core {
+                cpu: CpuState {
+                    pc: core.pc,
+                    a: regs[0],
+                    d: regs[1],
+                    e: regs[2],
+                    f: regs[3],
+                    g: regs[4],
+                    h: regs[5],
+                },
+                rom: Rom::new(core.rom),
+                fault: None,
+                halted: false,
+            });
+        }
+
+        let mut mc = Self {
+            vm,
+            cores,
+            active: 0,
+            scheduler: description.scheduler,
+            slice: 0,
+            mailbox: description.mailbox,
+        };
+        if let Some(addr) = mc.mailbox {
+            mc.vm.mem.ram.ram[addr as usize + 1] = core_count as u16;
+        }
+        Ok(mc)
+    }
+
+    /// Cycles the machine according to the [`Scheduler`] and then ticks all registered devices once.
+    /// Cores that run out of instructions are halted, the others keep running.
+    /// ## Errors
+    /// - A [`BError::ExecutionHaltedError`] if all cores are halted
+    /// - Any other error of [`Vm::cycle()`]. The core that caused it stays the active core
+    pub fn cycle(&mut self) -> Result<(), BError> {
+        if self.cores.iter().all(|c| c.halted) {
+            return Err(BError::ExecutionHaltedError {
+                value: self.vm.get_pc(),
+            });
+        }
+        match self.scheduler {
+            Scheduler::Lockstep => {
+                for core in 0..self.cores.len() {
+                    if !self.cores[core].halted {
+                        self.execute_core(core)?;
+                    }
+                }
+            }
+            Scheduler::RoundRobin { quantum } => loop {
+                if self.slice >= quantum.max(1) || self.cores[self.active].halted {
+                    let Some(next) = self.next_core() else {
+                        break;
+                    };
+                    self.slice = 0;
+                    self.execute_core(next)?;
+                } else {
+                    self.execute_core(self.active)?;
+                }
+                if !self.cores[self.active].halted {
+                    self.slice += 1;
+                    break;
+                }
+            },
+        }
+        let cycles = 1 + core::mem::take(&mut self.vm.stall);
+        self.vm.cycles += cycles;
+        self.vm.sync_perf_counters();
+        self.vm.tick_devices(cycles);
+        Ok(())
+    }
+
+    /// The next core in round robin order that is not halted. May be the active core
+    fn next_core(&self) -> Option<usize> {
+        let count = self.cores.len();
+        (1..=count)
+            .map(|offset| (self.active + offset) % count)
+            .find(|&core| !self.cores[core].halted)
+    }
+
+    /// Load `core` into the shared VM and execute one instruction on it
+    fn execute_core(&mut self, core: usize) -> Result<(), BError> {
+        self.switch_to(core);
+        if let Some(addr) = self.mailbox {
+            self.vm.mem.ram.ram[addr as usize] = core as u16;
+        }
+        match self.vm.execute() {
+            Err(BError::ExecutionHaltedError { value: _ }) => {
+                self.cores[core].halted = true;
+                Ok(())
+            }
+            r => r,
+        }
+    }
+
+    /// Park the active core and load `core` into the shared VM
+    fn switch_to(&mut self, core: usize) {
+        if core == self.active {
+            return;
+        }
+        let parked = &mut self.cores[self.active];
+        parked.cpu = self.vm.cpu_state();
+        parked.fault = self.vm.fault;
+        core::mem::swap(&mut parked.rom, &mut self.vm.rom);
+
+        let loaded = &mut self.cores[core];
+        self.vm.set_cpu_state(loaded.cpu);
+        self.vm.fault = loaded.fault;
+        core::mem::swap(&mut loaded.rom, &mut self.vm.rom);
+
+        self.active = core;
+        self.vm.refresh_rom_window();
+    }
+
+    /// Get the amount of cores
+    pub fn core_count(&self) -> usize {
+        self.cores.len()
+    }
+
+    /// Get the index of the core that is loaded into the shared VM
+    pub fn active_core(&self) -> usize {
+        self.active
+    }
+
+    /// Whether `core` ran out of instructions
+    /// ## Panics
+    /// If `core` does not exist
+    pub fn is_halted(&self, core: usize) -> bool {
+        self.cores[core].halted
+    }
+
+    /// Get the registers and the program counter of `core`
+    /// ## Panics
+    /// If `core` does not exist
+    pub fn core_state(&self, core: usize) -> CpuState {
+        if core == self.active {
+            self.vm.cpu_state()
+        } else {
+            self.cores[core].cpu
+        }
+    }
+
+    /// Set the registers and the program counter of `core`. This also resumes a halted core
+    /// ## Panics
+    /// If `core` does not exist
+    pub fn set_core_state(&mut self, core: usize, state: CpuState) {
+        if core == self.active {
+            self.vm.set_cpu_state(state);
+        } else {
+            self.cores[core].cpu = state;
+        }
+        self.cores[core].halted = false;
+    }
+
+    /// Get the shared VM. It holds the state of the active core
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+
+    /// Get the shared VM mutably, e.g. to register devices or inspect memory. It holds the state of the active core
+    pub fn vm_mut(&mut self) -> &mut Vm {
+        &mut self.vm
+    }
+
+    /// Get the amount of cycles executed since the machine was created
+    pub fn get_cycles(&self) -> u64 {
+        self.vm.cycles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "std")]
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[test]
+    fn test_ram() {
+        let mut ram = Ram::new(
+            empty_ram(),
+            RegionMap::try_from(vec![
+                Region::new(0x100, 0x1ff, AccessLevels::Read),
+                Region::new(0x200, 0x2ff, AccessLevels::None),
+            ])
+            .unwrap(),
+        );
+
+        // Test access levels
+        ram.write_ram(0, 1);
+        assert_eq!(ram.read_ram(0), 1);
+        ram.set_ram(0x101, 1234);
+        assert_eq!(ram.read_ram(0x101), 1234);
+        ram.set_ram(0x100, 5432);
+        ram.write_ram(0x100, 1);
+        assert_eq!(ram.read_ram(0x100), 5432);
+        ram.set_ram(0x200, 0xabc);
+        assert_eq!(ram.read_ram(0x200), 0);
+        ram.write_ram(0x200, 0xdef);
+        assert_eq!(ram.read_ram(0x200), 0);
+        assert_eq!(*ram.get_ram_region(0x200, 1), [0xabc]);
+
+        // Test set_ram
+        ram.set_ram(0, 1);
+        assert_eq!(ram.read_ram(0), 1);
+        ram.set_ram(0xff, 0xbeef);
+        assert_eq!(ram.read_ram(0xff), 0xbeef);
+
+        // Test set_ram_region
+        let arr = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+        ram.set_ram_region(0, &arr).unwrap();
+        assert_eq!(*ram.get_ram_region(0, arr.len()), arr);
+        assert!(ram.set_ram_region(0xfffc, &arr).is_err());
+
+        // Test bulk copies and views
+        ram.copy_ram_region(0, 2, 8).unwrap();
+        assert_eq!(*ram.view(0..10), [1, 2, 1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(ram.copy_ram_region(0, 0xfffc, 8).is_err());
+        ram.view_mut(0xfffe..0x10002).copy_from_slice(&[7, 9]);
+        assert_eq!(*ram.view(0xfffe..0x10002), [7, 9]);
+        assert!(ram.view(0x10001..0x10005).is_empty());
+
+        #[cfg(feature = "std")]
+        {
+            // Test callbacks
+            let out_var = Arc::new(Mutex::new(0));
+            let out = out_var.clone();
+
+            let callback = Box::new(move |write: WriteEvent| {
+                let mut out_p = out.lock().unwrap();
+                *out_p = write.value;
+            });
+
+            ram.register_callback(0x99, callback);
+            ram.write_ram(0x99, 0x1234);
+            assert_eq!(*out_var.lock().unwrap(), 0x1234);
+
+            // Test read callbacks
+            let reads = Arc::new(Mutex::new(0));
+            let r = reads.clone();
+            ram.register_read_callback(
+                0x98,
+                Box::new(move |stored: u16| {
+                    *r.lock().unwrap() += 1;
+                    stored + 1
+                }),
+            );
+            ram.set_ram(0x98, 0x10);
+            assert_eq!(ram.read_ram(0x98), 0x11);
+            assert_eq!(ram.peek_ram(0x98), 0x10);
+            assert_eq!(*reads.lock().unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn test_region_priority() {
+        let mut ram = Ram::new(
+            empty_ram(),
+            RegionMap::try_from(vec![
+                Region::new(0x100, 0x1ff, AccessLevels::Read),
+                Region::with_priority(0x110, 0x11f, AccessLevels::ReadWrite, 1),
+            ])
+            .unwrap(),
+        );
+
+        ram.write_ram(0x100, 1);
+        ram.write_ram(0x110, 2);
+        ram.write_ram(0x120, 3);
+        assert_eq!(ram.get_ram_region(0x100, 0x21)[0x00], 0);
+        assert_eq!(ram.get_ram_region(0x100, 0x21)[0x10], 2);
+        assert_eq!(ram.get_ram_region(0x100, 0x21)[0x20], 0);
+
+        // regions of the same priority may still not overlap
+        assert!(
+            RegionMap::try_from(vec![
+                Region::new(0x100, 0x1ff, AccessLevels::Read),
+                Region::new(0x110, 0x11f, AccessLevels::ReadWrite),
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut ram = Ram::new(
+            empty_ram(),
+            RegionMap::try_from(vec![Region::new(0x100, 0x1ff, AccessLevels::None)]).unwrap(),
+        );
+        ram.set_ram(0x100, 0x1234);
+        assert_eq!(ram.read_ram(0x100), 0);
+
+        ram.set_open_bus(0x100, 0x10, OpenBus::Ones).unwrap();
+        ram.set_open_bus(0x110, 0x10, OpenBus::LastValue).unwrap();
+        ram.set_open_bus(0x120, 0x10, OpenBus::Callback(Box::new(|addr| addr)))
+            .unwrap();
+        assert_eq!(ram.read_ram(0x100), 0xffff);
+        ram.write_ram(0x00, 0xabc);
+        assert_eq!(ram.read_ram(0x110), 0xabc);
+        assert_eq!(ram.read_ram(0x121), 0x121);
+        assert_eq!(ram.read_ram(0x130), 0);
+        assert!(ram.set_open_bus(0x10f, 2, OpenBus::Zero).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_write_only() {
+        let mut ram = Ram::new(
+            empty_ram(),
+            RegionMap::try_from(vec![Region::new(0x100, 0x10f, AccessLevels::WriteOnly)]).unwrap(),
+        );
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let w = written.clone();
+        ram.register_callback(
+            0x100,
+            Box::new(move |write: WriteEvent| w.lock().unwrap().push(write.value)),
+        );
+        ram.set_open_bus(0x100, 0x10, OpenBus::Ones).unwrap();
+
+        ram.write_ram(0x100, 0x42);
+        assert_eq!(*written.lock().unwrap(), vec![0x42]);
+        assert_eq!(*ram.get_ram_region(0x100, 1), [0x42]);
+        assert!(ram.take_fault().is_none());
+        // the written value can't be read back
+        assert_eq!(ram.read_ram(0x100), 0xffff);
+        assert!(matches!(
+            ram.take_fault(),
+            Some((0x100, 0xffff, FaultKind::ForbiddenRead, false))
+        ));
+    }
+
+    #[test]
+    fn test_mirrors() {
+        let mut ram = Ram::new(empty_ram(), RegionMap::try_from(vec![]).unwrap());
+        ram.add_mirror(0x100, 0x10, 0x10).unwrap();
+
+        ram.write_ram(0x101, 0xabc);
+        assert_eq!(ram.read_ram(0x11), 0xabc);
+        ram.set_ram(0x12, 0xdef);
+        assert_eq!(ram.read_ram(0x102), 0xdef);
+        // the underlying memory of the mirror is untouched
+        assert_eq!(*ram.get_ram_region(0x101, 1), [0]);
+
+        // overlapping mirrors and mirrors of mirrors are rejected
+        assert!(ram.add_mirror(0x108, 0x10, 0x200).is_err());
+        assert!(ram.add_mirror(0x200, 0x10, 0x100).is_err());
+        assert!(ram.add_mirror(0x200, 0x10, 0x200).is_err());
+        // a mirror over the target of another mirror would chain as well
+        let mut ram = Ram::new(empty_ram(), RegionMap::try_from(vec![]).unwrap());
+        ram.add_mirror(0x100, 0x10, 0x200).unwrap();
+        assert!(ram.add_mirror(0x200, 0x10, 0x300).is_err());
+        assert!(ram.add_mirror(0x1f8, 0x10, 0x300).is_err());
+        ram.add_mirror(0x1f0, 0x10, 0x300).unwrap();
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_region_callbacks() {
+        let mut ram = Ram::new(empty_ram(), RegionMap::try_from(vec![]).unwrap());
+
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let w = written.clone();
+        ram.register_region_callback(
+            0x400,
+            0x100,
+            Box::new(move |offset: u16, write: WriteEvent| {
+                w.lock().unwrap().push((offset, write.value))
+            }),
+        )
+        .unwrap();
+        ram.register_region_read_callback(0x400, 0x100, Box::new(|offset: u16, _| offset))
+            .unwrap();
+
+        ram.write_ram(0x400, 1);
+        ram.write_ram(0x4ff, 2);
+        ram.write_ram(0x500, 3);
+        assert_eq!(*written.lock().unwrap(), vec![(0, 1), (0xff, 2)]);
+        assert_eq!(ram.read_ram(0x410), 0x10);
+        assert_eq!(ram.read_ram(0x500), 3);
+
+        // overlapping and out of bounds ranges are rejected
+        assert!(matches!(
+            ram.register_region_callback(0x4ff, 2, Box::new(|_, _| {})),
+            Err(BError::MapError(_))
+        ));
+        assert!(matches!(
+            ram.register_region_callback(0xffff, 2, Box::new(|_, _| {})),
+            Err(BError::OutOfBoundsError(..))
+        ));
+    }
+
+    #[test]
+    fn test_mem() {
+        let mut mem = MemoryUnit::new(0, 0, 0, 0, 0, 0, empty_ram(), vec![]).unwrap();
+
+        // test set_reg
+        mem.set_reg(Register::A, 0x1234);
+        assert_eq!(mem.get_reg(Register::A), 0x1234);
+
+        // test get_reg on ram
+        assert_eq!(mem.get_reg(Register::None), 0);
+        mem.set_ram(0x1234, 0xabc);
+        assert_eq!(mem.get_reg(Register::MA), 0xabc);
+
+        // test get_reg on ram
+        mem.set_reg(Register::MA, 0xdef);
+        assert_eq!(mem.get_reg(Register::MA), 0xdef);
+        assert_eq!(*mem.get_ram_region(0x1234, 1), [0xdef]);
+
+        // test get_regs
+        mem.set_reg(Register::D, 0x1234);
+        assert_eq!(mem.get_regs(), (0x1234, 0x1234, 0, 0, 0, 0));
+
+        // don't test other methods, they are just wrappers for RAM
+    }
+
+    #[test]
+    fn test_rom() {
+        let rvs = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let rom = Rom::from_iter(rvs.clone());
+        assert_eq!(rom.get_address(0), Some(1));
+
+        assert_eq!(rom.get_rom_region(2, 0xff), &rvs[2..]);
+    }
+
+    #[test]
+    fn test_pc() {
+        let mut pc = Pc::new(0);
+        pc.inc();
+        pc.inc();
+        assert_eq!(pc.get_val(), 2);
+
+        pc.set(0xffff);
+        pc.inc();
+        assert_eq!(pc.get_val(), 0);
+    }
+
+    #[test]
+    fn test_instruction() {
+        match Instruction::from_u16(0b1000000000000010) {
+            Instruction::Alu(_) => {
+                panic!("incorrect data instruction decoding")
+            }
+            Instruction::Data(val) => {
+                assert_eq!(val, 2)
+            }
+        }
+
+        match Instruction::from_u16(0b0000000000000010) {
+            Instruction::Alu(inst) => {
+                // no need to test all ALU instruction fields. we trust `bitfield` is correctly implemented
+                assert_eq!(inst.get_eq(), true);
+            }
+            Instruction::Data(_) => {
+                panic!("incorrect alu instruction decoding")
+            }
+        }
+    }
+
+    #[test]
+    fn test_vm_description() {
+        // this test is very limited and does not correctly explore all cases. It will do for now.
+
+        // set up some values
+        let vm_desc = VmDescription {
+            pc: 0x123,
+            rom: vec![0x1234, 0x5678],
+            mem: Box::new(core::array::from_fn(|i| i as u16)),
+            rom_mappings: vec![(0x123, 0x456, 0x789)],
+            regs: [1, 2, 3, 4, 5, 6],
+            ..Default::default()
+        };
+
+        // serialize and deserialize
+        let serialized = vm_desc.serialize().unwrap();
+        let deserialized = VmDescription::deserialize(&serialized).unwrap();
+
+        // check values
+        assert_eq!(deserialized.pc, 0x123);
+        assert_eq!(deserialized.rom, vec![0x1234, 0x5678]);
+        assert_eq!(
+            deserialized.mem.as_slice(),
+            &core::array::from_fn::<u16, RAM_LEN, _>(|i| i as u16)
+        );
+        assert_eq!(deserialized.rom_mappings, &[(0x123, 0x456, 0x789)]);
+        assert_eq!(&deserialized.regs, &[1u16, 2, 3, 4, 5, 6]);
+
+        // compressed files are read
+        #[cfg(feature = "compression")]
+        {
+            let compressed = vm_desc.serialize_compressed().unwrap();
+            assert!(compressed.len() < serialized.len());
+            let deserialized = VmDescription::deserialize(&compressed).unwrap();
+            assert_eq!(deserialized.rom, vec![0x1234, 0x5678]);
+            assert_eq!(deserialized.mem, vm_desc.mem);
+            // segments can't inflate to more than all of RAM
+            let bomb = deflate(&vec![0; SEGMENT_LIMIT + 1]);
+            assert!(inflate(&bomb, SEGMENT_LIMIT).is_err());
+        }
+
+        assert_eq!(deserialized.metadata(), None);
+
+        // metadata round trips
+        let metadata = Metadata {
+            name: Some("test".to_string()),
+            build_timestamp: Some(1234),
+            entries: vec![("author".to_string(), "me".to_string())],
+            ..Default::default()
+        };
+        let devices = vec![
+            DeviceConfig::new("uart", "console", 0x6000),
+            DeviceConfig {
+                config: vec![1, 2, 3],
+                ..DeviceConfig::new("sensor", "thermometer", 0x7000)
+            },
+        ];
+        let vm_desc = VmDescription {
+            metadata: Some(metadata.clone()),
+            source: Some("[text]\nA = 1\n".to_string()),
+            device_configs: devices.clone(),
+            ..Default::default()
+        };
+        let with_metadata = VmDescription::deserialize(&vm_desc.serialize().unwrap()).unwrap();
+        assert_eq!(with_metadata.metadata(), Some(&metadata));
+        assert_eq!(with_metadata.source.as_deref(), Some("[text]\nA = 1\n"));
+        assert_eq!(with_metadata.device_configs, devices);
+
+        // unknown segments are skipped
+        let mut segments = read_segments(&serialized[5..]).unwrap();
+        segments.push((*b"XYZ\x00", b"abc"));
+        let mut extended = b"BVM\x04\x00".to_vec();
+        extended.append(&mut write_segments(&segments).unwrap());
+        let deserialized = VmDescription::deserialize(&extended).unwrap();
+        assert_eq!(deserialized.rom, vec![0x1234, 0x5678]);
+
+        // and so is a source that can't be decoded
+        let mut segments = read_segments(&serialized[5..]).unwrap();
+        segments.push((SEG_SOURCE, b"\x07abc"));
+        let mut extended = b"BVM\x04\x00".to_vec();
+        extended.append(&mut write_segments(&segments).unwrap());
+        let deserialized = VmDescription::deserialize(&extended).unwrap();
+        assert_eq!(deserialized.rom, vec![0x1234, 0x5678]);
+        assert_eq!(deserialized.source, None);
+
+        // files in the fixed layout from before the segments are still read
+        let mut legacy = b"BVM\x00\x01\x23\x00".to_vec();
+        legacy.append(&mut vec![0; 13]);
+        legacy.append(&mut b"RMP\x00\x00\x00\x00\x00".to_vec());
+        legacy.append(&mut b"ROM\x00\x00\x01\x00\x12\x34\x00RAM\x00".to_vec());
+        legacy.append(&mut vec![0; 2 * RAM_LEN]);
+        let deserialized = VmDescription::deserialize(&legacy).unwrap();
+        assert_eq!((deserialized.pc, deserialized.rom), (0x123, vec![0x1234]));
+
+        // unknown versions are not
+        let mut serialized = serialized;
+        assert_eq!(&serialized[..5], b"BVM\x04\x00");
+        serialized[3] = BVM_VERSION + 1;
+        assert!(matches!(
+            VmDescription::deserialize(&serialized),
+            Err(BError::DeserializationError(_))
+        ));
+    }
+
+    #[test]
+    fn test_raw_images() {
+        let vm_desc = VmDescription::from_rom_words(&[0x8001, 0x8002]);
+        assert_eq!(vm_desc.rom, vec![0x8001, 0x8002]);
+
+        let vm_desc = VmDescription::from_rom_image(&[0x80, 0x01], Endianness::Big).unwrap();
+        assert_eq!(vm_desc.rom, vec![0x8001]);
+        let mut vm_desc = VmDescription::from_rom_image(&[0x01, 0x80], Endianness::Little).unwrap();
+        assert_eq!(vm_desc.rom, vec![0x8001]);
+        assert!(VmDescription::from_rom_image(&[0x80], Endianness::Big).is_err());
+
+        vm_desc
+            .load_ram_image(0x10, &[0x34, 0x12], Endianness::Little)
+            .unwrap();
+        assert_eq!(vm_desc.mem[0x10], 0x1234);
+        assert!(
+            vm_desc
+                .load_ram_image(0xffff, &[0, 1, 2, 3], Endianness::Big)
+                .is_err()
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_vm() {
+        // this test relies on the assembler being correctly implemented. We do not test all
+        // functions of the VM but test wether some of them work correctly together.
+        let code = r"[macros]
+[text]
+A = 0x1234
+D = add, 0, A
+A = 0
+*A = add, 0, D
+A = 0x512
+JMP
+[consts 0x100]";
+
+        let vm_desc = crate::assembler::run(code).unwrap();
+
+        let mut vm = Vm::new(vm_desc).unwrap();
+        for _ in 0..6 {
+            vm.cycle().unwrap();
+        }
+        assert_eq!(vm.get_reg(Register::A), 0x512);
+        assert_eq!(vm.get_reg(Register::D), 0x1234);
+        assert_eq!(vm.get_pc(), 0x512);
+        assert_eq!(*vm.get_ram_region(0x0, 2), [0x1234, 0x0]);
+        assert!(matches!(
+            vm.cycle(),
+            Err(BError::ExecutionHaltedError { .. })
+        ));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_expressions() {
+        // a full 16 bit value from its bytes, and packed tables in consts
+        let code = r"[macros]
+define VALUE 0xbeef
+define PACKED hi(VALUE) * 0x100 + lo(VALUE)
+[text]
+A = hi(PACKED)
+D = add, 0, A
+A = 8
+E = add, 0, A
+label shift:
+D = lsl, D
+A = shift
+E = dec, E; JGT
+A = lo(VALUE)
+D = or, D, A
+A = table + 0x100
+*A = add, 0, D
+[consts 0x100]
+label table:
+M = lo(table)
+M = hi(0x1234) + 1
+M = 0";
+        let mut vm = Vm::new(crate::assembler::run(code).unwrap()).unwrap();
+        assert_eq!(*vm.get_ram_region(0x100, 2), [0x00, 0x13]);
+        for _ in 0..32 {
+            vm.cycle().unwrap();
+        }
+        assert_eq!(*vm.get_ram_region(0x200, 1), [0xbeef]);
+        assert!(crate::assembler::run("[text]\nA = lo(nothing)\n[consts 0x100]").is_err());
+        assert!(crate::assembler::run("[text]\nA = 0x7000 * 2\n[consts 0x100]").is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_checksum() {
+        use crate::assembler::const_processor::{ChecksumKind, checksum};
+
+        let code = r"[text]
+label start:
+A = 1
+label end:
+A = 2
+[consts 0x100]
+M = 7
+.checksum
+.checksum start end+1 crc16";
+        let vm = Vm::new(crate::assembler::run(code).unwrap()).unwrap();
+        let sum = checksum(&vm.rom()[..2], ChecksumKind::Sum);
+        let crc = checksum(&vm.rom()[..2], ChecksumKind::Crc16);
+        assert_eq!(*vm.get_ram_region(0x100, 3), [7, sum, crc]);
+        assert_eq!(sum, 0x8001u16.wrapping_add(0x8002));
+        assert!(crate::assembler::run("[text]\nA = 1\n[consts 0x100]\n.checksum 0 0x20").is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_reserve() {
+        let code = r"[macros]
+define LEN_ 2 * 2
+[text]
+D = inc, D
+A = buf
+*A = add, 0, D
+A = tail
+*A = add, 0, D
+[consts 0x100]
+M = 7
+label buf:
+.res LEN_
+label after:
+M = 8
+label tail:
+.res 0x10";
+        let vm_desc = crate::assembler::run(code).unwrap();
+        let consts = vm_desc.rom_mappings[0].0;
+        assert_eq!(
+            vm_desc.rom_mappings,
+            vec![(consts, 1, 0x100), (consts + 1, 1, 0x105)]
+        );
+        assert_eq!(vm_desc.rom.len(), consts as usize + 2);
+        let mut vm = Vm::new(vm_desc).unwrap();
+        for _ in 0..5 {
+            vm.cycle().unwrap();
+        }
+        assert_eq!(*vm.get_ram_region(0x100, 7), [7, 1, 0, 0, 0, 8, 1]);
+        assert!(crate::assembler::run("[text]\nA = 1\n[consts 0xfff0]\n.res 0x11").is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_std_prelude() {
+        let code = r"[macros]
+include <std>
+[text]
+std.stack.init(0x300)
+std.memset(0x200, 5, 4)
+std.memcpy(0x200, 0x210, 3)
+std.memcpy(big, 0x220, 2)
+std.cmpu(0x220, 0x221)
+std.push()
+std.cmps(0x220, 0x221)
+std.push()
+std.pop()
+A = 0x230
+*A = add, 0, D
+std.pop()
+A = 0x231
+*A = add, 0, D
+std.uart.init(0)
+std.uart.putc(0x3e)
+std.uart.puts(hi)
+[consts 0x100]
+label big:
+M = 0x8000
+M = 1
+label hi:
+M = 0x68
+M = 0x69
+M = 0";
+        let (mut vm, uart) =
+            crate::mmio::uart::connect_uart(crate::assembler::run(code).unwrap()).unwrap();
+        while vm.cycle().is_ok() {}
+        assert_eq!(*vm.get_ram_region(0x200, 5), [5, 5, 5, 5, 0]);
+        assert_eq!(*vm.get_ram_region(0x210, 4), [5, 5, 5, 0]);
+        assert_eq!(*vm.get_ram_region(0x220, 2), [0x8000, 1]);
+        // 0x8000 is above 1 unsigned and below it signed
+        let [signed, unsigned] = *vm.get_ram_region(0x230, 2) else {
+            unreachable!()
+        };
+        assert!((signed as i16) < 0 && (unsigned as i16) > 0);
+        assert_eq!(*vm.get_ram_region(0, 1), [0x300]);
+        assert_eq!(uart.output.try_iter().collect::<Vec<u8>>(), b">hi");
+        assert!(
+            crate::assembler::run("[macros]\ninclude <nothing>\n[text]\n[consts 0x100]").is_err()
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_uart_timing() {
+        use crate::mmio::uart::{DA, U_IFL, connect_uart};
+        // 2 cycles per bit, 20 cycles per byte
+        let code = r"[text]
+A = 2
+D = add, 0, A
+A = 0x6000
+*A = add, 0, D
+A = 0x41
+D = add, 0, A
+A = 0x6001
+*A = add, 0, D
+A = 1
+D = add, 0, A
+A = 0x6004
+*A = add, 0, D
+label end:
+A = end
+JMP
+[consts 0x100]";
+        let (mut vm, uart) = connect_uart(crate::assembler::run(code).unwrap()).unwrap();
+        for _ in 0..12 {
+            vm.cycle().unwrap();
+        }
+        uart.input.send(b'x').unwrap();
+        for _ in 0..18 {
+            vm.cycle().unwrap();
+        }
+        assert!(uart.output.try_recv().is_err());
+        vm.cycle().unwrap();
+        assert_eq!(uart.output.try_recv(), Ok(b'A'));
+        assert_eq!(vm.get_ram_region(U_IFL, 1)[0] & DA, 0);
+        vm.cycle().unwrap();
+        assert_eq!(vm.get_ram_region(U_IFL, 1)[0] & DA, DA);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_rng() {
+        use crate::mmio::rng::Rng;
+        let code = r"[text]
+A = 0x6200
+D = add, 0, *A
+E = add, 0, *A
+[consts 0x100]";
+        let mut vm_desc = crate::assembler::run(code).unwrap();
+        vm_desc.rng_seed = Some(42);
+        let mut vm = Vm::new(vm_desc).unwrap();
+        let state = vm.snapshot();
+
+        let rng = Rng::new(42);
+        let expected = [rng.next_u16(), rng.next_u16()];
+        for _ in 0..3 {
+            vm.cycle().unwrap();
+        }
+        assert_eq!([vm.get_reg(Register::D), vm.get_reg(Register::E)], expected);
+
+        vm.restore(&state).unwrap();
+        vm.set_pc(0);
+        for _ in 0..3 {
+            vm.cycle().unwrap();
+        }
+        assert_eq!([vm.get_reg(Register::D), vm.get_reg(Register::E)], expected);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_panel() {
+        use crate::mmio::panel::{PANEL_LAMPS, PANEL_SWITCHES, Panel, connect_panel};
+        let code = r"[text]
+A = 5
+D = add, 0, A
+A = 0x7fff
+*A = add, 0, D
+A = 0x7ffe
+E = add, 0, *A
+*A = add, 0, D
+[consts 0x100]";
+        let mut vm_desc = crate::assembler::run(code).unwrap();
+        vm_desc.forbidden_write_policy = ForbiddenWritePolicy::Error;
+        let (mut vm, panel) = connect_panel(vm_desc).unwrap();
+        panel.lock().unwrap().set_switch(3, true);
+        panel.lock().unwrap().toggle_switch(0);
+        for _ in 0..6 {
+            vm.cycle().unwrap();
+        }
+        assert_eq!(panel.lock().unwrap().get_lamps(), 5);
+        assert!(panel.lock().unwrap().lamp(2));
+        assert_eq!(vm.get_reg(Register::E), 0b1001);
+        // the switches are read only
+        assert!(matches!(
+            vm.cycle(),
+            Err(BError::AccessViolation {
+                address: PANEL_SWITCHES,
+                ..
+            })
+        ));
+        assert_eq!(*vm.get_ram_region(PANEL_LAMPS, 1), [5]);
+
+        let state = panel.lock().unwrap().save();
+        let mut restored = Panel::default();
+        restored.load(&state).unwrap();
+        assert_eq!(restored.get_lamps(), 5);
+        assert_eq!(restored.get_switches(), 0b1001);
+        assert!(restored.load(&state[1..]).is_err());
     }
 
-    /// Returns a segment of ROM between `address` and `address + length`. If part of the specified segment is outside
-    /// the ROM, it gets cut off
-    pub fn get_rom_region(&self, address: u16, length: usize) -> &[u16] {
-        self.rom.get_rom_region(address, length)
-    }
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_semihost_sandbox() {
+        use crate::mmio::semihost::{SH_ERROR, connect_semihosting};
+        // open the path at 0x100 for writing
+        let code = r"[text]
+A = 0x100
+D = add, 0, A
+A = 0x6301
+*A = add, 0, D
+A = 1
+D = add, 0, A
+A = 0x6302
+*A = add, 0, D
+A = 1
+D = add, 0, A
+A = 0x6300
+*A = add, 0, D
+A = 0x6304
+D = add, 0, *A
+[consts 0x1000]";
+        let base = std::env::temp_dir().join(format!("bric_vm_sandbox_{}", std::process::id()));
+        let root = base.join("root");
+        let outside = base.join("outside");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        let open = |path: &str| {
+            let mut vm_desc = crate::assembler::run(code).unwrap();
+            for (i, c) in path.bytes().enumerate() {
+                vm_desc.mem[0x100 + i] = c as u16;
+            }
+            let (mut vm, _) = connect_semihosting(vm_desc, &root).unwrap();
+            for _ in 0..14 {
+                vm.cycle().unwrap();
+            }
+            vm.get_reg(Register::D)
+        };
 
-    /// Set the PC
-    pub fn set_pc(&mut self, new: u16) {
-        self.pc.set(new);
+        assert_eq!(open("inside.txt"), 0);
+        assert_eq!(open("../outside/dotdot.txt"), SH_ERROR);
+        assert_eq!(
+            open(outside.join("absolute.txt").to_str().unwrap()),
+            SH_ERROR
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::symlink;
+            std::fs::write(outside.join("existing.txt"), "").unwrap();
+            symlink(&outside, root.join("dir")).unwrap();
+            symlink(outside.join("existing.txt"), root.join("link")).unwrap();
+            symlink(outside.join("dangling.txt"), root.join("dangling")).unwrap();
+            assert_eq!(open("dir/through_dir.txt"), SH_ERROR);
+            assert_eq!(open("link"), SH_ERROR);
+            assert_eq!(open("dangling"), SH_ERROR);
+        }
+        let mut escaped: Vec<_> = std::fs::read_dir(&outside)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        escaped.retain(|name| name != "existing.txt");
+        std::fs::remove_dir_all(&base).unwrap();
+        assert!(escaped.is_empty(), "{:?}", escaped);
     }
 
-    /// Get PC
-    pub fn get_pc(&self) -> u16 {
-        self.pc.get_val()
-    }
-}
+    #[test]
+    fn test_deterministic() {
+        struct HostDevice;
+        impl Device for HostDevice {
+            fn tick(&mut self, _cycles: u64, _ram: &mut Ram) {}
+            fn deterministic(&self) -> bool {
+                false
+            }
+        }
 
-#[cfg(test)]
-mod tests {
-    use std::sync::{Arc, Mutex};
+        let vm_desc = VmDescription {
+            rng_seed: Some(1),
+            deterministic: true,
+            ..Default::default()
+        };
+        let mut vm = Vm::new(vm_desc).unwrap();
+        assert!(vm.is_deterministic());
+        assert!(vm.register_device(Box::new(HostDevice)).is_err());
 
-    use super::*;
+        let vm_desc = VmDescription {
+            devices: vec![Box::new(HostDevice)],
+            deterministic: true,
+            ..Default::default()
+        };
+        assert!(matches!(Vm::new(vm_desc), Err(BError::MapError(_))));
+    }
 
     #[test]
-    fn test_ram() {
-        let mut ram = Ram::new(
-            [0; 65536],
-            RegionMap::try_from(vec![
-                Region::new(0x100, 0x1ff, AccessLevels::Read),
-                Region::new(0x200, 0x2ff, AccessLevels::None),
-            ])
-            .unwrap(),
-        );
+    fn test_execute_sandboxed() {
+        use crate::mmio::rng::Rng;
+
+        let rng = Rng::new(7);
+        for len in 0..64 {
+            let rom: Vec<u8> = (0..len).map(|_| rng.next_u16() as u8).collect();
+            let result = execute_sandboxed(&rom, 1000);
+            assert!(result.cycles <= 1000);
+        }
+        // jumps far past the end of ROM
+        let rom = [0xff, 0xff, 0x00, 0x07];
+        assert_eq!(execute_sandboxed(&rom, 10).outcome, SandboxOutcome::Halted);
+    }
 
-        // Test access levels
-        ram.write_ram(0, 1);
-        assert_eq!(ram.read_ram(0), 1);
-        ram.set_ram(0x101, 1234);
-        assert_eq!(ram.read_ram(0x101), 1234);
-        ram.set_ram(0x100, 5432);
-        ram.write_ram(0x100, 1);
-        assert_eq!(ram.read_ram(0x100), 5432);
-        ram.set_ram(0x200, 0xabc);
-        assert_eq!(ram.read_ram(0x200), 0);
-        ram.write_ram(0x200, 0xdef);
-        assert_eq!(ram.read_ram(0x200), 0);
-        assert_eq!(ram.get_ram_region(0x200, 1), &[0xabc]);
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_rom_block_bounds() {
+        let code = r"[text]
+A = 0x11
+*A = add, 0, A
+A = 0x0f
+*A = add, 0, A
+[consts 0x100]";
+        let mut vm_desc = crate::assembler::run(code).unwrap();
+        vm_desc.rom_blocks.push((0x10, 1));
+        vm_desc.forbidden_write_policy = ForbiddenWritePolicy::Error;
 
-        // Test set_ram
-        ram.set_ram(0, 1);
-        assert_eq!(ram.read_ram(0), 1);
-        ram.set_ram(0xff, 0xbeef);
-        assert_eq!(ram.read_ram(0xff), 0xbeef);
+        let mut vm = Vm::new(vm_desc).unwrap();
+        for _ in 0..4 {
+            vm.cycle().unwrap();
+        }
+        assert_eq!(*vm.get_ram_region(0x0f, 3), [0x0f, 0, 0x11]);
+    }
 
-        // Test set_ram_region
-        let arr = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_forbidden_write_policy() {
+        let code = r"[text]
+A = 0x10
+*A = add, 0, A
+[consts 0x100]";
+        let mut vm_desc = crate::assembler::run(code).unwrap();
+        vm_desc.rom_blocks.push((0x10, 1));
+        vm_desc.forbidden_write_policy = ForbiddenWritePolicy::Error;
 
-        ram.set_ram_region(0, &arr).unwrap();
-        assert_eq!(ram.get_ram_region(0, arr.len()), &arr);
+        let mut vm = Vm::new(vm_desc).unwrap();
+        vm.cycle().unwrap();
+        assert!(matches!(
+            vm.cycle(),
+            Err(BError::AccessViolation {
+                pc: 1,
+                address: 0x10
+            })
+        ));
+        assert_eq!(vm.get_pc(), 1);
+        assert_eq!(*vm.get_ram_region(0x10, 1), [0]);
+        assert_eq!(
+            vm.get_fault(),
+            Some(Fault {
+                pc: 1,
+                address: 0x10,
+                value: 0x10,
+                kind: FaultKind::ForbiddenWrite
+            })
+        );
 
-        // Test callbacks
-        let out_var = Arc::new(Mutex::new(0));
-        let out = out_var.clone();
+        // handlers receive the address and value
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let w = writes.clone();
+        vm.set_forbidden_write_policy(ForbiddenWritePolicy::Handler(Box::new(
+            move |address, value| w.lock().unwrap().push((address, value)),
+        )));
+        vm.cycle().unwrap();
+        assert_eq!(*writes.lock().unwrap(), vec![(0x10, 0x10)]);
+    }
 
-        let callback = Box::new(move |new_value: u16| {
-            let mut out_p = out.lock().unwrap();
-            *out_p = new_value;
-        });
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_write_event() {
+        let code = r"[text]
+A = 0x10
+*A = add, 0, A
+*A = add, 0, A
+*A = inc, *A
+[consts 0x100]";
+        let mut vm_desc = crate::assembler::run(code).unwrap();
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let w = writes.clone();
+        vm_desc
+            .callbacks
+            .push((0x10, Box::new(move |write| w.lock().unwrap().push(write))));
 
-        ram.register_callback(0x99, callback);
-        ram.write_ram(0x99, 0x1234);
-        assert_eq!(*out_var.lock().unwrap(), 0x1234);
+        let mut vm = Vm::new(vm_desc).unwrap();
+        for _ in 0..4 {
+            vm.cycle().unwrap();
+        }
+        let writes = writes.lock().unwrap();
+        assert_eq!(
+            *writes,
+            vec![
+                WriteEvent {
+                    value: 0x10,
+                    old: 0,
+                    pc: 1
+                },
+                WriteEvent {
+                    value: 0x10,
+                    old: 0x10,
+                    pc: 2
+                },
+                WriteEvent {
+                    value: 0x11,
+                    old: 0x10,
+                    pc: 3
+                },
+            ]
+        );
+        assert_eq!(
+            writes.iter().map(WriteEvent::changed).collect::<Vec<_>>(),
+            vec![true, false, true]
+        );
     }
 
+    #[cfg(feature = "std")]
     #[test]
-    fn test_mem() {
-        let mut mem = MemoryUnit::new(0, 0, 0, 0, 0, 0, [0; 65536], vec![]).unwrap();
-
-        // test set_reg
-        mem.set_reg(Register::A, 0x1234);
-        assert_eq!(mem.get_reg(Register::A), 0x1234);
+    fn test_snapshot() {
+        let code = r"[text]
+A = 0x10
+D = add, 0, A
+*A = add, 0, D
+[consts 0x100]";
+        let vm_desc = crate::assembler::run(code).unwrap();
+        let mut vm = Vm::new(vm_desc).unwrap();
+        vm.cycle().unwrap();
+        let state = vm.snapshot();
+        vm.cycle().unwrap();
+        vm.cycle().unwrap();
+        assert_eq!(*vm.get_ram_region(0x10, 1), [0x10]);
+
+        vm.restore(&state).unwrap();
+        assert_eq!(vm.get_cycles(), 1);
+        assert_eq!(
+            vm.cpu_state(),
+            CpuState {
+                pc: 1,
+                a: 0x10,
+                ..Default::default()
+            }
+        );
+        assert_eq!(*vm.get_ram_region(0x10, 1), [0]);
+    }
 
-        // test get_reg on ram
-        assert_eq!(mem.get_reg(Register::None), 0);
-        mem.set_ram(0x1234, 0xabc);
-        assert_eq!(mem.get_reg(Register::MA), 0xabc);
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_von_neumann() {
+        // overwrites the instruction at 0x4 with `A = 0x42` before executing it
+        let code = r"[text]
+A = 0x7fbd
+D = not, A
+A = 4
+*A = add, 0, D
+A = 0
+[consts 0x100]";
+        let mut vm_desc = crate::assembler::run(code).unwrap();
+        vm_desc.execution_mode = ExecutionMode::VonNeumann;
+        let mut vm = Vm::new(vm_desc).unwrap();
+        assert_eq!(*vm.get_ram_region(0, 1), [0xffbd]);
+        for _ in 0..5 {
+            vm.cycle().unwrap();
+        }
+        assert_eq!(*vm.get_ram_region(4, 1), [0x8042]);
+        assert_eq!(vm.get_reg(Register::A), 0x42);
+    }
 
-        // test get_reg on ram
-        mem.set_reg(Register::MA, 0xdef);
-        assert_eq!(mem.get_reg(Register::MA), 0xdef);
-        assert_eq!(mem.get_ram_region(0x1234, 1), &[0xdef]);
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_perf_counters() {
+        // loops twice, then latches the counters
+        let code = r"[text]
+A = 2
+D = add, 0, A
+label loop:
+A = loop
+D = dec, D; JNE
+A = 1
+D = add, 0, A
+A = 0x7000
+*A = add, 0, D
+[consts 0x100]";
+        let mut vm_desc = crate::assembler::run(code).unwrap();
+        vm_desc.perf_counters = Some(0x7000);
+        let mut vm = Vm::new(vm_desc).unwrap();
+        for _ in 0..10 {
+            vm.cycle().unwrap();
+        }
+        let counters = vm.perf_counters();
+        assert_eq!((counters.cycles, counters.instructions), (10, 10));
+        assert_eq!(counters.branches, 1);
+        assert_eq!(*vm.get_ram_region(0x7000, 7), [0, 10, 0, 10, 0, 1, 0]);
+
+        // latch again
+        vm.set_pc(6);
+        vm.cycle().unwrap();
+        vm.cycle().unwrap();
+        assert_eq!(*vm.get_ram_region(0x7000, 7), [0, 12, 0, 12, 0, 1, 0]);
+
+        vm.set_ram(0x7000, PERF_RESET);
+        vm.set_pc(6);
+        vm.cycle().unwrap();
+        assert_eq!(vm.perf_counters(), PerfCounters::default());
+        assert_eq!(*vm.get_ram_region(0x7000, 7), [0; 7]);
+    }
 
-        // test get_regs
-        mem.set_reg(Register::D, 0x1234);
-        assert_eq!(mem.get_regs(), (0x1234, 0x1234, 0, 0, 0, 0));
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_rom_window() {
+        // tries to patch the instruction at 0x8 with `A = 0x42` before executing it
+        let code = r"[text]
+A = 8
+D = add, 0, A
+A = 0x7000
+*A = add, 0, D
+A = 0x7fbd
+D = not, A
+A = 0x7001
+*A = add, 0, D
+A = 0
+[consts 0x100]";
+        let mut vm_desc = crate::assembler::run(code).unwrap();
+        vm_desc.rom_window = Some(0x7000);
+        let original = vm_desc.rom[8];
+        let mut vm = Vm::new(vm_desc).unwrap();
+        for _ in 0..4 {
+            vm.cycle().unwrap();
+        }
+        assert_eq!(*vm.get_ram_region(0x7000, 2), [8, original]);
+        for _ in 0..5 {
+            vm.cycle().unwrap();
+        }
+        // the window is read only
+        assert_eq!(vm.get_rom_region(8, 1), &[original]);
+        assert_eq!(*vm.get_ram_region(0x7001, 1), [original]);
+        assert_eq!(vm.get_reg(Register::A), 0);
+    }
 
-        // don't test other methods, they are just wrappers for RAM
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_rom_window_writable() {
+        // patches the instruction at 0x8 with `A = 0x42` before executing it
+        let code = r"[text]
+A = 8
+D = add, 0, A
+A = 0x7000
+*A = add, 0, D
+A = 0x7fbd
+D = not, A
+A = 0x7001
+*A = add, 0, D
+A = 0
+[consts 0x100]";
+        let mut vm_desc = crate::assembler::run(code).unwrap();
+        vm_desc.rom_window = Some(0x7000);
+        vm_desc.rom_writable = true;
+        let mut vm = Vm::new(vm_desc).unwrap();
+        for _ in 0..9 {
+            vm.cycle().unwrap();
+        }
+        assert_eq!(vm.get_rom_region(8, 1), &[0x8042]);
+        assert_eq!(vm.get_reg(Register::A), 0x42);
+
+        // writes from the host grow the ROM
+        vm.set_ram(0x7000, 0x200);
+        vm.set_ram(0x7001, 0x1234);
+        assert_eq!(vm.get_rom_region(0x200, 1), &[0x1234]);
+        vm.set_rom(0x200, 0x4321);
+        assert_eq!(*vm.get_ram_region(0x7001, 1), [0x4321]);
     }
 
+    #[cfg(feature = "std")]
     #[test]
-    fn test_rom() {
-        let rvs = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
-        let rom = Rom::from_iter(rvs.clone());
-        assert_eq!(rom.get_address(0), Some(1));
+    fn test_multicore() {
+        // core 0 posts 0x2a into the mailbox of core 1, core 1 waits for it and stores it with its core id
+        let sender = r"[text]
+A = 0x2a
+D = add, 0, A
+A = 0x7f04
+*A = add, 0, D
+A = 0x7f05
+*A = inc, 0
+[consts 0x100]";
+        let receiver = r"[text]
+label wait:
+A = 0x7f05
+D = add, 0, *A
+A = wait
+add, 0, D; JEQ
+A = 0x7f04
+D = add, 0, *A
+A = 0x7f00
+A = add, 0, *A
+*A = add, 0, D
+[consts 0x100]";
+        let receiver = crate::assembler::run(receiver).unwrap();
+        let mc_desc = MultiCoreDescription {
+            vm: crate::assembler::run(sender).unwrap(),
+            cores: vec![CoreDescription {
+                rom: receiver.rom,
+                ..Default::default()
+            }],
+            scheduler: Scheduler::RoundRobin { quantum: 3 },
+            mailbox: Some(0x7f00),
+        };
+        let mut mc = MultiCoreVm::new(mc_desc).unwrap();
+        assert_eq!(mc.core_count(), 2);
+        assert_eq!(*mc.vm().get_ram_region(0x7f01, 1), [2]);
 
-        assert_eq!(rom.get_rom_region(2, 0xff), &rvs[2..]);
+        let mut cycles = 0;
+        while mc.cycle().is_ok() && cycles < 100 {
+            cycles += 1;
+        }
+        assert!(mc.is_halted(0) && mc.is_halted(1));
+        assert_eq!(*mc.vm().get_ram_region(0x01, 1), [0x2a]);
+        assert_eq!(mc.core_state(0).d, 0x2a);
+        assert_eq!(mc.get_cycles(), cycles);
     }
 
     #[test]
-    fn test_pc() {
-        let mut pc = Pc::new(0);
-        pc.inc();
-        pc.inc();
-        assert_eq!(pc.get_val(), 2);
-
-        pc.set(0xffff);
-        pc.inc();
-        assert_eq!(pc.get_val(), 0);
+    fn test_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Vm>();
+        assert_send::<VmDescription>();
     }
 
+    #[cfg(feature = "std")]
     #[test]
-    fn test_instruction() {
-        match Instruction::from_u16(0b1000000000000010) {
-            Instruction::Alu(_) => {
-                panic!("incorrect data instruction decoding")
-            }
-            Instruction::Data(val) => {
-                assert_eq!(val, 2)
+    fn test_device_tick() {
+        // counts the ticks it receives and mirrors the count into RAM
+        struct Counter(Arc<Mutex<u64>>);
+        impl Device for Counter {
+            fn tick(&mut self, cycles: u64, ram: &mut Ram) {
+                let mut count = self.0.lock().unwrap();
+                *count += cycles;
+                ram.set_ram(0x10, *count as u16);
             }
         }
 
-        match Instruction::from_u16(0b0000000000000010) {
-            Instruction::Alu(inst) => {
-                // no need to test all ALU instruction fields. we trust `bitfield` is correctly implemented
-                assert_eq!(inst.get_eq(), true);
-            }
-            Instruction::Data(_) => {
-                panic!("incorrect alu instruction decoding")
-            }
+        let count = Arc::new(Mutex::new(0));
+        let vm_desc = VmDescription {
+            rom: vec![0x8000; 5],
+            devices: vec![Box::new(Counter(count.clone()))],
+            ..Default::default()
+        };
+        let mut vm = Vm::new(vm_desc).unwrap();
+        for _ in 0..5 {
+            vm.cycle().unwrap();
         }
+        // halting does not tick the devices
+        assert!(vm.cycle().is_err());
+        assert_eq!(*count.lock().unwrap(), 5);
+        assert_eq!(vm.get_cycles(), 5);
+        assert_eq!(*vm.get_ram_region(0x10, 1), [5]);
     }
 
     #[test]
-    fn test_vm_description() {
-        // this test is very limited and does not correctly explore all cases. It will do for now.
+    fn test_restore_devices() {
+        // holds one byte of state and refuses to load anything else
+        struct Latch(u8);
+        impl Device for Latch {
+            fn tick(&mut self, _cycles: u64, _ram: &mut Ram) {}
+            fn save(&self) -> Vec<u8> {
+                vec![self.0]
+            }
+            fn load(&mut self, state: &[u8]) -> Result<(), BError> {
+                let [value] = state else {
+                    return Err(BError::DeserializationError(FormatError::from("bad latch")));
+                };
+                self.0 = *value;
+                Ok(())
+            }
+        }
 
-        // set up some values
         let vm_desc = VmDescription {
-            pc: 0x123,
-            rom: vec![0x1234, 0x5678],
-            mem: Box::new(core::array::from_fn(|i| i as u16)),
-            rom_mappings: vec![(0x123, 0x456, 0x789)],
-            regs: [1, 2, 3, 4, 5, 6],
+            rom: vec![0x8000; 5],
+            devices: vec![Box::new(Latch(1)), Box::new(Latch(2))],
             ..Default::default()
         };
-
-        // serialize and deserialize
-        let serialized = vm_desc.serialize().unwrap();
-        let deserialized = VmDescription::deserialize(&serialized).unwrap();
-
-        // check values
-        assert_eq!(deserialized.pc, 0x123);
-        assert_eq!(deserialized.rom, vec![0x1234, 0x5678]);
-        assert_eq!(
-            deserialized.mem.as_slice(),
-            &core::array::from_fn::<u16, RAM_LEN, _>(|i| i as u16)
-        );
-        assert_eq!(deserialized.rom_mappings, &[(0x123, 0x456, 0x789)]);
-        assert_eq!(&deserialized.regs, &[1u16, 2, 3, 4, 5, 6]);
+        let mut vm = Vm::new(vm_desc).unwrap();
+        vm.mem.ram.last_bus_value = 0x42;
+        let mut state = vm.snapshot();
+        vm.cycle().unwrap();
+        vm.mem.ram.last_bus_value = 0x43;
+
+        // the second device fails, so the first one is put back and the CPU is untouched
+        state.devices = vec![vec![3], vec![]];
+        assert!(vm.restore(&state).is_err());
+        assert_eq!(vm.save_devices(), [[1], [2]]);
+        assert_eq!(vm.get_pc(), 1);
+
+        state.devices = vec![vec![3], vec![4]];
+        vm.restore(&state).unwrap();
+        assert_eq!(vm.save_devices(), [[3], [4]]);
+        assert_eq!(vm.get_pc(), 0);
+        assert_eq!(vm.mem.ram.last_bus_value, 0x42);
     }
 
+    #[cfg(feature = "std")]
     #[test]
-    fn test_vm() {
-        // this test relies on the assembler being correctly implemented. We do not test all
-        // functions of the VM but test wether some of them work correctly together.
-        let code = r"[macros]
-[text]
-A = 0x1234
-D = add, 0, A
-A = 0
-*A = add, 0, D
-A = 0x512
-JMP
-[consts 0x100]";
-
-        let vm_desc = crate::assembler::run(code).unwrap();
+    fn test_instruction_builder() {
+        use crate::assembler::text_processor::assemble_at;
+        use Register::*;
+
+        let cases = [
+            (InstructionBuilder::add(D, A).target(D), "D = add, D, A"),
+            (InstructionBuilder::sub(A, E).target(MA), "*A = sub, A, E"),
+            (
+                InstructionBuilder::and(None, F).jump(Jump::Jne),
+                "and, 0, F; JNE",
+            ),
+            (InstructionBuilder::xor(A, A).target(H), "H = xor, A, A"),
+            (InstructionBuilder::inc(MA).target(MA), "*A = inc, *A"),
+            (
+                InstructionBuilder::not(None).target(G).jump(Jump::Jmp),
+                "G = not, 0; JMP",
+            ),
+            (InstructionBuilder::lsr(D).jump(Jump::Jge), "lsr, D; JGE"),
+            (InstructionBuilder::rol(E).target(E), "E = rol, E"),
+        ];
+        for (builder, code) in cases {
+            assert_eq!(
+                builder.build().unwrap().0,
+                assemble_at(code, 0).unwrap()[0],
+                "{}",
+                code
+            );
+        }
+        assert!(InstructionBuilder::add(A, None).build().is_err());
+        assert!(InstructionBuilder::or(None, None).build().is_err());
+    }
 
-        let mut vm = Vm::new(vm_desc).unwrap();
-        for _ in 0..6 {
-            vm.cycle().unwrap();
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_instruction_text() {
+        use core::str::FromStr;
+
+        // assembling is slow, test a spread of words
+        for word in (0..=u16::MAX).step_by(13) {
+            let inst = Instruction::from_u16(word);
+            let text = inst.to_string();
+            let alu = AluInstruction(word);
+            // no mnemonic or an operand without a name
+            if word & BIT_15 == 0
+                && ((alu.get_u() && alu.get_op() > 0b100) || text.contains("None"))
+            {
+                continue;
+            }
+            let parsed = Instruction::from_str(&text).unwrap_or_else(|e| panic!("{}: {}", text, e));
+            assert_eq!(parsed.to_string(), text);
         }
-        assert_eq!(vm.get_reg(Register::A), 0x512);
-        assert_eq!(vm.get_reg(Register::D), 0x1234);
-        assert_eq!(vm.get_pc(), 0x512);
-        assert_eq!(vm.get_ram_region(0x0, 2), &[0x1234, 0x0]);
-        assert!(matches!(
-            vm.cycle(),
-            Err(BError::ExecutionHaltedError { .. })
-        ));
+        assert!(Instruction::from_str("A = 5\nA = 6").is_err());
+        assert!(Instruction::from_str("label here:").is_err());
+        assert!(Instruction::from_str("A = nowhere").is_err());
     }
 }
@@ -0,0 +1,118 @@
+//! The reference model of the ALU. The interpreter computes every ALU instruction with [`compute()`], so property tests
+//! and hardware implementations can be checked against it without running a VM. Operand selection (`zx`, `sw`) and
+//! the jump happen outside of the ALU, see [ISA](../docs/ISA.md).
+
+/// The condition flags of an ALU result, which is read as signed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Flags {
+    pub lt: bool,
+    pub eq: bool,
+    pub gt: bool,
+}
+
+impl Flags {
+    /// The flags of `result`
+    pub fn of(result: u16) -> Self {
+        let signed = result as i16;
+        Self {
+            lt: signed < 0,
+            eq: signed == 0,
+            gt: signed > 0,
+        }
+    }
+
+    /// Whether a jump on the condition bits `lt`, `eq` and `gt` of an instruction is taken
+    pub fn jumps(&self, lt: bool, eq: bool, gt: bool) -> bool {
+        (self.lt && lt) || (self.eq && eq) || (self.gt && gt)
+    }
+}
+
+/// Compute the operation `op` (3 bits) on `x` and `y`. `u` selects the arithmetic operations, otherwise `op` is a logic
+/// operation. Single operand operations ignore `y`. Returns `None` for the unused arithmetic operations `0b101` to
+/// `0b111`
+/// ## Examples
+/// ```rust
+/// use bric_vm::vm::alu::{Flags, compute};
+///
+/// // sub
+/// assert_eq!(compute(0b001, true, 2, 3), Some((0xffff, Flags { lt: true, eq: false, gt: false })));
+/// // asr keeps the sign
+/// assert_eq!(compute(0b100, true, 0x8004, 0).unwrap().0, 0xc002);
+/// // rol
+/// assert_eq!(compute(0b110, false, 0x8001, 0).unwrap().0, 0x0003);
+/// assert_eq!(compute(0b101, true, 1, 1), None);
+/// ```
+pub fn compute(op: u8, u: bool, x: u16, y: u16) -> Option<(u16, Flags)> {
+    let result = if u {
+        match op {
+            0b000 => x.wrapping_add(y),
+            0b001 => x.wrapping_sub(y),
+            0b010 => x.wrapping_add(1),
+            0b011 => x.wrapping_sub(1),
+            0b100 => ((x as i16) >> 1) as u16,
+            _ => return None,
+        }
+    } else {
+        match op & 0b111 {
+            0b000 => x & y,
+            0b001 => x | y,
+            0b010 => x ^ y,
+            0b011 => !x,
+            0b100 => x << 1,
+            0b101 => x >> 1,
+            0b110 => x.rotate_left(1),
+            _ => x.rotate_right(1),
+        }
+    };
+    Some((result, Flags::of(result)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute() {
+        let cases = [
+            (0b000, false, 0xff0f, 0x0ff0, 0x0f00),
+            (0b001, false, 0xff00, 0x000f, 0xff0f),
+            (0b010, false, 0xffff, 0x0f0f, 0xf0f0),
+            (0b011, false, 0x00ff, 0, 0xff00),
+            (0b100, false, 0xc001, 0, 0x8002),
+            (0b101, false, 0x8002, 0, 0x4001),
+            (0b111, false, 0x0001, 0, 0x8000),
+            (0b000, true, 0xffff, 2, 1),
+            (0b010, true, 0xffff, 0, 0),
+            (0b011, true, 0, 0, 0xffff),
+            (0b100, true, 0x0004, 0, 0x0002),
+        ];
+        for (op, u, x, y, result) in cases {
+            assert_eq!(compute(op, u, x, y).unwrap().0, result, "{:#05b} {}", op, u);
+        }
+        assert_eq!(compute(0b000, false, 0, 0).unwrap().1, Flags::of(0));
+        assert!(Flags::of(0).eq);
+        assert!(Flags::of(1).jumps(false, false, true));
+        assert!(!Flags::of(0x8000).jumps(false, true, true));
+        for op in 0b101..=0b111 {
+            assert_eq!(compute(op, true, 1, 1), None);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_asr_in_vm() {
+        let code = r"[text]
+A = 0x7fff
+A = not, A
+D = asr, A
+A = 0x4
+E = asr, A
+[consts 0x100]";
+        let mut vm = crate::vm::Vm::new(crate::assembler::run(code).unwrap()).unwrap();
+        for _ in 0..5 {
+            vm.cycle().unwrap();
+        }
+        let state = vm.cpu_state();
+        assert_eq!((state.d, state.e), (0xc000, 0x2));
+    }
+}
@@ -0,0 +1,233 @@
+//! How the words of RAM are stored. [`RamBacking::Flat`] keeps all of RAM in one allocation, [`RamBacking::Paged`]
+//! only allocates the pages that were written, which keeps VMs that touch little memory small.
+use super::{RAM_LEN, empty_ram};
+use alloc::{borrow::Cow, boxed::Box, vec::Vec};
+use core::ops::{Index, IndexMut, Range};
+
+/// Words per page of [`RamBacking::Paged`]
+pub const PAGE_LEN: usize = 256;
+const PAGES: usize = RAM_LEN / PAGE_LEN;
+
+static ZERO_PAGE: [u16; PAGE_LEN] = [0; PAGE_LEN];
+
+type Page = Box<[u16; PAGE_LEN]>;
+
+/// How the RAM of a VM is stored, see [`crate::vm::VmDescription::ram_backing`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RamBacking {
+    /// All of RAM in one allocation of 128KB. Every access is a plain index, this is the fastest
+    #[default]
+    Flat,
+    /// Pages of [`PAGE_LEN`] words that are allocated when they are first written. Untouched pages read zero. Reading
+    /// a region that spans pages, like all of RAM for a snapshot, copies it. Only mutably borrowing such a region turns
+    /// the RAM into [`RamBacking::Flat`]
+    Paged,
+}
+
+/// The words of RAM. Indexing works the same for both backings
+#[derive(Debug, Clone)]
+pub(crate) enum Store {
+    Flat(Box<[u16; RAM_LEN]>),
+    Paged(Vec<Option<Page>>),
+}
+
+impl Store {
+    pub(crate) fn new(ram: Box<[u16; RAM_LEN]>, backing: RamBacking) -> Self {
+        match backing {
+            RamBacking::Flat => Self::Flat(ram),
+            RamBacking::Paged => Self::paged(&ram),
+        }
+    }
+
+    /// Page `ram`, leaving out pages that are all zero
+    fn paged(ram: &[u16; RAM_LEN]) -> Self {
+        Self::Paged(
+            ram.chunks_exact(PAGE_LEN)
+                .map(|chunk| {
+                    chunk
+                        .iter()
+                        .any(|word| *word != 0)
+                        .then(|| Box::new(chunk.try_into().unwrap()))
+                })
+                .collect(),
+        )
+    }
+
+    pub(crate) fn backing(&self) -> RamBacking {
+        match self {
+            Self::Flat(_) => RamBacking::Flat,
+            Self::Paged(_) => RamBacking::Paged,
+        }
+    }
+
+    /// Switch to another backing, keeping the contents
+    pub(crate) fn set_backing(&mut self, backing: RamBacking) {
+        if backing != self.backing() {
+            *self = Self::new(self.to_flat(), backing);
+        }
+    }
+
+    /// Allocated pages. Flat RAM counts as all of them
+    pub(crate) fn resident_pages(&self) -> usize {
+        match self {
+            Self::Flat(_) => PAGES,
+            Self::Paged(pages) => pages.iter().filter(|page| page.is_some()).count(),
+        }
+    }
+
+    /// Copy the contents into one allocation
+    pub(crate) fn to_flat(&self) -> Box<[u16; RAM_LEN]> {
+        match self {
+            Self::Flat(ram) => ram.clone(),
+            Self::Paged(pages) => {
+                let mut ram = empty_ram();
+                for (chunk, page) in ram.chunks_exact_mut(PAGE_LEN).zip(pages) {
+                    if let Some(page) = page {
+                        chunk.copy_from_slice(&page[..]);
+                    }
+                }
+                ram
+            }
+        }
+    }
+
+    /// Overwrite all of RAM, keeping the backing
+    pub(crate) fn load(&mut self, ram: &[u16; RAM_LEN]) {
+        match self {
+            Self::Flat(own) => own.copy_from_slice(ram),
+            Self::Paged(_) => *self = Self::paged(ram),
+        }
+    }
+
+    /// The words in `range`. Borrowed unless the RAM is paged and the range spans pages
+    pub(crate) fn read(&self, range: Range<usize>) -> Cow<'_, [u16]> {
+        if range.is_empty() {
+            return Cow::Borrowed(&[]);
+        }
+        match self {
+            Self::Flat(ram) => Cow::Borrowed(&ram[range]),
+            Self::Paged(pages) => match single_page(&range) {
+                Some((page, inner)) => Cow::Borrowed(match &pages[page] {
+                    Some(page) => &page[inner],
+                    None => &ZERO_PAGE[inner],
+                }),
+                None => Cow::Owned(range.map(|address| self[address]).collect()),
+            },
+        }
+    }
+
+    /// Overwrite the words from `start` with `values`, only allocating the pages that are written
+    pub(crate) fn write(&mut self, start: usize, values: &[u16]) {
+        match self {
+            Self::Flat(ram) => ram[start..start + values.len()].copy_from_slice(values),
+            Self::Paged(_) => {
+                for (offset, value) in values.iter().enumerate() {
+                    self[start + offset] = *value;
+                }
+            }
+        }
+    }
+
+    /// Mutably borrow the words in `range`. A range that spans pages turns paged RAM into flat RAM
+    pub(crate) fn slice_mut(&mut self, range: Range<usize>) -> &mut [u16] {
+        if range.is_empty() {
+            return &mut [];
+        }
+        let single = single_page(&range);
+        if single.is_none() {
+            self.set_backing(RamBacking::Flat);
+        }
+        match (self, single) {
+            (Self::Flat(ram), _) => &mut ram[range],
+            (Self::Paged(pages), Some((page, inner))) => {
+                &mut pages[page].get_or_insert_with(|| Box::new(ZERO_PAGE))[inner]
+            }
+            (Self::Paged(_), None) => unreachable!(),
+        }
+    }
+
+    /// Copy the words in `source` to `destination`, the regions may overlap
+    pub(crate) fn copy_within(&mut self, source: Range<usize>, destination: usize) {
+        match self {
+            Self::Flat(ram) => ram.copy_within(source, destination),
+            Self::Paged(_) if destination <= source.start => {
+                for (offset, address) in source.enumerate() {
+                    self[destination + offset] = self[address];
+                }
+            }
+            Self::Paged(_) => {
+                for (offset, address) in source.enumerate().rev() {
+                    self[destination + offset] = self[address];
+                }
+            }
+        }
+    }
+}
+
+/// The page of `range` and the range inside of it, if it doesn't span pages
+fn single_page(range: &Range<usize>) -> Option<(usize, Range<usize>)> {
+    let page = range.start / PAGE_LEN;
+    let offset = page * PAGE_LEN;
+    (range.end <= offset + PAGE_LEN).then(|| (page, range.start - offset..range.end - offset))
+}
+
+impl Index<usize> for Store {
+    type Output = u16;
+
+    fn index(&self, address: usize) -> &u16 {
+        match self {
+            Self::Flat(ram) => &ram[address],
+            Self::Paged(pages) => match &pages[address / PAGE_LEN] {
+                Some(page) => &page[address % PAGE_LEN],
+                None => &ZERO_PAGE[0],
+            },
+        }
+    }
+}
+
+impl IndexMut<usize> for Store {
+    fn index_mut(&mut self, address: usize) -> &mut u16 {
+        match self {
+            Self::Flat(ram) => &mut ram[address],
+            Self::Paged(pages) => {
+                let page = pages[address / PAGE_LEN].get_or_insert_with(|| Box::new(ZERO_PAGE));
+                &mut page[address % PAGE_LEN]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paged_store() {
+        let mut store = Store::new(empty_ram(), RamBacking::Paged);
+        assert_eq!(store.resident_pages(), 0);
+        store[0x1234] = 7;
+        store.slice_mut(0x1235..0x1237).copy_from_slice(&[8, 9]);
+        assert_eq!(store.resident_pages(), 1);
+        assert_eq!(store[0x1234], 7);
+        assert_eq!(*store.read(0x4000..0x4002), [0, 0]);
+        // empty ranges, also at the end of RAM, don't touch any page
+        assert!(store.read(RAM_LEN..RAM_LEN).is_empty());
+        assert!(store.slice_mut(RAM_LEN..RAM_LEN).is_empty());
+        store.copy_within(0x1234..0x1237, 0x12ff);
+        assert_eq!(store.resident_pages(), 2);
+        // reading across pages copies them and keeps the store paged
+        assert_eq!(*store.read(0x12fe..0x1303), [0, 7, 8, 9, 0]);
+        store.write(0x20ff, &[1, 2]);
+        assert_eq!(store.backing(), RamBacking::Paged);
+        assert_eq!(store.resident_pages(), 4);
+        // only mutably borrowing across pages flattens it
+        store.slice_mut(0x12fe..0x1303)[0] = 1;
+        assert_eq!(store.backing(), RamBacking::Flat);
+        assert_eq!(store.to_flat()[0x1301], 9);
+
+        let mut store = Store::new(store.to_flat(), RamBacking::Paged);
+        assert_eq!(store.resident_pages(), 4);
+        store.load(&empty_ram());
+        assert_eq!(store.resident_pages(), 0);
+    }
+}
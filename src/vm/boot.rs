@@ -0,0 +1,45 @@
+//! The built-in boot ROM, used when [`crate::vm::VmDescription::boot_rom`] is set. It loads a program image from the
+//! [block storage device](../docs/boot.md) into ROM through the ROM window, copies the consts of the image into RAM
+//! and jumps to address 0. `boot.basm` next to this file is its source.
+
+/// ROM address of the boot ROM, the PC starts here. Images have to end in front of it
+pub const BOOT_ROM_BASE: u16 = 0x7f00;
+
+/// RAM address of the ROM window the boot ROM writes the image through. The word after the window is used by the boot
+/// ROM too
+pub const BOOT_WINDOW: u16 = 0x7f00;
+
+/// Amount of RAM words from [`BOOT_WINDOW`] the boot ROM uses, the window and the RAM address consts are copied to
+pub const BOOT_RAM_LEN: usize = 3;
+
+/// What the VM hands over to the program when the boot ROM jumps to it: the ROM window of the description and the RAM
+/// words the boot ROM used, as they were before booting
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Handover {
+    pub rom_window: Option<u16>,
+    pub rom_writable: bool,
+    pub ram: [u16; BOOT_RAM_LEN],
+}
+
+/// The instructions of the boot ROM, assembled at [`BOOT_ROM_BASE`]
+pub const BOOT_ROM: [u16; 69] = [
+    0x8000, 0x18e0, 0x18e8, 0xea01, 0x48d0, 0x4a20, 0xea02, 0x28f0, 0xea01, 0x48d0, 0x4a20, 0xea02,
+    0x28f8, 0xff1c, 0x68c2, 0xea01, 0x48d0, 0x4a20, 0xff00, 0x58d0, 0x5a28, 0xea02, 0x28d8, 0xff01,
+    0x38d0, 0x6b30, 0xff0d, 0x0007, 0xff43, 0x78c2, 0xea01, 0x48d0, 0x4a20, 0xea02, 0x28e8, 0xea01,
+    0x48d0, 0x4a20, 0xea02, 0x28f0, 0xea01, 0x48d0, 0x4a20, 0xea02, 0x28d8, 0xff02, 0x38d0, 0xff40,
+    0x68c2, 0xff00, 0x58d0, 0x5a28, 0xff01, 0x28d8, 0xff02, 0x28c8, 0x38d0, 0xff02, 0x28d8, 0x3a18,
+    0x38d0, 0x6b30, 0xff2f, 0x0007, 0x7b38, 0xff1c, 0x0007, 0x8000, 0x0007,
+];
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boot_rom_source() {
+        let words =
+            crate::assembler::text_processor::assemble_at(include_str!("boot.basm"), BOOT_ROM_BASE)
+                .unwrap();
+        assert_eq!(words, BOOT_ROM);
+    }
+}
@@ -0,0 +1,175 @@
+//! How often the CPU read and wrote every RAM address, to spot the memory usage patterns of a program. Exported as CSV
+//! or as a 256x256 image with one pixel per address, see [`crate::vm::Vm::enable_heat_map()`]
+use super::RAM_LEN;
+use alloc::{format, string::String, vec, vec::Vec};
+use core::fmt::Write;
+
+/// Width and height of the images, address `y * SIDE + x` is the pixel at `(x, y)`
+pub const SIDE: usize = 256;
+
+/// Reads and writes of every RAM address through `*A`. Instruction fetches, device and debugger accesses aren't counted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeatMap {
+    pub reads: Vec<u64>,
+    pub writes: Vec<u64>,
+}
+
+impl Default for HeatMap {
+    fn default() -> Self {
+        Self {
+            reads: vec![0; RAM_LEN],
+            writes: vec![0; RAM_LEN],
+        }
+    }
+}
+
+impl HeatMap {
+    pub(crate) fn record(&mut self, address: u16, read: bool, write: bool) {
+        self.reads[address as usize] += read as u64;
+        self.writes[address as usize] += write as u64;
+    }
+
+    /// Addresses that were read or written
+    pub fn touched(&self) -> usize {
+        self.reads
+            .iter()
+            .zip(&self.writes)
+            .filter(|(r, w)| **r + **w > 0)
+            .count()
+    }
+
+    /// `address,reads,writes` lines of the touched addresses, below a header
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("address,reads,writes\n");
+        for (address, (reads, writes)) in self.reads.iter().zip(&self.writes).enumerate() {
+            if reads + writes > 0 {
+                let _ = writeln!(csv, "{:#06x},{},{}", address, reads, writes);
+            }
+        }
+        csv
+    }
+
+    /// RGB pixels, rows first. Writes are red and reads are green, brighter the more often they happened on a
+    /// logarithmic scale. Untouched addresses are black
+    pub fn pixels(&self) -> Vec<u8> {
+        let (max_reads, max_writes) = (magnitude(&self.reads), magnitude(&self.writes));
+        let mut pixels = Vec::with_capacity(RAM_LEN * 3);
+        for (reads, writes) in self.reads.iter().zip(&self.writes) {
+            pixels.extend([
+                intensity(*writes, max_writes),
+                intensity(*reads, max_reads),
+                0,
+            ]);
+        }
+        pixels
+    }
+
+    /// A binary PPM image of [`HeatMap::pixels()`]
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::{assembler, vm::Vm};
+    ///
+    /// let code = r"[text]
+    /// A = 0x100
+    /// *A = add, 0, *A
+    /// [consts 0x200]";
+    /// let mut vm = Vm::new(assembler::run(code).unwrap()).unwrap();
+    /// vm.enable_heat_map();
+    /// while vm.cycle().is_ok() {}
+    ///
+    /// let heat = vm.heat_map().unwrap();
+    /// assert_eq!((heat.reads[0x100], heat.writes[0x100], heat.touched()), (1, 1, 1));
+    /// assert_eq!(heat.to_csv(), "address,reads,writes\n0x0100,1,1\n");
+    /// let ppm = heat.to_ppm();
+    /// assert!(ppm.starts_with(b"P6\n256 256\n255\n"));
+    /// assert_eq!(&ppm[ppm.len() - 3 * 0xff00..][..3], &[255, 255, 0]);
+    /// ```
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut ppm = format!("P6\n{} {}\n255\n", SIDE, SIDE).into_bytes();
+        ppm.extend(self.pixels());
+        ppm
+    }
+
+    /// A PNG image of [`HeatMap::pixels()`]
+    #[cfg(feature = "compression")]
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut raw = Vec::with_capacity(RAM_LEN * 3 + SIDE);
+        for row in self.pixels().chunks(SIDE * 3) {
+            // no filter
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+        let mut header = Vec::new();
+        header.extend((SIDE as u32).to_be_bytes());
+        header.extend((SIDE as u32).to_be_bytes());
+        // 8 bit RGB, deflate, no filters, not interlaced
+        header.extend([8, 2, 0, 0, 0]);
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png_chunk(&mut png, b"IHDR", &header);
+        png_chunk(
+            &mut png,
+            b"IDAT",
+            &miniz_oxide::deflate::compress_to_vec_zlib(&raw, 6),
+        );
+        png_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+/// Bits of the largest count
+fn magnitude(counts: &[u64]) -> u32 {
+    counts
+        .iter()
+        .max()
+        .map_or(0, |max| u64::BITS - max.leading_zeros())
+}
+
+fn intensity(count: u64, magnitude: u32) -> u8 {
+    if count == 0 {
+        return 0;
+    }
+    let bits = u64::BITS - count.leading_zeros();
+    (55 + 200 * bits / magnitude) as u8
+}
+
+#[cfg(feature = "compression")]
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+#[cfg(feature = "compression")]
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_png() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        let mut heat = HeatMap::default();
+        heat.record(0x0101, true, false);
+        let png = heat.to_png();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR"));
+        assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+        let idat = &png[33 + 8..png.len() - 16];
+        let raw = miniz_oxide::inflate::decompress_to_vec_zlib(idat).unwrap();
+        // second pixel of the second row, behind its filter byte
+        let row = &raw[SIDE * 3 + 1..][1..];
+        assert_eq!(&row[3..7], &[0, 255, 0, 0]);
+    }
+}
@@ -0,0 +1,95 @@
+//! Fault injection: single event upsets that flip bits of, or force values into, RAM, ROM, the registers or the PC,
+//! either right away or at a chosen cycle. Guest code that guards its data with checksums or redundancy can be
+//! checked against them. See [`crate::vm::Vm::inject()`] and [`crate::vm::Vm::schedule_injection()`].
+use core::fmt;
+
+use crate::{util::number_literal_to_u16, vm::Register};
+
+/// What an injection hits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// A RAM word. Written directly, bypassing access levels, mirrors and callbacks
+    Ram(u16),
+    /// A ROM word. Addresses past the end of ROM are ignored
+    Rom(u16),
+    Register(Register),
+    Pc,
+}
+
+impl Target {
+    /// Parse `pc`, a register like `D`, `*ADDR` for RAM or `rom:ADDR` for ROM
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::vm::{Register, inject::Target};
+    ///
+    /// assert_eq!(Target::parse("*0x100"), Some(Target::Ram(0x100)));
+    /// assert_eq!(Target::parse("rom:4"), Some(Target::Rom(4)));
+    /// assert_eq!(Target::parse("E"), Some(Target::Register(Register::E)));
+    /// assert_eq!(Target::parse("*A"), None);
+    /// ```
+    pub fn parse(input: &str) -> Option<Self> {
+        if input == "pc" {
+            return Some(Self::Pc);
+        }
+        if let Some(address) = input.strip_prefix('*') {
+            return number_literal_to_u16(address).ok().map(Self::Ram);
+        }
+        if let Some(address) = input.strip_prefix("rom:") {
+            return number_literal_to_u16(address).ok().map(Self::Rom);
+        }
+        match Register::from_str(input)? {
+            Register::MA | Register::None => None,
+            register => Some(Self::Register(register)),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ram(address) => write!(f, "*{:#06x}", address),
+            Self::Rom(address) => write!(f, "rom:{:#06x}", address),
+            Self::Register(register) => write!(f, "{}", register),
+            Self::Pc => write!(f, "pc"),
+        }
+    }
+}
+
+/// What an injection does to its target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    /// Flip the bits set in the mask
+    Flip(u16),
+    /// Replace the value
+    Force(u16),
+}
+
+impl Effect {
+    /// Get the value of the target after the injection
+    pub fn apply(&self, value: u16) -> u16 {
+        match self {
+            Self::Flip(mask) => value ^ mask,
+            Self::Force(forced) => *forced,
+        }
+    }
+}
+
+/// An injection scheduled for a cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Injection {
+    /// Value of [`crate::vm::Vm::get_cycles()`] at which the injection happens, before the instruction of that cycle
+    /// is executed
+    pub cycle: u64,
+    pub target: Target,
+    pub effect: Effect,
+}
+
+impl fmt::Display for Injection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.effect {
+            Effect::Flip(mask) => write!(f, "flip {:#06x} of {}", mask, self.target)?,
+            Effect::Force(value) => write!(f, "force {} to {:#06x}", self.target, value)?,
+        }
+        write!(f, " at cycle {}", self.cycle)
+    }
+}
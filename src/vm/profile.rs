@@ -0,0 +1,279 @@
+//! Profiling: how often every ROM address is executed, and how long calls take. The BRIC has no call instruction, so
+//! calls are recognized by convention: a taken jump to a function entry is a call and a later jump to the word after
+//! that jump is its return. Jumps to the entry of a function that is already being called, like a loop back to the
+//! top of a function or a recursive call, continue that call instead of starting another one. See
+//! [`crate::vm::Vm::enable_profile()`].
+use alloc::{collections::BTreeMap, string::String, vec, vec::Vec};
+use core::fmt;
+
+/// A call that hasn't returned yet
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    entry: u16,
+    return_to: u16,
+    /// Instructions executed before the call
+    began: u64,
+}
+
+/// Records the executed instructions and the calls of a VM
+#[derive(Debug, Clone)]
+pub(crate) struct Profiler {
+    counts: Vec<u64>,
+    /// Ordered
+    entries: Vec<u16>,
+    stack: Vec<Frame>,
+    calls: BTreeMap<u16, CallStats>,
+    executed: u64,
+}
+
+impl Profiler {
+    pub(crate) fn new(rom_len: usize, entries: &[u16]) -> Self {
+        let mut entries = entries.to_vec();
+        entries.sort_unstable();
+        entries.dedup();
+        Self {
+            counts: vec![0; rom_len],
+            entries,
+            stack: Vec::new(),
+            calls: BTreeMap::new(),
+            executed: 0,
+        }
+    }
+
+    /// Record the instruction at `pc`, which continued at `next`
+    pub(crate) fn record(&mut self, pc: u16, next: u16, jumped: bool) {
+        if let Some(count) = self.counts.get_mut(pc as usize) {
+            *count += 1;
+        }
+        self.executed += 1;
+        if !jumped {
+            return;
+        }
+        if let Some(depth) = self.stack.iter().rposition(|f| f.return_to == next) {
+            let frame = self.stack[depth];
+            let stats = self.calls.entry(frame.entry).or_default();
+            stats.calls += 1;
+            stats.inclusive += self.executed - frame.began;
+            // calls above it were branches that never returned
+            self.stack.truncate(depth);
+        } else if self.entries.binary_search(&next).is_ok() {
+            match self.stack.iter().rposition(|f| f.entry == next) {
+                Some(depth) => self.stack.truncate(depth + 1),
+                None => self.stack.push(Frame {
+                    entry: next,
+                    return_to: pc.wrapping_add(1),
+                    began: self.executed,
+                }),
+            }
+        }
+    }
+
+    /// Get the profile of the program, which is the first `program_len` words of `rom`
+    pub(crate) fn report(&self, rom: &[u16], program_len: usize) -> Profile {
+        // executed data after the program is kept
+        let executed = self.counts.iter().rposition(|count| *count > 0);
+        let end = executed.map_or(0, |last| last + 1).max(program_len);
+        let end = end.min(rom.len()).min(self.counts.len());
+        Profile {
+            rom: rom[..end].to_vec(),
+            counts: self.counts[..end].to_vec(),
+            calls: self.calls.clone(),
+        }
+    }
+}
+
+/// How often a function returned and the instructions executed by those calls, including the ones of the functions
+/// it called
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CallStats {
+    pub calls: u64,
+    pub inclusive: u64,
+}
+
+/// What a VM executed since [`crate::vm::Vm::enable_profile()`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    /// The program, see [`crate::vm::Vm::coverage()`] for where it ends
+    pub rom: Vec<u16>,
+    /// How often each word of `rom` was executed
+    pub counts: Vec<u64>,
+    /// Returned calls by function entry. Calls that haven't returned yet aren't counted
+    pub calls: BTreeMap<u16, CallStats>,
+}
+
+impl Profile {
+    /// Executed instructions
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The `n` most executed addresses with their counts, most executed first
+    pub fn hottest(&self, n: usize) -> Vec<(u16, u64)> {
+        let mut hot: Vec<(u16, u64)> = self
+            .counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(address, count)| (address as u16, *count))
+            .collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hot.truncate(n);
+        hot
+    }
+
+    /// Add up the counts per label of the `[text]` section. A label owns the addresses up to the next label, code in
+    /// front of the first label is owned by `<start>`. Labels that were never executed are left out, the most
+    /// executed label comes first
+    /// ## Examples
+    /// ```rust
+    /// use bric_vm::{assembler, vm::Vm};
+    ///
+    /// let code = r"[text]
+    /// A = 3
+    /// D = add, 0, A
+    /// label loop:
+    /// A = double
+    /// JMP
+    /// label back:
+    /// A = loop
+    /// D = dec, D; JGT
+    /// A = 0x7ff0
+    /// JMP
+    /// label double:
+    /// E = add, E, A
+    /// A = back
+    /// JMP
+    /// [consts 0x7ff0]";
+    /// let (vm_desc, info) = assembler::run_with_debug_info(code).unwrap();
+    /// let mut vm = Vm::new(vm_desc).unwrap();
+    /// vm.enable_profile(&info.labels.values().copied().collect::<Vec<_>>());
+    /// while vm.cycle().is_ok() {}
+    ///
+    /// let report = vm.profile().unwrap().by_symbol(&info.labels);
+    /// let double = report.symbols.iter().find(|s| s.name == "double").unwrap();
+    /// assert_eq!((double.calls, double.exclusive, double.inclusive), (3, 9, 9));
+    /// assert!(report.to_string().contains("double"));
+    /// ```
+    pub fn by_symbol(&self, labels: &BTreeMap<String, u16>) -> SymbolReport {
+        let mut starts: Vec<(u16, &str)> = labels
+            .iter()
+            .map(|(name, address)| (*address, name.as_str()))
+            .collect();
+        starts.sort_unstable();
+        if starts.first().is_none_or(|(address, _)| *address != 0) {
+            starts.insert(0, (0, "<start>"));
+        }
+        let mut symbols = Vec::new();
+        for (idx, (address, name)) in starts.iter().enumerate() {
+            let end = starts
+                .get(idx + 1)
+                .map_or(self.counts.len(), |(next, _)| *next as usize)
+                .min(self.counts.len());
+            let start = (*address as usize).min(end);
+            let exclusive = self.counts[start..end].iter().sum();
+            let stats = self.calls.get(address).copied().unwrap_or_default();
+            if exclusive == 0 && stats.calls == 0 {
+                continue;
+            }
+            symbols.push(SymbolProfile {
+                name: String::from(*name),
+                address: *address,
+                exclusive,
+                inclusive: stats.inclusive,
+                calls: stats.calls,
+            });
+        }
+        symbols.sort_by(|a, b| {
+            b.exclusive
+                .cmp(&a.exclusive)
+                .then(b.inclusive.cmp(&a.inclusive))
+                .then(a.address.cmp(&b.address))
+        });
+        SymbolReport {
+            total: self.total(),
+            symbols,
+        }
+    }
+}
+
+/// The executed instructions of a label, see [`Profile::by_symbol()`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolProfile {
+    pub name: String,
+    pub address: u16,
+    /// Instructions executed between the label and the next one
+    pub exclusive: u64,
+    /// Instructions executed by calls to the label, including called functions. See [`CallStats`]
+    pub inclusive: u64,
+    pub calls: u64,
+}
+
+/// The profile of a program by label, see [`Profile::by_symbol()`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolReport {
+    /// Executed instructions
+    pub total: u64,
+    /// Most executed first
+    pub symbols: Vec<SymbolProfile>,
+}
+
+impl fmt::Display for SymbolReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>7}  {:>12}  {:>12}  {:>8}  symbol",
+            "self %", "self", "inclusive", "calls"
+        )?;
+        for symbol in &self.symbols {
+            let share = symbol.exclusive as f64 * 100.0 / self.total.max(1) as f64;
+            write!(
+                f,
+                "\n{:>6.1}%  {:>12}  {:>12}  {:>8}  {} ({:#06x})",
+                share,
+                symbol.exclusive,
+                symbol.inclusive,
+                symbol.calls,
+                symbol.name,
+                symbol.address
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_call_tracking() {
+        let mut profiler = Profiler::new(0x20, &[0x10, 0x18]);
+        // call 0x10 from 4, which calls 0x18 from 0x11
+        profiler.record(4, 0x10, true);
+        profiler.record(0x10, 0x11, false);
+        profiler.record(0x11, 0x18, true);
+        // a loop back to the top of 0x18 continues the call
+        profiler.record(0x18, 0x18, true);
+        profiler.record(0x18, 0x12, true);
+        // the return of 0x10 also ends calls that never returned
+        profiler.record(0x12, 0x18, true);
+        profiler.record(0x18, 5, true);
+        let profile = profiler.report(&[0; 0x20], 0x20);
+        assert_eq!(profile.total(), 7);
+        assert_eq!(profile.hottest(1), vec![(0x18, 3)]);
+        assert_eq!(
+            profile.calls[&0x18],
+            CallStats {
+                calls: 1,
+                inclusive: 2
+            }
+        );
+        assert_eq!(
+            profile.calls[&0x10],
+            CallStats {
+                calls: 1,
+                inclusive: 6
+            }
+        );
+    }
+}
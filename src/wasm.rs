@@ -0,0 +1,123 @@
+//! JavaScript bindings for running BRICs in a browser, built with `wasm-pack build -- --no-default-features --features
+//! wasm,compression`.
+//! ```js
+//! import init, { Playground } from "./pkg/bric_vm.js";
+//!
+//! await init();
+//! const vm = new Playground("[text]\nA = 5\nD = add, 0, A\n[consts 0x100]");
+//! vm.run(100);
+//! console.log(vm.registers());
+//! ```
+use wasm_bindgen::prelude::*;
+
+use crate::{BError, assembler, debugger::Debugger, disassembler, vm::VmDescription};
+
+fn js_error(e: BError) -> JsError {
+    JsError::new(&e.to_string())
+}
+
+/// A program running in the debugger, with a UART at its usual address
+#[wasm_bindgen]
+pub struct Playground {
+    debugger: Debugger,
+}
+
+#[wasm_bindgen]
+impl Playground {
+    /// Assemble .basm source and load it
+    #[wasm_bindgen(constructor)]
+    pub fn new(source: &str) -> Result<Playground, JsError> {
+        Self::load(assembler::run(source).map_err(js_error)?)
+    }
+
+    /// Load the contents of a .bvm file
+    #[wasm_bindgen(js_name = fromBvm)]
+    pub fn from_bvm(bvm: &[u8]) -> Result<Playground, JsError> {
+        Self::load(VmDescription::deserialize(bvm).map_err(js_error)?)
+    }
+
+    fn load(vm_desc: VmDescription) -> Result<Playground, JsError> {
+        Ok(Self {
+            debugger: Debugger::new(vm_desc, vec![], true).map_err(js_error)?,
+        })
+    }
+
+    /// Execute one instruction
+    pub fn step(&mut self) {
+        self.debugger.step();
+    }
+
+    /// Execute up to `max_cycles` instructions, stopping at breakpoints and when the program halts
+    pub fn run(&mut self, max_cycles: usize) {
+        self.debugger.run(max_cycles);
+    }
+
+    /// Whether the program ran past the end of ROM
+    pub fn halted(&self) -> bool {
+        self.debugger.is_halted()
+    }
+
+    /// Get the program counter
+    pub fn pc(&self) -> u16 {
+        self.debugger.get_pc()
+    }
+
+    /// Get the A, D, E, F, G and H registers
+    pub fn registers(&self) -> Vec<u16> {
+        let cpu = self.debugger.cpu_state();
+        vec![cpu.a, cpu.d, cpu.e, cpu.f, cpu.g, cpu.h]
+    }
+
+    /// Get `length` words of RAM starting at `from`
+    pub fn ram(&self, from: u16, length: u16) -> Vec<u16> {
+        self.debugger.inspect_memory(from, length).to_vec()
+    }
+
+    /// Get the whole ROM
+    pub fn rom(&self) -> Vec<u16> {
+        self.debugger.get_rom().to_vec()
+    }
+
+    /// Set a breakpoint at ROM address `address`
+    #[wasm_bindgen(js_name = setBreakpoint)]
+    pub fn set_breakpoint(&mut self, address: u16) {
+        self.debugger.register_breakpoint(address);
+    }
+
+    /// Remove the breakpoint at ROM address `address`
+    #[wasm_bindgen(js_name = removeBreakpoint)]
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.debugger.remove_breakpoint(address)
+    }
+
+    /// Send text to the UART
+    #[wasm_bindgen(js_name = uartInput)]
+    pub fn uart_input(&mut self, text: &str) {
+        self.debugger.feed_uart(text.as_bytes(), None);
+    }
+
+    /// Get the output of the UART since the last call
+    #[wasm_bindgen(js_name = uartOutput)]
+    pub fn uart_output(&mut self) -> String {
+        self.debugger.get_uart_out()
+    }
+
+    /// Save a .bdb coredump
+    pub fn coredump(&self) -> Result<Vec<u8>, JsError> {
+        self.debugger.serialize().map_err(js_error)
+    }
+}
+
+/// Assemble .basm source into the contents of a .bvm file
+#[wasm_bindgen]
+pub fn assemble(source: &str) -> Result<Vec<u8>, JsError> {
+    assembler::run(source)
+        .and_then(|vm_desc| vm_desc.serialize())
+        .map_err(js_error)
+}
+
+/// Disassemble ROM words into .basm lines prefixed with their address
+#[wasm_bindgen]
+pub fn disassemble(rom: &[u16]) -> Result<String, JsError> {
+    disassembler::disassemble(rom, true).map_err(js_error)
+}